@@ -1,12 +1,15 @@
+use kvlite::cache::CachePolicy;
 use kvlite::db::dbimpl::DBImpl;
 use kvlite::db::key_types::RawUserKey;
-use kvlite::db::options::WriteOptions;
+use kvlite::db::options::{Options, WriteOptions};
 use kvlite::db::DB;
 use kvlite::memory::{MrMwSkipMapMemTable, MrSwSkipMapMemTable};
+use kvlite::sstable::block_cache::BlockCache;
 use kvlite::wal::simple_wal::SimpleWriteAheadLog;
 use procfs::CpuInfo;
 use rand::distributions::Uniform;
 use rand::{Rng, RngCore};
+use std::sync::Arc;
 use std::time::Duration;
 use tempfile::TempDir;
 
@@ -14,6 +17,7 @@ const NUM_KVS: u128 = 1000000;
 const KEY_SIZE: usize = std::mem::size_of::<i128>();
 const VALUE_SIZE: usize = 100;
 const RAW_SIZE: f64 = ((KEY_SIZE + VALUE_SIZE) * NUM_KVS as usize) as f64 / 1024f64 / 1024f64;
+const BLOCK_CACHE_CAPACITY: usize = 8 << 20;
 
 fn print_environment() {
     println!("KVLite: version {}", env!("CARGO_PKG_VERSION"));
@@ -63,6 +67,13 @@ impl BenchMark {
         self._temp_dir = temp_dir;
     }
 
+    fn reopen_db_with_options(&mut self, options: Options) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = DataBase::open_with_options(temp_dir.path(), options).unwrap();
+        self.db = db;
+        self._temp_dir = temp_dir;
+    }
+
     fn fill_seq(&self) {
         let write_options = WriteOptions { sync: false };
 
@@ -192,6 +203,52 @@ impl BenchMark {
         );
     }
 
+    /// Read random keys with each cache policy and print the hit rates of caches.
+    fn read_random_cache_policies(&mut self) {
+        let num_kvs = NUM_KVS / 10;
+        let policies = [
+            ("lru", CachePolicy::Lru),
+            (
+                "lru_high_pri_pool",
+                CachePolicy::LruWithHighPriPool {
+                    high_pri_pool_ratio: 0.5,
+                },
+            ),
+            ("clock", CachePolicy::Clock),
+        ];
+        for (policy_name, policy) in policies {
+            self.reopen_db_with_options(Options {
+                block_cache: Arc::new(BlockCache::with_policy(BLOCK_CACHE_CAPACITY, policy)),
+                ..Options::default()
+            });
+            let write_options = WriteOptions { sync: false };
+            let mut random = rand::thread_rng();
+            for i in 0u128..num_kvs {
+                let mut value = Vec::from([0u8; VALUE_SIZE]);
+                random.fill_bytes(&mut value);
+                self.db
+                    .set(&write_options, Vec::from(i.to_be_bytes()), value)
+                    .unwrap();
+            }
+
+            let mut random = rand::thread_rng().sample_iter(Uniform::new(0, num_kvs));
+            let start = std::time::Instant::now();
+            for _ in 0u128..num_kvs {
+                self.db
+                    .get(&Vec::from(random.next().unwrap().to_be_bytes()))
+                    .unwrap();
+            }
+            let elapsed = (std::time::Instant::now() - start).as_secs_f64();
+            let block_cache = self.db.block_cache_statistics();
+            println!(
+                "{:<20}: {:10.3} reads per second | block cache hit rate: {:.3}",
+                format!("read_random_{}", policy_name),
+                num_kvs as f64 / elapsed,
+                block_cache.hit_rate()
+            );
+        }
+    }
+
     fn print_write(&self, bench_name: &str, micros_per_op: f64, size_per_sec: f64) {
         let file_size = fs_extra::dir::get_size(self.db.db_path()).unwrap();
         println!(
@@ -210,11 +267,7 @@ fn main() {
         println!("Use system default memory allocator");
     }
 
-    if cfg!(feature = "snappy_compression") {
-        println!("Use snappy compression algorithm");
-    } else {
-        println!("No compression algorithm");
-    }
+    println!("Compression: {:?}", Options::default().compression);
 
     println!("-------------------------------------------------");
     let mut benchmark = BenchMark::new();
@@ -224,4 +277,5 @@ fn main() {
     benchmark.fill_random();
     benchmark.read_random();
     benchmark.overwrite();
+    benchmark.read_random_cache_policies();
 }
@@ -4,3 +4,10 @@ pub fn u32_from_le_bytes(bytes: &[u8]) -> u32 {
     buf.clone_from_slice(bytes);
     u32::from_le_bytes(buf)
 }
+
+pub fn u64_from_le_bytes(bytes: &[u8]) -> u64 {
+    debug_assert_eq!(bytes.len(), 8);
+    let mut buf = [0u8; 8];
+    buf.clone_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
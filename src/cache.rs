@@ -4,62 +4,179 @@ use std::marker::PhantomData;
 use std::mem::MaybeUninit;
 use std::ptr;
 use std::ptr::NonNull;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 
+/// Default capacity of each shard, in which every entry is charged 1.
 const CACHE_CAP: usize = 256;
 
 const NUM_SHARD_BITS: usize = 4;
 const NUM_SHARD: usize = 1 << NUM_SHARD_BITS;
 
+/// Eviction policy of [ShardLRUCache].
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum CachePolicy {
+    /// Evict the least recently used entry.
+    #[default]
+    Lru,
+    /// LRU with a pool of `high_pri_pool_ratio` of the capacity reserved for high priority
+    /// entries and the entries looked up after insertion. Entries out of the pool are
+    /// evicted first, so one pass of scanning cannot flush the entries in the pool.
+    LruWithHighPriPool { high_pri_pool_ratio: f64 },
+    /// CLOCK (second chance) algorithm. Looking up an entry only sets its reference bit
+    /// without moving it, and referenced entries survive one sweep of eviction.
+    Clock,
+}
+
+/// Priority of entries, which only takes effect in [CachePolicy::LruWithHighPriPool].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CachePriority {
+    /// e.g. filters and indexes of sstables.
+    High,
+    /// e.g. data blocks.
+    Low,
+}
+
+/// Hit and miss counters of [ShardLRUCache::look_up].
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CacheStatistics {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStatistics {
+    pub fn hit_rate(&self) -> f64 {
+        if self.hits + self.misses == 0 {
+            0.0
+        } else {
+            self.hits as f64 / (self.hits + self.misses) as f64
+        }
+    }
+}
+
+/// Called with the entries evicted for capacity, but not with the erased ones. It is called
+/// after the lock of the shard is released, so it may be slow, e.g. write to a file.
+pub type EvictionCallback<K, V> = Arc<dyn Fn(&K, &V) + Send + Sync>;
+
 pub struct ShardLRUCache<K: Eq + Hash + Send + Sync, V: Send + Sync> {
     caches: [Mutex<LRUCache<K, V>>; NUM_SHARD],
+    eviction_callback: Option<EvictionCallback<K, V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
     _k: PhantomData<K>,
     _v: PhantomData<V>,
 }
 
 impl<K: Eq + Hash + Send + Sync, V: Send + Sync> Default for ShardLRUCache<K, V> {
     fn default() -> Self {
+        Self::new(CACHE_CAP * NUM_SHARD)
+    }
+}
+
+impl<K: Eq + Hash + Send + Sync, V: Send + Sync> ShardLRUCache<K, V> {
+    /// Create a LRU cache whose total charge of entries is limited to `capacity`, which is
+    /// evenly divided among the shards.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_policy(capacity, CachePolicy::Lru)
+    }
+
+    /// Create a cache of `capacity` that evicts entries by `policy`.
+    pub fn with_policy(capacity: usize, policy: CachePolicy) -> Self {
+        Self::with_eviction_callback(capacity, policy, None)
+    }
+
+    /// Create a cache of `capacity` that evicts entries by `policy` and passes them to
+    /// `eviction_callback` before dropping them.
+    pub fn with_eviction_callback(
+        capacity: usize,
+        policy: CachePolicy,
+        eviction_callback: Option<EvictionCallback<K, V>>,
+    ) -> Self {
+        let shard_capacity = capacity.div_ceil(NUM_SHARD);
         ShardLRUCache {
-            caches: [
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-                Mutex::new(LRUCache::new()),
-            ],
+            caches: [(); NUM_SHARD].map(|_| Mutex::new(LRUCache::new(shard_capacity, policy))),
+            eviction_callback,
+            hits: AtomicU64::default(),
+            misses: AtomicU64::default(),
             _k: PhantomData,
             _v: PhantomData,
         }
     }
-}
 
-impl<K: Eq + Hash + Send + Sync, V: Send + Sync> ShardLRUCache<K, V> {
+    /// Create a cache with the default capacity that evicts entries by `policy`.
+    pub fn default_with_policy(policy: CachePolicy) -> Self {
+        Self::with_policy(CACHE_CAP * NUM_SHARD, policy)
+    }
+
+    /// Insert key-value charged 1 with high priority when key is not found.
     pub fn insert_no_exists(&self, key: K, value: V, hash: u32) {
-        let mut guard: MutexGuard<LRUCache<K, V>> = self.caches[shard(hash)].lock().unwrap();
-        guard.insert_no_exists(key, value, hash);
+        self.insert_with_charge(key, value, 1, CachePriority::High, hash);
+    }
+
+    /// Insert key-value when key is not found, entries are evicted by the policy of cache
+    /// until the usage of the shard does not exceed its capacity.
+    pub fn insert_with_charge(
+        &self,
+        key: K,
+        value: V,
+        charge: usize,
+        priority: CachePriority,
+        hash: u32,
+    ) {
+        let evicted = {
+            let mut guard: MutexGuard<LRUCache<K, V>> = self.caches[shard(hash)].lock().unwrap();
+            guard.insert_no_exists(key, value, charge, priority, hash)
+        };
+        // the evicted entries are released when `evicted` is dropped
+        if let Some(eviction_callback) = &self.eviction_callback {
+            for entry in &evicted {
+                let entry = unsafe { &*entry.0 };
+                eviction_callback(entry.key(), entry.value());
+            }
+        }
     }
 
     pub fn look_up(&self, key: &K, hash: u32) -> EntryTracker<K, V> {
         let mut guard: MutexGuard<LRUCache<K, V>> = self.caches[shard(hash)].lock().unwrap();
-        guard.look_up(key, hash)
+        let entry_tracker = guard.look_up(key, hash);
+        if entry_tracker.0.is_null() {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        entry_tracker
     }
 
     pub fn erase(&self, key: &K, hash: u32) {
         let mut guard: MutexGuard<LRUCache<K, V>> = self.caches[shard(hash)].lock().unwrap();
         guard.erase(key, hash);
     }
+
+    /// Total charge of the entries in cache.
+    pub fn usage(&self) -> usize {
+        self.caches
+            .iter()
+            .map(|cache| cache.lock().unwrap().usage)
+            .sum()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.caches
+            .iter()
+            .map(|cache| cache.lock().unwrap().capacity)
+            .sum()
+    }
+
+    pub fn policy(&self) -> CachePolicy {
+        self.caches[0].lock().unwrap().policy
+    }
+
+    pub fn statistics(&self) -> CacheStatistics {
+        CacheStatistics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
 }
 
 unsafe impl<K: Eq + Hash + Send + Sync, V: Send + Sync> Send for ShardLRUCache<K, V> {}
@@ -70,10 +187,28 @@ fn shard(hash: u32) -> usize {
     (hash >> (32 - NUM_SHARD_BITS)) as usize
 }
 
+/// The entries are kept in a list:
+///
+/// ```text
+/// head <-> high priority pool <-> low_pri_head <-> low priority pool <-> tail
+/// ```
+///
+/// New or looked up entries are attached after `head` or `low_pri_head`, and the victim of
+/// eviction is the oldest entry of low priority pool, or that of high priority pool if the
+/// former is empty. The high priority pool is only used by
+/// [CachePolicy::LruWithHighPriPool].
 struct LRUCache<K: Eq, V> {
     table: HashTable<K, V>,
-    // dummy head, tail.next is the oldest entry
+    policy: CachePolicy,
+    capacity: usize,
+    /// Total charge of the entries in `table`.
+    usage: usize,
+    high_pri_pool_capacity: usize,
+    high_pri_pool_usage: usize,
+    // dummy head, head.next is the newest entry
     head: NonNull<LRUEntry<K, V>>,
+    // dummy head of low priority pool
+    low_pri_head: NonNull<LRUEntry<K, V>>,
     // dummy tail, tail.prev is the oldest entry
     tail: NonNull<LRUEntry<K, V>>,
 }
@@ -82,30 +217,49 @@ unsafe impl<K: Eq, V> Send for LRUCache<K, V> {}
 unsafe impl<K: Eq, V> Sync for LRUCache<K, V> {}
 
 impl<K: Eq, V> LRUCache<K, V> {
-    fn new() -> LRUCache<K, V> {
+    fn new(capacity: usize, policy: CachePolicy) -> LRUCache<K, V> {
         let head = LRUEntry::new_empty();
+        let low_pri_head = LRUEntry::new_empty();
         let tail = LRUEntry::new_empty();
+        let high_pri_pool_capacity = match policy {
+            CachePolicy::LruWithHighPriPool {
+                high_pri_pool_ratio,
+            } => {
+                debug_assert!((0.0..=1.0).contains(&high_pri_pool_ratio));
+                (capacity as f64 * high_pri_pool_ratio) as usize
+            }
+            _ => 0,
+        };
         unsafe {
-            (*head).next = tail;
-            (*tail).prev = head;
+            (*head).next = low_pri_head;
+            (*low_pri_head).prev = head;
+            (*low_pri_head).next = tail;
+            (*tail).prev = low_pri_head;
             LRUCache {
                 table: HashTable::default(),
+                policy,
+                capacity,
+                usage: 0,
+                high_pri_pool_capacity,
+                high_pri_pool_usage: 0,
                 head: NonNull::new_unchecked(head),
+                low_pri_head: NonNull::new_unchecked(low_pri_head),
                 tail: NonNull::new_unchecked(tail),
             }
         }
     }
 
-    fn attach(&mut self, n: *mut LRUEntry<K, V>) {
+    /// Insert `n` after `prev`.
+    fn link(prev: *mut LRUEntry<K, V>, n: *mut LRUEntry<K, V>) {
         unsafe {
-            (*n).next = (self.head.as_ref()).next;
-            (*n).prev = self.head.as_ptr();
-            (self.head.as_mut()).next = n;
+            (*n).next = (*prev).next;
+            (*n).prev = prev;
+            (*prev).next = n;
             (*(*n).next).prev = n;
         }
     }
 
-    fn detach(n: *mut LRUEntry<K, V>) {
+    fn unlink(n: *mut LRUEntry<K, V>) {
         debug_assert!(!n.is_null());
         unsafe {
             (*(*n).next).prev = (*n).prev;
@@ -113,41 +267,116 @@ impl<K: Eq, V> LRUCache<K, V> {
         }
     }
 
+    fn attach(&mut self, n: *mut LRUEntry<K, V>) {
+        unsafe {
+            let high_pri = matches!(self.policy, CachePolicy::LruWithHighPriPool { .. })
+                && ((*n).priority == CachePriority::High || (*n).referenced);
+            (*n).in_high_pri_pool = high_pri;
+            if high_pri {
+                Self::link(self.head.as_ptr(), n);
+                self.high_pri_pool_usage += (*n).charge;
+                self.maintain_pool_size();
+            } else {
+                Self::link(self.low_pri_head.as_ptr(), n);
+            }
+        }
+    }
+
+    fn detach(&mut self, n: *mut LRUEntry<K, V>) {
+        Self::unlink(n);
+        unsafe {
+            if (*n).in_high_pri_pool {
+                self.high_pri_pool_usage -= (*n).charge;
+            }
+        }
+    }
+
+    /// Move the oldest entries of high priority pool to low priority pool if the pool
+    /// overflows.
+    fn maintain_pool_size(&mut self) {
+        while self.high_pri_pool_usage > self.high_pri_pool_capacity {
+            unsafe {
+                let old = (self.low_pri_head.as_ref()).prev;
+                debug_assert_ne!(old, self.head.as_ptr());
+                Self::unlink(old);
+                self.high_pri_pool_usage -= (*old).charge;
+                (*old).in_high_pri_pool = false;
+                Self::link(self.low_pri_head.as_ptr(), old);
+            }
+        }
+    }
+
+    /// Get the oldest entry, the cache should not be empty.
+    fn oldest(&self) -> *mut LRUEntry<K, V> {
+        unsafe {
+            let mut old = (self.tail.as_ref()).prev;
+            if old == self.low_pri_head.as_ptr() {
+                old = (self.low_pri_head.as_ref()).prev;
+            }
+            debug_assert_ne!(old, self.head.as_ptr());
+            old
+        }
+    }
+
     fn look_up(&mut self, key: &K, hash: u32) -> EntryTracker<K, V> {
         let n = self.table.look_up(key, hash);
         if !n.is_null() {
-            Self::detach(n);
-            self.attach(n);
             unsafe {
+                (*n).referenced = true;
+                if self.policy != CachePolicy::Clock {
+                    self.detach(n);
+                    self.attach(n);
+                }
                 (*n).ref_count.fetch_add(1, Ordering::Release);
             }
         }
         EntryTracker(n)
     }
 
-    /// Insert key-value when key is not found.
-    fn insert_no_exists(&mut self, key: K, value: V, hash: u32) {
+    /// Insert key-value when key is not found, return the evicted entries, which are held
+    /// until the returned trackers are dropped out of the lock.
+    fn insert_no_exists(
+        &mut self,
+        key: K,
+        value: V,
+        charge: usize,
+        priority: CachePriority,
+        hash: u32,
+    ) -> Vec<EntryTracker<K, V>> {
+        let mut evicted = vec![];
         let entry = self.table.look_up(&key, hash);
         if entry.is_null() {
-            if self.table.len >= CACHE_CAP {
+            while self.table.len > 0 && self.usage + charge > self.capacity {
+                let old = self.oldest();
                 unsafe {
-                    let old = (self.tail.as_ref()).prev;
-                    debug_assert_ne!(self.tail.as_ptr(), old);
-                    Self::detach(old);
+                    if self.policy == CachePolicy::Clock && (*old).referenced {
+                        // second chance
+                        (*old).referenced = false;
+                        self.detach(old);
+                        self.attach(old);
+                        continue;
+                    }
+                    self.detach(old);
+                    self.usage -= (*old).charge;
+                    (*old).ref_count.fetch_add(1, Ordering::Release);
+                    evicted.push(EntryTracker(old));
                     self.table.remove(old);
                 }
             }
-            let new_entry = LRUEntry::new(key, value, hash);
+            self.usage += charge;
+            let new_entry = LRUEntry::new(key, value, charge, priority, hash);
             self.attach(new_entry);
             self.table.insert(new_entry);
         }
+        evicted
     }
 
     fn erase(&mut self, key: &K, hash: u32) {
         let n = self.table.look_up(key, hash);
         if !n.is_null() {
-            Self::detach(n);
+            self.detach(n);
             unsafe {
+                self.usage -= (*n).charge;
                 self.table.remove(n);
             }
         }
@@ -158,13 +387,16 @@ impl<K: Eq, V> Drop for LRUCache<K, V> {
     fn drop(&mut self) {
         unsafe {
             let mut node = (self.head.as_ref()).next;
-            for _ in 0..self.table.len {
+            while node != self.tail.as_ptr() {
                 debug_assert!(!node.is_null());
                 let prev = node;
                 node = (*node).next;
-                release(prev);
+                if prev != self.low_pri_head.as_ptr() {
+                    release(prev);
+                }
             }
             let _head = *Box::from_raw(self.head.as_ptr());
+            let _low_pri_head = *Box::from_raw(self.low_pri_head.as_ptr());
             let _tail = *Box::from_raw(self.tail.as_ptr());
         }
     }
@@ -172,6 +404,14 @@ impl<K: Eq, V> Drop for LRUCache<K, V> {
 
 pub struct EntryTracker<K: Eq, V>(pub *const LRUEntry<K, V>);
 
+impl<K: Eq, V> EntryTracker<K, V> {
+    /// Get the value of the entry, return `None` if the key is not found.
+    #[inline]
+    pub fn value(&self) -> Option<&V> {
+        unsafe { self.0.as_ref() }.map(LRUEntry::value)
+    }
+}
+
 impl<K: Eq, V> Drop for EntryTracker<K, V> {
     fn drop(&mut self) {
         if !self.0.is_null() {
@@ -184,6 +424,11 @@ pub struct LRUEntry<K: Eq, V> {
     key: MaybeUninit<K>,
     value: MaybeUninit<V>,
     hash: u32,
+    charge: usize,
+    priority: CachePriority,
+    in_high_pri_pool: bool,
+    /// Whether the entry is looked up after insertion, or after the last sweep of CLOCK.
+    referenced: bool,
     next_hash: *mut LRUEntry<K, V>,
     prev: *mut LRUEntry<K, V>,
     next: *mut LRUEntry<K, V>,
@@ -191,7 +436,7 @@ pub struct LRUEntry<K: Eq, V> {
 }
 
 impl<K: Eq, V> LRUEntry<K, V> {
-    fn new(key: K, value: V, hash: u32) -> *mut Self {
+    fn new(key: K, value: V, charge: usize, priority: CachePriority, hash: u32) -> *mut Self {
         let layout = Layout::new::<LRUEntry<K, V>>();
         unsafe {
             let node_ptr = std::alloc::alloc(layout) as *mut Self;
@@ -202,6 +447,10 @@ impl<K: Eq, V> LRUEntry<K, V> {
                     key: MaybeUninit::new(key),
                     value: MaybeUninit::new(value),
                     hash,
+                    charge,
+                    priority,
+                    in_high_pri_pool: false,
+                    referenced: false,
                     next_hash: ptr::null_mut(),
                     prev: ptr::null_mut(),
                     next: ptr::null_mut(),
@@ -224,6 +473,10 @@ impl<K: Eq, V> LRUEntry<K, V> {
                     key: MaybeUninit::uninit(),
                     value: MaybeUninit::uninit(),
                     hash: 0,
+                    charge: 0,
+                    priority: CachePriority::Low,
+                    in_high_pri_pool: false,
+                    referenced: false,
                     next_hash: ptr::null_mut(),
                     prev: ptr::null_mut(),
                     next: ptr::null_mut(),
@@ -234,6 +487,11 @@ impl<K: Eq, V> LRUEntry<K, V> {
         }
     }
 
+    #[inline]
+    fn key(&self) -> &K {
+        unsafe { self.key.assume_init_ref() }
+    }
+
     #[inline]
     pub fn value(&self) -> &V {
         unsafe { self.value.assume_init_ref() }
@@ -347,13 +605,16 @@ fn release<K: Eq, V>(n: *mut LRUEntry<K, V>) {
 
 #[cfg(test)]
 mod tests {
-    use crate::cache::{HashTable, LRUCache, LRUEntry, ShardLRUCache, CACHE_CAP, TABLE_SIZE};
+    use crate::cache::{
+        CachePolicy, CachePriority, EvictionCallback, HashTable, LRUCache, LRUEntry, ShardLRUCache,
+        CACHE_CAP, NUM_SHARD, TABLE_SIZE,
+    };
     use crate::hash::murmur_hash;
-    use std::sync::{Arc, Barrier};
+    use std::sync::{Arc, Barrier, Mutex};
 
     fn make_entry(i: usize) -> *mut LRUEntry<String, String> {
         let h = murmur_hash(&i.to_le_bytes(), 0x12345678);
-        LRUEntry::new(i.to_string(), i.to_string(), h)
+        LRUEntry::new(i.to_string(), i.to_string(), 1, CachePriority::Low, h)
     }
 
     #[test]
@@ -363,7 +624,13 @@ mod tests {
         let p = table.look_up(&s, 321);
         assert!(p.is_null());
 
-        let entry = LRUEntry::new(String::from("key1"), String::from("value1"), 1234);
+        let entry = LRUEntry::new(
+            String::from("key1"),
+            String::from("value1"),
+            1,
+            CachePriority::Low,
+            1234,
+        );
         table.insert(entry);
         let p = table.look_up(&s, 1234);
         assert!(p.is_null());
@@ -397,13 +664,13 @@ mod tests {
 
     #[test]
     fn test_lru_cache() {
-        let mut lru_cache = LRUCache::new();
+        let mut lru_cache = LRUCache::new(CACHE_CAP, CachePolicy::Lru);
 
         for i in 0..CACHE_CAP {
             let key = i.to_string();
             let value = i.to_string();
             let h = murmur_hash(key.as_bytes(), 0x87654321);
-            lru_cache.insert_no_exists(key, value, h);
+            lru_cache.insert_no_exists(key, value, 1, CachePriority::Low, h);
         }
         assert_eq!(lru_cache.table.len, CACHE_CAP);
 
@@ -422,7 +689,7 @@ mod tests {
             let key = i.to_string();
             let value = i.to_string();
             let h = murmur_hash(key.as_bytes(), 0x87654321);
-            lru_cache.insert_no_exists(key, value, h);
+            lru_cache.insert_no_exists(key, value, 1, CachePriority::Low, h);
         }
         assert_eq!(lru_cache.table.len, CACHE_CAP);
 
@@ -436,12 +703,12 @@ mod tests {
 
     #[test]
     fn test_erase() {
-        let mut lru_cache = LRUCache::new();
+        let mut lru_cache = LRUCache::new(CACHE_CAP, CachePolicy::Lru);
         for i in 0..CACHE_CAP * 2 {
             let key = i.to_string();
             let value = i.to_string();
             let h = murmur_hash(key.as_bytes(), 0x87654321);
-            lru_cache.insert_no_exists(key, value, h);
+            lru_cache.insert_no_exists(key, value, 1, CachePriority::Low, h);
         }
         for i in 0..CACHE_CAP * 2 {
             if (i & 1) == 0 {
@@ -514,4 +781,177 @@ mod tests {
             assert!(tracker.0.is_null());
         }
     }
+
+    #[test]
+    fn test_charge() {
+        let mut lru_cache = LRUCache::new(1000, CachePolicy::Lru);
+        for i in 0..10 {
+            let key = i.to_string();
+            let h = murmur_hash(key.as_bytes(), 0x87654321);
+            lru_cache.insert_no_exists(key, i.to_string(), 100, CachePriority::Low, h);
+        }
+        assert_eq!(lru_cache.usage, 1000);
+
+        // make "0" the most recently used
+        let h0 = murmur_hash(b"0", 0x87654321);
+        drop(lru_cache.look_up(&"0".to_string(), h0));
+
+        // evict "1", "2" and "3"
+        let h = murmur_hash(b"large", 0x87654321);
+        lru_cache.insert_no_exists(
+            "large".to_string(),
+            "large".to_string(),
+            250,
+            CachePriority::Low,
+            h,
+        );
+        assert_eq!(lru_cache.usage, 950);
+        for i in 0..10 {
+            let key = i.to_string();
+            let h = murmur_hash(key.as_bytes(), 0x87654321);
+            let tracker = lru_cache.look_up(&key, h);
+            assert_eq!(tracker.value().is_none(), (1..=3).contains(&i), "{}", i);
+        }
+
+        lru_cache.erase(&"large".to_string(), h);
+        assert_eq!(lru_cache.usage, 700);
+
+        // an entry larger than capacity evicts all the others
+        let h = murmur_hash(b"huge", 0x87654321);
+        lru_cache.insert_no_exists(
+            "huge".to_string(),
+            "huge".to_string(),
+            2000,
+            CachePriority::Low,
+            h,
+        );
+        assert_eq!(lru_cache.usage, 2000);
+        assert_eq!(lru_cache.table.len, 1);
+    }
+
+    fn contains(lru_cache: &mut LRUCache<String, String>, key: &str) -> bool {
+        let h = murmur_hash(key.as_bytes(), 0x87654321);
+        lru_cache.look_up(&key.to_string(), h).value().is_some()
+    }
+
+    fn insert(lru_cache: &mut LRUCache<String, String>, key: &str, priority: CachePriority) {
+        let h = murmur_hash(key.as_bytes(), 0x87654321);
+        lru_cache.insert_no_exists(key.to_string(), key.to_string(), 1, priority, h);
+    }
+
+    #[test]
+    fn test_high_pri_pool() {
+        let policy = CachePolicy::LruWithHighPriPool {
+            high_pri_pool_ratio: 0.5,
+        };
+        let mut lru_cache = LRUCache::new(10, policy);
+        for i in 0..5 {
+            insert(&mut lru_cache, &format!("high{}", i), CachePriority::High);
+        }
+        // scan
+        for i in 0..20 {
+            insert(&mut lru_cache, &format!("low{}", i), CachePriority::Low);
+        }
+        assert_eq!(lru_cache.usage, 10);
+        assert_eq!(lru_cache.high_pri_pool_usage, 5);
+        for i in 0..5 {
+            assert!(contains(&mut lru_cache, &format!("high{}", i)));
+        }
+        assert!(!contains(&mut lru_cache, "low14"));
+
+        // low priority entry looked up is moved to the high priority pool
+        assert!(contains(&mut lru_cache, "low15"));
+        for i in 20..40 {
+            insert(&mut lru_cache, &format!("low{}", i), CachePriority::Low);
+        }
+        assert_eq!(lru_cache.high_pri_pool_usage, 5);
+        assert!(contains(&mut lru_cache, "low15"));
+        assert!(!contains(&mut lru_cache, "high0"));
+        for i in 1..5 {
+            assert!(contains(&mut lru_cache, &format!("high{}", i)));
+        }
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut lru_cache = LRUCache::new(4, CachePolicy::Clock);
+        for key in ["a", "b", "c", "d"] {
+            insert(&mut lru_cache, key, CachePriority::Low);
+        }
+        assert!(contains(&mut lru_cache, "a"));
+        assert!(contains(&mut lru_cache, "c"));
+
+        // "a" and "c" get a second chance
+        insert(&mut lru_cache, "e", CachePriority::Low);
+        assert!(!contains(&mut lru_cache, "b"));
+        insert(&mut lru_cache, "f", CachePriority::Low);
+        assert!(!contains(&mut lru_cache, "d"));
+        for key in ["a", "c", "e", "f"] {
+            assert!(contains(&mut lru_cache, key), "{}", key);
+        }
+        assert_eq!(lru_cache.usage, 4);
+    }
+
+    #[test]
+    fn test_statistics() {
+        for policy in [
+            CachePolicy::Lru,
+            CachePolicy::LruWithHighPriPool {
+                high_pri_pool_ratio: 0.5,
+            },
+            CachePolicy::Clock,
+        ] {
+            let lru_cache = ShardLRUCache::with_policy(1 << 10, policy);
+            assert_eq!(lru_cache.policy(), policy);
+            for i in 0..100 {
+                let key = i.to_string();
+                let h = murmur_hash(key.as_bytes(), 0x87654321);
+                lru_cache.insert_no_exists(key, i.to_string(), h);
+            }
+            for i in 0..150 {
+                let key = i.to_string();
+                let h = murmur_hash(key.as_bytes(), 0x87654321);
+                lru_cache.look_up(&key, h);
+            }
+            let statistics = lru_cache.statistics();
+            assert_eq!(statistics.hits, 100);
+            assert_eq!(statistics.misses, 50);
+            assert!((statistics.hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_eviction_callback() {
+        type Cache = ShardLRUCache<String, String>;
+        let cache_slot: Arc<Mutex<Option<Arc<Cache>>>> = Arc::default();
+        let evicted = Arc::new(Mutex::new(vec![]));
+        let (slot, evicted2) = (cache_slot.clone(), evicted.clone());
+        let eviction_callback: EvictionCallback<String, String> =
+            Arc::new(move |key: &String, value: &String| {
+                // the callback may take the locks of shards
+                let cache = slot.lock().unwrap().clone().unwrap();
+                assert!(cache.usage() <= cache.capacity());
+                evicted2.lock().unwrap().push((key.clone(), value.clone()));
+            });
+        let cache = Arc::new(Cache::with_eviction_callback(
+            NUM_SHARD,
+            CachePolicy::Lru,
+            Some(eviction_callback),
+        ));
+        *cache_slot.lock().unwrap() = Some(cache.clone());
+
+        for i in 0..100 {
+            let key = i.to_string();
+            let h = murmur_hash(key.as_bytes(), 0x87654321);
+            cache.insert_with_charge(key, i.to_string(), 1, CachePriority::Low, h);
+        }
+        let evicted = evicted.lock().unwrap();
+        assert_eq!(evicted.len() + cache.usage(), 100);
+        for (key, value) in evicted.iter() {
+            assert_eq!(key, value);
+            let h = murmur_hash(key.as_bytes(), 0x87654321);
+            assert!(cache.look_up(key, h).value().is_none());
+        }
+        cache_slot.lock().unwrap().take();
+    }
 }
@@ -0,0 +1,280 @@
+use crate::error::KVLiteError;
+use crate::sstable::blob_file::{tag_value, untag_value, BlobIndex, ValueType};
+use crate::sstable::manager::level_n::LevelNManager;
+use crate::sstable::table_handle::TableReadHandle;
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+/// Collect the blob files in which the values referenced by `tables` make up less than
+/// `live_ratio` of the file size.
+///
+/// The sstables in level 1 and deeper referencing these blob files are rewritten, and the
+/// referenced values are written to new blob files or stored in the sstables, depending on
+/// [crate::db::options::Options::min_blob_size]. Blob files referenced by level 0 are skipped
+/// since level 0 is compacted soon, and so are the sstables being compacted. Returns the
+/// number of blob files no longer referenced, which are deleted once no reader holds them.
+pub(crate) fn collect_blob_garbage(
+    leveln_manager: &Arc<LevelNManager>,
+    tables: Vec<Arc<TableReadHandle>>,
+    live_ratio: f64,
+) -> Result<usize> {
+    // blob file number -> (referenced length, file size)
+    let mut blob_files: HashMap<u64, (u64, u64)> = HashMap::new();
+    let mut level0_blob_files = HashSet::new();
+    for table in &tables {
+        debug_assert_eq!(table.blob_references().len(), table.blob_files().len());
+        for ((file_number, length), blob_file) in
+            table.blob_references().iter().zip(table.blob_files())
+        {
+            blob_files
+                .entry(*file_number)
+                .or_insert((0, blob_file.file_size()))
+                .0 += length;
+            if table.level() == 0 {
+                level0_blob_files.insert(*file_number);
+            }
+        }
+    }
+    let garbage: HashSet<u64> = blob_files
+        .into_iter()
+        .filter(|(file_number, (live_length, file_size))| {
+            (*live_length as f64) < live_ratio * *file_size as f64
+                && !level0_blob_files.contains(file_number)
+        })
+        .map(|(file_number, _)| file_number)
+        .collect();
+
+    let mut skipped = HashSet::new();
+    for table in tables {
+        let referenced: Vec<u64> = table
+            .blob_references()
+            .iter()
+            .map(|(file_number, _)| *file_number)
+            .filter(|file_number| garbage.contains(file_number))
+            .collect();
+        if referenced.is_empty() {
+            continue;
+        }
+        if table.test_and_set_compacting() {
+            if let Err(e) = rewrite_table(leveln_manager, table.clone(), &garbage) {
+                table.abandon_compacting();
+                return Err(e);
+            }
+        } else {
+            skipped.extend(referenced);
+        }
+    }
+    info!(
+        "collect {} blob files, {} blob files are skipped",
+        garbage.len() - skipped.len(),
+        skipped.len()
+    );
+    Ok(garbage.len() - skipped.len())
+}
+
+/// Rewrite `table` to a new sstable in the same level, the blobs in `garbage` are read.
+fn rewrite_table(
+    leveln_manager: &Arc<LevelNManager>,
+    table: Arc<TableReadHandle>,
+    garbage: &HashSet<u64>,
+) -> Result<()> {
+    let level = NonZeroUsize::new(table.level())
+        .ok_or_else(|| KVLiteError::Custom("blobs of level 0 are not collected".into()))?;
+    let kvs = TableReadHandle::raw_iter(table.clone())?
+        .map(|(key, seq_num, value)| match untag_value(value) {
+            (value, ValueType::BlobIndex) => {
+                let blob_index = BlobIndex::decode(&value)?;
+                if garbage.contains(&blob_index.file_number) {
+                    let value = table.resolve_value(value, ValueType::BlobIndex)?;
+                    Ok((key, seq_num, tag_value(value, ValueType::Value)))
+                } else {
+                    Ok((key, seq_num, tag_value(value, ValueType::BlobIndex)))
+                }
+            }
+            (value, ValueType::Value) => Ok((key, seq_num, tag_value(value, ValueType::Value))),
+        })
+        .collect::<Result<_>>()?;
+
+    let mut new_table = leveln_manager.create_table_write_handle(level, table.kv_total());
+    new_table.add_range_tombstones(table.range_tombstones().to_vec());
+    new_table.write_sstable_from_raw_vec(kvs)?;
+    let _version = leveln_manager.lock_version();
+    leveln_manager.upsert_table_handle(new_table);
+    leveln_manager.ready_to_delete(table);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compaction::blob_gc::collect_blob_garbage;
+    use crate::compaction::level_n::start_compact;
+    use crate::db::key_types::SequenceNumber;
+    use crate::db::options::Options;
+    use crate::db::MAX_LEVEL;
+    use crate::sstable::blob_file::BLOB_DIR;
+    use crate::sstable::manager::level_n::LevelNManager;
+    use crate::sstable::table_handle::TableReadHandle;
+    use std::collections::BTreeMap;
+    use std::num::NonZeroUsize;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_collect_blob_garbage() {
+        let path = tempfile::TempDir::new().unwrap();
+        let db_path = path.path().to_str().unwrap();
+        let options = Options {
+            min_blob_size: Some(100),
+            ..Options::default()
+        };
+        let manager = LevelNManager::open_tables(db_path.to_string(), options.clone()).unwrap();
+        let key = |i: usize| format!("key{:03}", i).into_bytes();
+        let value = |i: usize, level: usize| format!("value{:03}_{}", i, level).repeat(10);
+        let num_blob_files = || {
+            std::fs::read_dir(path.path().join(BLOB_DIR))
+                .unwrap()
+                .count()
+        };
+
+        // level 1 overwrites 80 keys of level 2, odd keys have small values
+        for (level, range) in [(2, 0..100), (1, 0..80)] {
+            let mut handle = manager
+                .create_table_write_handle(NonZeroUsize::new(level).unwrap(), range.len() as u64);
+            let kvs = range
+                .map(|i| {
+                    let value = if i % 2 == 0 {
+                        value(i, level).into_bytes()
+                    } else {
+                        format!("value{:03}_{}", i, level).into_bytes()
+                    };
+                    (key(i), value)
+                })
+                .collect();
+            handle.write_sstable_from_vec(kvs).unwrap();
+            manager.upsert_table_handle(handle);
+        }
+        assert_eq!(num_blob_files(), 2);
+
+        let check = |manager: &LevelNManager| {
+            for i in 0..100 {
+                let level = if i < 80 { 1 } else { 2 };
+                let expected = if i % 2 == 0 {
+                    value(i, level).into_bytes()
+                } else {
+                    format!("value{:03}_{}", i, level).into_bytes()
+                };
+                assert_eq!(
+                    manager.query(&key(i), SequenceNumber::MAX).unwrap(),
+                    Some(expected)
+                );
+            }
+            // iterators of all the levels resolve the blob indexes
+            for (key, value) in manager.get_iterators().into_iter().flatten() {
+                let i: usize = String::from_utf8(key[3..].to_vec())
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let value = String::from_utf8(value).unwrap();
+                assert!(value.starts_with(&format!("value{:03}_", i)));
+                assert_eq!(value.len(), if i % 2 == 0 { 100 } else { 10 });
+            }
+        };
+        check(&manager);
+
+        // compaction moves the blob indexes without writing blob files
+        let one = NonZeroUsize::new(1).unwrap();
+        let handle_to_compact = manager.get_handle_to_compact(one).unwrap();
+        start_compact(one, handle_to_compact, manager.clone());
+        assert_eq!(manager.level_size(1), 0);
+        assert_eq!(num_blob_files(), 2);
+        check(&manager);
+
+        let all_tables = || {
+            (1..=MAX_LEVEL)
+                .flat_map(|level| {
+                    let guard = manager
+                        .get_level_tables_lock(NonZeroUsize::new(level).unwrap())
+                        .read()
+                        .unwrap();
+                    guard.values().cloned().collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let blob_references = |tables: Vec<Arc<TableReadHandle>>| {
+            let mut references = BTreeMap::new();
+            for (file_number, length) in tables.iter().flat_map(|t| t.blob_references()) {
+                *references.entry(*file_number).or_insert(0) += length;
+            }
+            references.into_iter().collect::<Vec<_>>()
+        };
+        // 10 of the 50 values in the blob file of level 2 are referenced
+        let references = blob_references(all_tables());
+        assert_eq!(references.len(), 2);
+        assert!(references[0].1 < references[1].1 / 3);
+
+        assert_eq!(
+            collect_blob_garbage(&manager, all_tables(), 0.1).unwrap(),
+            0
+        );
+        assert_eq!(
+            collect_blob_garbage(&manager, all_tables(), 0.5).unwrap(),
+            1
+        );
+        // the values of level 2 are written to the blob files of the rewritten sstables
+        let references = blob_references(all_tables());
+        assert_eq!(references[0].0, 2);
+        assert!(references[1..]
+            .iter()
+            .all(|(file_number, _)| *file_number > 2));
+        assert_eq!(num_blob_files(), references.len());
+        assert!(!path.path().join(BLOB_DIR).join("1").exists());
+        check(&manager);
+        manager.close();
+        drop(manager);
+
+        let manager = LevelNManager::open_tables(db_path.to_string(), options).unwrap();
+        check(&manager);
+        manager.close();
+    }
+
+    #[test]
+    fn test_blob_read_error() {
+        let path = tempfile::TempDir::new().unwrap();
+        let db_path = path.path().to_str().unwrap();
+        let options = Options {
+            min_blob_size: Some(100),
+            ..Options::default()
+        };
+        let manager = LevelNManager::open_tables(db_path.to_string(), options).unwrap();
+        let one = NonZeroUsize::new(1).unwrap();
+        let key = |i: usize| format!("key{:03}", i).into_bytes();
+        let mut handle = manager.create_table_write_handle(one, 10);
+        let kvs = (0..10)
+            .map(|i| (key(i), format!("value{:03}", i).repeat(20).into_bytes()))
+            .collect();
+        handle.write_sstable_from_vec(kvs).unwrap();
+        manager.upsert_table_handle(handle);
+
+        for entry in std::fs::read_dir(path.path().join(BLOB_DIR)).unwrap() {
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(entry.unwrap().path())
+                .unwrap();
+            file.set_len(0).unwrap();
+        }
+        assert!(manager.query(&key(0), SequenceNumber::MAX).is_err());
+        let mut iterator = manager.get_iterators().remove(0);
+        assert!(iterator.next().is_none());
+        assert!(iterator.shared_status().check().is_err());
+
+        // the sstable can be compacted after its rewriting fails
+        let tables: Vec<_> = {
+            let guard = manager.get_level_tables_lock(one).read().unwrap();
+            guard.values().cloned().collect()
+        };
+        assert!(collect_blob_garbage(&manager, tables.clone(), 2.0).is_err());
+        assert!(tables[0].test_and_set_compacting());
+        manager.close();
+    }
+}
@@ -1,12 +1,12 @@
-use crate::collections::skip_list::skipmap::{IntoIter, IntoPtrIter, ReadWriteMode, SrSwSkipMap};
-use crate::db::key_types::{DBKey, RawUserKey};
-use crate::db::Value;
+use crate::compaction::{merge_to_output, CompactionInput, CompactionOutput};
+use crate::db::key_types::DBKey;
+use crate::db::range_tombstone::RangeTombstone;
 use crate::memory::MemTable;
 use crate::sstable::manager::level_0::Level0Manager;
 use crate::sstable::manager::level_n::LevelNManager;
 use crate::sstable::table_handle::TableReadHandle;
 use crate::wal::WAL;
-use std::cmp::Ordering;
+use crate::Result;
 use std::collections::VecDeque;
 use std::marker::PhantomData;
 use std::num::NonZeroUsize;
@@ -42,8 +42,6 @@ struct Compactor<SK: DBKey, UK: DBKey, M: MemTable<SK, UK>, L: WAL<SK, UK>> {
     leveln_manager: Arc<LevelNManager>,
     level0_table_handles: Vec<Arc<TableReadHandle>>,
     level1_table_handles: VecDeque<Arc<TableReadHandle>>,
-    #[cfg(debug_assertions)]
-    kv_count: usize,
     _phantom_key: PhantomData<SK>,
     _phantom_uk: PhantomData<UK>,
     _phantom_table: PhantomData<M>,
@@ -65,8 +63,6 @@ where
             leveln_manager,
             level0_table_handles,
             level1_table_handles,
-            #[cfg(debug_assertions)]
-            kv_count: 0,
             _phantom_key: PhantomData,
             _phantom_uk: PhantomData,
             _phantom_table: PhantomData,
@@ -76,157 +72,103 @@ where
     fn run(&mut self) {
         debug_assert!(!self.level0_table_handles.is_empty());
 
-        let level0_skip_map: SrSwSkipMap<RawUserKey, Value> = self.merge_level0_tables();
-        let mut kv_total = level0_skip_map.len();
-
-        if self.level1_table_handles.is_empty() {
-            let level1_table_size = (kv_total + 1) / self.level0_table_handles.len();
-            debug_assert!(level1_table_size >= LEVEL0_FILES_THRESHOLD);
-
-            let mut temp_kvs: Vec<(RawUserKey, Value)> = vec![];
-            let iter: IntoIter<RawUserKey, Value, { ReadWriteMode::SrSw }> =
-                level0_skip_map.into_iter();
-            for (k, v) in iter {
-                temp_kvs.push((k, v));
-                #[cfg(debug_assertions)]
-                {
-                    self.kv_count += 1;
-                }
-
-                if temp_kvs.len() >= level1_table_size {
-                    self.add_table_handle_from_vec(temp_kvs);
-                    temp_kvs = vec![];
+        match self.compact() {
+            Ok(output) => {
+                output.install(self.level1_table_handles.iter().cloned());
+                // level0 sstables are deleted after the outputs are inserted
+                for table in &self.level0_table_handles {
+                    self.level0_manager.ready_to_delete(table.table_id());
                 }
+                self.leveln_manager
+                    .may_compact(unsafe { NonZeroUsize::new_unchecked(1) });
             }
-            if !temp_kvs.is_empty() {
-                self.add_table_handle_from_vec(temp_kvs);
-            }
-        } else {
-            for table in &self.level1_table_handles {
-                kv_total += table.kv_total() as usize;
-            }
-
-            let level1_table_size = kv_total / self.level1_table_handles.len();
-            debug_assert!(level1_table_size > 0);
-
-            let mut temp_kvs = vec![];
-
-            macro_rules! add_kv {
-                ($key:expr, $value:expr) => {
-                    temp_kvs.push(($key, $value));
-
-                    #[cfg(debug_assertions)]
-                    {
-                        self.kv_count += 1;
-                    }
-
-                    if temp_kvs.len() >= level1_table_size {
-                        self.add_table_handle_from_vec(temp_kvs);
-                        temp_kvs = vec![];
-                    }
-                };
-            }
-
-            let mut level0_iter: IntoPtrIter<RawUserKey, Value, { ReadWriteMode::SrSw }> =
-                level0_skip_map.into_ptr_iter();
-            let mut kv = level0_iter.current_mut_no_consume();
-
-            for level1_table_handle in self.level1_table_handles.iter() {
-                for (level1_key, level1_value) in TableReadHandle::iter(level1_table_handle.clone())
+            Err(e) => {
+                error!("failed to compact level0 sstables: {}", e);
+                for table in self
+                    .level0_table_handles
+                    .iter()
+                    .chain(&self.level1_table_handles)
                 {
-                    if kv.is_null() {
-                        // write all the remain key-values in level1 tables.
-                        add_kv!(level1_key, level1_value);
-                        continue;
-                    }
-
-                    loop {
-                        let level0_key = unsafe { &(*kv).entry.key };
-                        debug_assert!(!level0_key.is_empty());
-                        match level0_key.cmp(&level1_key) {
-                            // set to level0_value
-                            // drop level1_value
-                            Ordering::Equal => {
-                                let level0_entry = unsafe { std::mem::take(&mut (*kv).entry) };
-                                let (level0_key, level0_value) = level0_entry.key_value();
-                                add_kv!(level0_key, level0_value);
-                                #[cfg(debug_assertions)]
-                                {
-                                    self.kv_count += 1;
-                                }
-                                kv = level0_iter.next_node();
-                                break;
-                            }
-                            // insert level1_value
-                            Ordering::Greater => {
-                                add_kv!(level1_key, level1_value);
-                                break;
-                            }
-                            // insert level0_value
-                            Ordering::Less => {
-                                let level0_entry = unsafe { std::mem::take(&mut (*kv).entry) };
-                                let (level0_key, level0_value) = level0_entry.key_value();
-                                add_kv!(level0_key, level0_value);
-                                kv = level0_iter.next_node();
-                                if kv.is_null() {
-                                    add_kv!(level1_key, level1_value);
-                                    break;
-                                }
-                            }
-                        }
-                    }
+                    table.abandon_compacting();
                 }
             }
-
-            // write all the remain kv in level0 tables.
-            while !kv.is_null() {
-                unsafe {
-                    let entry = std::mem::take(&mut (*kv).entry);
-                    add_kv!(entry.key, entry.value);
-                }
-                kv = level0_iter.next_node();
-            }
-
-            if !temp_kvs.is_empty() {
-                self.add_table_handle_from_vec(temp_kvs);
-            }
-        }
-
-        #[cfg(debug_assertions)]
-        {
-            if self.kv_count != kv_total {
-                error!("self.kv_count: {}, kv_total: {}", self.kv_count, kv_total);
-            }
         }
-
-        for table in &self.level1_table_handles {
-            self.leveln_manager.ready_to_delete(table.clone());
-        }
-        for table in &self.level0_table_handles {
-            self.level0_manager.ready_to_delete(table.table_id());
-        }
-        self.leveln_manager
-            .may_compact(unsafe { NonZeroUsize::new_unchecked(1) });
     }
 
-    fn merge_level0_tables(&self) -> SrSwSkipMap<RawUserKey, Value> {
-        let skip_map = SrSwSkipMap::new();
-        for table in &self.level0_table_handles {
-            for (key, value) in TableReadHandle::iter(table.clone()) {
-                skip_map.insert(key, value);
+    /// Merge the inputs to the sstables of level1, which are removed if the merging fails.
+    fn compact(&self) -> Result<CompactionOutput> {
+        let snapshots = self.leveln_manager.snapshots().to_vec();
+        let level0_range_tombstones: Vec<RangeTombstone> = self
+            .level0_table_handles
+            .iter()
+            .flat_map(|table| table.range_tombstones().iter().cloned())
+            .collect();
+        let mut kv_total = self
+            .level0_table_handles
+            .iter()
+            .map(|table| table.kv_total() as usize)
+            .sum::<usize>();
+
+        let level1_table_size = if self.level1_table_handles.is_empty() {
+            (kv_total + 1) / self.level0_table_handles.len()
+        } else {
+            for table in &self.level1_table_handles {
+                kv_total += table.kv_total() as usize;
+            }
+            kv_total / self.level1_table_handles.len()
+        };
+
+        // Level1 tables deleted by the range tombstones of level0 tables are dropped without
+        // reading, unless their versions are read by snapshots.
+        let comparator = self.leveln_manager.comparator().clone();
+        let mut range_tombstones: Vec<RangeTombstone> = level0_range_tombstones.clone();
+        let mut level1_tables = vec![];
+        for handle in self.level1_table_handles.iter() {
+            let (min_key, max_key) = handle.min_max_key();
+            if !level0_range_tombstones.iter().any(|t| {
+                t.covers_range(min_key, max_key, comparator.as_ref())
+                    && t.drops(
+                        handle.smallest_seq_num(),
+                        handle.largest_seq_num(),
+                        &snapshots,
+                    )
+            }) {
+                range_tombstones.extend_from_slice(handle.range_tombstones());
+                level1_tables.push(handle.clone());
             }
         }
-        skip_map
-    }
 
-    fn add_table_handle_from_vec(&self, temp_kvs: Vec<(RawUserKey, Value)>) {
-        if !temp_kvs.is_empty() {
-            let mut new_table = self.leveln_manager.create_table_write_handle(
-                unsafe { NonZeroUsize::new_unchecked(1) },
-                temp_kvs.len() as u32,
-            );
-            new_table.write_sstable_from_vec(temp_kvs).unwrap();
-            self.leveln_manager.upsert_table_handle(new_table);
+        // every level0 table is an input since they overlap, the newest one first
+        let mut inputs = self
+            .level0_table_handles
+            .iter()
+            .rev()
+            .map(|table| {
+                let input: CompactionInput = (
+                    Box::new(TableReadHandle::raw_iter(table.clone())?),
+                    table.range_tombstones().to_vec(),
+                );
+                Ok(input)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let level1_iterators = level1_tables
+            .into_iter()
+            .map(TableReadHandle::raw_iter)
+            .collect::<Result<Vec<_>>>()?;
+        inputs.push((Box::new(level1_iterators.into_iter().flatten()), vec![]));
+
+        let mut output = CompactionOutput::new(
+            unsafe { NonZeroUsize::new_unchecked(1) },
+            self.leveln_manager.clone(),
+            level1_table_size.max(1),
+            range_tombstones,
+        );
+        if let Err(e) =
+            merge_to_output(inputs, snapshots, &mut output).and_then(|_| output.finish())
+        {
+            output.abandon();
+            return Err(e);
         }
+        Ok(output)
     }
 }
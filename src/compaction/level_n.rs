@@ -1,11 +1,11 @@
-use std::cmp::Ordering;
+use std::collections::VecDeque;
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 
-use crate::db::key_types::RawUserKey;
-use crate::db::Value;
+use crate::compaction::{merge_to_output, CompactionInput, CompactionOutput};
 use crate::sstable::manager::level_n::LevelNManager;
 use crate::sstable::table_handle::TableReadHandle;
+use crate::Result;
 
 pub(crate) fn start_compact(
     compact_level: NonZeroUsize,
@@ -20,8 +20,6 @@ struct Compactor {
     compact_level: NonZeroUsize,
     handle_to_compact: Arc<TableReadHandle>,
     leveln_manager: Arc<LevelNManager>,
-    #[cfg(debug_assertions)]
-    kv_count: usize,
 }
 
 impl Compactor {
@@ -35,17 +33,46 @@ impl Compactor {
             compact_level,
             handle_to_compact,
             leveln_manager,
-            #[cfg(debug_assertions)]
-            kv_count: 0,
         }
     }
 
     fn run(&mut self) {
+        let next_level = unsafe { NonZeroUsize::new_unchecked(self.compact_level.get() + 1) };
         let next_level_table_handles = self.leveln_manager.get_overlap_tables(
-            unsafe { NonZeroUsize::new_unchecked(self.compact_level.get() + 1) },
+            next_level,
             self.handle_to_compact.min_key(),
             self.handle_to_compact.max_key(),
         );
+        match self.compact(&next_level_table_handles) {
+            Ok(output) => {
+                output.install(
+                    std::iter::once(self.handle_to_compact.clone()).chain(next_level_table_handles),
+                );
+                self.leveln_manager.may_compact(next_level);
+            }
+            Err(e) => {
+                error!(
+                    "failed to compact sstable {} of level {}: {}",
+                    self.handle_to_compact.table_id(),
+                    self.compact_level,
+                    e
+                );
+                self.handle_to_compact.abandon_compacting();
+                for table in &next_level_table_handles {
+                    table.abandon_compacting();
+                }
+            }
+        }
+    }
+
+    /// Merge the inputs to the sstables of the next level, which are removed if the merging
+    /// fails.
+    fn compact(
+        &self,
+        next_level_table_handles: &VecDeque<Arc<TableReadHandle>>,
+    ) -> Result<CompactionOutput> {
+        let next_level = unsafe { NonZeroUsize::new_unchecked(self.compact_level.get() + 1) };
+        let snapshots = self.leveln_manager.snapshots().to_vec();
         let mut total = self.handle_to_compact.kv_total() as usize;
         for handle in next_level_table_handles.iter() {
             total += handle.kv_total() as usize;
@@ -53,149 +80,52 @@ impl Compactor {
 
         let new_table_size = total / next_level_table_handles.len().max(2) + 1;
 
-        let mut temp_kvs: Vec<(RawUserKey, Value)> = vec![];
-        let mut table_to_compact_iter = TableReadHandle::iter(self.handle_to_compact.clone());
-
-        macro_rules! add_kv {
-            ($key:expr, $value:expr) => {
-                temp_kvs.push(($key, $value));
-                #[cfg(debug_assertions)]
-                {
-                    self.kv_count += 1;
-                }
-                if temp_kvs.len() >= new_table_size {
-                    self.add_table_handle(temp_kvs);
-                    temp_kvs = vec![];
-                }
-            };
-        }
-
-        if next_level_table_handles.is_empty() {
-            for (key, value) in table_to_compact_iter {
-                add_kv!(key, value);
-            }
-        } else {
-            enum CurLevelState {
-                Start,
-                HasValue((RawUserKey, Value)),
-                End,
-            }
-
-            let mut cur_level_state = CurLevelState::Start;
-
-            for next_level_table_handle in next_level_table_handles.iter() {
-                for (next_level_key, next_level_value) in
-                    TableReadHandle::iter(next_level_table_handle.clone())
-                {
-                    match cur_level_state {
-                        CurLevelState::Start => loop {
-                            let cur_level_kv = match table_to_compact_iter.next() {
-                                Some(kv) => kv,
-                                None => {
-                                    add_kv!(next_level_key, next_level_value);
-                                    cur_level_state = CurLevelState::End;
-                                    break;
-                                }
-                            };
-                            match cur_level_kv.0.cmp(&next_level_key) {
-                                // reverse next level key-value
-                                Ordering::Less => {
-                                    add_kv!(cur_level_kv.0, cur_level_kv.1);
-                                }
-                                // drop next level key-value
-                                Ordering::Equal => {
-                                    add_kv!(cur_level_kv.0, cur_level_kv.1);
-                                    #[cfg(debug_assertions)]
-                                    {
-                                        self.kv_count += 1;
-                                    }
-                                    break;
-                                }
-                                // remain current level key-value
-                                Ordering::Greater => {
-                                    add_kv!(next_level_key, next_level_value);
-                                    cur_level_state = CurLevelState::HasValue(cur_level_kv);
-                                    break;
-                                }
-                            }
-                        },
-
-                        CurLevelState::HasValue(mut cur_level_kv) => loop {
-                            match cur_level_kv.0.cmp(&next_level_key) {
-                                Ordering::Less => {
-                                    add_kv!(cur_level_kv.0, cur_level_kv.1);
-                                    match table_to_compact_iter.next() {
-                                        Some(kv) => cur_level_kv = kv,
-                                        None => {
-                                            add_kv!(next_level_key, next_level_value);
-                                            cur_level_state = CurLevelState::End;
-                                            break;
-                                        }
-                                    }
-                                }
-                                Ordering::Equal => {
-                                    add_kv!(cur_level_kv.0, cur_level_kv.1);
-                                    #[cfg(debug_assertions)]
-                                    {
-                                        self.kv_count += 1;
-                                    }
-                                    cur_level_state = match table_to_compact_iter.next() {
-                                        Some(kv) => CurLevelState::HasValue(kv),
-                                        None => CurLevelState::End,
-                                    };
-                                    break;
-                                }
-                                Ordering::Greater => {
-                                    add_kv!(next_level_key, next_level_value);
-                                    cur_level_state = CurLevelState::HasValue(cur_level_kv);
-                                    break;
-                                }
-                            }
-                        },
-                        CurLevelState::End => {
-                            add_kv!(next_level_key, next_level_value);
-                        }
-                    }
-                }
-            }
-
-            // write all the remain key-values in compaction level table
-            if let CurLevelState::HasValue(kv) = cur_level_state {
-                add_kv!(kv.0, kv.1);
-            }
-            for kv in table_to_compact_iter {
-                add_kv!(kv.0, kv.1);
-            }
-        }
-
-        #[cfg(debug_assertions)]
-        {
-            if self.kv_count != total {
-                error!("self.kv_count: {}, total: {}", self.kv_count, total);
+        // Next level tables deleted by the range tombstones of `handle_to_compact` are
+        // dropped without reading, unless their versions are read by snapshots.
+        let comparator = self.leveln_manager.comparator().clone();
+        let newer_range_tombstones = self.handle_to_compact.range_tombstones();
+        let mut range_tombstones = newer_range_tombstones.to_vec();
+        let mut older_tables = vec![];
+        for handle in next_level_table_handles.iter() {
+            let (min_key, max_key) = handle.min_max_key();
+            if !newer_range_tombstones.iter().any(|t| {
+                t.covers_range(min_key, max_key, comparator.as_ref())
+                    && t.drops(
+                        handle.smallest_seq_num(),
+                        handle.largest_seq_num(),
+                        &snapshots,
+                    )
+            }) {
+                range_tombstones.extend_from_slice(handle.range_tombstones());
+                older_tables.push(handle.clone());
             }
         }
 
-        if !temp_kvs.is_empty() {
-            self.add_table_handle(temp_kvs);
-        }
-
-        self.leveln_manager
-            .ready_to_delete(self.handle_to_compact.clone());
-        for table in next_level_table_handles {
-            self.leveln_manager.ready_to_delete(table);
-        }
-        self.leveln_manager
-            .may_compact(unsafe { NonZeroUsize::new_unchecked(self.compact_level.get() + 1) });
-    }
+        let older_iterators = older_tables
+            .into_iter()
+            .map(TableReadHandle::raw_iter)
+            .collect::<Result<Vec<_>>>()?;
+        let inputs: Vec<CompactionInput> = vec![
+            (
+                Box::new(TableReadHandle::raw_iter(self.handle_to_compact.clone())?),
+                newer_range_tombstones.to_vec(),
+            ),
+            (Box::new(older_iterators.into_iter().flatten()), vec![]),
+        ];
 
-    fn add_table_handle(&self, temp_kvs: Vec<(RawUserKey, Value)>) {
-        debug_assert!(!temp_kvs.is_empty());
-        let mut new_table = self.leveln_manager.create_table_write_handle(
-            unsafe { NonZeroUsize::new_unchecked(self.compact_level.get() + 1) },
-            temp_kvs.len() as u32,
+        let mut output = CompactionOutput::new(
+            next_level,
+            self.leveln_manager.clone(),
+            new_table_size,
+            range_tombstones,
         );
-        new_table.write_sstable_from_vec(temp_kvs).unwrap();
-        self.leveln_manager.upsert_table_handle(new_table);
+        if let Err(e) =
+            merge_to_output(inputs, snapshots, &mut output).and_then(|_| output.finish())
+        {
+            output.abandon();
+            return Err(e);
+        }
+        Ok(output)
     }
 }
 
@@ -204,8 +134,13 @@ mod tests {
     use std::num::NonZeroUsize;
 
     use crate::compaction::level_n::start_compact;
+    use crate::db::key_types::SequenceNumber;
+    use crate::db::options::Options;
+    use crate::db::range_tombstone::RangeTombstone;
+    use crate::sstable::blob_file::BLOB_DIR;
     use crate::sstable::manager::level_n::tests::create_manager;
-    use crate::sstable::table_handle::temp_file_name;
+    use crate::sstable::manager::level_n::LevelNManager;
+    use crate::sstable::table_handle::{temp_file_name, TableWriteHandle};
 
     #[test]
     fn test_compact() {
@@ -217,7 +152,7 @@ mod tests {
         for (level, _table_id, range) in &handle_args {
             let mut handle = manager.create_table_write_handle(
                 NonZeroUsize::new(*level).unwrap(),
-                (range.end - range.start) as u32,
+                (range.end - range.start) as u64,
             );
             let mut kvs = vec![];
             for i in range.clone() {
@@ -246,4 +181,106 @@ mod tests {
         start_compact(one, handle_to_compact, manager.clone());
         assert_eq!(manager.level_size(1), 0);
     }
+
+    #[test]
+    fn test_compact_range_tombstones() {
+        let path = tempfile::TempDir::new().unwrap();
+        let db_path = path.path().to_str().unwrap();
+        let manager = create_manager(db_path);
+
+        let handle_args = vec![(1, 100..105), (2, 100..110), (2, 112..130), (2, 150..160)];
+        for (level, range) in &handle_args {
+            let mut handle = manager.create_table_write_handle(
+                NonZeroUsize::new(*level).unwrap(),
+                (range.end - range.start) as u64,
+            );
+            if *level == 1 {
+                handle.add_range_tombstones(vec![RangeTombstone::new(
+                    "key105".into(),
+                    "key140".into(),
+                    1,
+                )]);
+            }
+            let mut kvs = vec![];
+            for i in range.clone() {
+                kvs.push((
+                    format!("key{}", i).into_bytes(),
+                    format!("value{}_{}", i, level).into_bytes(),
+                ));
+            }
+            handle.write_sstable_from_vec(kvs).unwrap();
+            manager.upsert_table_handle(handle);
+        }
+
+        let one = NonZeroUsize::new(1).unwrap();
+        let handle_to_compact = manager.get_handle_to_compact(one).unwrap();
+        assert_eq!(handle_to_compact.max_key(), "key140".as_bytes());
+        start_compact(one, handle_to_compact, manager.clone());
+        assert_eq!(manager.level_size(1), 0);
+
+        for i in 100..105 {
+            assert_eq!(
+                manager
+                    .query(&format!("key{}", i).into_bytes(), SequenceNumber::MAX)
+                    .unwrap(),
+                Some(format!("value{}_1", i).into_bytes())
+            );
+        }
+        for i in 105..140 {
+            assert_eq!(
+                manager
+                    .query(&format!("key{}", i).into_bytes(), SequenceNumber::MAX)
+                    .unwrap(),
+                Some(vec![])
+            );
+        }
+        for i in 150..160 {
+            assert_eq!(
+                manager
+                    .query(&format!("key{}", i).into_bytes(), SequenceNumber::MAX)
+                    .unwrap(),
+                Some(format!("value{}_2", i).into_bytes())
+            );
+        }
+
+        let two = NonZeroUsize::new(2).unwrap();
+        let guard = manager.get_level_tables_lock(two).read().unwrap();
+        let kv_total: u64 = guard.values().map(|t| t.kv_total()).sum();
+        assert_eq!(kv_total, 5 + 10);
+    }
+
+    #[test]
+    fn test_compact_error() {
+        let path = tempfile::TempDir::new().unwrap();
+        let db_path = path.path().to_str().unwrap();
+        let options = Options {
+            min_blob_size: Some(100),
+            ..Options::default()
+        };
+        let manager = LevelNManager::open_tables(db_path.to_string(), options).unwrap();
+
+        // large values written without blob store are moved to blob files by compaction
+        let mut handle = TableWriteHandle::new(db_path, 1, 1, 10, &Options::default());
+        let kvs = (0..10)
+            .map(|i| (format!("key{}", i).into_bytes(), vec![i; 1000]))
+            .collect();
+        handle.write_sstable_from_vec(kvs).unwrap();
+        manager.upsert_table_handle(handle);
+        std::fs::remove_dir(path.path().join(BLOB_DIR)).unwrap();
+
+        // the failed compaction leaves the input in place
+        let one = NonZeroUsize::new(1).unwrap();
+        let handle_to_compact = manager.get_handle_to_compact(one).unwrap();
+        start_compact(one, handle_to_compact, manager.clone());
+        assert!(manager.level_size(1) > 0);
+        assert_eq!(manager.level_size(2), 0);
+        assert_eq!(std::fs::read_dir(path.path().join("2")).unwrap().count(), 0);
+        assert_eq!(
+            manager
+                .query(&b"key1".to_vec(), SequenceNumber::MAX)
+                .unwrap(),
+            Some(vec![1; 1000])
+        );
+        assert!(manager.get_handle_to_compact(one).is_some());
+    }
 }
@@ -1,2 +1,378 @@
+pub(crate) mod blob_gc;
 pub mod level_0;
 pub(crate) mod level_n;
+
+use crate::db::comparator::Comparator;
+use crate::db::db_iter::VersionedKeyValue;
+use crate::db::key_types::{RawUserKey, SequenceNumber};
+use crate::db::range_tombstone::RangeTombstone;
+use crate::db::snapshot::VersionFilter;
+use crate::db::{Value, MAX_LEVEL};
+use crate::sstable::manager::level_n::LevelNManager;
+use crate::sstable::table_handle::{TableReadHandle, TableWriteHandle};
+use crate::Result;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+/// Write the versions and range tombstones merged by compaction to the sstables of `level`.
+///
+/// Versions are read by [crate::sstable::table_handle::TableReadHandle::raw_iter], so blob
+/// indexes are moved to the output sstables without reading the blobs.
+///
+/// The output sstables split the key space into disjoint partitions, range tombstones are
+/// truncated to the partition of each sstable so that sstables in `level` never overlap.
+/// The versions of a key are written to the same sstable. The sstables are inserted into
+/// `level` together with the deletion of the inputs by [CompactionOutput::install].
+pub(crate) struct CompactionOutput {
+    level: NonZeroUsize,
+    leveln_manager: Arc<LevelNManager>,
+    comparator: Arc<dyn Comparator>,
+    table_size: usize,
+    range_tombstones: Vec<RangeTombstone>,
+    kvs: Vec<VersionedKeyValue>,
+    /// Inclusive lower bound of the next sstable, `None` means unbounded.
+    lower_bound: Option<RawUserKey>,
+    /// Written sstables to insert.
+    tables: Vec<TableWriteHandle>,
+}
+
+impl CompactionOutput {
+    pub(crate) fn new(
+        level: NonZeroUsize,
+        leveln_manager: Arc<LevelNManager>,
+        table_size: usize,
+        range_tombstones: Vec<RangeTombstone>,
+    ) -> CompactionOutput {
+        debug_assert!(table_size > 0);
+        // There is no older key-value under the last level.
+        let range_tombstones = if level.get() == MAX_LEVEL {
+            vec![]
+        } else {
+            range_tombstones
+        };
+        CompactionOutput {
+            level,
+            comparator: leveln_manager.comparator().clone(),
+            leveln_manager,
+            table_size,
+            range_tombstones,
+            kvs: Vec::with_capacity(table_size),
+            lower_bound: None,
+            tables: vec![],
+        }
+    }
+
+    /// Add a version of `key`, versions are added in the order of keys, and the versions of
+    /// a key in descending order of sequence number.
+    pub(crate) fn add(
+        &mut self,
+        key: RawUserKey,
+        seq_num: SequenceNumber,
+        value: Value,
+    ) -> Result<()> {
+        if let Some((last_key, ..)) = self.kvs.last() {
+            debug_assert_ne!(self.comparator.compare(last_key, &key), Ordering::Greater);
+            if self.kvs.len() >= self.table_size && last_key != &key {
+                // The last key has no known successor only if it is the maximum key or
+                // malformed, the remaining key-values are written by `finish`.
+                if let Some(upper_bound) = self.comparator.successor(last_key) {
+                    self.write_table(Some(upper_bound))?;
+                }
+            }
+        }
+        self.kvs.push((key, seq_num, value));
+        Ok(())
+    }
+
+    /// Sequence number of the last added version if it is a version of `key`.
+    fn last_seq_num(&self, key: &[u8]) -> Option<SequenceNumber> {
+        self.kvs
+            .last()
+            .filter(|(last_key, ..)| last_key == key)
+            .map(|(_, seq_num, _)| *seq_num)
+    }
+
+    /// Write the remaining key-values and range tombstones.
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        self.write_table(None)
+    }
+
+    /// Remove the written sstables if the compaction fails, the inputs are left in place.
+    pub(crate) fn abandon(self) {
+        for table in self.tables {
+            table.abandon();
+        }
+    }
+
+    /// Insert the written sstables and delete the input sstables of level1 and below while
+    /// holding the version lock, so that ingestions see either the inputs or the outputs.
+    pub(crate) fn install(self, inputs: impl IntoIterator<Item = Arc<TableReadHandle>>) {
+        let _version = self.leveln_manager.lock_version();
+        for table in self.tables {
+            self.leveln_manager.upsert_table_handle(table);
+        }
+        for table in inputs {
+            self.leveln_manager.ready_to_delete(table);
+        }
+    }
+
+    /// Write key-values and range tombstones in [`self.lower_bound`, `upper_bound`) to a new
+    /// sstable.
+    fn write_table(&mut self, upper_bound: Option<RawUserKey>) -> Result<()> {
+        let range_tombstones: Vec<RangeTombstone> = self
+            .range_tombstones
+            .iter()
+            .filter_map(|t| {
+                t.truncate(
+                    self.lower_bound.as_ref(),
+                    upper_bound.as_ref(),
+                    self.comparator.as_ref(),
+                )
+            })
+            .collect();
+        let kvs = std::mem::replace(&mut self.kvs, Vec::with_capacity(self.table_size));
+        self.lower_bound = upper_bound;
+        if kvs.is_empty() && range_tombstones.is_empty() {
+            return Ok(());
+        }
+
+        let mut new_table = self
+            .leveln_manager
+            .create_table_write_handle(self.level, kvs.len() as u64);
+        new_table.add_range_tombstones(range_tombstones);
+        if let Err(e) = new_table.write_sstable_from_raw_vec(kvs) {
+            new_table.abandon();
+            return Err(e);
+        }
+        self.tables.push(new_table);
+        Ok(())
+    }
+}
+
+/// Input of compaction, the versions of an sstable or of disjoint sstables in key order, and
+/// the range tombstones deleting the keys of older inputs.
+pub(crate) type CompactionInput = (
+    Box<dyn Iterator<Item = VersionedKeyValue>>,
+    Vec<RangeTombstone>,
+);
+
+/// Version read from the `input_id`-th input, greater items are merged first.
+///
+/// The versions of a key are merged from the newest input to the oldest by `input_id`, not
+/// by sequence number. Databases without sequence numbers write at sequence number 0 while
+/// ingested sstables get a global sequence number of at least 1, so ordering by sequence
+/// number would put ingested versions before newer writes.
+struct MergingItem {
+    version: VersionedKeyValue,
+    input_id: usize,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl PartialEq for MergingItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MergingItem {}
+
+impl PartialOrd for MergingItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MergingItem {
+    /// Smaller key is greater, and the version of newer input is greater for the same key.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.comparator
+            .compare(&other.version.0, &self.version.0)
+            .then_with(|| other.input_id.cmp(&self.input_id))
+    }
+}
+
+/// Merge the sorted versions of `inputs`, which are ordered from newest to oldest, to
+/// `output`.
+///
+/// Versions are dropped if they are read by neither the latest reads nor the snapshots at
+/// `snapshots`. A version older than a range tombstone of newer inputs covering it is
+/// dropped unless a snapshot lies between them. Otherwise the version is kept for the
+/// snapshot, and a deletion at the sequence number of the tombstone is added before it, so
+/// that the later reads of the output sstable don't see it.
+pub(crate) fn merge_to_output(
+    inputs: Vec<CompactionInput>,
+    snapshots: Vec<SequenceNumber>,
+    output: &mut CompactionOutput,
+) -> Result<()> {
+    let comparator = output.comparator.clone();
+    // range tombstones of the inputs newer than each input
+    let mut newer_range_tombstones = Vec::with_capacity(inputs.len());
+    let mut iterators = Vec::with_capacity(inputs.len());
+    let mut range_tombstones = vec![];
+    for (iterator, input_range_tombstones) in inputs {
+        newer_range_tombstones.push(range_tombstones.clone());
+        range_tombstones.extend(input_range_tombstones);
+        iterators.push(iterator);
+    }
+
+    let mut heap = BinaryHeap::with_capacity(iterators.len());
+    for (input_id, iterator) in iterators.iter_mut().enumerate() {
+        if let Some(version) = iterator.next() {
+            heap.push(MergingItem {
+                version,
+                input_id,
+                comparator: comparator.clone(),
+            });
+        }
+    }
+
+    let mut version_filter = VersionFilter::new(snapshots.clone());
+    while let Some(MergingItem {
+        version: (key, seq_num, value),
+        input_id,
+        comparator,
+    }) = heap.pop()
+    {
+        if let Some(version) = iterators[input_id].next() {
+            heap.push(MergingItem {
+                version,
+                input_id,
+                comparator: comparator.clone(),
+            });
+        }
+        // the oldest range tombstone deleting the version
+        let tombstone = newer_range_tombstones[input_id]
+            .iter()
+            .filter(|t| t.seq_num > seq_num && t.covers(&key, comparator.as_ref()))
+            .min_by_key(|t| t.seq_num);
+        if let Some(tombstone) = tombstone {
+            if tombstone.drops(seq_num, seq_num, &snapshots) {
+                continue;
+            }
+            // the deletion is already added if the last version of the key is not newer than
+            // the tombstone
+            let deleted = matches!(
+                output.last_seq_num(&key),
+                Some(last_seq_num) if last_seq_num <= tombstone.seq_num
+            );
+            if !deleted && version_filter.retain(&key, tombstone.seq_num) {
+                output.add(key.clone(), tombstone.seq_num, Value::default())?;
+            }
+        }
+        if version_filter.retain(&key, seq_num) {
+            output.add(key, seq_num, value)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compaction::{merge_to_output, CompactionInput, CompactionOutput};
+    use crate::db::db_iter::VersionedKeyValue;
+    use crate::db::key_types::SequenceNumber;
+    use crate::db::range_tombstone::RangeTombstone;
+    use crate::sstable::blob_file::{tag_value, ValueType};
+    use crate::sstable::manager::level_n::tests::create_manager;
+    use crate::sstable::table_handle::TableReadHandle;
+    use std::num::NonZeroUsize;
+
+    /// Versions with values tagged as the raw table iterators do.
+    fn versions(versions: &[(&str, SequenceNumber, &str)]) -> Vec<VersionedKeyValue> {
+        versions
+            .iter()
+            .map(|(key, seq_num, value)| {
+                let value = tag_value(value.as_bytes().to_vec(), ValueType::Value);
+                (key.as_bytes().to_vec(), *seq_num, value)
+            })
+            .collect()
+    }
+
+    fn input(
+        input_versions: &[(&str, SequenceNumber, &str)],
+        range_tombstones: Vec<RangeTombstone>,
+    ) -> CompactionInput {
+        (
+            Box::new(versions(input_versions).into_iter()),
+            range_tombstones,
+        )
+    }
+
+    #[test]
+    fn test_merge_with_snapshots() {
+        let path = tempfile::TempDir::new().unwrap();
+        let manager = create_manager(path.path().to_str().unwrap());
+        let one = NonZeroUsize::new(1).unwrap();
+
+        let tombstone22 = RangeTombstone::new("b".into(), "e".into(), 22);
+        let tombstone12 = RangeTombstone::new("d".into(), "f".into(), 12);
+        let inputs = vec![
+            input(
+                &[("a", 31, "a31"), ("c", 23, "c23")],
+                vec![tombstone22.clone()],
+            ),
+            input(
+                &[
+                    ("a", 15, "a15"),
+                    ("b", 21, "b21"),
+                    ("b", 18, "b18"),
+                    ("c", 16, "c16"),
+                    ("d", 14, "d14"),
+                ],
+                vec![tombstone12.clone()],
+            ),
+            input(
+                &[
+                    ("a", 5, "a5"),
+                    ("b", 3, "b3"),
+                    ("c", 8, "c8"),
+                    ("d", 9, "d9"),
+                    ("e", 11, "e11"),
+                    ("e", 2, "e2"),
+                ],
+                vec![],
+            ),
+        ];
+        let mut output = CompactionOutput::new(
+            one,
+            manager.clone(),
+            100,
+            vec![tombstone22.clone(), tombstone12.clone()],
+        );
+        merge_to_output(inputs, vec![10, 20], &mut output).unwrap();
+        output.finish().unwrap();
+        output.install(vec![]);
+
+        let guard = manager.get_level_tables_lock(one).read().unwrap();
+        let tables: Vec<_> = guard.values().cloned().collect();
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].range_tombstones(), &[tombstone22, tombstone12]);
+        let merged: Vec<VersionedKeyValue> = TableReadHandle::raw_iter(tables[0].clone())
+            .unwrap()
+            .collect();
+        // Versions covered by the tombstones are kept for the snapshots between them, and
+        // deletions at the tombstones hide them from the later reads. `b21` and `e11` are
+        // read by no snapshot.
+        assert_eq!(
+            merged,
+            versions(&[
+                ("a", 31, "a31"),
+                ("a", 15, "a15"),
+                ("a", 5, "a5"),
+                ("b", 22, ""),
+                ("b", 18, "b18"),
+                ("b", 3, "b3"),
+                ("c", 23, "c23"),
+                ("c", 16, "c16"),
+                ("c", 8, "c8"),
+                ("d", 22, ""),
+                ("d", 14, "d14"),
+                ("d", 9, "d9"),
+                ("e", 12, ""),
+                ("e", 2, "e2"),
+            ])
+        );
+    }
+}
@@ -0,0 +1,637 @@
+//! Column families are named keyspaces of a database, each of them has its own memory
+//! tables, sstables and [Options], and the database of a column family is stored in
+//! `{db_path}/cf/{column family id}`.
+//!
+//! The writes to all the column families are appended to one log, so a
+//! [ColumnFamilyWriteBatch] spanning column families is committed atomically. The memory
+//! tables of all the column families are frozen together once one of them is full, and the
+//! frozen log is cleared after all of them are written to level0.
+
+use crate::collections::skip_list::skipmap::{ReadWriteMode, SrSwSkipMap};
+use crate::db::comparator::Comparator;
+use crate::db::db_iter::DBIterator;
+use crate::db::dbimpl::DBImpl;
+use crate::db::key_types::RawUserKey;
+use crate::db::options::{Options, WriteOptions};
+use crate::db::{Value, DB};
+use crate::error::KVLiteError;
+use crate::memory::{MemTable, SkipMapMemTable};
+use crate::wal::column_family_wal::{ColumnFamilyLog, ColumnFamilyWAL, ColumnFamilyWrite};
+use crate::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Name of the column family created with the database, which cannot be dropped.
+pub const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+
+const COLUMN_FAMILIES_FILE_NAME: &str = "COLUMN_FAMILIES";
+const COLUMN_FAMILY_DIR: &str = "cf";
+
+/// Writes to column families, which are committed atomically by [ColumnFamilyDB::write].
+#[derive(Default)]
+pub struct ColumnFamilyWriteBatch {
+    writes: Vec<(String, ColumnFamilyWrite)>,
+}
+
+impl ColumnFamilyWriteBatch {
+    pub fn new() -> ColumnFamilyWriteBatch {
+        ColumnFamilyWriteBatch::default()
+    }
+
+    pub fn set(&mut self, column_family: &str, key: RawUserKey, value: Value) {
+        self.writes.push((
+            column_family.to_string(),
+            ColumnFamilyWrite::Put(key, value),
+        ));
+    }
+
+    pub fn remove(&mut self, column_family: &str, key: RawUserKey) {
+        self.writes.push((
+            column_family.to_string(),
+            ColumnFamilyWrite::Put(key, Value::default()),
+        ));
+    }
+
+    /// Delete all the keys in [`key_start`, `key_end`) of the column family.
+    pub fn delete_range(
+        &mut self,
+        column_family: &str,
+        key_start: RawUserKey,
+        key_end: RawUserKey,
+    ) {
+        self.writes.push((
+            column_family.to_string(),
+            ColumnFamilyWrite::DeleteRange(key_start, key_end),
+        ));
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+}
+
+struct ColumnFamily<M: MemTable<RawUserKey, RawUserKey> + 'static> {
+    id: u32,
+    comparator: Arc<dyn Comparator>,
+    db: DBImpl<RawUserKey, RawUserKey, M, ColumnFamilyWAL>,
+}
+
+/// Database of column families sharing one log.
+pub struct ColumnFamilyDB<M: MemTable<RawUserKey, RawUserKey> + 'static> {
+    db_path: String,
+    /// Writers hold the lock while appending to the log and writing to the memory tables,
+    /// so the memory tables are frozen consistently with the log.
+    log: Arc<Mutex<ColumnFamilyLog>>,
+    column_families: RwLock<HashMap<String, ColumnFamily<M>>>,
+    /// Ids are not reused, so the writes of dropped column families in the log are skipped.
+    next_column_family_id: AtomicU32,
+    /// Set if a write appended to the log fails to be applied to the memory tables, then
+    /// writes and flushes are rejected so that the log is kept for the next open to replay.
+    poisoned: AtomicBool,
+}
+
+impl<M: MemTable<RawUserKey, RawUserKey> + 'static> ColumnFamilyDB<M> {
+    /// Open the database at `db_path` and all its column families, the column families
+    /// absent in `column_family_options` are opened with `options`.
+    pub fn open(
+        db_path: impl AsRef<Path>,
+        options: Options,
+        column_family_options: HashMap<String, Options>,
+    ) -> Result<Self> {
+        let db_path = db_path.as_ref().as_os_str().to_str().unwrap().to_string();
+        std::fs::create_dir_all(Path::new(&db_path).join(COLUMN_FAMILY_DIR))?;
+        let (next_column_family_id, names) = match read_column_families(&db_path)? {
+            Some(column_families) => column_families,
+            None => {
+                let names = BTreeMap::from([(0, DEFAULT_COLUMN_FAMILY_NAME.to_string())]);
+                write_column_families(&db_path, 1, &names)?;
+                (1, names)
+            }
+        };
+
+        // remove the column families dropped before their directories are removed
+        for entry in std::fs::read_dir(Path::new(&db_path).join(COLUMN_FAMILY_DIR))? {
+            let path = entry?.path();
+            let id = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.parse::<u32>().ok());
+            if id.filter(|id| names.contains_key(id)).is_none() {
+                info!("remove dropped column family {:?}", path);
+                std::fs::remove_dir_all(path)?;
+            }
+        }
+
+        let mut mem_tables: HashMap<u32, M> = names.keys().map(|id| (*id, M::default())).collect();
+        let log =
+            ColumnFamilyLog::open_and_load_logs(&db_path, |id, write| match mem_tables.get(&id) {
+                Some(mem_table) => apply_write(mem_table, write),
+                None => Ok(()),
+            })?;
+        let log = Arc::new(Mutex::new(log));
+
+        let mut column_families = HashMap::with_capacity(names.len());
+        for (id, name) in names {
+            let options = column_family_options
+                .get(&name)
+                .cloned()
+                .unwrap_or_else(|| options.clone());
+            let mem_table = mem_tables.remove(&id).unwrap();
+            let column_family = open_column_family(&db_path, &log, id, options, mem_table)?;
+            column_families.insert(name, column_family);
+        }
+        Ok(ColumnFamilyDB {
+            db_path,
+            log,
+            column_families: RwLock::new(column_families),
+            next_column_family_id: AtomicU32::new(next_column_family_id),
+            poisoned: AtomicBool::new(false),
+        })
+    }
+
+    /// Names of all the column families.
+    pub fn column_families(&self) -> Vec<String> {
+        let column_families = self.column_families.read().unwrap();
+        column_families.keys().cloned().collect()
+    }
+
+    /// Create an empty column family named `name` with `options`.
+    pub fn create_column_family(&self, name: &str, options: Options) -> Result<()> {
+        if name.is_empty() || name.contains('\n') {
+            return Err(KVLiteError::Custom(format!(
+                "invalid column family name `{}`",
+                name
+            )));
+        }
+        let mut column_families = self.column_families.write().unwrap();
+        if column_families.contains_key(name) {
+            return Err(KVLiteError::Custom(format!(
+                "column family `{}` already exists",
+                name
+            )));
+        }
+        let id = self.next_column_family_id.fetch_add(1, Ordering::SeqCst);
+        let mut names = column_family_names(&column_families);
+        names.insert(id, name.to_string());
+        write_column_families(&self.db_path, id + 1, &names)?;
+
+        let column_family =
+            open_column_family(&self.db_path, &self.log, id, options, M::default())?;
+        column_families.insert(name.to_string(), column_family);
+        info!("create column family `{}` with id {}", name, id);
+        Ok(())
+    }
+
+    /// Drop the column family named `name` and delete all its keys.
+    pub fn drop_column_family(&self, name: &str) -> Result<()> {
+        if name == DEFAULT_COLUMN_FAMILY_NAME {
+            return Err(KVLiteError::Custom(
+                "default column family cannot be dropped".to_string(),
+            ));
+        }
+        let mut column_families = self.column_families.write().unwrap();
+        let column_family = column_families
+            .remove(name)
+            .ok_or_else(|| no_column_family(name))?;
+        let names = column_family_names(&column_families);
+        write_column_families(
+            &self.db_path,
+            self.next_column_family_id.load(Ordering::SeqCst),
+            &names,
+        )?;
+
+        // wait for the background tasks of the column family before removing its files
+        let id = column_family.id;
+        drop(column_family);
+        std::fs::remove_dir_all(column_family_path(&self.db_path, id))?;
+        info!("drop column family `{}` with id {}", name, id);
+        Ok(())
+    }
+
+    pub fn get(&self, column_family: &str, key: &RawUserKey) -> Result<Option<Value>> {
+        let column_families = self.column_families.read().unwrap();
+        get_column_family(&column_families, column_family)?
+            .db
+            .get(key)
+    }
+
+    pub fn range_get(
+        &self,
+        column_family: &str,
+        key_start: &RawUserKey,
+        key_end: &RawUserKey,
+    ) -> Result<SrSwSkipMap<RawUserKey, Value>> {
+        let column_families = self.column_families.read().unwrap();
+        get_column_family(&column_families, column_family)?
+            .db
+            .range_get(key_start, key_end)
+    }
+
+    /// Get an iterator for all the valid key-value pairs in the column family.
+    pub fn get_db_iterator<const RW_MODE: ReadWriteMode>(
+        &self,
+        column_family: &str,
+    ) -> Result<DBIterator>
+    where
+        M: SkipMapMemTable<RawUserKey, RawUserKey, { RW_MODE }>,
+    {
+        let column_families = self.column_families.read().unwrap();
+        Ok(get_column_family(&column_families, column_family)?
+            .db
+            .get_db_iterator())
+    }
+
+    pub fn set(
+        &self,
+        write_options: &WriteOptions,
+        column_family: &str,
+        key: RawUserKey,
+        value: Value,
+    ) -> Result<()> {
+        let mut batch = ColumnFamilyWriteBatch::new();
+        batch.set(column_family, key, value);
+        self.write(write_options, batch)
+    }
+
+    pub fn remove(
+        &self,
+        write_options: &WriteOptions,
+        column_family: &str,
+        key: RawUserKey,
+    ) -> Result<()> {
+        let mut batch = ColumnFamilyWriteBatch::new();
+        batch.remove(column_family, key);
+        self.write(write_options, batch)
+    }
+
+    /// Delete all the keys in [`key_start`, `key_end`) of the column family.
+    pub fn delete_range(
+        &self,
+        write_options: &WriteOptions,
+        column_family: &str,
+        key_start: RawUserKey,
+        key_end: RawUserKey,
+    ) -> Result<()> {
+        let mut batch = ColumnFamilyWriteBatch::new();
+        batch.delete_range(column_family, key_start, key_end);
+        self.write(write_options, batch)
+    }
+
+    /// Commit the writes of `batch` atomically, nothing is written if any column family of
+    /// the batch does not exist. If the batch is logged but fails to be applied to the
+    /// memory tables, the database is poisoned and must be reopened to recover the batch.
+    pub fn write(&self, write_options: &WriteOptions, batch: ColumnFamilyWriteBatch) -> Result<()> {
+        let column_families = self.column_families.read().unwrap();
+        let mut targets = Vec::with_capacity(batch.len());
+        let mut writes = Vec::with_capacity(batch.len());
+        for (name, write) in batch.writes {
+            let column_family = get_column_family(&column_families, &name)?;
+            if let ColumnFamilyWrite::DeleteRange(key_start, key_end) = &write {
+                if column_family.comparator.compare(key_start, key_end) != std::cmp::Ordering::Less
+                {
+                    continue;
+                }
+            }
+            targets.push(column_family);
+            writes.push((column_family.id, write));
+        }
+        if writes.is_empty() {
+            return Ok(());
+        }
+
+        let mut log = self.log.lock().unwrap();
+        self.check_poisoned()?;
+        log.append(write_options, &writes)?;
+        // the logged writes are all applied, the first error poisons the database
+        let mut result = Ok(());
+        for (column_family, (_, write)) in targets.into_iter().zip(writes) {
+            let applied = match write {
+                ColumnFamilyWrite::Put(key, value) => {
+                    column_family.db.apply_logged_write(key, value)
+                }
+                ColumnFamilyWrite::DeleteRange(key_start, key_end) => column_family
+                    .db
+                    .apply_logged_range_deletion(&key_start, &key_end),
+            };
+            if let Err(e) = applied {
+                error!(
+                    "failed to apply the logged write to column family {}: {}",
+                    column_family.id, e
+                );
+                result = result.and(Err(e));
+            }
+        }
+        if result.is_err() {
+            self.poisoned.store(true, Ordering::SeqCst);
+            return result;
+        }
+        if column_families
+            .values()
+            .any(|column_family| column_family.db.is_mut_mem_table_full())
+        {
+            Self::may_freeze(&column_families, &mut log)?;
+        }
+        Ok(())
+    }
+
+    /// Write the memory tables of all the column families to level0 sstables, and wait
+    /// until they are written.
+    pub fn flush(&self) -> Result<()> {
+        let column_families = self.column_families.read().unwrap();
+        loop {
+            for column_family in column_families.values() {
+                column_family.db.wait_for_level0_writer();
+            }
+            let mut log = self.log.lock().unwrap();
+            self.check_poisoned()?;
+            if Self::may_freeze(&column_families, &mut log)? {
+                break;
+            }
+        }
+        // the log is unlocked, since it is cleared by the level0 writers
+        for column_family in column_families.values() {
+            column_family.db.wait_for_level0_writer();
+        }
+        Ok(())
+    }
+
+    /// Fail if the database is poisoned by a write, whose memory tables miss the logged writes
+    /// and must not be flushed.
+    fn check_poisoned(&self) -> Result<()> {
+        if self.poisoned.load(Ordering::SeqCst) {
+            return Err(KVLiteError::Custom(
+                "database is poisoned by a failed write, reopen it to recover from the log".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Freeze the memory tables of all the column families with unflushed writes, unless
+    /// the memory tables frozen last time are being written to level0. Return whether they
+    /// are frozen.
+    fn may_freeze(
+        column_families: &HashMap<String, ColumnFamily<M>>,
+        log: &mut ColumnFamilyLog,
+    ) -> Result<bool> {
+        if column_families
+            .values()
+            .any(|column_family| column_family.db.is_writing_level0())
+        {
+            return Ok(false);
+        }
+
+        let to_freeze: Vec<&ColumnFamily<M>> = column_families
+            .values()
+            .filter(|column_family| column_family.db.has_unflushed_writes())
+            .collect();
+        if !to_freeze.is_empty() {
+            log.freeze(to_freeze.len())?;
+        }
+        for column_family in to_freeze {
+            column_family.db.freeze();
+        }
+        Ok(true)
+    }
+}
+
+fn apply_write<M: MemTable<RawUserKey, RawUserKey>>(
+    mem_table: &M,
+    write: ColumnFamilyWrite,
+) -> Result<()> {
+    match write {
+        ColumnFamilyWrite::Put(key, value) if value.is_empty() => mem_table.remove(key),
+        ColumnFamilyWrite::Put(key, value) => mem_table.set(key, value),
+        ColumnFamilyWrite::DeleteRange(key_start, key_end) => {
+            mem_table.delete_range(&key_start, &key_end)
+        }
+    }
+}
+
+fn open_column_family<M: MemTable<RawUserKey, RawUserKey> + 'static>(
+    db_path: &str,
+    log: &Arc<Mutex<ColumnFamilyLog>>,
+    id: u32,
+    options: Options,
+    mem_table: M,
+) -> Result<ColumnFamily<M>> {
+    let comparator = options.comparator.clone();
+    let path = column_family_path(db_path, id);
+    options.check_persisted(&path)?;
+    let wal = Arc::new(Mutex::new(ColumnFamilyWAL::new(id, log.clone())));
+    let db = DBImpl::open_with_wal(path, options, wal, mem_table)?;
+    Ok(ColumnFamily { id, comparator, db })
+}
+
+fn get_column_family<'a, M: MemTable<RawUserKey, RawUserKey> + 'static>(
+    column_families: &'a HashMap<String, ColumnFamily<M>>,
+    name: &str,
+) -> Result<&'a ColumnFamily<M>> {
+    column_families
+        .get(name)
+        .ok_or_else(|| no_column_family(name))
+}
+
+fn no_column_family(name: &str) -> KVLiteError {
+    KVLiteError::Custom(format!("column family `{}` does not exist", name))
+}
+
+fn column_family_names<M: MemTable<RawUserKey, RawUserKey> + 'static>(
+    column_families: &HashMap<String, ColumnFamily<M>>,
+) -> BTreeMap<u32, String> {
+    column_families
+        .iter()
+        .map(|(name, column_family)| (column_family.id, name.clone()))
+        .collect()
+}
+
+fn column_family_path(db_path: &str, id: u32) -> String {
+    format!("{}/{}/{}", db_path, COLUMN_FAMILY_DIR, id)
+}
+
+/// Read the next column family id and the column families from the file, whose first
+/// line is the next id, followed by a line of `{id} {name}` for each column family.
+fn read_column_families(db_path: &str) -> Result<Option<(u32, BTreeMap<u32, String>)>> {
+    let path = Path::new(db_path).join(COLUMN_FAMILIES_FILE_NAME);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(path)?;
+    let corrupted = || KVLiteError::Custom("corrupted column families file".to_string());
+    let mut lines = content.lines();
+    let next_id = lines
+        .next()
+        .and_then(|line| line.parse::<u32>().ok())
+        .ok_or_else(corrupted)?;
+    let mut names = BTreeMap::new();
+    for line in lines {
+        let (id, name) = line.split_once(' ').ok_or_else(corrupted)?;
+        names.insert(
+            id.parse::<u32>().map_err(|_| corrupted())?,
+            name.to_string(),
+        );
+    }
+    Ok(Some((next_id, names)))
+}
+
+/// Replace the column families file, see [read_column_families].
+fn write_column_families(db_path: &str, next_id: u32, names: &BTreeMap<u32, String>) -> Result<()> {
+    let mut content = format!("{}\n", next_id);
+    for (id, name) in names {
+        content.push_str(&format!("{} {}\n", id, name));
+    }
+    let path = Path::new(db_path).join(COLUMN_FAMILIES_FILE_NAME);
+    let temp_path = Path::new(db_path).join(format!("{}.tmp", COLUMN_FAMILIES_FILE_NAME));
+    let mut file = File::create(&temp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(temp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::skip_list::skipmap::ReadWriteMode::MrMw;
+    use crate::db::column_family::{
+        ColumnFamilyDB, ColumnFamilyWriteBatch, COLUMN_FAMILY_DIR, DEFAULT_COLUMN_FAMILY_NAME,
+    };
+    use crate::db::comparator::BytewiseComparator;
+    use crate::db::key_types::RawUserKey;
+    use crate::db::options::{Options, WriteOptions};
+    use crate::memory::MrMwSkipMapMemTable;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    type TestDB = ColumnFamilyDB<MrMwSkipMapMemTable<RawUserKey>>;
+
+    #[test]
+    fn test_column_family() {
+        let _ = env_logger::try_init();
+        let temp_dir = tempfile::Builder::new()
+            .prefix("column_family")
+            .tempdir()
+            .unwrap();
+        let path = temp_dir.path();
+        let wo = WriteOptions { sync: false };
+        let key = |i: u64| format!("key{:06}", i).into_bytes();
+        let value = |i: u64, cf: &str| format!("{}_{:06}", cf, i).into_bytes();
+        let cf_options = || {
+            HashMap::from([(
+                "cf1".to_string(),
+                Options {
+                    comparator: Arc::new(BytewiseComparator),
+                    ..Options::default()
+                },
+            )])
+        };
+
+        let db = TestDB::open(path, Options::default(), HashMap::new()).unwrap();
+        assert_eq!(db.column_families(), vec![DEFAULT_COLUMN_FAMILY_NAME]);
+        db.create_column_family("cf1", cf_options().remove("cf1").unwrap())
+            .unwrap();
+        assert!(db.create_column_family("cf1", Options::default()).is_err());
+        assert!(db.drop_column_family(DEFAULT_COLUMN_FAMILY_NAME).is_err());
+
+        // a batch spanning column families, nothing is written if one of them does not exist
+        let mut batch = ColumnFamilyWriteBatch::new();
+        for i in 0..100 {
+            batch.set(DEFAULT_COLUMN_FAMILY_NAME, key(i), value(i, "default"));
+            batch.set("cf1", key(i), value(i, "cf1"));
+        }
+        batch.remove("cf1", key(0));
+        batch.delete_range("cf1", key(10), key(20));
+        let mut bad_batch = ColumnFamilyWriteBatch::new();
+        bad_batch.set(
+            DEFAULT_COLUMN_FAMILY_NAME,
+            key(1000),
+            value(1000, "default"),
+        );
+        bad_batch.set("cf2", key(1000), value(1000, "cf2"));
+        assert!(db.write(&wo, bad_batch).is_err());
+        db.write(&wo, batch).unwrap();
+
+        let check = |db: &TestDB| {
+            assert!(db
+                .get(DEFAULT_COLUMN_FAMILY_NAME, &key(1000))
+                .unwrap()
+                .is_none());
+            for i in 0..100 {
+                assert_eq!(
+                    db.get(DEFAULT_COLUMN_FAMILY_NAME, &key(i)).unwrap(),
+                    Some(value(i, "default"))
+                );
+                let expected = if i == 0 || (10..20).contains(&i) {
+                    None
+                } else {
+                    Some(value(i, "cf1"))
+                };
+                assert_eq!(db.get("cf1", &key(i)).unwrap(), expected);
+            }
+            let kvs = db.range_get("cf1", &key(0), &key(99)).unwrap();
+            assert_eq!(kvs.into_iter().filter(|(_, v)| !v.is_empty()).count(), 89);
+            let iterator = db.get_db_iterator::<{ MrMw }>("cf1").unwrap();
+            let count = iterator
+                .filter(|(k, v)| k < &key(100) && !v.is_empty())
+                .count();
+            assert_eq!(count, 89);
+        };
+        check(&db);
+        drop(db);
+
+        // writes are loaded from the shared log
+        let db = TestDB::open(path, Options::default(), cf_options()).unwrap();
+        let mut column_families = db.column_families();
+        column_families.sort();
+        assert_eq!(column_families, vec!["cf1", DEFAULT_COLUMN_FAMILY_NAME]);
+        check(&db);
+
+        // large values make the memory tables of all the column families frozen together
+        for i in 100..300 {
+            let mut large_value = value(i, "cf1");
+            large_value.resize(32 * 1024, 0);
+            db.set(&wo, "cf1", key(i), large_value).unwrap();
+        }
+        db.flush().unwrap();
+        for id in [0, 1] {
+            let level0 = path.join(COLUMN_FAMILY_DIR).join(id.to_string()).join("0");
+            assert!(std::fs::read_dir(level0).unwrap().count() > 0);
+        }
+        check(&db);
+        drop(db);
+
+        let db = TestDB::open(path, Options::default(), cf_options()).unwrap();
+        check(&db);
+        for i in 100..300 {
+            let large_value = db.get("cf1", &key(i)).unwrap().unwrap();
+            assert!(large_value.starts_with(&value(i, "cf1")));
+        }
+
+        // a dropped column family is removed, and its writes in the log are skipped after
+        // a column family of the same name is created
+        db.drop_column_family("cf1").unwrap();
+        assert!(db.get("cf1", &key(1)).is_err());
+        assert!(!path.join(COLUMN_FAMILY_DIR).join("1").exists());
+        drop(db);
+        let db = TestDB::open(path, Options::default(), HashMap::new()).unwrap();
+        assert_eq!(db.column_families(), vec![DEFAULT_COLUMN_FAMILY_NAME]);
+        db.create_column_family("cf1", Options::default()).unwrap();
+        assert!(path.join(COLUMN_FAMILY_DIR).join("2").exists());
+        assert!(db.get("cf1", &key(1)).unwrap().is_none());
+        drop(db);
+        let db = TestDB::open(path, Options::default(), HashMap::new()).unwrap();
+        assert!(db.get("cf1", &key(1)).unwrap().is_none());
+        assert_eq!(
+            db.get(DEFAULT_COLUMN_FAMILY_NAME, &key(1)).unwrap(),
+            Some(value(1, "default"))
+        );
+    }
+}
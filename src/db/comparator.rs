@@ -0,0 +1,158 @@
+use crate::db::key_types::RawUserKey;
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+/// Total order of the raw user keys stored in sstables.
+///
+/// The order must agree with the [Ord] of the user keys in memory tables,
+/// or keys flushed to the disk cannot be found again.
+pub trait Comparator: Send + Sync {
+    /// Name of the comparator, which is persisted in the database directory.
+    /// A database must always be opened with the comparator of the same name.
+    fn name(&self) -> &str;
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+
+    /// Get the smallest key that is larger than `key`, return `None` if there is no such key
+    /// or it is unknown.
+    fn successor(&self, key: &[u8]) -> Option<RawUserKey> {
+        let mut succ = Vec::with_capacity(key.len() + 1);
+        succ.extend_from_slice(key);
+        succ.push(0);
+        Some(succ)
+    }
+
+    /// Get the largest key that is smaller than `key`, return `None` if it is unknown.
+    fn predecessor(&self, key: &[u8]) -> Option<RawUserKey> {
+        match key.split_last() {
+            Some((0, prefix)) => Some(prefix.to_vec()),
+            _ => None,
+        }
+    }
+}
+
+/// Lexicographic order of bytes, which is the default comparator.
+#[derive(Default)]
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        "kvlite.BytewiseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Order of keys encoded as little-endian [i32], e.g. [crate::db::key_types::I32UserKey].
+///
+/// Malformed keys, which are not 4 bytes, are ordered by length first and then by bytes, and
+/// have no known successor or predecessor.
+#[derive(Default)]
+pub struct I32Comparator;
+
+impl I32Comparator {
+    /// Decode `key`, return `None` if it is malformed.
+    #[inline]
+    fn decode(key: &[u8]) -> Option<i32> {
+        key.try_into().ok().map(i32::from_le_bytes)
+    }
+}
+
+impl Comparator for I32Comparator {
+    fn name(&self) -> &str {
+        "kvlite.I32Comparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match (Self::decode(a), Self::decode(b)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => a.len().cmp(&b.len()).then_with(|| a.cmp(b)),
+        }
+    }
+
+    fn successor(&self, key: &[u8]) -> Option<RawUserKey> {
+        Self::decode(key)?
+            .checked_add(1)
+            .map(|n| n.to_le_bytes().to_vec())
+    }
+
+    fn predecessor(&self, key: &[u8]) -> Option<RawUserKey> {
+        Self::decode(key)?
+            .checked_sub(1)
+            .map(|n| n.to_le_bytes().to_vec())
+    }
+}
+
+pub fn default_comparator() -> Arc<dyn Comparator> {
+    Arc::new(BytewiseComparator)
+}
+
+/// Raw user key ordered by a [Comparator], used as the key of ordered collections.
+#[derive(Clone)]
+pub struct ComparableKey {
+    pub key: RawUserKey,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl ComparableKey {
+    pub fn new(key: RawUserKey, comparator: Arc<dyn Comparator>) -> ComparableKey {
+        ComparableKey { key, comparator }
+    }
+}
+
+impl PartialEq for ComparableKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ComparableKey {}
+
+impl PartialOrd for ComparableKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ComparableKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.comparator.compare(&self.key, &other.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::comparator::{BytewiseComparator, Comparator, I32Comparator};
+    use std::cmp::Ordering;
+
+    #[test]
+    fn test_comparator() {
+        let bytewise = BytewiseComparator;
+        assert_eq!(bytewise.compare(b"key1", b"key10"), Ordering::Less);
+        assert_eq!(bytewise.successor(b"key1").unwrap(), b"key1\0".to_vec());
+        assert_eq!(bytewise.predecessor(b"key1\0").unwrap(), b"key1".to_vec());
+        assert!(bytewise.predecessor(b"key1").is_none());
+
+        let i32_comparator = I32Comparator;
+        let (a, b) = (256i32.to_le_bytes(), 1i32.to_le_bytes());
+        assert_eq!(bytewise.compare(&a, &b), Ordering::Less);
+        assert_eq!(i32_comparator.compare(&a, &b), Ordering::Greater);
+        assert_eq!(
+            i32_comparator.compare(&(-1i32).to_le_bytes(), &b),
+            Ordering::Less
+        );
+        assert_eq!(i32_comparator.successor(&a).unwrap(), 257i32.to_le_bytes());
+        assert!(i32_comparator.successor(&i32::MAX.to_le_bytes()).is_none());
+
+        // malformed keys are ordered by length first
+        assert_eq!(i32_comparator.compare(b"abc", &a), Ordering::Less);
+        assert_eq!(i32_comparator.compare(b"abcde", &a), Ordering::Greater);
+        assert_eq!(i32_comparator.compare(b"abd", b"abc"), Ordering::Greater);
+        assert_eq!(i32_comparator.compare(b"", b"abc"), Ordering::Less);
+        assert!(i32_comparator.successor(b"abc").is_none());
+        assert!(i32_comparator.predecessor(b"abcde").is_none());
+    }
+}
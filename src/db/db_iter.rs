@@ -1,20 +1,61 @@
 use crate::collections::skip_list::skipmap::ReadWriteMode;
 use crate::collections::skip_list::MemoryAllocator;
-use crate::db::key_types::RawUserKey;
+use crate::db::comparator::Comparator;
+use crate::db::key_types::{RawUserKey, SequenceNumber};
+use crate::db::slice_transform::KeyPrefix;
 use crate::db::Value;
+use crate::error::KVLiteError;
 use crate::memory::{MemTableCloneIterator, SkipMapMemTable};
-use crate::sstable::manager::level_iter::{Level0Iterator, MergingIterator};
+use crate::sstable::manager::level_iter::{LevelNIterator, MergingIterator};
 use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
 
 pub type InternalKeyValue = (RawUserKey, Value);
+/// Version of a key written at the sequence number.
+pub(crate) type VersionedKeyValue = (RawUserKey, SequenceNumber, Value);
+
+/// First error met by the sstable iterators sharing it, the iterators stop at the error.
+#[derive(Clone, Default)]
+pub(crate) struct IteratorStatus(Arc<Mutex<Option<KVLiteError>>>);
+
+impl IteratorStatus {
+    /// Record `error` unless an error is already recorded.
+    pub(crate) fn set_error(&self, error: KVLiteError) {
+        let mut guard = self.0.lock().unwrap();
+        if guard.is_none() {
+            *guard = Some(error);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_ok(&self) -> bool {
+        self.0.lock().unwrap().is_none()
+    }
+
+    /// Return the recorded error, if any.
+    pub(crate) fn check(&self) -> crate::Result<()> {
+        match self.0.lock().unwrap().as_ref() {
+            Some(e) => Err(KVLiteError::Custom(e.to_string())),
+            None => Ok(()),
+        }
+    }
+}
 
-#[derive(PartialEq, Eq)]
 pub(crate) struct KeyValueIterItem {
     pub(crate) key: RawUserKey,
     pub(crate) value: Value,
     pub(crate) iter_id: usize,
+    comparator: Arc<dyn Comparator>,
 }
 
+impl PartialEq for KeyValueIterItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for KeyValueIterItem {}
+
 impl PartialOrd for KeyValueIterItem {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
@@ -26,39 +67,72 @@ impl Ord for KeyValueIterItem {
     /// Smaller key is larger.
     /// [std::collections::BinaryHeap] returns the greatest item
     fn cmp(&self, other: &Self) -> Ordering {
-        other
-            .key
-            .cmp(&self.key)
+        self.comparator
+            .compare(&other.key, &self.key)
             .then(self.iter_id.cmp(&other.iter_id))
     }
 }
 
 impl KeyValueIterItem {
-    pub(crate) fn new(key: RawUserKey, value: Value, iter_id: usize) -> KeyValueIterItem {
+    pub(crate) fn new(
+        key: RawUserKey,
+        value: Value,
+        iter_id: usize,
+        comparator: Arc<dyn Comparator>,
+    ) -> KeyValueIterItem {
         KeyValueIterItem {
             key,
             value,
             iter_id,
+            comparator,
         }
     }
 }
 pub type DBIterator = MergingIterator<Box<dyn Iterator<Item = InternalKeyValue>>>;
 
 impl DBIterator {
+    /// Merge the iterators of all the components, only the keys of `key_prefix` are
+    /// returned if it is not `None`.
     pub(crate) fn new<
         M: SkipMapMemTable<RawUserKey, Value, { RW_MODE }> + 'static,
         const RW_MODE: ReadWriteMode,
+        It0: Iterator<Item = InternalKeyValue> + 'static,
     >(
         imm_mem_iterator: MemTableCloneIterator<RawUserKey, Value, M, { RW_MODE }>,
         mut_mem_iterator: MemTableCloneIterator<RawUserKey, Value, M, { RW_MODE }>,
-        level0_iterator: Level0Iterator,
-        mut leveln_iterators: Vec<Box<dyn Iterator<Item = InternalKeyValue>>>,
+        level0_iterator: MergingIterator<It0>,
+        leveln_iterators: Vec<LevelNIterator>,
+        comparator: Arc<dyn Comparator>,
+        key_prefix: Option<KeyPrefix>,
     ) -> DBIterator {
-        leveln_iterators.reverse();
-        leveln_iterators.reserve(3);
-        leveln_iterators.push(Box::new(level0_iterator));
-        leveln_iterators.push(Box::new(imm_mem_iterator));
-        leveln_iterators.push(Box::new(mut_mem_iterator));
-        Self::from_iterators(leveln_iterators)
+        let mut range_tombstones = Vec::with_capacity(leveln_iterators.len() + 3);
+        let mut iterators: Vec<Box<dyn Iterator<Item = InternalKeyValue>>> =
+            Vec::with_capacity(leveln_iterators.len() + 3);
+        let mut statuses = level0_iterator.shared_statuses();
+        for iterator in leveln_iterators.into_iter().rev() {
+            range_tombstones.push(iterator.range_tombstones());
+            statuses.push(iterator.shared_status());
+            iterators.push(boxed(iterator, &key_prefix));
+        }
+        range_tombstones.push(level0_iterator.range_tombstones());
+        iterators.push(boxed(level0_iterator, &key_prefix));
+        range_tombstones.push(imm_mem_iterator.range_tombstones());
+        iterators.push(boxed(imm_mem_iterator, &key_prefix));
+        range_tombstones.push(mut_mem_iterator.range_tombstones());
+        iterators.push(boxed(mut_mem_iterator, &key_prefix));
+        Self::with_range_tombstones(iterators, range_tombstones, statuses, comparator)
+    }
+}
+
+fn boxed(
+    iterator: impl Iterator<Item = InternalKeyValue> + 'static,
+    key_prefix: &Option<KeyPrefix>,
+) -> Box<dyn Iterator<Item = InternalKeyValue>> {
+    match key_prefix {
+        Some(key_prefix) => {
+            let key_prefix = key_prefix.clone();
+            Box::new(iterator.filter(move |(key, _)| key_prefix.matches(key)))
+        }
+        None => Box::new(iterator),
     }
 }
@@ -1,21 +1,34 @@
-use crate::cache::ShardLRUCache;
+use crate::cache::CacheStatistics;
 use crate::collections::skip_list::skipmap::{ReadWriteMode, SrSwSkipMap};
 use crate::collections::skip_list::MemoryAllocator;
+use crate::compaction::blob_gc::collect_blob_garbage;
 use crate::db::db_iter::DBIterator;
-use crate::db::key_types::{DBKey, RawUserKey};
-use crate::db::options::WriteOptions;
-use crate::db::{Value, DB, WRITE_BUFFER_SIZE};
+use crate::db::key_types::{DBKey, RawUserKey, SequenceNumber};
+use crate::db::options::{Options, WriteOptions};
+use crate::db::range_tombstone::remove_covered_keys;
+use crate::db::row_cache::RowCache;
+use crate::db::slice_transform::KeyPrefix;
+use crate::db::snapshot::SnapshotList;
+use crate::db::{Value, DB, MAX_LEVEL, WRITE_BUFFER_SIZE};
+use crate::error::KVLiteError;
 use crate::memory::{MemTable, MemTableCloneIterator, SkipMapMemTable};
 use crate::sstable::manager::level_0::Level0Manager;
 use crate::sstable::manager::level_n::LevelNManager;
+use crate::sstable::properties_block::TableProperties;
+use crate::sstable::sst_file_writer::{copy_with_global_seq_num, validate_key_order};
+use crate::sstable::table_handle::{temp_file_name, TableReadHandle};
+use crate::sstable::TableID;
 use crate::wal::WAL;
 use crate::Result;
 use arc_swap::ArcSwap;
 use crossbeam_channel::Sender;
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
 use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 pub struct DBImpl<
     SK: DBKey + 'static,
@@ -24,16 +37,24 @@ pub struct DBImpl<
     L: WAL<SK, UK> + 'static,
 > {
     db_path: String,
+    options: Options,
     pub(crate) wal: Arc<Mutex<L>>,
     pub(crate) mut_mem_table: ArcSwap<M>,
     imm_mem_table: Arc<ArcSwap<M>>,
 
     level0_manager: Arc<Level0Manager<SK, UK, M, L>>,
     leveln_manager: Arc<LevelNManager>,
+    row_cache: Option<RowCache>,
 
     level0_writer_handle: Option<JoinHandle<()>>,
     write_level0_channel: Option<Sender<()>>,
     background_task_write_to_level0_is_running: Arc<AtomicBool>,
+    /// Sequence number of the next write, continuing after the sequence numbers recovered
+    /// from the log and sstables.
+    next_seq_num: AtomicU64,
+    /// Sequence number of the last write visible to snapshots, see
+    /// [DBImpl::publish_seq_num].
+    published_seq_num: AtomicU64,
 }
 
 impl<SK, UK, M, L> DB<SK, UK, M> for DBImpl<SK, UK, M, L>
@@ -44,43 +65,7 @@ where
     L: WAL<SK, UK> + 'static,
 {
     fn open(db_path: impl AsRef<Path>) -> Result<Self> {
-        let db_path = db_path.as_ref().as_os_str().to_str().unwrap().to_string();
-
-        let index_cache = Arc::new(ShardLRUCache::default());
-        let leveln_manager = LevelNManager::open_tables(db_path.clone(), index_cache.clone());
-
-        let mut mut_mem_table = M::default();
-
-        let wal = Arc::new(Mutex::new(
-            L::open_and_load_logs(&db_path, &mut mut_mem_table).unwrap(),
-        ));
-
-        let imm_mem_table = Arc::new(ArcSwap::new(Arc::new(M::default())));
-        let channel = crossbeam_channel::unbounded();
-
-        let background_task_write_to_level0_is_running = Arc::new(AtomicBool::default());
-        let (level0_manager, level0_writer_handle) =
-            Level0Manager::<SK, UK, M, L>::start_task_write_level0(
-                db_path.clone(),
-                leveln_manager.clone(),
-                wal.clone(),
-                imm_mem_table.clone(),
-                index_cache,
-                channel.1,
-                background_task_write_to_level0_is_running.clone(),
-            );
-
-        Ok(DBImpl {
-            db_path,
-            wal,
-            mut_mem_table: ArcSwap::new(Arc::new(mut_mem_table)),
-            imm_mem_table,
-            leveln_manager,
-            level0_manager,
-            level0_writer_handle: Some(level0_writer_handle),
-            write_level0_channel: Some(channel.0),
-            background_task_write_to_level0_is_running,
-        })
+        Self::open_with_options(db_path, Options::default())
     }
 
     fn get(&self, key: &SK) -> Result<Option<Value>> {
@@ -102,11 +87,8 @@ where
             wal_guard.append(write_options, &key, Some(&value))?;
         }
 
-        let mut_mem_table = self.get_mut_mem_table();
-        mut_mem_table.set(key, value)?;
-        if self.should_freeze(mut_mem_table.approximate_memory_usage()) {
-            self.freeze();
-        }
+        self.apply_logged_write(key, value)?;
+        self.may_freeze();
         Ok(())
     }
 
@@ -116,12 +98,8 @@ where
             wal_guard.append(write_options, &key, None)?;
         }
 
-        let mut_mem_table = self.get_mut_mem_table();
-        mut_mem_table.remove(key)?;
-
-        if self.should_freeze(mut_mem_table.approximate_memory_usage()) {
-            self.freeze();
-        }
+        self.apply_logged_write(key, Value::default())?;
+        self.may_freeze();
         Ok(())
     }
 
@@ -130,18 +108,32 @@ where
         self.leveln_manager.range_query(
             key_start.raw_user_key(),
             key_end.raw_user_key(),
+            key_start.seq_num(),
             &mut skip_map,
-        );
+        )?;
         self.level0_manager.range_query(
             key_start.raw_user_key(),
             key_end.raw_user_key(),
+            key_start.seq_num(),
             &mut skip_map,
-        );
+        )?;
 
         let imm_mem_table = self.get_imm_mem_table();
+        remove_covered_keys(
+            &imm_mem_table.range_tombstones().to_vec(),
+            key_start.seq_num(),
+            &mut skip_map,
+            self.options.comparator.as_ref(),
+        );
         imm_mem_table.range_get(key_start, key_end, &mut skip_map);
 
         let mut_mem_table = self.get_mut_mem_table();
+        remove_covered_keys(
+            &mut_mem_table.range_tombstones().to_vec(),
+            key_start.seq_num(),
+            &mut skip_map,
+            self.options.comparator.as_ref(),
+        );
         mut_mem_table.range_get(key_start, key_end, &mut skip_map);
         Ok(skip_map)
     }
@@ -158,11 +150,123 @@ where
     M: MemTable<SK, UK> + 'static,
     L: WAL<SK, UK>,
 {
-    pub(crate) fn should_freeze(&self, table_size: u64) -> bool {
-        table_size >= WRITE_BUFFER_SIZE
-            && !self
-                .background_task_write_to_level0_is_running
-                .load(Ordering::Acquire)
+    /// Open the database at `db_path` with `options`.
+    pub fn open_with_options(db_path: impl AsRef<Path>, options: Options) -> Result<Self> {
+        let db_path = db_path.as_ref().as_os_str().to_str().unwrap().to_string();
+        options.check_persisted(&db_path)?;
+        let mut mut_mem_table = M::default();
+        let wal = Arc::new(Mutex::new(
+            L::open_and_load_logs(&db_path, &mut mut_mem_table).unwrap(),
+        ));
+        Self::open_with_wal(db_path, options, wal, mut_mem_table)
+    }
+
+    /// Open the database at `db_path` whose writes are logged to the opened `wal`, and
+    /// `mut_mem_table` holds the writes loaded from it. The options must be checked by
+    /// [Options::check_persisted] before opening `wal`.
+    pub(crate) fn open_with_wal(
+        db_path: String,
+        options: Options,
+        wal: Arc<Mutex<L>>,
+        mut_mem_table: M,
+    ) -> Result<Self> {
+        let leveln_manager = LevelNManager::open_tables(db_path.clone(), options.clone())?;
+
+        let imm_mem_table = Arc::new(ArcSwap::new(Arc::new(M::default())));
+        let channel = crossbeam_channel::unbounded();
+
+        let background_task_write_to_level0_is_running = Arc::new(AtomicBool::default());
+        let (level0_manager, level0_writer_handle) =
+            Level0Manager::<SK, UK, M, L>::start_task_write_level0(
+                db_path.clone(),
+                leveln_manager.clone(),
+                wal.clone(),
+                imm_mem_table.clone(),
+                channel.1,
+                background_task_write_to_level0_is_running.clone(),
+            );
+
+        let row_cache = options.row_cache_capacity.map(RowCache::new);
+        let db = DBImpl {
+            db_path,
+            options,
+            wal,
+            mut_mem_table: ArcSwap::new(Arc::new(mut_mem_table)),
+            imm_mem_table,
+            leveln_manager,
+            level0_manager,
+            row_cache,
+            level0_writer_handle: Some(level0_writer_handle),
+            write_level0_channel: Some(channel.0),
+            background_task_write_to_level0_is_running,
+            next_seq_num: AtomicU64::default(),
+            published_seq_num: AtomicU64::default(),
+        };
+        let largest_seq_num = db.largest_seq_num();
+        db.next_seq_num
+            .store(largest_seq_num + 1, Ordering::Release);
+        db.published_seq_num
+            .store(largest_seq_num, Ordering::Release);
+        Ok(db)
+    }
+
+    /// Write `key` appended to the log to the mutable memory table, an empty `value` deletes
+    /// `key`. The caller checks whether to freeze the table after the write.
+    pub(crate) fn apply_logged_write(&self, key: SK, value: Value) -> Result<()> {
+        let mut_mem_table = self.get_mut_mem_table();
+        if value.is_empty() {
+            mut_mem_table.remove(key)
+        } else {
+            mut_mem_table.set(key, value)
+        }
+    }
+
+    /// Write the range deletion appended to the log to the mutable memory table, see
+    /// [DBImpl::apply_logged_write].
+    pub(crate) fn apply_logged_range_deletion(&self, key_start: &SK, key_end: &SK) -> Result<()>
+    where
+        UK: From<SK>,
+    {
+        self.get_mut_mem_table().delete_range(key_start, key_end)
+    }
+
+    /// Append `batch` to the log as one record and merge it into the mutable memory table,
+    /// `mem_usage` is the memory size of its key-values. Empty values are deletions.
+    pub(crate) fn write_batch(
+        &self,
+        write_options: &WriteOptions,
+        batch: SrSwSkipMap<SK, Value>,
+        mem_usage: u64,
+    ) -> Result<()> {
+        {
+            let mut wal_guard = self.wal.lock().unwrap();
+            wal_guard.append_batch(write_options, &batch)?;
+        }
+
+        self.get_mut_mem_table().merge(batch, mem_usage);
+        self.may_freeze();
+        Ok(())
+    }
+
+    /// Freeze the mutable memory table if it is full, unless the immutable memory table is
+    /// being written to level0.
+    pub(crate) fn may_freeze(&self) {
+        if self.is_mut_mem_table_full() && !self.is_writing_level0() {
+            self.freeze();
+        }
+    }
+
+    /// Whether the mutable memory table reaches [WRITE_BUFFER_SIZE].
+    #[inline]
+    pub(crate) fn is_mut_mem_table_full(&self) -> bool {
+        self.get_mut_mem_table().approximate_memory_usage() >= WRITE_BUFFER_SIZE
+    }
+
+    /// Whether the immutable memory table is being written to level0.
+    #[inline]
+    pub(crate) fn is_writing_level0(&self) -> bool {
+        self.background_task_write_to_level0_is_running
+            .load(Ordering::Acquire)
     }
 
     pub(crate) fn freeze(&self) {
@@ -174,6 +278,15 @@ where
             wal_guard.freeze_mut_log().unwrap();
         }
 
+        // keys of the flushed immutable table are only found in sstables from now on
+        if let Some(row_cache) = &self.row_cache {
+            let flushed = self.get_imm_mem_table();
+            row_cache.invalidate(
+                flushed.kv_iter().map(|(key, _)| key),
+                !flushed.range_tombstones().is_empty(),
+            );
+        }
+
         let imm = self.mut_mem_table.swap(Arc::new(M::default()));
         self.imm_mem_table.store(imm);
 
@@ -184,6 +297,21 @@ where
         }
     }
 
+    /// Write the mutable memory table to a level0 sstable, and wait until it is written.
+    pub fn flush(&self) {
+        self.wait_for_level0_writer();
+        if self.has_unflushed_writes() {
+            self.freeze();
+            self.wait_for_level0_writer();
+        }
+    }
+
+    /// Whether the mutable memory table has keys or range tombstones to flush.
+    pub(crate) fn has_unflushed_writes(&self) -> bool {
+        let mut_mem_table = self.get_mut_mem_table();
+        mut_mem_table.len() > 0 || !mut_mem_table.range_tombstones().is_empty()
+    }
+
     pub(crate) fn get_mut_mem_table(&self) -> Arc<M> {
         let guard = self.mut_mem_table.load();
         guard.clone()
@@ -194,7 +322,43 @@ where
         guard.clone()
     }
 
+    /// Delete all the keys in [`key_start`, `key_end`).
+    pub fn delete_range(
+        &self,
+        write_options: &WriteOptions,
+        key_start: &SK,
+        key_end: &SK,
+    ) -> Result<()>
+    where
+        UK: From<SK>,
+    {
+        if self
+            .options
+            .comparator
+            .compare(key_start.raw_user_key(), key_end.raw_user_key())
+            != std::cmp::Ordering::Less
+        {
+            return Ok(());
+        }
+        {
+            let mut wal_guard = self.wal.lock().unwrap();
+            wal_guard.append_range_deletion(write_options, key_start, key_end)?;
+        }
+
+        self.apply_logged_range_deletion(key_start, key_end)?;
+        self.may_freeze();
+        Ok(())
+    }
+
     fn query(&self, key: &SK) -> Result<Option<Value>> {
+        let seq_num = key.seq_num();
+        // the row cache only holds the latest values
+        let row_cache = self
+            .row_cache
+            .as_ref()
+            .filter(|_| seq_num == SequenceNumber::MAX);
+        let row_cache_sequence = row_cache.map(RowCache::sequence);
+
         // query mutable memory table
         {
             let mut_mem = self.get_mut_mem_table();
@@ -202,6 +366,13 @@ where
             if option.is_some() {
                 return Ok(option);
             }
+            if mut_mem.range_tombstones().covers(
+                key.raw_user_key(),
+                seq_num,
+                self.options.comparator.as_ref(),
+            ) {
+                return Ok(Some(Value::default()));
+            }
         }
 
         // query immutable memory table
@@ -211,20 +382,340 @@ where
             if option.is_some() {
                 return Ok(option);
             }
+            if imm_mem.range_tombstones().covers(
+                key.raw_user_key(),
+                seq_num,
+                self.options.comparator.as_ref(),
+            ) {
+                return Ok(Some(Value::default()));
+            }
         }
 
-        // query level0 sstables
-        let option = self.level0_manager.query(key.raw_user_key()).unwrap();
-        if option.is_some() {
-            return Ok(option);
+        // query row cache
+        if let Some(row_cache) = row_cache {
+            let option = row_cache.look_up(key.raw_user_key());
+            if option.is_some() {
+                return Ok(option);
+            }
         }
 
+        // query level0 sstables
+        let mut option = self.level0_manager.query(key.raw_user_key(), seq_num)?;
+
         // query sstables
-        let option = self.leveln_manager.query(key.raw_user_key()).unwrap();
+        if option.is_none() {
+            option = self.leveln_manager.query(key.raw_user_key(), seq_num)?;
+        }
+
+        if let (Some(row_cache), Some(sequence), Some(value)) =
+            (row_cache, row_cache_sequence, &option)
+        {
+            row_cache.insert(key.raw_user_key().clone(), value.clone(), sequence);
+        }
         Ok(option)
     }
 
+    /// Hit and miss counters of the row cache, return `None` if it is disabled.
+    pub fn row_cache_statistics(&self) -> Option<CacheStatistics> {
+        self.row_cache.as_ref().map(RowCache::statistics)
+    }
+
+    /// Hit and miss counters of the block cache, which are shared by the databases
+    /// sharing the cache.
+    pub fn block_cache_statistics(&self) -> CacheStatistics {
+        self.options.block_cache.statistics()
+    }
+
+    /// Properties of all the sstables, keyed by their levels and table ids.
+    pub fn get_properties_of_all_tables(
+        &self,
+    ) -> Result<BTreeMap<(usize, TableID), TableProperties>> {
+        let mut properties = BTreeMap::new();
+        for table in self.all_tables() {
+            properties.insert((table.level(), table.table_id()), table.properties()?);
+        }
+        Ok(properties)
+    }
+
+    /// Largest sequence number recorded in the memory tables and sstables, 0 if none is
+    /// recorded.
+    pub(crate) fn largest_seq_num(&self) -> SequenceNumber {
+        let mem_tables = [self.get_mut_mem_table(), self.get_imm_mem_table()];
+        let in_memory = mem_tables
+            .iter()
+            .filter_map(|table| table.seq_num_range())
+            .map(|(_, largest)| largest);
+        let in_tables = self
+            .all_tables()
+            .into_iter()
+            .map(|table| table.largest_seq_num());
+        in_memory.chain(in_tables).max().unwrap_or(0)
+    }
+
+    /// Take a new sequence number for a write.
+    #[inline]
+    pub(crate) fn take_seq_num(&self) -> SequenceNumber {
+        self.next_seq_num.fetch_add(1, Ordering::Release)
+    }
+
+    /// Sequence number of the next write.
+    #[inline]
+    pub(crate) fn next_seq_num(&self) -> SequenceNumber {
+        self.next_seq_num.load(Ordering::Acquire)
+    }
+
+    /// Publish the sequence number taken by [DBImpl::take_seq_num] once its write is
+    /// inserted or fails. Sequence numbers are published in order, so this waits for the
+    /// writes taking the sequence numbers before `seq_num`.
+    pub(crate) fn publish_seq_num(&self, seq_num: SequenceNumber) {
+        while self.published_seq_num.load(Ordering::Acquire) + 1 != seq_num {
+            std::thread::yield_now();
+        }
+        self.published_seq_num.store(seq_num, Ordering::Release);
+    }
+
+    /// Sequence number of the last published write, the writes at and before it are all
+    /// inserted.
+    #[inline]
+    pub(crate) fn published_seq_num(&self) -> SequenceNumber {
+        self.published_seq_num.load(Ordering::Acquire)
+    }
+
+    /// Wait until the writes at and before `seq_num` are published.
+    pub(crate) fn wait_for_published(&self, seq_num: SequenceNumber) {
+        while self.published_seq_num() < seq_num {
+            std::thread::yield_now();
+        }
+    }
+
+    /// Snapshots whose versions are retained by flushes and compactions.
+    #[inline]
+    pub(crate) fn snapshots(&self) -> &SnapshotList {
+        self.leveln_manager.snapshots()
+    }
+
+    /// Handles of all the sstables, the sstables are kept until the handles are dropped, so
+    /// the files can be read after releasing the locks.
+    fn all_tables(&self) -> Vec<Arc<TableReadHandle>> {
+        let mut tables = vec![];
+        {
+            let guard = self.level0_manager.get_level0_tables_lock().read().unwrap();
+            tables.extend(guard.values().cloned());
+        }
+        for level in 1..=MAX_LEVEL {
+            let guard = self
+                .leveln_manager
+                .get_level_tables_lock(NonZeroUsize::new(level).unwrap())
+                .read()
+                .unwrap();
+            tables.extend(guard.values().cloned());
+        }
+        tables
+    }
+
+    /// Rewrite the blob files in which the values referenced by sstables make up less than
+    /// [Options::blob_gc_live_ratio] of the file size, see [crate::sstable::blob_file].
+    ///
+    /// The sstables referencing these blob files are rewritten with the referenced values,
+    /// except those in level0 or being compacted, whose blob files are collected by later
+    /// calls. Returns the number of blob files collected, or the error reading the blobs or
+    /// writing the sstables, the sstables rewritten before the error are kept.
+    pub fn garbage_collect_blobs(&self) -> Result<usize> {
+        collect_blob_garbage(
+            &self.leveln_manager,
+            self.all_tables(),
+            self.options.blob_gc_live_ratio,
+        )
+    }
+
+    /// Ingest the sstables written by
+    /// [crate::sstable::sst_file_writer::SstFileWriter] at `paths`, which are copied into the
+    /// database, so the keys of them are newer than the keys written before.
+    ///
+    /// The keys of each sstable are validated to be in increasing order, and the sstables
+    /// should neither overlap each other nor reference blob files. All the sstables are
+    /// assigned the same global sequence number taken from the database, which is recorded
+    /// in their properties blocks. All of them are copied into the database before any of
+    /// them is published, so a failed copy publishes none. The ingestion of several
+    /// sstables is not atomic though: if publishing one of them fails, those published
+    /// before stay in the database and the rest are discarded. Each sstable is placed at
+    /// the lowest level where it overlaps no sstable in that level and the levels above,
+    /// or level0 if it overlaps level0 or level1. The memory tables overlapping the
+    /// sstables are flushed before.
+    pub fn ingest_external_files(&self, paths: &[impl AsRef<Path>]) -> Result<()> {
+        let comparator = self.options.comparator.clone();
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path
+                .as_ref()
+                .to_str()
+                .ok_or_else(|| KVLiteError::Custom("invalid sstable path".into()))?
+                .to_string();
+            let handle = Arc::new(TableReadHandle::try_open_file(
+                path.clone(),
+                0,
+                0,
+                &self.options,
+            )?);
+            if !handle.blob_references().is_empty() {
+                return Err(KVLiteError::Custom(format!(
+                    "sstable `{}` references blob files",
+                    path
+                )));
+            }
+            validate_key_order(handle.clone())?;
+            files.push((path, handle));
+        }
+        files.sort_by(|(_, a), (_, b)| comparator.compare(a.min_key(), b.min_key()));
+        for pair in files.windows(2) {
+            if comparator.compare(pair[0].1.max_key(), pair[1].1.min_key())
+                != std::cmp::Ordering::Less
+            {
+                return Err(KVLiteError::Custom(format!(
+                    "sstables `{}` and `{}` overlap",
+                    pair[0].0, pair[1].0
+                )));
+            }
+        }
+
+        let global_seq_num = self.take_seq_num();
+        let result = self.install_external_files(&files, global_seq_num);
+        // the sequence number is published even if the ingestion fails, not to block the
+        // later writes
+        self.publish_seq_num(global_seq_num);
+        result
+    }
+
+    /// Copy the validated sstables of [DBImpl::ingest_external_files] into the database with
+    /// `global_seq_num`, and insert them into the levels.
+    fn install_external_files(
+        &self,
+        files: &[(String, Arc<TableReadHandle>)],
+        global_seq_num: SequenceNumber,
+    ) -> Result<()> {
+        let mut staged_paths: Vec<String> = Vec::with_capacity(files.len());
+        for (i, (path, _)) in files.iter().enumerate() {
+            let staged_path = temp_file_name(&format!("{}/0/ingest{}", self.db_path, i));
+            if let Err(e) = copy_with_global_seq_num(path, &staged_path, global_seq_num) {
+                let _ = std::fs::remove_file(&staged_path);
+                for staged_path in &staged_paths {
+                    let _ = std::fs::remove_file(staged_path);
+                }
+                return Err(e);
+            }
+            staged_paths.push(staged_path);
+        }
+
+        for (_, handle) in files {
+            let (min_key, max_key) = handle.min_max_key();
+            self.flush_overlapping_mem_tables(min_key, max_key);
+        }
+        {
+            // no compaction moves sstables between choosing the level and inserting
+            let _version = self.leveln_manager.lock_version();
+            for (i, ((path, handle), staged_path)) in files.iter().zip(&staged_paths).enumerate() {
+                let (min_key, max_key) = handle.min_max_key();
+                let result = match self.ingestion_level(min_key, max_key) {
+                    0 => self.level0_manager.ingest_file(staged_path),
+                    level => self
+                        .leveln_manager
+                        .ingest_file(NonZeroUsize::new(level).unwrap(), staged_path),
+                };
+                if let Err(e) = result {
+                    // the sstables ingested before stay in the database
+                    for staged_path in &staged_paths[i..] {
+                        let _ = std::fs::remove_file(staged_path);
+                    }
+                    return Err(e);
+                }
+                info!("ingest sstable `{}`", path);
+            }
+        }
+
+        if let Some(row_cache) = &self.row_cache {
+            row_cache.invalidate(std::iter::empty(), true);
+        }
+        Ok(())
+    }
+
+    /// Flush the memory tables having keys in [`min_key`, `max_key`] to level0.
+    fn flush_overlapping_mem_tables(&self, min_key: &[u8], max_key: &[u8]) {
+        let comparator = self.options.comparator.as_ref();
+        let in_range = |key: &[u8]| {
+            comparator.compare(min_key, key) != std::cmp::Ordering::Greater
+                && comparator.compare(key, max_key) != std::cmp::Ordering::Greater
+        };
+        let is_overlapping = |table: &M| {
+            table.kv_iter().any(|(key, _)| in_range(key))
+                || table.range_tombstones().to_vec().iter().any(|tombstone| {
+                    comparator.compare(&tombstone.start, max_key) != std::cmp::Ordering::Greater
+                        && comparator.compare(min_key, &tombstone.end) == std::cmp::Ordering::Less
+                })
+        };
+
+        if is_overlapping(&self.get_mut_mem_table()) {
+            self.wait_for_level0_writer();
+            self.freeze();
+        }
+        let imm_mem_table = self.get_imm_mem_table();
+        if is_overlapping(&imm_mem_table) {
+            self.wait_for_level0_writer();
+            // the flushed immutable table is kept for reads until the next freeze, drop it
+            // so that it does not shadow the ingested keys
+            self.imm_mem_table
+                .compare_and_swap(&imm_mem_table, Arc::new(M::default()));
+        }
+    }
+
+    pub(crate) fn wait_for_level0_writer(&self) {
+        while self
+            .background_task_write_to_level0_is_running
+            .load(Ordering::Acquire)
+        {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Compact the level0 sstables to level1 in the current thread.
+    #[cfg(test)]
+    pub(crate) fn compact_level0(&self) {
+        let (level0_tables, min_key, max_key) =
+            self.level0_manager.assign_level0_tables_to_compact();
+        let level1_tables = self.leveln_manager.get_overlap_tables(
+            NonZeroUsize::new(1).unwrap(),
+            &min_key,
+            &max_key,
+        );
+        crate::compaction::level_0::compact_and_insert(
+            &self.level0_manager,
+            &self.leveln_manager,
+            level0_tables,
+            level1_tables,
+        );
+    }
+
+    /// The lowest level where no sstable in that level and the levels above overlaps
+    /// [`min_key`, `max_key`], level0 if level0 or level1 overlaps.
+    fn ingestion_level(&self, min_key: &[u8], max_key: &[u8]) -> usize {
+        if self.level0_manager.is_overlapping(min_key, max_key) {
+            return 0;
+        }
+        (1..=MAX_LEVEL)
+            .take_while(|&level| {
+                !self.leveln_manager.is_overlapping(
+                    NonZeroUsize::new(level).unwrap(),
+                    min_key,
+                    max_key,
+                )
+            })
+            .last()
+            .unwrap_or(0)
+    }
+
     /// Get an iterator for all the valid key-value pairs in databases.
+    ///
+    /// The iteration stops at the first blob or block of sstables which can't be read, whose
+    /// error is returned by [DBIterator::status].
     pub fn get_db_iterator<const RW_MODE: ReadWriteMode>(&self) -> DBIterator
     where
         M: SkipMapMemTable<RawUserKey, RawUserKey, { RW_MODE }>,
@@ -242,8 +733,46 @@ where
             mut_mem_iterator,
             level0_iterator,
             leveln_iterators,
+            self.options.comparator.clone(),
+            None,
         )
     }
+
+    /// Get an iterator for the valid key-value pairs which have the same prefix as `key`.
+    ///
+    /// The prefix is extracted by [Options::prefix_extractor], sstables whose filter
+    /// does not contain the prefix are skipped.
+    pub fn get_db_prefix_iterator<const RW_MODE: ReadWriteMode>(
+        &self,
+        key: &[u8],
+    ) -> Result<DBIterator>
+    where
+        M: SkipMapMemTable<RawUserKey, RawUserKey, { RW_MODE }>,
+    {
+        let prefix_extractor =
+            self.options.prefix_extractor.clone().ok_or_else(|| {
+                KVLiteError::Custom("prefix extractor is not configured".to_string())
+            })?;
+        let key_prefix = KeyPrefix::from_key(prefix_extractor, key)
+            .ok_or_else(|| KVLiteError::Custom("key is not in prefix domain".to_string()))?;
+
+        let imm_mem = self.get_imm_mem_table();
+        let imm_mem_iterator = MemTableCloneIterator::new(imm_mem);
+
+        let mut_mem = self.get_mut_mem_table();
+        let mut_mem_iterator = MemTableCloneIterator::new(mut_mem);
+
+        let level0_iterator = self.level0_manager.get_level0_prefix_iterator(&key_prefix);
+        let leveln_iterators = self.leveln_manager.get_prefix_iterators(&key_prefix);
+        Ok(DBIterator::new(
+            imm_mem_iterator,
+            mut_mem_iterator,
+            level0_iterator,
+            leveln_iterators,
+            self.options.comparator.clone(),
+            Some(key_prefix),
+        ))
+    }
 }
 
 impl<SK, UK, M, L> Drop for DBImpl<SK, UK, M, L>
@@ -266,14 +795,19 @@ where
 #[cfg(test)]
 pub(crate) mod tests {
     use crate::collections::skip_list::skipmap::ReadWriteMode::{MrMw, MrSw, SrSw};
+    use crate::db::comparator::I32Comparator;
     use crate::db::dbimpl::DBImpl;
     use crate::db::key_types::RawUserKey;
-    use crate::db::options::WriteOptions;
-    use crate::db::{DB, MAX_LEVEL};
+    use crate::db::options::{Options, WriteOptions};
+    use crate::db::slice_transform::{FixedPrefixTransform, KeyPrefix};
+    use crate::db::{Value, DB, MAX_LEVEL};
     use crate::memory::{
         BTreeMemTable, MemTable, MrMwSkipMapMemTable, MrSwSkipMapMemTable, MutexSkipMapMemTable,
     };
+    use crate::sstable::blob_file::BLOB_DIR;
     use crate::sstable::manager::level_n::tests::create_manager;
+    use crate::sstable::sst_file_writer::SstFileWriter;
+    use crate::sstable::table_handle::temp_file_name;
     use crate::wal::simple_wal::SimpleWriteAheadLog;
     use log::info;
     use rand::Rng;
@@ -719,4 +1253,462 @@ pub(crate) mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_row_cache() {
+        let _ = env_logger::try_init();
+        let temp_dir = tempfile::Builder::new()
+            .prefix("row_cache")
+            .tempdir()
+            .unwrap();
+        let path = temp_dir.path();
+        let wo = WriteOptions { sync: false };
+        let key = |i: u64| format!("key{:06}", i).into_bytes();
+        let value = |i: u64, round: u64| format!("value{:06}_{:0100}", i, round).into_bytes();
+        let options = Options {
+            row_cache_capacity: Some(1 << 20),
+            ..Options::default()
+        };
+        let db = DBImpl::<
+            RawUserKey,
+            RawUserKey,
+            MrMwSkipMapMemTable<RawUserKey>,
+            SimpleWriteAheadLog,
+        >::open_with_options(path, options)
+        .unwrap();
+
+        let check = |db: &DBImpl<_, _, _, _>, expected: &dyn Fn(u64) -> Option<Value>| {
+            for _ in 0..2 {
+                for i in (0..NUM_KEYS).step_by(7) {
+                    assert_eq!(db.get(&key(i)).unwrap(), expected(i), "key{:06}", i);
+                }
+            }
+        };
+
+        for round in 0..2 {
+            for i in 0..NUM_KEYS {
+                db.set(&wo, key(i), value(i, round)).unwrap();
+            }
+            std::thread::sleep(Duration::from_secs(1));
+            check(&db, &|i| Some(value(i, round)));
+        }
+        assert!(db.row_cache_statistics().unwrap().hits > 0);
+
+        // flush the overwrites and the range tombstone to sstables
+        db.delete_range(&wo, &key(20000), &key(30000)).unwrap();
+        for i in 0..NUM_KEYS {
+            db.set(&wo, key(i + NUM_KEYS), value(i, 2)).unwrap();
+        }
+        std::thread::sleep(Duration::from_secs(1));
+        check(&db, &|i| {
+            if (20000..30000).contains(&i) {
+                None
+            } else {
+                Some(value(i, 1))
+            }
+        });
+    }
+
+    #[test]
+    fn test_delete_range() {
+        let _ = env_logger::try_init();
+        let temp_dir = tempfile::Builder::new()
+            .prefix("delete_range")
+            .tempdir()
+            .unwrap();
+        let path = temp_dir.path();
+        let wo = WriteOptions { sync: false };
+        let key = |i: u64| format!("key{:06}", i).into_bytes();
+        let value = |i: u64, round: u64| format!("value{:06}_{:0100}", i, round).into_bytes();
+        let is_deleted = |i: u64| (20000..30000).contains(&i) && !(25000..26000).contains(&i);
+
+        let check = |db: &DBImpl<
+            RawUserKey,
+            RawUserKey,
+            MrMwSkipMapMemTable<RawUserKey>,
+            SimpleWriteAheadLog,
+        >| {
+            for i in (0..NUM_KEYS).step_by(7) {
+                let expected = if is_deleted(i) {
+                    None
+                } else if (25000..26000).contains(&i) {
+                    Some(value(i, 1))
+                } else {
+                    Some(value(i, 0))
+                };
+                assert_eq!(db.get(&key(i)).unwrap(), expected, "key{:06}", i);
+            }
+
+            let kvs = db.range_get(&key(19990), &key(30009)).unwrap();
+            let kvs: Vec<_> = kvs.into_iter().filter(|(_, v)| !v.is_empty()).collect();
+            assert_eq!(kvs.len(), 10 + 1000 + 10);
+
+            let count = db.get_db_iterator().filter(|(_, v)| !v.is_empty()).count();
+            assert_eq!(count as u64, NUM_KEYS - 9000);
+        };
+
+        let db = DBImpl::<
+            RawUserKey,
+            RawUserKey,
+            MrMwSkipMapMemTable<RawUserKey>,
+            SimpleWriteAheadLog,
+        >::open(path)
+        .unwrap();
+        for i in 0..NUM_KEYS {
+            db.set(&wo, key(i), value(i, 0)).unwrap();
+        }
+        db.delete_range(&wo, &key(20000), &key(30000)).unwrap();
+        for i in 25000..26000 {
+            db.set(&wo, key(i), value(i, 1)).unwrap();
+        }
+        check(&db);
+
+        // flush the range tombstone to sstables
+        for i in 0..NUM_KEYS / 2 {
+            db.set(&wo, format!("other{}", i).into_bytes(), value(i, 0))
+                .unwrap();
+            db.remove(&wo, format!("other{}", i).into_bytes()).unwrap();
+        }
+        std::thread::sleep(Duration::from_secs(1));
+        let level0_has_range_tombstones = {
+            let guard = db.level0_manager.get_level0_tables_lock().read().unwrap();
+            guard.values().any(|t| !t.range_tombstones().is_empty())
+        };
+        let leveln_has_range_tombstones = (1..=MAX_LEVEL).any(|level| {
+            let lock = db
+                .leveln_manager
+                .get_level_tables_lock(NonZeroUsize::new(level).unwrap());
+            let guard = lock.read().unwrap();
+            guard.values().any(|t| !t.range_tombstones().is_empty())
+        });
+        assert!(level0_has_range_tombstones || leveln_has_range_tombstones);
+        let properties = db.get_properties_of_all_tables().unwrap();
+        assert!(properties.values().any(|p| p.num_range_deletions > 0));
+        assert!(properties.values().any(|p| p.num_deletions > 0));
+        for ((level, _), p) in &properties {
+            assert!(*level <= MAX_LEVEL);
+            assert!(p.num_entries > 0 && p.raw_key_size > 0);
+        }
+        check(&db);
+        drop(db);
+
+        let db = DBImpl::<
+            RawUserKey,
+            RawUserKey,
+            MrMwSkipMapMemTable<RawUserKey>,
+            SimpleWriteAheadLog,
+        >::open(path)
+        .unwrap();
+        check(&db);
+    }
+
+    #[test]
+    fn test_prefix_iterator() {
+        let _ = env_logger::try_init();
+        let temp_dir = tempfile::Builder::new()
+            .prefix("prefix_iterator")
+            .tempdir()
+            .unwrap();
+        let path = temp_dir.path();
+        let wo = WriteOptions { sync: false };
+        const NUM_TENANTS: u64 = 4;
+        const NUM_OBJECTS: u64 = 20000;
+        let key = |tenant: u64, object: u64| format!("t{:03}obj{:06}", tenant, object).into_bytes();
+        let options = Options {
+            prefix_extractor: Some(Arc::new(FixedPrefixTransform::new(4))),
+            ..Options::default()
+        };
+
+        let db = DBImpl::<
+            RawUserKey,
+            RawUserKey,
+            MrMwSkipMapMemTable<RawUserKey>,
+            SimpleWriteAheadLog,
+        >::open_with_options(path, options)
+        .unwrap();
+        for tenant in 0..NUM_TENANTS {
+            for object in 0..NUM_OBJECTS {
+                db.set(
+                    &wo,
+                    key(tenant, object),
+                    format!("{:0100}", object).into_bytes(),
+                )
+                .unwrap();
+            }
+        }
+        db.delete_range(&wo, &key(2, 5000), &key(2, 10000)).unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        for tenant in 0..NUM_TENANTS {
+            let prefix = format!("t{:03}", tenant).into_bytes();
+            let kvs: Vec<_> = db
+                .get_db_prefix_iterator(&key(tenant, 0))
+                .unwrap()
+                .filter(|(_, v)| !v.is_empty())
+                .collect();
+            assert!(kvs.iter().all(|(k, _)| k.starts_with(&prefix)));
+            let expected = if tenant == 2 {
+                NUM_OBJECTS - 5000
+            } else {
+                NUM_OBJECTS
+            };
+            assert_eq!(kvs.len() as u64, expected);
+        }
+
+        // sstables flushed before or after the keys of tenant 0 are written do not contain it.
+        let key_prefix =
+            KeyPrefix::from_key(Arc::new(FixedPrefixTransform::new(4)), &key(0, 0)).unwrap();
+        let mut tables: Vec<_> = {
+            let guard = db.level0_manager.get_level0_tables_lock().read().unwrap();
+            guard.values().cloned().collect()
+        };
+        for level in 1..=MAX_LEVEL {
+            let lock = db
+                .leveln_manager
+                .get_level_tables_lock(NonZeroUsize::new(level).unwrap());
+            let guard = lock.read().unwrap();
+            tables.extend(guard.values().cloned());
+        }
+        assert!(tables.iter().any(|t| !t.may_contain_prefix(&key_prefix)));
+
+        assert!(db.get_db_prefix_iterator(b"t0").is_err());
+        drop(db);
+
+        let result = DBImpl::<
+            RawUserKey,
+            RawUserKey,
+            MrMwSkipMapMemTable<RawUserKey>,
+            SimpleWriteAheadLog,
+        >::open(path);
+        assert!(result.is_err());
+    }
+    #[test]
+    fn test_prefix_range_query() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("prefix_range_query")
+            .tempdir()
+            .unwrap();
+        let path = temp_dir.path();
+        let wo = WriteOptions { sync: false };
+        const NUM_TENANTS: u64 = 4;
+        const NUM_OBJECTS: u64 = 10000;
+        let key = |tenant: u64, object: u64| format!("t{:03}obj{:06}", tenant, object).into_bytes();
+        let options = Options {
+            prefix_extractor: Some(Arc::new(FixedPrefixTransform::new(4))),
+            ..Options::default()
+        };
+
+        let db = DBImpl::<
+            RawUserKey,
+            RawUserKey,
+            MrMwSkipMapMemTable<RawUserKey>,
+            SimpleWriteAheadLog,
+        >::open_with_options(path, options)
+        .unwrap();
+        for tenant in 0..NUM_TENANTS {
+            for object in 0..NUM_OBJECTS {
+                db.set(
+                    &wo,
+                    key(tenant, object),
+                    format!("{:0100}", object).into_bytes(),
+                )
+                .unwrap();
+            }
+        }
+        db.delete_range(&wo, &key(2, 5000), &key(2, 10000)).unwrap();
+        db.flush();
+
+        // range queries in a tenant skip the sstables by prefix filters.
+        for tenant in 0..NUM_TENANTS {
+            let kvs = db
+                .range_get(&key(tenant, 4000), &key(tenant, 11999))
+                .unwrap();
+            let count = kvs.iter().filter(|(_, v)| !v.is_empty()).count();
+            let expected = if tenant == 2 { 1000 } else { 6000 };
+            assert_eq!(count, expected);
+        }
+
+        // range queries across a few tenants check the prefixes of the tenants
+        let kvs = db.range_get(&key(1, 5000), &key(3, 4999)).unwrap();
+        let count = kvs.iter().filter(|(_, v)| !v.is_empty()).count();
+        assert_eq!(count, 15000);
+    }
+
+    #[test]
+    fn test_blob_read_error() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("blob_read_error")
+            .tempdir()
+            .unwrap();
+        let path = temp_dir.path();
+        let wo = WriteOptions { sync: false };
+        let options = Options {
+            min_blob_size: Some(100),
+            ..Options::default()
+        };
+        let db = DBImpl::<
+            RawUserKey,
+            RawUserKey,
+            MrMwSkipMapMemTable<RawUserKey>,
+            SimpleWriteAheadLog,
+        >::open_with_options(path, options)
+        .unwrap();
+        db.set(&wo, b"key".to_vec(), vec![1; 1000]).unwrap();
+        db.flush();
+        // the flushed immutable table is kept for reads until the next flush
+        db.set(&wo, b"other".to_vec(), vec![2; 10]).unwrap();
+        db.flush();
+        assert_eq!(db.get(&b"key".to_vec()).unwrap(), Some(vec![1; 1000]));
+
+        // the value can't be read from the truncated blob file, which may still be open
+        for entry in std::fs::read_dir(path.join(BLOB_DIR)).unwrap() {
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(entry.unwrap().path())
+                .unwrap();
+            file.set_len(0).unwrap();
+        }
+        assert!(db.get(&b"key".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_persisted_options() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("persisted_options")
+            .tempdir()
+            .unwrap();
+        let path = temp_dir.path();
+        type Db =
+            DBImpl<RawUserKey, RawUserKey, MrMwSkipMapMemTable<RawUserKey>, SimpleWriteAheadLog>;
+
+        let db = Db::open(path).unwrap();
+        db.set(
+            &WriteOptions { sync: false },
+            b"key".to_vec(),
+            b"value".to_vec(),
+        )
+        .unwrap();
+        drop(db);
+
+        // a database created before the names are recorded
+        std::fs::remove_file(path.join("COMPARATOR")).unwrap();
+        std::fs::remove_file(path.join("PREFIX_EXTRACTOR")).unwrap();
+        let options = Options {
+            prefix_extractor: Some(Arc::new(FixedPrefixTransform::new(4))),
+            ..Options::default()
+        };
+        assert!(Db::open_with_options(path, options).is_err());
+        let options = Options {
+            comparator: Arc::new(I32Comparator),
+            ..Options::default()
+        };
+        assert!(Db::open_with_options(path, options).is_err());
+
+        let db = Db::open(path).unwrap();
+        assert_eq!(db.get(&b"key".to_vec()).unwrap(), Some(b"value".to_vec()));
+        drop(db);
+        assert!(!path.join("COMPARATOR").exists());
+        assert!(!path.join("PREFIX_EXTRACTOR").exists());
+    }
+
+    #[test]
+    fn test_ingest_external_files() {
+        let _ = env_logger::try_init();
+        let temp_dir = tempfile::Builder::new()
+            .prefix("ingest_external_files")
+            .tempdir()
+            .unwrap();
+        let path = temp_dir.path().join("db");
+        let wo = WriteOptions { sync: false };
+        let key = |i: u64| format!("key{:06}", i).into_bytes();
+        let value = |i: u64, round: u64| format!("value{:06}_{}", i, round).into_bytes();
+        let options = Options::default();
+        let write_sstable = |name: &str, range: std::ops::Range<u64>, round: u64| {
+            let file_path = temp_dir.path().join(name);
+            let mut writer = SstFileWriter::create(&file_path, &options).unwrap();
+            for i in range {
+                if i % 10 == 0 {
+                    writer.delete(key(i)).unwrap();
+                } else {
+                    writer.put(key(i), value(i, round)).unwrap();
+                }
+            }
+            writer.finish().unwrap();
+            file_path
+        };
+        type Db =
+            DBImpl<RawUserKey, RawUserKey, MrMwSkipMapMemTable<RawUserKey>, SimpleWriteAheadLog>;
+
+        let db = Db::open(&path).unwrap();
+        for i in 0..1000 {
+            db.set(&wo, key(i), value(i, 0)).unwrap();
+        }
+
+        let overlapping = write_sstable("overlapping.sst", 500..1500, 1);
+        let disjoint = write_sstable("disjoint.sst", 5000..6000, 1);
+        assert!(db
+            .ingest_external_files(&[&overlapping, &write_sstable("other.sst", 1000..2000, 1)])
+            .is_err());
+        db.ingest_external_files(&[&overlapping, &disjoint])
+            .unwrap();
+
+        let check = |db: &Db, newer: std::ops::Range<u64>| {
+            for i in (0..1500).chain(5000..6000) {
+                let expected = if i < 500 {
+                    Some(value(i, 0))
+                } else if i % 10 == 0 {
+                    None
+                } else if newer.contains(&i) {
+                    Some(value(i, 2))
+                } else {
+                    Some(value(i, 1))
+                };
+                assert_eq!(db.get(&key(i)).unwrap(), expected, "key{:06}", i);
+            }
+        };
+        check(&db, 0..0);
+
+        // the memory table overlapping the sstable is flushed, so the sstable is placed at
+        // level0, and the other one is placed at the last level
+        let properties = db.get_properties_of_all_tables().unwrap();
+        let ingested: Vec<_> = properties
+            .iter()
+            .filter(|(_, p)| p.largest_seq_num > 0)
+            .collect();
+        assert_eq!(ingested.len(), 2);
+        assert_eq!(ingested[0].0 .0, 0);
+        assert_eq!(ingested[1].0 .0, MAX_LEVEL);
+        assert!(ingested
+            .iter()
+            .all(|(_, p)| p.smallest_seq_num == 1 && p.largest_seq_num == 1));
+
+        // placed at the level above the overlapping sstable
+        let newer = write_sstable("newer.sst", 5500..5600, 2);
+        db.ingest_external_files(&[&newer]).unwrap();
+        let properties = db.get_properties_of_all_tables().unwrap();
+        let newer_level = properties
+            .iter()
+            .find(|(_, p)| p.largest_seq_num == 2)
+            .map(|((level, _), _)| *level);
+        assert_eq!(newer_level, Some(MAX_LEVEL - 1));
+        check(&db, 5500..5600);
+        assert_eq!(db.next_seq_num(), 3);
+
+        // all the sstables are staged before publishing, a failed ingestion publishes none
+        let first = write_sstable("first.sst", 7000..7100, 3);
+        let second = write_sstable("second.sst", 8000..8100, 3);
+        let staged_path = |i: usize| path.join("0").join(temp_file_name(&format!("ingest{}", i)));
+        std::fs::write(staged_path(1), b"").unwrap();
+        assert!(db.ingest_external_files(&[&first, &second]).is_err());
+        assert!(!staged_path(0).exists());
+        assert_eq!(db.get(&key(7001)).unwrap(), None);
+        assert_eq!(
+            db.get_properties_of_all_tables().unwrap().len(),
+            properties.len()
+        );
+        drop(db);
+
+        let db = Db::open(&path).unwrap();
+        check(&db, 5500..5600);
+    }
 }
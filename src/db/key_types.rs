@@ -7,6 +7,16 @@ use std::convert::TryInto;
 pub trait DBKey: Ord + Send + Clone + Sync + Default + Into<RawUserKey> + From<RawUserKey> {
     fn raw_user_key(&self) -> &RawUserKey;
     fn mem_size(&self) -> usize;
+
+    /// Create a key with `raw_user_key` and the other fields of `self`.
+    fn with_raw_user_key(&self, raw_user_key: RawUserKey) -> Self;
+
+    /// Sequence number of the reads by this key, which see the versions of at most this
+    /// sequence number. Keys without sequence numbers read the latest versions.
+    #[inline]
+    fn seq_num(&self) -> SequenceNumber {
+        SequenceNumber::MAX
+    }
 }
 
 /// Raw user key stored in disk
@@ -20,6 +30,10 @@ impl DBKey for RawUserKey {
     fn mem_size(&self) -> usize {
         self.len() * std::mem::size_of::<u8>()
     }
+
+    fn with_raw_user_key(&self, raw_user_key: RawUserKey) -> Self {
+        raw_user_key
+    }
 }
 
 impl<K: DBKey> From<SeqNumKey<K>> for RawUserKey {
@@ -75,10 +89,54 @@ impl DBKey for I32UserKey {
     fn mem_size(&self) -> usize {
         4 + 4
     }
+
+    fn with_raw_user_key(&self, raw_user_key: RawUserKey) -> Self {
+        I32UserKey::from(raw_user_key)
+    }
 }
 
 pub type SequenceNumber = u64;
 
+/// Largest sequence number that can be stored in sstables, whose keys pack the sequence
+/// number and the [KeyType] in 8 bytes.
+pub const MAX_SEQUENCE_NUMBER: SequenceNumber = (1 << 56) - 1;
+
+/// Type of the internal keys in sstables.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    Deletion = 0,
+    Value = 1,
+}
+
+impl KeyType {
+    /// Type of the key whose value is `value`, empty values are deletions.
+    #[inline]
+    pub fn of_value(value: &[u8]) -> KeyType {
+        if value.is_empty() {
+            KeyType::Deletion
+        } else {
+            KeyType::Value
+        }
+    }
+}
+
+/// Pack `seq_num` and `key_type` to the trailer of internal keys, `seq << 8 | type`.
+#[inline]
+pub(crate) fn pack_seq_num_and_type(seq_num: SequenceNumber, key_type: KeyType) -> u64 {
+    debug_assert!(seq_num <= MAX_SEQUENCE_NUMBER);
+    seq_num << 8 | key_type as u64
+}
+
+/// Unpack the trailer of internal keys packed by [pack_seq_num_and_type].
+#[inline]
+pub(crate) fn unpack_seq_num_and_type(packed: u64) -> (SequenceNumber, KeyType) {
+    let key_type = match packed & 0xff {
+        0 => KeyType::Deletion,
+        _ => KeyType::Value,
+    };
+    (packed >> 8, key_type)
+}
+
 /// User key with log sequence number(LSN)
 #[derive(PartialEq, Eq, Default, Clone)]
 pub struct SeqNumKey<UK: DBKey> {
@@ -146,4 +204,13 @@ impl<K: DBKey> DBKey for SeqNumKey<K> {
     fn mem_size(&self) -> usize {
         self.user_key.mem_size() + std::mem::size_of::<SequenceNumber>()
     }
+
+    fn with_raw_user_key(&self, raw_user_key: RawUserKey) -> Self {
+        SeqNumKey::new(K::from(raw_user_key), self.seq_num)
+    }
+
+    #[inline]
+    fn seq_num(&self) -> SequenceNumber {
+        self.seq_num
+    }
 }
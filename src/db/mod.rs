@@ -5,10 +5,16 @@ use crate::memory::MemTable;
 use crate::Result;
 use std::path::Path;
 
+pub mod column_family;
+pub mod comparator;
 pub mod db_iter;
 pub mod dbimpl;
 pub mod key_types;
 pub mod options;
+pub mod range_tombstone;
+pub mod row_cache;
+pub mod slice_transform;
+pub mod snapshot;
 pub mod write_batch_db;
 
 pub const WRITE_BUFFER_SIZE: u64 = 4 * 1024 * 1024;
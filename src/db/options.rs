@@ -1,3 +1,254 @@
+use crate::db::comparator::{default_comparator, BytewiseComparator, Comparator};
+use crate::db::slice_transform::SliceTransform;
+use crate::error::KVLiteError;
+use crate::filter::{builtin_filter_policy, default_filter_policy, FilterPolicy};
+use crate::sstable::block_cache::BlockCache;
+use crate::sstable::compression::CompressionType;
+use crate::sstable::DEFAULT_BLOCK_RESTART_INTERVAL;
+use crate::Result;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const COMPARATOR_FILE_NAME: &str = "COMPARATOR";
+const PREFIX_EXTRACTOR_FILE_NAME: &str = "PREFIX_EXTRACTOR";
+
+/// Levels whose sstables pin their filter and index blocks in memory.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum PinningTier {
+    /// Filter and index blocks are only cached in the block cache.
+    #[default]
+    None,
+    /// Pin the filter and index blocks of level 0, which are checked by every point lookup.
+    Level0,
+    /// Pin the filter and index blocks of all levels.
+    All,
+}
+
+impl PinningTier {
+    /// Whether the sstables in `level` pin their filter and index blocks.
+    #[inline]
+    pub fn pins(self, level: usize) -> bool {
+        match self {
+            PinningTier::None => false,
+            PinningTier::Level0 => level == 0,
+            PinningTier::All => true,
+        }
+    }
+}
+
+/// Options to open a database
+#[derive(Clone)]
+pub struct Options {
+    /// Comparator that defines the order of keys in sstables, it must be
+    /// the same as the one used to create the database.
+    ///
+    /// Default: [crate::db::comparator::BytewiseComparator]
+    pub comparator: Arc<dyn Comparator>,
+
+    /// If set, prefixes of keys are inserted into the filter blocks, which are used by
+    /// [crate::db::dbimpl::DBImpl::get_db_prefix_iterator] to skip sstables. It must be
+    /// the same as the one used to create the database.
+    ///
+    /// Default: None
+    pub prefix_extractor: Option<Arc<dyn SliceTransform>>,
+
+    /// Policy to build the filter blocks of sstables. The name of the policy is recorded in
+    /// each sstable, so it can be changed when reopening the database.
+    ///
+    /// Default: [crate::filter::BlockedBloomFilterPolicy]
+    pub filter_policy: Arc<dyn FilterPolicy>,
+
+    /// If not empty, sstables in level `i` are written with `filter_policy_per_level[i]`,
+    /// and the levels beyond it use the last one.
+    ///
+    /// Default: empty, all levels use `filter_policy`
+    pub filter_policy_per_level: Vec<Arc<dyn FilterPolicy>>,
+
+    /// Cache of decompressed data blocks read by point lookups, and of the filter and index
+    /// blocks of sstables, which are inserted with high priority. Databases opened with
+    /// clones of the same `Arc` share the cache.
+    ///
+    /// Default: a new 8MB [BlockCache]
+    pub block_cache: Arc<BlockCache>,
+
+    /// Sstables in these levels hold their filter and index blocks in memory, so point
+    /// lookups never read them again after the block cache evicts them.
+    ///
+    /// Default: [PinningTier::None]
+    pub pin_filter_and_index_blocks: PinningTier,
+
+    /// If set, values found in sstables by point lookups are cached by user key, in a
+    /// [crate::db::row_cache::RowCache] of this capacity in bytes.
+    ///
+    /// Default: None
+    pub row_cache_capacity: Option<usize>,
+
+    /// Number of keys between restart points of data blocks. Keys after a restart point
+    /// only store the suffixes after the prefixes shared with the previous keys, so larger
+    /// intervals save more space but make point lookups scan more keys.
+    ///
+    /// Default: 16
+    pub block_restart_interval: usize,
+
+    /// Codec to compress the data blocks of sstables. The codec is recorded in each block,
+    /// and blocks are stored uncompressed if the compression ratio is poor.
+    ///
+    /// Default: [CompressionType::None]
+    pub compression: CompressionType,
+
+    /// If not empty, data blocks in level `i` are compressed with
+    /// `compression_per_level[i]`, and the levels beyond it use the last one, e.g. none for
+    /// the upper levels and Zstd for the bottom level.
+    ///
+    /// Default: empty, all levels use `compression`
+    pub compression_per_level: Vec<CompressionType>,
+
+    /// If positive, each sstable compressed with Zstd trains a dictionary of at most this
+    /// size in bytes from its values, which helps small values that compress poorly
+    /// block by block. The dictionary is stored in the sstable.
+    ///
+    /// Default: 0, data blocks are compressed without dictionary
+    pub zstd_max_dict_bytes: usize,
+
+    /// Maximum size in bytes of the values sampled to train the Zstd dictionary, data
+    /// blocks are buffered in memory until enough values are sampled.
+    ///
+    /// Default: 1MB
+    pub zstd_max_train_bytes: usize,
+
+    /// If true, the dictionary is trained once per level instead of per sstable: the first
+    /// sstable written to a level after the database is opened trains it, and the later
+    /// sstables of the level reuse it without sampling. Each sstable still stores a copy
+    /// of the dictionary, so it can be read on its own.
+    ///
+    /// Default: false
+    pub zstd_dict_per_level: bool,
+
+    /// If set, values of at least this many bytes are written to blob files when sstables
+    /// are written, and the sstables only store references to them, so compactions do not
+    /// rewrite large values. Blob files are stored in the `blob` directory of the database.
+    ///
+    /// Default: None, values are stored in sstables
+    pub min_blob_size: Option<usize>,
+
+    /// [crate::db::dbimpl::DBImpl::garbage_collect_blobs] rewrites the blob files in which
+    /// the values referenced by sstables make up less than this ratio of the file size.
+    ///
+    /// Default: 0.5
+    pub blob_gc_live_ratio: f64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            comparator: default_comparator(),
+            prefix_extractor: None,
+            filter_policy: default_filter_policy(),
+            filter_policy_per_level: vec![],
+            block_cache: Arc::new(BlockCache::default()),
+            pin_filter_and_index_blocks: PinningTier::default(),
+            row_cache_capacity: None,
+            block_restart_interval: DEFAULT_BLOCK_RESTART_INTERVAL,
+            compression: CompressionType::default(),
+            compression_per_level: vec![],
+            zstd_max_dict_bytes: 0,
+            zstd_max_train_bytes: 1 << 20,
+            zstd_dict_per_level: false,
+            min_blob_size: None,
+            blob_gc_live_ratio: 0.5,
+        }
+    }
+}
+
+impl Options {
+    /// Get the filter policy to write sstables in `level`.
+    pub fn level_filter_policy(&self, level: usize) -> &Arc<dyn FilterPolicy> {
+        match self.filter_policy_per_level.last() {
+            Some(last) => self.filter_policy_per_level.get(level).unwrap_or(last),
+            None => &self.filter_policy,
+        }
+    }
+
+    /// Get the compression type of data blocks written to `level`.
+    pub fn level_compression(&self, level: usize) -> CompressionType {
+        match self.compression_per_level.last() {
+            Some(last) => *self.compression_per_level.get(level).unwrap_or(last),
+            None => self.compression,
+        }
+    }
+
+    /// Find the filter policy named `name` to read sstables, the configured policies are
+    /// searched before the built-in ones.
+    pub(crate) fn find_filter_policy(&self, name: &str) -> Option<Arc<dyn FilterPolicy>> {
+        std::iter::once(&self.filter_policy)
+            .chain(self.filter_policy_per_level.iter())
+            .find(|filter_policy| filter_policy.name() == name)
+            .cloned()
+            .or_else(|| builtin_filter_policy(name))
+    }
+
+    /// Record the names of the comparator and the prefix extractor when creating a new
+    /// database, or check they are the same as the recorded ones in an existing database.
+    ///
+    /// The database is new if `db_path` is absent or empty, so this is called before any
+    /// file of the database is created. Existing databases without recorded names were
+    /// created with [BytewiseComparator] and no prefix extractor.
+    pub(crate) fn check_persisted(&self, db_path: &str) -> Result<()> {
+        let create =
+            std::fs::read_dir(db_path).map_or(true, |mut entries| entries.next().is_none());
+        std::fs::create_dir_all(db_path)?;
+        check_name(
+            db_path,
+            create,
+            COMPARATOR_FILE_NAME,
+            ("comparator", BytewiseComparator.name()),
+            self.comparator.name(),
+        )?;
+        check_name(
+            db_path,
+            create,
+            PREFIX_EXTRACTOR_FILE_NAME,
+            ("prefix extractor", "none"),
+            self.prefix_extractor
+                .as_ref()
+                .map_or("none", |prefix_extractor| prefix_extractor.name()),
+        )
+    }
+}
+
+/// Write `name` of `option` to a new database, or compare it with the name recorded in an
+/// existing database, which is `legacy_name` if no name is recorded.
+fn check_name(
+    db_path: &str,
+    create: bool,
+    file_name: &str,
+    (option, legacy_name): (&str, &str),
+    name: &str,
+) -> Result<()> {
+    let path = Path::new(db_path).join(file_name);
+    if create {
+        let mut file = File::create(&path)?;
+        file.write_all(name.as_bytes())?;
+        file.sync_all()?;
+        return Ok(());
+    }
+    let mut persisted_name = String::new();
+    if path.exists() {
+        File::open(&path)?.read_to_string(&mut persisted_name)?;
+    } else {
+        persisted_name.push_str(legacy_name);
+    }
+    if persisted_name != name {
+        return Err(KVLiteError::Custom(format!(
+            "{} mismatch: database is created with {}, but opened with {}",
+            option, persisted_name, name
+        )));
+    }
+    Ok(())
+}
+
 /// Options that control write operations
 pub struct WriteOptions {
     /// If true, the write will be flushed from the operating system
@@ -16,3 +267,18 @@ pub struct WriteOptions {
     /// system call followed by "fsync()".
     pub sync: bool,
 }
+
+/// Limits of a write batch, writes exceeding them fail with
+/// [KVLiteError::WriteBatchFull] and leave the batch unchanged.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct WriteBatchOptions {
+    /// Maximum memory usage in bytes of the keys and values in the batch, 0 means no limit.
+    ///
+    /// Default: 0
+    pub max_bytes: u64,
+
+    /// Maximum number of keys in the batch, 0 means no limit.
+    ///
+    /// Default: 0
+    pub max_count: usize,
+}
@@ -0,0 +1,224 @@
+use crate::collections::skip_list::skipmap::SrSwSkipMap;
+use crate::db::comparator::Comparator;
+use crate::db::key_types::{DBKey, RawUserKey, SequenceNumber};
+use crate::db::Value;
+use std::cmp::Ordering;
+use std::sync::RwLock;
+
+/// A range tombstone deletes the versions of the keys in [`start`, `end`) older than
+/// `seq_num`, and is only visible to the readers at `seq_num` or later.
+///
+/// Range tombstones of a memory table or a sstable only delete the keys in
+/// older memory tables or sstables. Keys in the same table are hidden by
+/// deletions at the sequence numbers of the range tombstones instead, which
+/// the memory table writes for its own keys in the range when the range
+/// deletion is written, and compactions write for the older versions kept
+/// for snapshots. Range tombstones written by keys without sequence numbers
+/// are at [SequenceNumber::MAX].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: RawUserKey,
+    pub end: RawUserKey,
+    pub seq_num: SequenceNumber,
+}
+
+impl RangeTombstone {
+    pub fn new(start: RawUserKey, end: RawUserKey, seq_num: SequenceNumber) -> RangeTombstone {
+        RangeTombstone {
+            start,
+            end,
+            seq_num,
+        }
+    }
+
+    /// Whether the tombstone is visible to the readers at `seq_num`.
+    #[inline]
+    pub fn is_visible(&self, seq_num: SequenceNumber) -> bool {
+        self.seq_num <= seq_num
+    }
+
+    #[inline]
+    pub fn covers(&self, key: &[u8], comparator: &dyn Comparator) -> bool {
+        comparator.compare(&self.start, key) != Ordering::Greater
+            && comparator.compare(key, &self.end) == Ordering::Less
+    }
+
+    /// Whether all the keys in [`min_key`, `max_key`] are deleted.
+    pub fn covers_range(
+        &self,
+        min_key: &[u8],
+        max_key: &[u8],
+        comparator: &dyn Comparator,
+    ) -> bool {
+        comparator.compare(&self.start, min_key) != Ordering::Greater
+            && comparator.compare(max_key, &self.end) == Ordering::Less
+    }
+
+    /// Whether the versions with sequence numbers in [`smallest_seq_num`, `largest_seq_num`]
+    /// are older than the tombstone and read by none of `snapshots`, which are in ascending
+    /// order, so that they can be dropped if they are covered.
+    ///
+    /// Tombstones at [SequenceNumber::MAX] have no sequence numbers, they drop all the
+    /// versions regardless of the snapshots.
+    pub fn drops(
+        &self,
+        smallest_seq_num: SequenceNumber,
+        largest_seq_num: SequenceNumber,
+        snapshots: &[SequenceNumber],
+    ) -> bool {
+        if largest_seq_num >= self.seq_num {
+            return false;
+        }
+        if self.seq_num == SequenceNumber::MAX {
+            return true;
+        }
+        let i = snapshots.partition_point(|snapshot| *snapshot < smallest_seq_num);
+        !matches!(snapshots.get(i), Some(snapshot) if *snapshot < self.seq_num)
+    }
+
+    /// Get a key which is greater or equal to all the keys covered by the tombstone.
+    ///
+    /// `end` is exclusive, so the largest covered key is the predecessor of `end` if it is known.
+    pub fn max_covered_key(&self, comparator: &dyn Comparator) -> RawUserKey {
+        comparator
+            .predecessor(&self.end)
+            .unwrap_or_else(|| self.end.clone())
+    }
+
+    /// Truncate the tombstone to [`lower`, `upper`), `None` means unbounded.
+    /// Return `None` if nothing left.
+    pub fn truncate(
+        &self,
+        lower: Option<&RawUserKey>,
+        upper: Option<&RawUserKey>,
+        comparator: &dyn Comparator,
+    ) -> Option<RangeTombstone> {
+        let start = match lower {
+            Some(lower) if comparator.compare(lower, &self.start) == Ordering::Greater => lower,
+            _ => &self.start,
+        };
+        let end = match upper {
+            Some(upper) if comparator.compare(upper, &self.end) == Ordering::Less => upper,
+            _ => &self.end,
+        };
+        if comparator.compare(start, end) == Ordering::Less {
+            Some(RangeTombstone::new(
+                start.clone(),
+                end.clone(),
+                self.seq_num,
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `key` is covered by the tombstones visible to the readers at `seq_num`.
+pub fn is_covered(
+    range_tombstones: &[RangeTombstone],
+    key: &[u8],
+    seq_num: SequenceNumber,
+    comparator: &dyn Comparator,
+) -> bool {
+    range_tombstones
+        .iter()
+        .any(|t| t.is_visible(seq_num) && t.covers(key, comparator))
+}
+
+/// Remove the keys in `kvs` covered by the tombstones visible to the readers at `seq_num`,
+/// the key-values in `kvs` are read from older tables.
+pub fn remove_covered_keys<UK: DBKey>(
+    range_tombstones: &[RangeTombstone],
+    seq_num: SequenceNumber,
+    kvs: &mut SrSwSkipMap<UK, Value>,
+    comparator: &dyn Comparator,
+) {
+    if !range_tombstones.iter().any(|t| t.is_visible(seq_num)) {
+        return;
+    }
+    let covered: Vec<UK> = kvs
+        .iter()
+        .filter(|(key, _)| is_covered(range_tombstones, key.raw_user_key(), seq_num, comparator))
+        .map(|(key, _)| key.clone())
+        .collect();
+    for key in covered {
+        kvs.remove(key);
+    }
+}
+
+/// Range tombstones in a memory table.
+#[derive(Default)]
+pub struct RangeTombstones {
+    inner: RwLock<Vec<RangeTombstone>>,
+}
+
+impl RangeTombstones {
+    pub fn add(&self, range_tombstone: RangeTombstone) {
+        let mut guard = self.inner.write().unwrap();
+        guard.push(range_tombstone);
+    }
+
+    /// Whether `key` is covered by the tombstones visible to the readers at `seq_num`.
+    pub fn covers(&self, key: &[u8], seq_num: SequenceNumber, comparator: &dyn Comparator) -> bool {
+        let guard = self.inner.read().unwrap();
+        is_covered(&guard, key, seq_num, comparator)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let guard = self.inner.read().unwrap();
+        guard.is_empty()
+    }
+
+    pub fn to_vec(&self) -> Vec<RangeTombstone> {
+        let guard = self.inner.read().unwrap();
+        guard.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::comparator::{BytewiseComparator, Comparator, I32Comparator};
+    use crate::db::key_types::SequenceNumber;
+    use crate::db::range_tombstone::RangeTombstone;
+
+    #[test]
+    fn test_range_tombstone() {
+        let c = &BytewiseComparator;
+        let t = RangeTombstone::new("key10".into(), "key20".into(), 10);
+        assert!(t.covers(b"key10", c));
+        assert!(t.covers(b"key15", c));
+        assert!(!t.covers(b"key20", c));
+        assert!(!t.covers(b"key0", c));
+        assert!(t.covers_range(b"key11", b"key19", c));
+        assert!(!t.covers_range(b"key11", b"key20", c));
+
+        let upper = c.successor(b"key12").unwrap();
+        let truncated = t.truncate(None, Some(&upper), c).unwrap();
+        assert_eq!(truncated.max_covered_key(c), b"key12".to_vec());
+        let truncated = t.truncate(Some(&upper), None, c).unwrap();
+        assert_eq!(truncated.start, upper);
+        assert_eq!(truncated.end, Vec::from("key20"));
+        assert_eq!(truncated.seq_num, 10);
+        assert!(t.truncate(Some(&Vec::from("key3")), None, c).is_none());
+
+        assert!(t.is_visible(10));
+        assert!(!t.is_visible(9));
+        assert!(t.drops(1, 9, &[]));
+        assert!(!t.drops(1, 10, &[]));
+        assert!(t.drops(5, 9, &[3, 10]));
+        assert!(!t.drops(5, 9, &[3, 7, 10]));
+        assert!(!t.drops(5, 9, &[5]));
+        let t = RangeTombstone::new("key10".into(), "key20".into(), SequenceNumber::MAX);
+        assert!(t.drops(5, 9, &[7]));
+
+        let c = &I32Comparator;
+        let t = RangeTombstone::new(
+            1i32.to_le_bytes().into(),
+            300i32.to_le_bytes().into(),
+            SequenceNumber::MAX,
+        );
+        assert!(t.covers(&256i32.to_le_bytes(), c));
+        assert!(!t.covers(&(-1i32).to_le_bytes(), c));
+        assert_eq!(t.max_covered_key(c), 299i32.to_le_bytes().to_vec());
+    }
+}
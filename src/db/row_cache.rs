@@ -0,0 +1,138 @@
+//! Cache of the values found in sstables by point lookups.
+
+use crate::cache::{CachePolicy, CachePriority, CacheStatistics, ShardLRUCache};
+use crate::db::key_types::{RawUserKey, SequenceNumber};
+use crate::db::Value;
+use crate::hash::murmur_hash;
+use std::sync::Mutex;
+
+const ROW_CACHE_SEED: u32 = 0x3d5c81a9;
+
+struct RowCacheEntry {
+    value: Value,
+    /// Invalidation sequence when the lookup of the value started.
+    sequence: SequenceNumber,
+}
+
+struct InvalidationState {
+    /// Increased by every invalidation.
+    sequence: SequenceNumber,
+    /// Entries inserted before this sequence are invalid.
+    valid_since: SequenceNumber,
+}
+
+/// Values of sstables keyed by user key, whose capacity is the total size of keys and
+/// values in bytes.
+///
+/// Newer values of a key only enter sstables when a memory table is flushed, so the keys
+/// of an immutable memory table are erased before it is dropped, and all the entries are
+/// invalidated if it has range tombstones. A value is only inserted if no invalidation
+/// happens after its lookup starts.
+pub struct RowCache {
+    cache: ShardLRUCache<RawUserKey, RowCacheEntry>,
+    state: Mutex<InvalidationState>,
+}
+
+impl RowCache {
+    pub fn new(capacity: usize) -> RowCache {
+        RowCache {
+            cache: ShardLRUCache::with_policy(capacity, CachePolicy::Lru),
+            state: Mutex::new(InvalidationState {
+                sequence: 0,
+                valid_since: 0,
+            }),
+        }
+    }
+
+    /// Get the invalidation sequence, which should be called before looking up memory
+    /// tables and passed to [RowCache::insert].
+    pub(crate) fn sequence(&self) -> SequenceNumber {
+        self.state.lock().unwrap().sequence
+    }
+
+    pub(crate) fn look_up(&self, key: &RawUserKey) -> Option<Value> {
+        let valid_since = self.state.lock().unwrap().valid_since;
+        let entry_tracker = self.cache.look_up(key, murmur_hash(key, ROW_CACHE_SEED));
+        entry_tracker
+            .value()
+            .filter(|entry| entry.sequence >= valid_since)
+            .map(|entry| entry.value.clone())
+    }
+
+    /// Insert the value found in sstables unless the cache is invalidated after `sequence`.
+    pub(crate) fn insert(&self, key: RawUserKey, value: Value, sequence: SequenceNumber) {
+        let state = self.state.lock().unwrap();
+        if state.sequence != sequence {
+            return;
+        }
+        let h = murmur_hash(&key, ROW_CACHE_SEED);
+        // replace the invalid entry
+        self.cache.erase(&key, h);
+        let charge = key.len() + value.len() + std::mem::size_of::<RowCacheEntry>();
+        self.cache.insert_with_charge(
+            key,
+            RowCacheEntry { value, sequence },
+            charge,
+            CachePriority::Low,
+            h,
+        );
+    }
+
+    /// Erase `keys` whose newer values are written to sstables, or invalidate all the
+    /// entries if `all` is true.
+    pub(crate) fn invalidate<'a>(&self, keys: impl Iterator<Item = &'a RawUserKey>, all: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.sequence += 1;
+        if all {
+            state.valid_since = state.sequence;
+        } else {
+            for key in keys {
+                self.cache.erase(key, murmur_hash(key, ROW_CACHE_SEED));
+            }
+        }
+    }
+
+    /// Total size of the cached keys and values in bytes.
+    pub fn usage(&self) -> usize {
+        self.cache.usage()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cache.capacity()
+    }
+
+    pub fn statistics(&self) -> CacheStatistics {
+        self.cache.statistics()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::row_cache::RowCache;
+
+    #[test]
+    fn test_invalidate() {
+        let row_cache = RowCache::new(1 << 20);
+        let sequence = row_cache.sequence();
+        row_cache.insert(b"k1".to_vec(), b"v1".to_vec(), sequence);
+        row_cache.insert(b"k2".to_vec(), b"v2".to_vec(), sequence);
+        assert_eq!(row_cache.look_up(&b"k1".to_vec()), Some(b"v1".to_vec()));
+
+        // the lookup of k3 starts before k3 is invalidated
+        let stale_sequence = row_cache.sequence();
+        row_cache.invalidate([b"k1".to_vec(), b"k3".to_vec()].iter(), false);
+        row_cache.insert(b"k3".to_vec(), b"v3".to_vec(), stale_sequence);
+        assert!(row_cache.look_up(&b"k1".to_vec()).is_none());
+        assert_eq!(row_cache.look_up(&b"k2".to_vec()), Some(b"v2".to_vec()));
+        assert!(row_cache.look_up(&b"k3".to_vec()).is_none());
+
+        row_cache.invalidate(std::iter::empty(), true);
+        assert!(row_cache.look_up(&b"k2".to_vec()).is_none());
+        let sequence = row_cache.sequence();
+        row_cache.insert(b"k2".to_vec(), b"v2'".to_vec(), sequence);
+        assert_eq!(row_cache.look_up(&b"k2".to_vec()), Some(b"v2'".to_vec()));
+
+        let statistics = row_cache.statistics();
+        assert!(statistics.hits > 0 && statistics.misses > 0);
+    }
+}
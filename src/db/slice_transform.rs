@@ -0,0 +1,261 @@
+use crate::db::key_types::RawUserKey;
+use crate::filter::SEED;
+use crate::hash::murmur_hash;
+use std::sync::Arc;
+
+/// Extract the prefix of raw user keys.
+///
+/// If a prefix extractor is configured, the prefixes of keys are also inserted into the
+/// filter block of sstables, so that sstables without the prefix are skipped by
+/// prefix iterators and range queries.
+///
+/// Keys with the same prefix must be contiguous in the order of the comparator, or range
+/// queries may miss keys.
+pub trait SliceTransform: Send + Sync {
+    /// Name of the prefix extractor, which is persisted in the database directory.
+    fn name(&self) -> &str;
+
+    /// Get the prefix of `key`, `key` must be in domain.
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8];
+
+    /// Whether `key` has a prefix.
+    fn in_domain(&self, key: &[u8]) -> bool;
+
+    /// Get the prefixes of the keys in domain in [`key_start`, `key_end`] in order, return
+    /// `None` if they are unknown or more than `limit`. Both keys must be in domain.
+    fn prefixes_in_range(
+        &self,
+        _key_start: &[u8],
+        _key_end: &[u8],
+        _limit: usize,
+    ) -> Option<Vec<RawUserKey>> {
+        None
+    }
+}
+
+/// Use the first `prefix_len` bytes as the prefix,
+/// keys shorter than `prefix_len` are not in domain.
+///
+/// The prefixes in a range are enumerated in bytewise order, which requires keys ordered by
+/// [crate::db::comparator::BytewiseComparator].
+pub struct FixedPrefixTransform {
+    prefix_len: usize,
+    name: String,
+}
+
+impl FixedPrefixTransform {
+    pub fn new(prefix_len: usize) -> FixedPrefixTransform {
+        FixedPrefixTransform {
+            prefix_len,
+            name: format!("kvlite.FixedPrefix.{}", prefix_len),
+        }
+    }
+}
+
+impl SliceTransform for FixedPrefixTransform {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        debug_assert!(self.in_domain(key));
+        &key[..self.prefix_len]
+    }
+
+    fn in_domain(&self, key: &[u8]) -> bool {
+        key.len() >= self.prefix_len
+    }
+
+    fn prefixes_in_range(
+        &self,
+        key_start: &[u8],
+        key_end: &[u8],
+        limit: usize,
+    ) -> Option<Vec<RawUserKey>> {
+        let mut prefix = self.transform(key_start).to_vec();
+        let last = self.transform(key_end);
+        let mut prefixes = vec![];
+        while prefix.as_slice() <= last {
+            if prefixes.len() == limit {
+                return None;
+            }
+            prefixes.push(prefix.clone());
+            // the next prefix of the same length
+            match prefix.iter().rposition(|b| *b != u8::MAX) {
+                Some(i) => {
+                    prefix[i] += 1;
+                    prefix[i + 1..].fill(0);
+                }
+                None => break,
+            }
+        }
+        Some(prefixes)
+    }
+}
+
+/// Maximum number of the prefixes checked by a range query across several prefixes.
+const MAX_RANGE_PREFIXES: usize = 16;
+
+/// Hash inserted into the filter blocks since format version 9 if any key is not in the
+/// domain of the prefix extractor.
+pub(crate) fn out_of_domain_hash() -> u32 {
+    prefix_hash(b"kvlite.OutOfDomain")
+}
+
+#[inline]
+pub(crate) fn prefix_hash(prefix: &[u8]) -> u32 {
+    murmur_hash(prefix, SEED)
+}
+
+/// Keys whose prefix extracted by `prefix_extractor` is `prefix`.
+#[derive(Clone)]
+pub struct KeyPrefix {
+    prefix_extractor: Arc<dyn SliceTransform>,
+    prefix: RawUserKey,
+    hash: u32,
+}
+
+impl KeyPrefix {
+    /// Return `None` if `key` is not in the domain of `prefix_extractor`.
+    pub fn from_key(prefix_extractor: Arc<dyn SliceTransform>, key: &[u8]) -> Option<KeyPrefix> {
+        if prefix_extractor.in_domain(key) {
+            let prefix = prefix_extractor.transform(key).to_vec();
+            Some(KeyPrefix::new(prefix_extractor, prefix))
+        } else {
+            None
+        }
+    }
+
+    fn new(prefix_extractor: Arc<dyn SliceTransform>, prefix: RawUserKey) -> KeyPrefix {
+        let hash = prefix_hash(&prefix);
+        KeyPrefix {
+            prefix_extractor,
+            prefix,
+            hash,
+        }
+    }
+
+    /// Get the common prefix of all the keys in [`key_start`, `key_end`], return `None` if
+    /// `key_start` and `key_end` have different prefixes.
+    pub fn from_range(
+        prefix_extractor: Arc<dyn SliceTransform>,
+        key_start: &[u8],
+        key_end: &[u8],
+    ) -> Option<KeyPrefix> {
+        let key_prefix = Self::from_key(prefix_extractor, key_start)?;
+        if key_prefix.matches(key_end) {
+            Some(key_prefix)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn matches(&self, key: &[u8]) -> bool {
+        self.prefix_extractor.in_domain(key) && self.prefix_extractor.transform(key) == self.prefix
+    }
+
+    #[inline]
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// Hash of the prefix in filter blocks.
+    #[inline]
+    pub fn hash(&self) -> u32 {
+        self.hash
+    }
+}
+
+/// Prefixes of the keys in a range, which are used to skip sstables by the prefix filters.
+pub enum RangePrefix {
+    /// All the keys in the range have the prefix.
+    Single(KeyPrefix),
+    /// The keys in domain in the range have one of the prefixes, and the range may also have
+    /// keys not in domain.
+    Multiple(Vec<KeyPrefix>),
+}
+
+impl RangePrefix {
+    /// Return `None` if the prefixes of the keys in [`key_start`, `key_end`] are unknown or
+    /// too many.
+    pub fn from_range(
+        prefix_extractor: Arc<dyn SliceTransform>,
+        key_start: &[u8],
+        key_end: &[u8],
+    ) -> Option<RangePrefix> {
+        if let Some(key_prefix) =
+            KeyPrefix::from_range(prefix_extractor.clone(), key_start, key_end)
+        {
+            return Some(RangePrefix::Single(key_prefix));
+        }
+        if !prefix_extractor.in_domain(key_start) || !prefix_extractor.in_domain(key_end) {
+            return None;
+        }
+        let prefixes =
+            prefix_extractor.prefixes_in_range(key_start, key_end, MAX_RANGE_PREFIXES)?;
+        Some(RangePrefix::Multiple(
+            prefixes
+                .into_iter()
+                .map(|prefix| KeyPrefix::new(prefix_extractor.clone(), prefix))
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::slice_transform::{
+        FixedPrefixTransform, KeyPrefix, RangePrefix, SliceTransform,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_fixed_prefix() {
+        let transform = Arc::new(FixedPrefixTransform::new(4));
+        assert!(!transform.in_domain(b"ten"));
+        assert_eq!(transform.transform(b"ten1obj1"), b"ten1");
+
+        let key_prefix = KeyPrefix::from_key(transform.clone(), b"ten1obj1").unwrap();
+        assert_eq!(key_prefix.prefix(), b"ten1");
+        assert!(key_prefix.matches(b"ten1obj2"));
+        assert!(!key_prefix.matches(b"ten2obj1"));
+        assert!(!key_prefix.matches(b"ten"));
+        assert!(KeyPrefix::from_key(transform.clone(), b"ten").is_none());
+
+        let key_prefix =
+            KeyPrefix::from_range(transform.clone(), b"ten1obj1", b"ten1obj9").unwrap();
+        assert_eq!(key_prefix.prefix(), b"ten1");
+        assert!(KeyPrefix::from_range(transform.clone(), b"ten1obj1", b"ten2obj1").is_none());
+
+        let prefixes = transform
+            .prefixes_in_range(b"ten8obj1", b"tenAobj1", 16)
+            .unwrap();
+        assert_eq!(prefixes.len(), (b'A' - b'8' + 1) as usize);
+        assert_eq!(prefixes[1], b"ten9");
+        assert!(transform
+            .prefixes_in_range(b"ten1obj1", b"teo1obj1", 16)
+            .is_none());
+        let prefixes = transform
+            .prefixes_in_range(b"te\xff\xffobj", b"tf\0\x01obj", 16)
+            .unwrap();
+        assert_eq!(
+            prefixes,
+            vec![
+                b"te\xff\xff".to_vec(),
+                b"tf\0\0".to_vec(),
+                b"tf\0\x01".to_vec()
+            ]
+        );
+
+        match RangePrefix::from_range(transform.clone(), b"ten1obj1", b"ten3obj1") {
+            Some(RangePrefix::Multiple(key_prefixes)) => assert_eq!(key_prefixes.len(), 3),
+            _ => panic!("expect multiple prefixes"),
+        }
+        assert!(matches!(
+            RangePrefix::from_range(transform.clone(), b"ten1obj1", b"ten1obj9"),
+            Some(RangePrefix::Single(_))
+        ));
+        assert!(RangePrefix::from_range(transform, b"ten", b"ten3obj1").is_none());
+    }
+}
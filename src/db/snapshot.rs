@@ -0,0 +1,119 @@
+//! Snapshots of the sequence numbers read by long-lived readers.
+//!
+//! SSTables keep several versions of a key, flushes and compactions only drop the versions
+//! that neither the latest reads nor the alive snapshots can read.
+
+use crate::db::key_types::SequenceNumber;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Sequence numbers read by the alive snapshots, and the number of snapshots at each one.
+#[derive(Default)]
+pub(crate) struct SnapshotList {
+    seq_nums: Mutex<BTreeMap<SequenceNumber, usize>>,
+}
+
+impl SnapshotList {
+    /// Register a snapshot reading the versions of at most `seq_num`.
+    pub(crate) fn acquire(&self, seq_num: SequenceNumber) {
+        *self.seq_nums.lock().unwrap().entry(seq_num).or_default() += 1;
+    }
+
+    /// Release a snapshot registered by [SnapshotList::acquire].
+    pub(crate) fn release(&self, seq_num: SequenceNumber) {
+        let mut seq_nums = self.seq_nums.lock().unwrap();
+        let count = seq_nums
+            .get_mut(&seq_num)
+            .expect("release an unregistered snapshot");
+        *count -= 1;
+        if *count == 0 {
+            seq_nums.remove(&seq_num);
+        }
+    }
+
+    /// Sequence numbers of the alive snapshots in ascending order.
+    pub(crate) fn to_vec(&self) -> Vec<SequenceNumber> {
+        self.seq_nums.lock().unwrap().keys().copied().collect()
+    }
+}
+
+/// Filter of the versions of keys, which retains the newest version and the newest one
+/// visible to each snapshot.
+///
+/// Snapshots split the sequence numbers into stripes, the versions in the same stripe are
+/// read by the same snapshots, so only the newest one of each stripe is retained. Without
+/// snapshots, only the newest version of each key is retained.
+pub(crate) struct VersionFilter {
+    /// Sequence numbers of the snapshots in ascending order.
+    snapshots: Vec<SequenceNumber>,
+    last_key: Option<Vec<u8>>,
+    /// Stripe of the last retained version of `last_key`.
+    last_stripe: usize,
+}
+
+impl VersionFilter {
+    pub(crate) fn new(snapshots: Vec<SequenceNumber>) -> VersionFilter {
+        debug_assert!(snapshots.windows(2).all(|w| w[0] < w[1]));
+        VersionFilter {
+            snapshots,
+            last_key: None,
+            last_stripe: 0,
+        }
+    }
+
+    /// Whether the version of `key` at `seq_num` is retained. Versions should be filtered
+    /// in the order of keys, and the versions of a key in descending order of sequence
+    /// number.
+    pub(crate) fn retain(&mut self, key: &[u8], seq_num: SequenceNumber) -> bool {
+        let stripe = self
+            .snapshots
+            .partition_point(|snapshot| *snapshot < seq_num);
+        if self.last_key.as_deref() != Some(key) {
+            self.last_key = Some(key.to_vec());
+            self.last_stripe = stripe;
+            return true;
+        }
+        if stripe < self.last_stripe {
+            self.last_stripe = stripe;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::snapshot::{SnapshotList, VersionFilter};
+
+    #[test]
+    fn test_version_filter() {
+        let snapshots = SnapshotList::default();
+        snapshots.acquire(10);
+        snapshots.acquire(5);
+        snapshots.acquire(10);
+        snapshots.release(10);
+        assert_eq!(snapshots.to_vec(), vec![5, 10]);
+
+        let mut filter = VersionFilter::new(snapshots.to_vec());
+        let versions = vec![
+            (b"a", 12, true),
+            (b"a", 11, false),
+            (b"a", 10, true),
+            (b"a", 7, false),
+            (b"a", 3, true),
+            (b"a", 1, false),
+            (b"b", 4, true),
+            (b"b", 2, false),
+        ];
+        for (key, seq_num, retained) in versions {
+            assert_eq!(filter.retain(key, seq_num), retained, "{}", seq_num);
+        }
+
+        snapshots.release(5);
+        snapshots.release(10);
+        let mut filter = VersionFilter::new(snapshots.to_vec());
+        assert!(filter.retain(b"a", 12));
+        assert!(!filter.retain(b"a", 3));
+    }
+}
@@ -1,13 +1,13 @@
 use crate::collections::skip_list::skipmap::SrSwSkipMap;
 use crate::db::dbimpl::DBImpl;
 use crate::db::key_types::{DBKey, SeqNumKey, SequenceNumber};
-use crate::db::options::WriteOptions;
+use crate::db::options::{Options, WriteBatchOptions, WriteOptions};
 use crate::db::{Value, DB};
+use crate::error::KVLiteError;
 use crate::memory::MemTable;
 use crate::wal::TransactionWAL;
 use crate::Result;
 use std::path::Path;
-use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::sync::Arc;
 
 pub struct SnapShot<UK, M, L>
@@ -26,10 +26,10 @@ where
     M: MemTable<SeqNumKey<UK>, UK> + 'static,
     L: TransactionWAL<SeqNumKey<UK>, UK> + 'static,
 {
-    pub fn range_get(&self, key_start: UK, key_end: UK) -> SrSwSkipMap<UK, Value> {
+    pub fn range_get(&self, key_start: UK, key_end: UK) -> Result<SrSwSkipMap<UK, Value>> {
         let key_start = SeqNumKey::new(key_start, self.next_seq_num - 1);
         let key_end = SeqNumKey::new(key_end, self.next_seq_num - 1);
-        self.db.range_get(&key_start, &key_end).unwrap()
+        self.db.range_get(&key_start, &key_end)
     }
 
     pub fn get(&self, key: UK) -> Result<Option<Value>> {
@@ -45,10 +45,18 @@ where
     L: TransactionWAL<SeqNumKey<UK>, UK> + 'static,
 {
     fn drop(&mut self) {
-        self.db.alive_seq_num_count.fetch_sub(1, Ordering::Release);
+        self.db.release_seq_num(self.next_seq_num);
     }
 }
 
+/// Memory usage and position of the undo log when a savepoint is set.
+struct SavePoint {
+    undo_len: usize,
+    mem_usage: i64,
+}
+
+/// Writes buffered until [WriteBatch::commit] is called, which are written atomically with
+/// one sequence number. Dropping a batch without committing it discards the writes.
 pub struct WriteBatch<UK, M, L>
 where
     UK: DBKey + From<SeqNumKey<UK>> + 'static,
@@ -59,7 +67,12 @@ where
     table: SrSwSkipMap<SeqNumKey<UK>, Value>,
     seq_num: SequenceNumber,
     write_options: WriteOptions,
-    mem_usage: AtomicI64,
+    batch_options: WriteBatchOptions,
+    mem_usage: i64,
+    /// Keys written since the first savepoint and their previous values, `None` if the key
+    /// was not in the batch.
+    undo_log: Vec<(SeqNumKey<UK>, Option<Value>)>,
+    save_points: Vec<SavePoint>,
 }
 
 impl<UK, M, L> WriteBatch<UK, M, L>
@@ -68,52 +81,112 @@ where
     M: MemTable<SeqNumKey<UK>, UK> + 'static,
     L: TransactionWAL<SeqNumKey<UK>, UK>,
 {
-    pub fn range_get(&self, key_start: UK, key_end: UK) -> SrSwSkipMap<UK, Value> {
+    /// Range query the writes of the batch and the database at the time the batch is
+    /// created.
+    pub fn range_get(&self, key_start: UK, key_end: UK) -> Result<SrSwSkipMap<UK, Value>> {
+        let mut kvs: SrSwSkipMap<UK, Value> = self.db.range_get(
+            &SeqNumKey::new(key_start.clone(), self.seq_num - 1),
+            &SeqNumKey::new(key_end.clone(), self.seq_num - 1),
+        )?;
         let key_start_sn = SeqNumKey::new(key_start, self.seq_num);
         let key_end_sn = SeqNumKey::new(key_end, self.seq_num);
-        let mut kvs: SrSwSkipMap<UK, Value> =
-            self.db.range_get(&key_start_sn, &key_end_sn).unwrap();
         self.table
             .range_get::<UK>(&key_start_sn, &key_end_sn, &mut kvs);
-        kvs
+        Ok(kvs)
     }
 
+    /// Get the value written by the batch, or the value in the database at the time the
+    /// batch is created. Keys deleted by the batch are `None`.
     pub fn get(&self, key: UK) -> Result<Option<Value>> {
         let seq_num_key = SeqNumKey::new(key, self.seq_num);
         match self.table.get_clone(&seq_num_key) {
+            Some(v) if v.is_empty() => Ok(None),
             Some(v) => Ok(Some(v)),
-            None => self.db.get(&seq_num_key),
+            None => self.db.get(&SeqNumKey::new(
+                seq_num_key.user_key().clone(),
+                self.seq_num - 1,
+            )),
         }
     }
 
     pub fn set(&mut self, key: UK, value: Value) -> Result<()> {
-        let key_len = key.mem_size() as i64;
-        let value_len = value.len() as i64;
-        let mem_add = match self.table.insert(SeqNumKey::new(key, self.seq_num), value) {
-            Some(v) => value_len - (v.len() as i64),
-            None => (key_len + value_len),
-        } * std::mem::size_of::<u8>() as i64;
-        self.mem_usage.fetch_add(mem_add, Ordering::Release);
-        Ok(())
+        self.write(key, value)
     }
 
     pub fn remove(&mut self, key: UK) -> Result<()> {
-        let key_mem_size = key.mem_size();
-        let mem_add = match self
-            .table
-            .insert(SeqNumKey::new(key, self.seq_num), Value::default())
+        self.write(key, Value::default())
+    }
+
+    fn write(&mut self, key: UK, value: Value) -> Result<()> {
+        let key = SeqNumKey::new(key, self.seq_num);
+        let old_value = self.table.get_clone(&key);
+        let mem_add = match &old_value {
+            Some(v) => value.len() as i64 - v.len() as i64,
+            None => (key.user_key().mem_size() + value.len()) as i64,
+        } * std::mem::size_of::<u8>() as i64;
+        let mem_usage = self.mem_usage + mem_add;
+        let count = self.table.len() + old_value.is_none() as usize;
+        if (self.batch_options.max_bytes > 0 && mem_usage as u64 > self.batch_options.max_bytes)
+            || (self.batch_options.max_count > 0 && count > self.batch_options.max_count)
         {
-            Some(v) => -((v.len() * std::mem::size_of::<u8>()) as i64),
-            None => key_mem_size as i64,
-        };
-        self.mem_usage.fetch_add(mem_add, Ordering::Release);
+            return Err(KVLiteError::WriteBatchFull);
+        }
+
+        if !self.save_points.is_empty() {
+            self.undo_log.push((key.clone(), old_value));
+        }
+        self.table.insert(key, value);
+        self.mem_usage = mem_usage;
         Ok(())
     }
 
-    pub fn abort(&mut self) -> Result<()> {
-        std::mem::take(&mut self.table);
+    /// Record the current state of the batch, which can be restored by
+    /// [WriteBatch::rollback_to_savepoint]. Savepoints can be nested.
+    pub fn set_savepoint(&mut self) {
+        self.save_points.push(SavePoint {
+            undo_len: self.undo_log.len(),
+            mem_usage: self.mem_usage,
+        });
+    }
+
+    /// Discard the writes since the latest savepoint, and remove the savepoint.
+    pub fn rollback_to_savepoint(&mut self) -> Result<()> {
+        let save_point = self
+            .save_points
+            .pop()
+            .ok_or_else(|| KVLiteError::Custom("no savepoint is set".into()))?;
+        for (key, old_value) in self.undo_log.drain(save_point.undo_len..).rev() {
+            match old_value {
+                Some(value) => {
+                    self.table.insert(key, value);
+                }
+                None => {
+                    self.table.remove(key);
+                }
+            }
+        }
+        self.mem_usage = save_point.mem_usage;
         Ok(())
     }
+
+    /// Discard all the writes and savepoints.
+    pub fn abort(&mut self) {
+        std::mem::take(&mut self.table);
+        self.undo_log.clear();
+        self.save_points.clear();
+        self.mem_usage = 0;
+    }
+
+    /// Write the writes of the batch to the database.
+    pub fn commit(mut self) -> Result<()> {
+        if self.table.is_empty() {
+            return Ok(());
+        }
+        let table = std::mem::take(&mut self.table);
+        debug_assert!(self.mem_usage >= 0);
+        self.db
+            .multi_write(&self.write_options, table, self.mem_usage as u64)
+    }
 }
 
 impl<UK, M, L> Drop for WriteBatch<UK, M, L>
@@ -123,15 +196,7 @@ where
     L: TransactionWAL<SeqNumKey<UK>, UK> + 'static,
 {
     fn drop(&mut self) {
-        if !self.table.is_empty() {
-            let table = std::mem::take(&mut self.table);
-            let mem_usage = self.mem_usage.load(Ordering::Acquire);
-            debug_assert!(mem_usage >= 0);
-            self.db
-                .multi_write(&self.write_options, table, mem_usage as u64)
-                .unwrap();
-        }
-        self.db.alive_seq_num_count.fetch_sub(1, Ordering::Release);
+        self.db.release_seq_num(self.seq_num);
     }
 }
 
@@ -142,8 +207,6 @@ where
     L: TransactionWAL<SeqNumKey<UK>, UK> + 'static,
 {
     inner: DBImpl<SeqNumKey<UK>, UK, M, L>,
-    next_seq_num: AtomicU64,
-    alive_seq_num_count: AtomicU64,
 }
 
 impl<UK, M, L> DB<SeqNumKey<UK>, UK, M> for WriteBatchDB<UK, M, L>
@@ -153,12 +216,7 @@ where
     L: TransactionWAL<SeqNumKey<UK>, UK>,
 {
     fn open(db_path: impl AsRef<Path>) -> Result<Self> {
-        let inner = DBImpl::<SeqNumKey<UK>, UK, M, L>::open(db_path)?;
-        Ok(WriteBatchDB {
-            inner,
-            next_seq_num: AtomicU64::new(1),
-            alive_seq_num_count: AtomicU64::new(0),
-        })
+        Self::open_with_options(db_path, Options::default())
     }
 
     #[inline]
@@ -166,14 +224,18 @@ where
         self.inner.get(key)
     }
 
+    /// Write `key` with a new sequence number, the sequence number of `key` is ignored.
     #[inline]
     fn set(&self, write_options: &WriteOptions, key: SeqNumKey<UK>, value: Value) -> Result<()> {
-        self.inner.set(write_options, key, value)
+        self.write_by_user_key(write_options, UK::from(key), value)?;
+        Ok(())
     }
 
+    /// Remove `key` with a new sequence number, the sequence number of `key` is ignored.
     #[inline]
     fn remove(&self, write_options: &WriteOptions, key: SeqNumKey<UK>) -> Result<()> {
-        self.inner.remove(write_options, key)
+        self.write_by_user_key(write_options, UK::from(key), Value::default())?;
+        Ok(())
     }
 
     #[inline]
@@ -196,8 +258,13 @@ where
     M: MemTable<SeqNumKey<UK>, UK> + 'static,
     L: TransactionWAL<SeqNumKey<UK>, UK>,
 {
+    pub fn open_with_options(db_path: impl AsRef<Path>, options: Options) -> Result<Self> {
+        let inner = DBImpl::<SeqNumKey<UK>, UK, M, L>::open_with_options(db_path, options)?;
+        Ok(WriteBatchDB { inner })
+    }
+
     pub fn get_by_user_key(&self, key: UK) -> Result<Option<Value>> {
-        let lsn_key = SeqNumKey::new(key, self.next_seq_num.fetch_add(1, Ordering::Release));
+        let lsn_key = SeqNumKey::new(key, self.inner.published_seq_num());
         self.get(&lsn_key)
     }
 
@@ -207,74 +274,135 @@ where
         key: UK,
         value: Value,
     ) -> Result<()> {
-        let lsn_key = SeqNumKey::new(key, self.next_seq_num.fetch_add(1, Ordering::Release));
-        self.set(write_options, lsn_key, value)
+        self.write_by_user_key(write_options, key, value)?;
+        Ok(())
     }
 
     pub fn remove_by_user_key(&self, write_options: &WriteOptions, key: UK) -> Result<()> {
-        let lsn_key = SeqNumKey::new(key, self.next_seq_num.fetch_add(1, Ordering::Release));
-        self.remove(write_options, lsn_key)
+        self.write_by_user_key(write_options, key, Value::default())?;
+        Ok(())
+    }
+
+    /// Write `key` with a new sequence number, and return the sequence number. An empty
+    /// `value` deletes `key`.
+    pub(crate) fn write_by_user_key(
+        &self,
+        write_options: &WriteOptions,
+        key: UK,
+        value: Value,
+    ) -> Result<SequenceNumber> {
+        let lsn = self.inner.take_seq_num();
+        let lsn_key = SeqNumKey::new(key, lsn);
+        let result = if value.is_empty() {
+            self.inner.remove(write_options, lsn_key)
+        } else {
+            self.inner.set(write_options, lsn_key, value)
+        };
+        // the sequence number of a failed write is published too, not to block the later
+        // writes
+        self.inner.publish_seq_num(lsn);
+        result?;
+        Ok(lsn)
     }
 
     pub fn snapshot(db: &Arc<Self>) -> SnapShot<UK, M, L> {
         SnapShot {
             db: db.clone(),
-            next_seq_num: db.next_seq_num.load(Ordering::Acquire),
+            next_seq_num: db.acquire_seq_num(),
         }
     }
 
     pub fn new_write_batch(db: &Arc<Self>, write_options: WriteOptions) -> WriteBatch<UK, M, L> {
+        Self::new_write_batch_with_options(db, write_options, WriteBatchOptions::default())
+    }
+
+    pub fn new_write_batch_with_options(
+        db: &Arc<Self>,
+        write_options: WriteOptions,
+        batch_options: WriteBatchOptions,
+    ) -> WriteBatch<UK, M, L> {
         WriteBatch {
             db: db.clone(),
             table: SrSwSkipMap::default(),
-            seq_num: db.next_seq_num.load(Ordering::Acquire),
-            mem_usage: AtomicI64::default(),
+            seq_num: db.acquire_seq_num(),
+            mem_usage: 0,
             write_options,
+            batch_options,
+            undo_log: vec![],
+            save_points: vec![],
         }
     }
 
     pub fn multi_write(
         &self,
         write_options: &WriteOptions,
-        mut batch: SrSwSkipMap<SeqNumKey<UK>, Value>,
+        batch: SrSwSkipMap<SeqNumKey<UK>, Value>,
         mem_usage: u64,
     ) -> Result<()> {
-        {
-            let lsn = self.next_seq_num.fetch_add(1, Ordering::Release);
-            let mut wal_guard = self.inner.wal.lock().unwrap();
-            for (key, value) in batch.iter_mut() {
-                key.set_seq_num(lsn);
-                wal_guard.append(write_options, &key, Some(value))?;
-            }
+        self.write_batch(write_options, batch, mem_usage)?;
+        Ok(())
+    }
+
+    /// Write `batch` with a new sequence number, and return the sequence number.
+    pub(crate) fn write_batch(
+        &self,
+        write_options: &WriteOptions,
+        mut batch: SrSwSkipMap<SeqNumKey<UK>, Value>,
+        mem_usage: u64,
+    ) -> Result<SequenceNumber> {
+        let lsn = self.inner.take_seq_num();
+        for (key, _) in batch.iter_mut() {
+            key.set_seq_num(lsn);
         }
+        let result = self.inner.write_batch(write_options, batch, mem_usage);
+        self.inner.publish_seq_num(lsn);
+        result?;
+        Ok(lsn)
+    }
 
-        let mem_table = self.inner.get_mut_mem_table();
-        mem_table.merge(batch, mem_usage);
+    /// Return the next sequence number, and register a snapshot at the sequence number
+    /// before it until [WriteBatchDB::release_seq_num] is called, so that the versions read
+    /// by the older sequence numbers are retained in sstables. The writes before the
+    /// sequence number are all published when it returns.
+    pub(crate) fn acquire_seq_num(&self) -> SequenceNumber {
+        let snapshots = self.inner.snapshots();
+        loop {
+            let seq_num = self.inner.next_seq_num();
+            snapshots.acquire(seq_num - 1);
+            // a write taking the sequence number before the snapshot is registered may be
+            // flushed or compacted without the versions the snapshot reads, so the snapshot
+            // is not simply taken at the published sequence number
+            if self.inner.next_seq_num() == seq_num {
+                // the writes which took the sequence numbers may not be inserted yet
+                self.inner.wait_for_published(seq_num - 1);
+                return seq_num;
+            }
+            snapshots.release(seq_num - 1);
+        }
+    }
 
-        self.may_freeze();
-        Ok(())
+    /// Release a sequence number returned by [WriteBatchDB::acquire_seq_num].
+    pub(crate) fn release_seq_num(&self, seq_num: SequenceNumber) {
+        self.inner.snapshots().release(seq_num - 1);
     }
 
-    fn may_freeze(&self) {
-        let mem_table = self.inner.get_mut_mem_table();
-        if self.alive_seq_num_count.load(Ordering::Acquire) == 0
-            && self
-                .inner
-                .should_freeze(mem_table.approximate_memory_usage())
-        {
-            self.inner.freeze();
-        }
+    #[inline]
+    pub(crate) fn next_seq_num(&self) -> SequenceNumber {
+        self.inner.next_seq_num()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::db::comparator::I32Comparator;
     use crate::db::key_types::{I32UserKey, RawUserKey, SeqNumKey, SequenceNumber};
-    use crate::db::options::WriteOptions;
+    use crate::db::options::{Options, WriteBatchOptions, WriteOptions};
     use crate::db::write_batch_db::WriteBatchDB;
     use crate::db::DB;
+    use crate::error::KVLiteError;
     use crate::memory::{MrSwSkipMapMemTable, MutexSkipMapMemTable};
     use crate::wal::lsn_wal::LSNWriteAheadLog;
+    use std::collections::HashSet;
     use std::sync::Arc;
 
     #[test]
@@ -301,8 +429,7 @@ mod tests {
         let key2 = SeqNumKey::new(Vec::from(2i32.to_be_bytes()), SequenceNumber::MAX);
         let value2 = Vec::from(3i32.to_be_bytes());
         assert!(db.get(&key2).unwrap().is_none());
-        // commit write batch
-        drop(txn1);
+        txn1.commit().unwrap();
         assert_eq!(db.get(&key2).unwrap().unwrap(), value2);
         let key2 = SeqNumKey::new(Vec::from(2i32.to_be_bytes()), 0);
         assert!(db.get(&key2).unwrap().is_none());
@@ -315,11 +442,279 @@ mod tests {
                 Vec::from(1000i32.to_be_bytes()),
             )
             .unwrap();
+            txn2.commit().unwrap();
         }
         assert_eq!(
             snapshot.get(Vec::from(10i32.to_be_bytes())).unwrap(),
             Some(Vec::from(11i32.to_be_bytes()))
         );
+
+        // dropping a batch discards the writes
+        {
+            let mut txn3 = WriteBatchDB::new_write_batch(&db, WriteOptions { sync: false });
+            txn3.remove(Vec::from(10i32.to_be_bytes())).unwrap();
+        }
+        assert_eq!(
+            db.get_by_user_key(Vec::from(10i32.to_be_bytes())).unwrap(),
+            Some(Vec::from(1000i32.to_be_bytes()))
+        );
+    }
+
+    #[test]
+    fn test_savepoint() {
+        let temp_dir = tempfile::Builder::new().prefix("txn").tempdir().unwrap();
+        let db =
+            Arc::new(
+                WriteBatchDB::<
+                    RawUserKey,
+                    MutexSkipMapMemTable<SeqNumKey<RawUserKey>>,
+                    LSNWriteAheadLog,
+                >::open(temp_dir.path())
+                .unwrap(),
+            );
+        let key = |i: u8| vec![i];
+
+        let mut batch = WriteBatchDB::new_write_batch_with_options(
+            &db,
+            WriteOptions { sync: false },
+            WriteBatchOptions {
+                max_bytes: 0,
+                max_count: 3,
+            },
+        );
+        assert!(batch.rollback_to_savepoint().is_err());
+        batch.set(key(1), b"v1".to_vec()).unwrap();
+        batch.set_savepoint();
+        batch.set(key(1), b"v1_1".to_vec()).unwrap();
+        batch.set(key(2), b"v2".to_vec()).unwrap();
+        batch.set_savepoint();
+        batch.remove(key(2)).unwrap();
+        batch.set(key(3), b"v3".to_vec()).unwrap();
+        assert_eq!(
+            batch.set(key(4), b"v4".to_vec()),
+            Err(KVLiteError::WriteBatchFull)
+        );
+        assert_eq!(batch.get(key(2)).unwrap(), None);
+
+        batch.rollback_to_savepoint().unwrap();
+        assert_eq!(batch.get(key(2)).unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(batch.get(key(3)).unwrap(), None);
+        batch.rollback_to_savepoint().unwrap();
+        assert_eq!(batch.get(key(1)).unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(batch.get(key(2)).unwrap(), None);
+        assert_eq!(batch.mem_usage, 3);
+        batch.commit().unwrap();
+        assert_eq!(db.get_by_user_key(key(1)).unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(db.get_by_user_key(key(2)).unwrap(), None);
+
+        let mut batch = WriteBatchDB::new_write_batch_with_options(
+            &db,
+            WriteOptions { sync: false },
+            WriteBatchOptions {
+                max_bytes: 8,
+                max_count: 0,
+            },
+        );
+        batch.set(key(1), b"value1".to_vec()).unwrap();
+        assert_eq!(
+            batch.set(key(2), b"v2".to_vec()),
+            Err(KVLiteError::WriteBatchFull)
+        );
+        batch.set(key(1), b"v1_2".to_vec()).unwrap();
+        batch.set(key(2), b"v2".to_vec()).unwrap();
+        batch.commit().unwrap();
+        assert_eq!(db.get_by_user_key(key(2)).unwrap(), Some(b"v2".to_vec()));
+    }
+
+    #[test]
+    fn test_reopen() {
+        type TestDB =
+            WriteBatchDB<RawUserKey, MutexSkipMapMemTable<SeqNumKey<RawUserKey>>, LSNWriteAheadLog>;
+        let temp_dir = tempfile::Builder::new().prefix("txn").tempdir().unwrap();
+        let path = temp_dir.path();
+        let write_options = WriteOptions { sync: false };
+        let key = |i: u32| i.to_be_bytes().to_vec();
+
+        let db = Arc::new(TestDB::open(path).unwrap());
+        // large values make memory tables flushed to sstables
+        for i in 0..200 {
+            let mut batch = WriteBatchDB::new_write_batch(&db, WriteOptions { sync: false });
+            batch.set(key(i), vec![1u8; 32 * 1024]).unwrap();
+            batch.commit().unwrap();
+        }
+        db.inner.flush();
+        // the latest writes are left in the write-ahead log
+        for i in 0..10 {
+            db.set_by_user_key(&write_options, key(i), b"latest".to_vec())
+                .unwrap();
+        }
+        let last_seq_num = db.next_seq_num() - 1;
+        assert!(db
+            .inner
+            .get_properties_of_all_tables()
+            .unwrap()
+            .values()
+            .any(|properties| properties.largest_seq_num > 0));
+        drop(db);
+
+        let db = Arc::new(TestDB::open(path).unwrap());
+        assert!(db.next_seq_num() > last_seq_num);
+        for i in 0..10 {
+            assert_eq!(
+                db.get_by_user_key(key(i)).unwrap(),
+                Some(b"latest".to_vec())
+            );
+        }
+        db.set_by_user_key(&write_options, key(0), b"reopened".to_vec())
+            .unwrap();
+        assert_eq!(
+            db.get_by_user_key(key(0)).unwrap(),
+            Some(b"reopened".to_vec())
+        );
+        assert_eq!(
+            WriteBatchDB::snapshot(&db).get(key(1)).unwrap(),
+            Some(b"latest".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_snapshot_after_flush() {
+        type TestDB =
+            WriteBatchDB<RawUserKey, MutexSkipMapMemTable<SeqNumKey<RawUserKey>>, LSNWriteAheadLog>;
+        let temp_dir = tempfile::Builder::new().prefix("txn").tempdir().unwrap();
+        let write_options = WriteOptions { sync: false };
+        let key = |i: u32| i.to_be_bytes().to_vec();
+
+        let db = Arc::new(TestDB::open(temp_dir.path()).unwrap());
+        for i in 0..10 {
+            db.set_by_user_key(&write_options, key(i), b"old".to_vec())
+                .unwrap();
+        }
+        db.inner.flush();
+        let snapshot = WriteBatchDB::snapshot(&db);
+        // the overwritten and range deleted versions are kept in sstables for the snapshot
+        for i in 0..20 {
+            db.set_by_user_key(&write_options, key(i), b"new".to_vec())
+                .unwrap();
+        }
+        db.inner.flush();
+        let seq_num = db.inner.take_seq_num();
+        db.inner
+            .delete_range(
+                &write_options,
+                &SeqNumKey::new(key(5), seq_num),
+                &SeqNumKey::new(key(8), seq_num),
+            )
+            .unwrap();
+        db.inner.publish_seq_num(seq_num);
+        db.inner.flush();
+        db.inner.compact_level0();
+        assert!(db
+            .inner
+            .get_properties_of_all_tables()
+            .unwrap()
+            .keys()
+            .all(|(level, _)| *level == 1));
+
+        for i in 0..10 {
+            assert_eq!(snapshot.get(key(i)).unwrap(), Some(b"old".to_vec()));
+            let value = if (5..8).contains(&i) {
+                None
+            } else {
+                Some(b"new".to_vec())
+            };
+            assert_eq!(db.get_by_user_key(key(i)).unwrap(), value);
+        }
+        assert_eq!(snapshot.get(key(10)).unwrap(), None);
+        let kvs = snapshot.range_get(key(0), key(19)).unwrap();
+        assert_eq!(
+            kvs.into_iter().collect::<Vec<_>>(),
+            (0..10)
+                .map(|i| (key(i), b"old".to_vec()))
+                .collect::<Vec<_>>()
+        );
+        let kvs = WriteBatchDB::snapshot(&db)
+            .range_get(key(0), key(19))
+            .unwrap();
+        assert_eq!(
+            kvs.into_iter().collect::<Vec<_>>(),
+            (0..20)
+                .filter(|i| !(5..8).contains(i))
+                .map(|i| (key(i), b"new".to_vec()))
+                .collect::<Vec<_>>()
+        );
+
+        // the versions are dropped after the snapshot is released, the latest writes overlap
+        // all the level1 sstables
+        drop(snapshot);
+        for i in [0, 19] {
+            db.set_by_user_key(&write_options, key(i), b"latest".to_vec())
+                .unwrap();
+        }
+        db.inner.flush();
+        db.inner.compact_level0();
+        // the latest versions of the keys, including the deletions of the range deleted ones
+        let properties = db.inner.get_properties_of_all_tables().unwrap();
+        assert!(properties.keys().all(|(level, _)| *level == 1));
+        assert_eq!(properties.values().map(|p| p.num_entries).sum::<u64>(), 20);
+        assert_eq!(
+            db.get_by_user_key(key(0)).unwrap(),
+            Some(b"latest".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_concurrent_snapshots() {
+        type TestDB =
+            WriteBatchDB<RawUserKey, MutexSkipMapMemTable<SeqNumKey<RawUserKey>>, LSNWriteAheadLog>;
+        const NUM_WRITERS: u32 = 4;
+        const NUM_KEYS: u32 = 200;
+        let temp_dir = tempfile::Builder::new().prefix("txn").tempdir().unwrap();
+        let key = |i: u32| i.to_be_bytes().to_vec();
+        let db = Arc::new(TestDB::open(temp_dir.path()).unwrap());
+
+        let writers: Vec<_> = (0..NUM_WRITERS)
+            .map(|writer| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    // syncing widens the gap between taking and publishing sequence numbers
+                    let write_options = WriteOptions { sync: true };
+                    (writer * NUM_KEYS..(writer + 1) * NUM_KEYS)
+                        .map(|i| {
+                            let seq_num = db
+                                .write_by_user_key(&write_options, key(i), b"value".to_vec())
+                                .unwrap();
+                            (i, seq_num)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        // snapshots taken while writing
+        let observed: Vec<_> = (0..200)
+            .map(|_| {
+                let snapshot = WriteBatchDB::snapshot(&db);
+                let kvs = snapshot
+                    .range_get(key(0), key(NUM_WRITERS * NUM_KEYS))
+                    .unwrap();
+                let keys: HashSet<RawUserKey> = kvs.iter().map(|(k, _)| k.clone()).collect();
+                (snapshot.next_seq_num, keys)
+            })
+            .collect();
+
+        // a snapshot sees all the writes before its sequence number, and none after it
+        let seq_nums: Vec<(u32, SequenceNumber)> = writers
+            .into_iter()
+            .flat_map(|writer| writer.join().unwrap())
+            .collect();
+        for (next_seq_num, keys) in observed {
+            let expected: HashSet<RawUserKey> = seq_nums
+                .iter()
+                .filter(|(_, seq_num)| *seq_num < next_seq_num)
+                .map(|(i, _)| key(*i))
+                .collect();
+            assert_eq!(keys, expected);
+        }
     }
 
     #[test]
@@ -347,4 +742,54 @@ mod tests {
             assert!(db.get_by_user_key(I32UserKey::new(4)).unwrap().is_none());
         }
     }
+
+    #[test]
+    fn test_i32key_comparator() {
+        let temp_dir = tempfile::Builder::new().prefix("txn").tempdir().unwrap();
+        let path = temp_dir.path();
+        let options = Options {
+            comparator: Arc::new(I32Comparator),
+            ..Options::default()
+        };
+        let db =
+            Arc::new(
+                WriteBatchDB::<
+                    I32UserKey,
+                    MutexSkipMapMemTable<SeqNumKey<I32UserKey>>,
+                    LSNWriteAheadLog,
+                >::open_with_options(path, options)
+                .unwrap(),
+            );
+        let write_options = WriteOptions { sync: false };
+
+        // large values make memory tables flushed to sstables, where negative keys are
+        // smaller than positive keys only if ordered by `I32Comparator`.
+        for i in -200..200i32 {
+            let mut value = vec![0u8; 32 * 1024];
+            value[..4].copy_from_slice(&i.to_le_bytes());
+            db.set_by_user_key(&write_options, I32UserKey::new(i), value)
+                .unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_secs(1));
+
+        for i in -200..200 {
+            let value = db.get_by_user_key(I32UserKey::new(i)).unwrap().unwrap();
+            assert_eq!(value[..4], i.to_le_bytes());
+        }
+        let kvs = WriteBatchDB::snapshot(&db)
+            .range_get(I32UserKey::new(-50), I32UserKey::new(50))
+            .unwrap();
+        assert_eq!(kvs.len(), 101);
+        for (i, (key, _value)) in (-50..=50).zip(kvs.iter()) {
+            assert!(key == &I32UserKey::new(i));
+        }
+        drop(db);
+
+        let result = WriteBatchDB::<
+            I32UserKey,
+            MutexSkipMapMemTable<SeqNumKey<I32UserKey>>,
+            LSNWriteAheadLog,
+        >::open(path);
+        assert!(result.is_err());
+    }
 }
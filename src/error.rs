@@ -15,6 +15,29 @@ pub enum KVLiteError {
     #[error("invalid command")]
     InvalidCommand,
 
+    /// A transaction timed out waiting for a row lock.
+    #[error("timed out waiting for a row lock")]
+    LockTimeout,
+
+    /// A transaction is aborted since waiting for a row lock makes a deadlock.
+    #[error("deadlock detected")]
+    Deadlock,
+
+    /// A transaction is not committed since it conflicts with the transactions committed
+    /// after it began.
+    #[error("write conflict, the transaction is busy")]
+    Busy,
+
+    /// A transaction cannot be validated since the write history it began with is
+    /// discarded, it may succeed if retried.
+    #[error("write history is insufficient, try again")]
+    TryAgain,
+
+    /// A write makes a write batch exceed its size or count limit, see
+    /// [crate::db::options::WriteBatchOptions].
+    #[error("write batch exceeds its size or count limit")]
+    WriteBatchFull,
+
     #[error("{0}")]
     Custom(String),
 }
@@ -22,9 +45,13 @@ pub enum KVLiteError {
 impl PartialEq for KVLiteError {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (Self::IOError(_), Self::IOError(_)) | (Self::InvalidCommand, Self::InvalidCommand) => {
-                true
-            }
+            (Self::IOError(_), Self::IOError(_))
+            | (Self::InvalidCommand, Self::InvalidCommand)
+            | (Self::LockTimeout, Self::LockTimeout)
+            | (Self::Deadlock, Self::Deadlock)
+            | (Self::Busy, Self::Busy)
+            | (Self::TryAgain, Self::TryAgain)
+            | (Self::WriteBatchFull, Self::WriteBatchFull) => true,
             (Self::Custom(s1), Self::Custom(s2)) => s1.eq(s2),
             _ => false,
         }
@@ -1,3 +1,4 @@
+use crate::filter::{corrupted_filter, Filter, FilterPolicy};
 use std::cmp::max;
 
 const BITS_PER_KEY: usize = 10;
@@ -51,6 +52,38 @@ impl BloomFilter {
     }
 }
 
+impl Filter for BloomFilter {
+    #[inline]
+    fn may_contain(&self, h: u32) -> bool {
+        BloomFilter::may_contain(self, h)
+    }
+}
+
+/// Standard bloom filter with `BITS_PER_KEY` bits per key.
+#[derive(Default)]
+pub struct BloomFilterPolicy;
+
+impl FilterPolicy for BloomFilterPolicy {
+    fn name(&self) -> &str {
+        "kvlite.BloomFilter"
+    }
+
+    fn create_filter(&self, hashes: &[u32]) -> Vec<u8> {
+        let mut filter = BloomFilter::create_filter(hashes.len());
+        for &h in hashes {
+            filter.add(h);
+        }
+        filter.0
+    }
+
+    fn load_filter(&self, data: Vec<u8>) -> crate::Result<Box<dyn Filter>> {
+        if data.is_empty() {
+            return Err(corrupted_filter(self));
+        }
+        Ok(Box::new(BloomFilter(data)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::filter::bloom_filter::BloomFilter;
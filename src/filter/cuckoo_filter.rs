@@ -0,0 +1,165 @@
+//! Cuckoo filter: [https://www.cs.cmu.edu/~dga/papers/cuckoo-conext2014.pdf]
+//!
+//! A fingerprint of each key is stored in one of its two candidate buckets, the alternate
+//! bucket of bucket `i` is `(hash(fingerprint) - i) mod num_buckets`, so the number of
+//! buckets needs not to be a power of two.
+//!
+//! ```text
+//! +----------------------------------------------------------+
+//! | num_buckets | bucket 1 (4 fingerprints) | ... | bucket n |
+//! +----------------------------------------------------------+
+//! \------------/\--------------------------/
+//!      u32                 u16 * 4
+//! ```
+
+use crate::filter::{corrupted_filter, Filter, FilterPolicy};
+use crate::hash::mix64;
+use std::convert::TryInto;
+
+const BUCKET_SIZE: usize = 4;
+const LOAD_FACTOR: f64 = 0.9;
+const MAX_KICKS: usize = 500;
+
+/// Empty slot of buckets.
+const EMPTY: u16 = 0;
+
+#[inline]
+fn fast_range(h: u64, n: usize) -> usize {
+    (((h >> 32) * n as u64) >> 32) as usize
+}
+
+/// Fingerprint and the first candidate bucket of a key hash.
+#[inline]
+fn index_and_fingerprint(h: u32, num_buckets: usize) -> (usize, u16) {
+    let h64 = mix64(h as u64);
+    let fingerprint = (h64 as u16).max(1);
+    (fast_range(h64, num_buckets), fingerprint)
+}
+
+#[inline]
+fn alt_index(index: usize, fingerprint: u16, num_buckets: usize) -> usize {
+    let h = fast_range(mix64(fingerprint as u64), num_buckets);
+    (h + num_buckets - index) % num_buckets
+}
+
+struct CuckooTable {
+    num_buckets: usize,
+    slots: Vec<u16>,
+    rng_state: u64,
+}
+
+impl CuckooTable {
+    fn new(num_buckets: usize) -> CuckooTable {
+        CuckooTable {
+            num_buckets,
+            slots: vec![EMPTY; num_buckets * BUCKET_SIZE],
+            rng_state: num_buckets as u64,
+        }
+    }
+
+    fn try_insert_to_bucket(&mut self, index: usize, fingerprint: u16) -> bool {
+        let bucket = &mut self.slots[index * BUCKET_SIZE..(index + 1) * BUCKET_SIZE];
+        match bucket.iter_mut().find(|slot| **slot == EMPTY) {
+            Some(slot) => {
+                *slot = fingerprint;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Return false if the table is too full.
+    fn insert(&mut self, h: u32) -> bool {
+        let (mut index, mut fingerprint) = index_and_fingerprint(h, self.num_buckets);
+        if self.try_insert_to_bucket(index, fingerprint) {
+            return true;
+        }
+        index = alt_index(index, fingerprint, self.num_buckets);
+        for _ in 0..MAX_KICKS {
+            if self.try_insert_to_bucket(index, fingerprint) {
+                return true;
+            }
+            // kick out a random fingerprint to its alternate bucket
+            self.rng_state = mix64(self.rng_state);
+            let slot = index * BUCKET_SIZE + self.rng_state as usize % BUCKET_SIZE;
+            std::mem::swap(&mut self.slots[slot], &mut fingerprint);
+            index = alt_index(index, fingerprint, self.num_buckets);
+        }
+        false
+    }
+}
+
+pub struct CuckooFilter {
+    num_buckets: usize,
+    slots: Vec<u16>,
+}
+
+impl CuckooFilter {
+    #[inline]
+    fn bucket_contains(&self, index: usize, fingerprint: u16) -> bool {
+        self.slots[index * BUCKET_SIZE..(index + 1) * BUCKET_SIZE].contains(&fingerprint)
+    }
+}
+
+impl Filter for CuckooFilter {
+    fn may_contain(&self, h: u32) -> bool {
+        if self.num_buckets == 0 {
+            return false;
+        }
+        let (index, fingerprint) = index_and_fingerprint(h, self.num_buckets);
+        self.bucket_contains(index, fingerprint)
+            || self.bucket_contains(alt_index(index, fingerprint, self.num_buckets), fingerprint)
+    }
+}
+
+/// Cuckoo filter with 16-bit fingerprints, whose false positive rate is about 0.01%.
+#[derive(Default)]
+pub struct CuckooFilterPolicy;
+
+impl FilterPolicy for CuckooFilterPolicy {
+    fn name(&self) -> &str {
+        "kvlite.CuckooFilter"
+    }
+
+    fn create_filter(&self, hashes: &[u32]) -> Vec<u8> {
+        // Duplicated fingerprints cannot be kicked out of their buckets.
+        let mut hashes = hashes.to_vec();
+        hashes.sort_unstable();
+        hashes.dedup();
+
+        let mut num_buckets = if hashes.is_empty() {
+            0
+        } else {
+            (hashes.len() as f64 / BUCKET_SIZE as f64 / LOAD_FACTOR).ceil() as usize
+        };
+        let table = loop {
+            let mut table = CuckooTable::new(num_buckets);
+            if hashes.iter().all(|&h| table.insert(h)) {
+                break table;
+            }
+            num_buckets += num_buckets / 10 + 1;
+        };
+
+        let mut data = Vec::with_capacity(4 + table.slots.len() * 2);
+        data.extend_from_slice(&(table.num_buckets as u32).to_le_bytes());
+        for fingerprint in table.slots {
+            data.extend_from_slice(&fingerprint.to_le_bytes());
+        }
+        data
+    }
+
+    fn load_filter(&self, data: Vec<u8>) -> crate::Result<Box<dyn Filter>> {
+        if data.len() < 4 {
+            return Err(corrupted_filter(self));
+        }
+        let num_buckets = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+        if data.len() - 4 != num_buckets * BUCKET_SIZE * 2 {
+            return Err(corrupted_filter(self));
+        }
+        let slots: Vec<u16> = data[4..]
+            .chunks_exact(2)
+            .map(|fingerprint| u16::from_le_bytes(fingerprint.try_into().unwrap()))
+            .collect();
+        Ok(Box::new(CuckooFilter { num_buckets, slots }))
+    }
+}
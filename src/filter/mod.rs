@@ -1,5 +1,140 @@
 pub mod bloom_filter;
+pub mod cuckoo_filter;
+pub mod ribbon_filter;
+
+use crate::filter::bloom_filter::BloomFilterPolicy;
+use crate::filter::cuckoo_filter::CuckooFilterPolicy;
+use crate::filter::ribbon_filter::RibbonFilterPolicy;
+use std::alloc::Layout;
+use std::sync::Arc;
 
 pub type DefaultBloomFilter = filters_rs::BlockedBloomFilter;
 
 pub const SEED: u32 = 0xc7b4e193;
+
+/// Filter of key hashes decoded from a filter block.
+pub trait Filter: Send + Sync {
+    /// Return false if the hash `h` is definitely not added to the filter.
+    fn may_contain(&self, h: u32) -> bool;
+}
+
+/// Policy to build and decode the filter blocks of sstables.
+///
+/// The name of the policy is recorded in the footer of each sstable, so that sstables
+/// written with different policies can be read by the same database.
+pub trait FilterPolicy: Send + Sync {
+    /// Name of the policy, it must be unique among the policies of a database.
+    fn name(&self) -> &str;
+
+    /// Build the filter block of the key hashes, which may contain duplicates.
+    fn create_filter(&self, hashes: &[u32]) -> Vec<u8>;
+
+    /// Decode the filter block built by [FilterPolicy::create_filter], an error is returned
+    /// if `data` is not a filter block of this policy.
+    fn load_filter(&self, data: Vec<u8>) -> crate::Result<Box<dyn Filter>>;
+}
+
+/// Cache-line-blocked bloom filter of `filters-rs`, which is the default policy.
+#[derive(Default)]
+pub struct BlockedBloomFilterPolicy;
+
+impl FilterPolicy for BlockedBloomFilterPolicy {
+    fn name(&self) -> &str {
+        "kvlite.BlockedBloomFilter"
+    }
+
+    fn create_filter(&self, hashes: &[u32]) -> Vec<u8> {
+        let mut filter = DefaultBloomFilter::create_filter(hashes.len().max(1));
+        for &h in hashes {
+            filter.add(h);
+        }
+        debug_assert!(filter.len() >= 8);
+        unsafe { std::slice::from_raw_parts(filter.get_raw_part(), filter.len()) }.to_vec()
+    }
+
+    fn load_filter(&self, data: Vec<u8>) -> crate::Result<Box<dyn Filter>> {
+        if data.len() < 8 {
+            return Err(corrupted_filter(self));
+        }
+        // The bit vector of blocked bloom filter must be aligned to cache line.
+        let buf = unsafe { std::alloc::alloc(Layout::from_size_align(data.len(), 64).unwrap()) };
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len()) };
+        Ok(Box::new(DefaultBloomFilter::from_raw_part(buf, data.len())))
+    }
+}
+
+pub(crate) fn corrupted_filter(policy: &dyn FilterPolicy) -> crate::error::KVLiteError {
+    crate::error::KVLiteError::Custom(format!("corrupted filter block of {}", policy.name()))
+}
+
+impl Filter for DefaultBloomFilter {
+    #[inline]
+    fn may_contain(&self, h: u32) -> bool {
+        DefaultBloomFilter::may_contain(self, h)
+    }
+}
+
+pub fn default_filter_policy() -> Arc<dyn FilterPolicy> {
+    Arc::new(BlockedBloomFilterPolicy)
+}
+
+/// Get the built-in filter policy named `name`.
+pub fn builtin_filter_policy(name: &str) -> Option<Arc<dyn FilterPolicy>> {
+    let policies: Vec<Arc<dyn FilterPolicy>> = vec![
+        Arc::new(BlockedBloomFilterPolicy),
+        Arc::new(BloomFilterPolicy),
+        Arc::new(RibbonFilterPolicy),
+        Arc::new(CuckooFilterPolicy),
+    ];
+    policies
+        .into_iter()
+        .find(|filter_policy| filter_policy.name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::{builtin_filter_policy, SEED};
+    use crate::hash::murmur_hash;
+
+    #[test]
+    fn test_filter_policies() {
+        let hashes: Vec<u32> = (0..10000)
+            .map(|i| murmur_hash(format!("key{}", i).as_bytes(), SEED))
+            .collect();
+        for name in [
+            "kvlite.BlockedBloomFilter",
+            "kvlite.BloomFilter",
+            "kvlite.RibbonFilter",
+            "kvlite.CuckooFilter",
+        ] {
+            let filter_policy = builtin_filter_policy(name).unwrap();
+            assert_eq!(filter_policy.name(), name);
+            let filter = filter_policy
+                .load_filter(filter_policy.create_filter(&hashes))
+                .unwrap();
+            for &h in &hashes {
+                assert!(filter.may_contain(h), "{}", name);
+            }
+            let false_pos_count = (10000..20000)
+                .filter(|i| filter.may_contain(murmur_hash(format!("key{}", i).as_bytes(), SEED)))
+                .count();
+            assert!(
+                false_pos_count < 200,
+                "{} false positive rate: {}/10000",
+                name,
+                false_pos_count
+            );
+
+            let empty_filter = filter_policy
+                .load_filter(filter_policy.create_filter(&[]))
+                .unwrap();
+            assert!(!empty_filter.may_contain(hashes[0]), "{}", name);
+            assert!(filter_policy.load_filter(vec![]).is_err(), "{}", name);
+        }
+        let cuckoo_filter_policy = builtin_filter_policy("kvlite.CuckooFilter").unwrap();
+        let mut truncated = cuckoo_filter_policy.create_filter(&hashes);
+        truncated.pop();
+        assert!(cuckoo_filter_policy.load_filter(truncated).is_err());
+        assert!(builtin_filter_policy("unknown").is_none());
+    }
+}
@@ -49,6 +49,14 @@ pub fn murmur_hash(key: &[u8], seed: u32) -> u32 {
     h
 }
 
+/// Finalizer of splitmix64, which spreads every bit of `x` to the whole result.
+#[inline]
+pub fn mix64(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::hash::murmur_hash;
@@ -1,4 +1,5 @@
 use crate::env::file_system::SequentialReadableFile;
+use crate::error::KVLiteError;
 use crate::Result;
 use std::fs::File;
 use std::io;
@@ -117,3 +118,106 @@ pub fn read_bytes_exact(reader: &mut (impl Read + Seek), length: u64) -> Result<
     handle.read_exact(&mut max_key)?;
     Ok(max_key)
 }
+
+/// Maximum byte size of a LEB128 varint of u64.
+pub const MAX_VARINT_LENGTH: usize = 10;
+
+/// Append `value` to `buf` as a LEB128 varint, 7 bits per byte from the lowest, and the
+/// highest bit of each byte tells whether more bytes follow.
+pub fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+pub fn write_varint(writer: &mut impl Write, value: u64) -> Result<()> {
+    let mut buf = Vec::with_capacity(MAX_VARINT_LENGTH);
+    put_varint(&mut buf, value);
+    writer.write_all(&buf)?;
+    Ok(())
+}
+
+/// Decode a varint at the start of `buf`, returns the value and its byte size, or `None`
+/// if `buf` ends before the varint or the varint overflows u64.
+pub fn decode_varint(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, byte) in buf.iter().take(MAX_VARINT_LENGTH).enumerate() {
+        value |= varint_bits(*byte, i)?;
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// Bits of the `i`-th byte of a varint, return `None` if they overflow u64, only the lowest
+/// bit of the last byte fits.
+#[inline]
+fn varint_bits(byte: u8, i: usize) -> Option<u64> {
+    let bits = (byte & 0x7f) as u64;
+    if i == MAX_VARINT_LENGTH - 1 && bits > 1 {
+        return None;
+    }
+    Some(bits << (7 * i))
+}
+
+pub fn read_varint(reader: &mut impl Read) -> Result<u64> {
+    let mut value = 0u64;
+    for i in 0..MAX_VARINT_LENGTH {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        value |= varint_bits(byte[0], i)
+            .ok_or_else(|| KVLiteError::Custom("varint overflows u64".into()))?;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(KVLiteError::Custom("varint overflows u64".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ioutils::{decode_varint, put_varint, read_varint, write_varint, MAX_VARINT_LENGTH};
+
+    #[test]
+    fn test_varint() {
+        let values = [0, 1, 127, 128, 300, 16383, 16384, u32::MAX as u64, u64::MAX];
+        let mut buf = vec![];
+        for value in values.iter() {
+            put_varint(&mut buf, *value);
+        }
+        assert_eq!(buf[..4], [0, 1, 127, 0x80]);
+
+        let mut offset = 0;
+        for value in values.iter() {
+            let (decoded, length) = decode_varint(&buf[offset..]).unwrap();
+            assert_eq!(decoded, *value);
+            offset += length;
+        }
+        assert_eq!(offset, buf.len());
+        // truncated u64::MAX
+        assert_eq!(
+            decode_varint(&buf[buf.len() - MAX_VARINT_LENGTH..buf.len() - 1]),
+            None
+        );
+
+        let mut written = vec![];
+        for value in values.iter() {
+            write_varint(&mut written, *value).unwrap();
+        }
+        assert_eq!(written, buf);
+        let mut reader = std::io::Cursor::new(written);
+        for value in values.iter() {
+            assert_eq!(read_varint(&mut reader).unwrap(), *value);
+        }
+        assert!(read_varint(&mut reader).is_err());
+
+        // the last byte of u64::MAX with a bit above 64 bits
+        let mut overflowed = buf[buf.len() - MAX_VARINT_LENGTH..].to_vec();
+        overflowed[MAX_VARINT_LENGTH - 1] = 0x03;
+        assert_eq!(decode_varint(&overflowed), None);
+        assert!(read_varint(&mut std::io::Cursor::new(overflowed)).is_err());
+    }
+}
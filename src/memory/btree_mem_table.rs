@@ -1,5 +1,6 @@
 use crate::collections::skip_list::skipmap::SrSwSkipMap;
 use crate::db::key_types::{DBKey, RawUserKey, SequenceNumber};
+use crate::db::range_tombstone::RangeTombstones;
 use crate::db::{DBCommand, Value};
 use crate::memory::{InternalKeyValueIterator, MemTable};
 use crate::Result;
@@ -13,6 +14,7 @@ pub struct BTreeMemTable<SK: DBKey> {
     rw_lock: RwLock<()>,
     inner: UnsafeCell<BTreeMap<SK, Value>>,
     mem_usage: AtomicI64,
+    range_tombstones: RangeTombstones,
 }
 
 unsafe impl<SK: DBKey> Sync for BTreeMemTable<SK> {}
@@ -74,6 +76,7 @@ impl<K: DBKey> Default for BTreeMemTable<K> {
             rw_lock: RwLock::default(),
             inner: UnsafeCell::new(BTreeMap::default()),
             mem_usage: AtomicI64::default(),
+            range_tombstones: RangeTombstones::default(),
         }
     }
 }
@@ -105,6 +108,10 @@ impl MemTable<RawUserKey, RawUserKey> for BTreeMemTable<RawUserKey> {
         debug_assert!(mem_size >= 0);
         mem_size as u64
     }
+
+    fn range_tombstones(&self) -> &RangeTombstones {
+        &self.range_tombstones
+    }
 }
 
 #[cfg(test)]
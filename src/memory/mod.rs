@@ -3,6 +3,7 @@
 use crate::collections::skip_list::skipmap::{Node, ReadWriteMode, SkipMap, SrSwSkipMap};
 use crate::collections::skip_list::MemoryAllocator;
 use crate::db::key_types::{DBKey, RawUserKey, SequenceNumber};
+use crate::db::range_tombstone::{RangeTombstone, RangeTombstones};
 use crate::db::{DBCommand, Value};
 pub use btree_mem_table::BTreeMemTable;
 pub use mrmw_skip_map_mem_table::MrMwSkipMapMemTable;
@@ -22,6 +23,44 @@ pub trait MemTable<SK: DBKey, UK: DBKey>:
 {
     fn merge(&self, kvs: SrSwSkipMap<SK, Value>, memory_size: u64);
     fn approximate_memory_usage(&self) -> u64;
+    fn range_tombstones(&self) -> &RangeTombstones;
+
+    /// Smallest and largest sequence numbers of the keys, `None` if the keys have no
+    /// sequence numbers or the table is empty.
+    fn seq_num_range(&self) -> Option<(SequenceNumber, SequenceNumber)> {
+        None
+    }
+
+    /// Delete all the keys in [`key_start`, `key_end`).
+    ///
+    /// Keys in this memory table are removed directly, and a range tombstone at the sequence
+    /// number of `key_start` is recorded to delete the keys in older tables. Only the keys in
+    /// the range are visited.
+    fn delete_range(&self, key_start: &SK, key_end: &SK) -> crate::Result<()>
+    where
+        UK: From<SK>,
+    {
+        if key_start >= key_end {
+            return Ok(());
+        }
+        let mut kvs = SrSwSkipMap::new();
+        self.range_get(key_start, key_end, &mut kvs);
+        let keys: Vec<SK> = kvs
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(key, _)| key_start.with_raw_user_key(key.raw_user_key().clone()))
+            .filter(|key| key < key_end)
+            .collect();
+        for key in keys {
+            self.remove(key)?;
+        }
+        self.range_tombstones().add(RangeTombstone::new(
+            key_start.raw_user_key().clone(),
+            key_end.raw_user_key().clone(),
+            key_start.seq_num(),
+        ));
+        Ok(())
+    }
 }
 
 pub trait SkipMapMemTable<SK: DBKey, UK: DBKey, const RW_MODE: ReadWriteMode>:
@@ -37,7 +76,7 @@ pub struct MemTableCloneIterator<
     M: SkipMapMemTable<SK, UK, { RW_MODE }>,
     const RW_MODE: ReadWriteMode,
 > {
-    _mem_table: Arc<M>,
+    mem_table: Arc<M>,
     node: *mut Node<SK, Value, RW_MODE>,
     _marker: PhantomData<(UK, Value)>,
 }
@@ -52,11 +91,16 @@ impl<
     pub fn new(mem_table: Arc<M>) -> Self {
         let node = mem_table.get_inner().first_node() as *mut _;
         MemTableCloneIterator {
-            _mem_table: mem_table,
+            mem_table,
             node,
             _marker: PhantomData,
         }
     }
+
+    /// Get the range tombstones of the memory table.
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.mem_table.range_tombstones().to_vec()
+    }
 }
 
 impl<
@@ -92,6 +136,15 @@ pub trait InternalKeyValueIterator {
 
     /// # Note: InternalKey should not be duplicated.
     fn kv_iter(&self) -> Box<dyn Iterator<Item = (&RawUserKey, &Value)> + '_>;
+
+    /// Iterate all the versions of keys, ordered by key and then by sequence number in
+    /// descending order. Tables without sequence numbers have a version of each key at
+    /// sequence number 0.
+    fn versions_iter(
+        &self,
+    ) -> Box<dyn Iterator<Item = (&RawUserKey, SequenceNumber, &Value)> + '_> {
+        Box::new(self.kv_iter().map(|(key, value)| (key, 0, value)))
+    }
 }
 
 impl InternalKeyValueIterator for SrSwSkipMap<RawUserKey, Value> {
@@ -1,6 +1,7 @@
 use crate::collections::skip_list::skipmap::ReadWriteMode::MrMw;
 use crate::collections::skip_list::skipmap::{SkipMap, SrSwSkipMap};
 use crate::db::key_types::{DBKey, RawUserKey, SeqNumKey, SequenceNumber};
+use crate::db::range_tombstone::RangeTombstones;
 use crate::db::{DBCommand, Value};
 use crate::memory::skip_map_mem_table::{get_by_lsn_key, range_get_by_lsn_key};
 use crate::memory::{InternalKeyValueIterator, MemTable, SkipMapMemTable};
@@ -10,6 +11,7 @@ use std::sync::atomic::{AtomicI64, Ordering};
 pub struct MrMwSkipMapMemTable<SK: DBKey> {
     inner: SkipMap<SK, Value, { MrMw }>,
     mem_usage: AtomicI64,
+    range_tombstones: RangeTombstones,
 }
 
 impl<SK: DBKey> Default for MrMwSkipMapMemTable<SK> {
@@ -17,6 +19,7 @@ impl<SK: DBKey> Default for MrMwSkipMapMemTable<SK> {
         MrMwSkipMapMemTable {
             inner: SkipMap::default(),
             mem_usage: AtomicI64::default(),
+            range_tombstones: RangeTombstones::default(),
         }
     }
 }
@@ -85,6 +88,10 @@ impl MemTable<RawUserKey, RawUserKey> for MrMwSkipMapMemTable<RawUserKey> {
         debug_assert!(mem_usage >= 0);
         mem_usage as u64
     }
+
+    fn range_tombstones(&self) -> &RangeTombstones {
+        &self.range_tombstones
+    }
 }
 
 impl SkipMapMemTable<RawUserKey, RawUserKey, { MrMw }> for MrMwSkipMapMemTable<RawUserKey> {
@@ -1,5 +1,6 @@
 use crate::collections::skip_list::skipmap::{MrSwSkipMap, SrSwSkipMap};
 use crate::db::key_types::{DBKey, RawUserKey, SeqNumKey, SequenceNumber};
+use crate::db::range_tombstone::RangeTombstones;
 use crate::db::{DBCommand, Value};
 use crate::memory::skip_map_mem_table::{get_by_lsn_key, range_get_by_lsn_key};
 use crate::memory::{InternalKeyValueIterator, MemTable};
@@ -12,6 +13,7 @@ pub struct MrSwSkipMapMemTable<SK: DBKey> {
     lock: Mutex<()>,
     inner: MrSwSkipMap<SK, Value>,
     mem_usage: AtomicI64,
+    range_tombstones: RangeTombstones,
 }
 
 unsafe impl<SK: DBKey> Sync for MrSwSkipMapMemTable<SK> {}
@@ -81,6 +83,10 @@ impl MemTable<RawUserKey, RawUserKey> for MrSwSkipMapMemTable<RawUserKey> {
         debug_assert!(mem_usage >= 0);
         mem_usage as u64
     }
+
+    fn range_tombstones(&self) -> &RangeTombstones {
+        &self.range_tombstones
+    }
 }
 
 impl<UK: DBKey> DBCommand<SeqNumKey<UK>, UK> for MrSwSkipMapMemTable<SeqNumKey<UK>> {
@@ -1,6 +1,7 @@
 use crate::collections::skip_list::skipmap::{Node, ReadWriteMode, SkipMap, SrSwSkipMap};
 use crate::collections::skip_list::MemoryAllocator;
-use crate::db::key_types::{DBKey, RawUserKey, SeqNumKey};
+use crate::db::key_types::{DBKey, RawUserKey, SeqNumKey, SequenceNumber};
+use crate::db::range_tombstone::RangeTombstones;
 use crate::db::{DBCommand, Value};
 use crate::memory::{InternalKeyValueIterator, MemTable};
 use crate::Result;
@@ -12,6 +13,7 @@ pub struct MutexSkipMapMemTable<SK: DBKey> {
     lock: Mutex<()>,
     inner_guarded: SrSwSkipMap<SK, Value>,
     mem_usage: AtomicI64,
+    range_tombstones: RangeTombstones,
 }
 
 impl DBCommand<RawUserKey, RawUserKey> for MutexSkipMapMemTable<RawUserKey> {
@@ -81,6 +83,10 @@ impl MemTable<RawUserKey, RawUserKey> for MutexSkipMapMemTable<RawUserKey> {
         debug_assert!(mem_usage > 0);
         mem_usage as u64
     }
+
+    fn range_tombstones(&self) -> &RangeTombstones {
+        &self.range_tombstones
+    }
 }
 
 pub(super) fn range_get_by_lsn_key<UK: DBKey, const RW_MODE: ReadWriteMode>(
@@ -112,7 +118,8 @@ pub(super) fn range_get_by_lsn_key<UK: DBKey, const RW_MODE: ReadWriteMode>(
             let lsn_key = SeqNumKey::new((*node).entry.key.user_key().clone(), key_end.seq_num());
             node = Node::find_last_le_from_node(node, &lsn_key);
             debug_assert!(!node.is_null());
-            if (*node).entry.key.user_key().eq(lsn_key.user_key()) {
+            // the node stays at the oldest version if all the versions are newer than the key
+            if (*node).entry.key.le(&lsn_key) {
                 kvs.insert(lsn_key.user_key().clone(), (*node).entry.value.clone());
             }
         }
@@ -203,6 +210,24 @@ impl<K: DBKey + 'static> InternalKeyValueIterator for MutexSkipMapMemTable<SeqNu
             }
         }))
     }
+
+    fn versions_iter(
+        &self,
+    ) -> Box<dyn Iterator<Item = (&RawUserKey, SequenceNumber, &Value)> + '_> {
+        let mut versions: Vec<_> = self
+            .inner_guarded
+            .iter_ptr()
+            .map(|n| unsafe {
+                let key = &(*n).entry.key;
+                (key.raw_user_key(), key.seq_num(), &(*n).entry.value)
+            })
+            .collect();
+        // versions of a key are ordered by sequence number in ascending order in the map
+        for key_versions in versions.chunk_by_mut(|a, b| a.0 == b.0) {
+            key_versions.reverse();
+        }
+        Box::new(versions.into_iter())
+    }
 }
 
 impl<UK: 'static + DBKey> MemTable<SeqNumKey<UK>, UK> for MutexSkipMapMemTable<SeqNumKey<UK>> {
@@ -217,6 +242,21 @@ impl<UK: 'static + DBKey> MemTable<SeqNumKey<UK>, UK> for MutexSkipMapMemTable<S
         debug_assert!(mem_usage >= 0, "mem_usage: {}", mem_usage);
         mem_usage as u64
     }
+
+    fn range_tombstones(&self) -> &RangeTombstones {
+        &self.range_tombstones
+    }
+
+    fn seq_num_range(&self) -> Option<(SequenceNumber, SequenceNumber)> {
+        let _guard = self.lock.lock().unwrap();
+        self.inner_guarded
+            .iter()
+            .map(|(key, _)| key.seq_num())
+            .fold(None, |range, seq_num| match range {
+                Some((smallest, largest)) => Some((seq_num.min(smallest), seq_num.max(largest))),
+                None => Some((seq_num, seq_num)),
+            })
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,350 @@
+//! Blob files of the values separated from sstables, see
+//! [crate::db::options::Options::min_blob_size].
+//!
+//! A blob file is the concatenation of the large values written by one sstable writer, and
+//! the data blocks store [BlobIndex]es of the values instead, so compactions move the
+//! indexes without rewriting the values. Blob files are immutable, a blob file is deleted
+//! once all the sstables referencing it are deleted, and
+//! [crate::db::dbimpl::DBImpl::garbage_collect_blobs] rewrites the blob files whose values
+//! are mostly overwritten or deleted.
+
+use crate::db::Value;
+use crate::error::KVLiteError;
+use crate::ioutils::{decode_varint, put_varint, BufWriterWithPos};
+use crate::Result;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Directory of blob files in the database directory.
+pub const BLOB_DIR: &str = "blob";
+
+/// Type of the value of a data block entry, which is stored in the lowest bit of the value
+/// length since format version 7.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    Value = 0,
+    BlobIndex = 1,
+}
+
+/// Append the type to `value` unless it is a deletion, which is how values are passed
+/// between the raw table iterators and the table writers of compaction.
+#[inline]
+pub(crate) fn tag_value(mut value: Value, value_type: ValueType) -> Value {
+    if !value.is_empty() {
+        value.push(value_type as u8);
+    }
+    value
+}
+
+/// Split the value tagged by [tag_value] to the value and its type.
+#[inline]
+pub(crate) fn untag_value(mut value: Value) -> (Value, ValueType) {
+    match value.pop() {
+        Some(1) => (value, ValueType::BlobIndex),
+        _ => (value, ValueType::Value),
+    }
+}
+
+/// Reference to a value in a blob file.
+///
+/// ```text
+/// +--------------------------------+
+/// | file number | offset | length |
+/// +--------------------------------+
+/// \------------/\-------/\-------/
+///     varint     varint   varint
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlobIndex {
+    pub file_number: u64,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl BlobIndex {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(16);
+        put_varint(&mut buf, self.file_number);
+        put_varint(&mut buf, self.offset);
+        put_varint(&mut buf, self.length);
+        buf
+    }
+
+    pub(crate) fn decode(buf: &[u8]) -> Result<BlobIndex> {
+        let corrupted = || KVLiteError::Custom("corrupted blob index".into());
+        let mut pos = 0;
+        let mut read_number = || -> Result<u64> {
+            let (number, length) = decode_varint(&buf[pos..]).ok_or_else(corrupted)?;
+            pos += length;
+            Ok(number)
+        };
+        Ok(BlobIndex {
+            file_number: read_number()?,
+            offset: read_number()?,
+            length: read_number()?,
+        })
+    }
+}
+
+/// Blob file opened for reading, which is shared by the sstable handles referencing it.
+pub(crate) struct BlobFile {
+    file_number: u64,
+    file_path: String,
+    file: File,
+    file_size: u64,
+    /// Number of sstables referencing the file that are not deleted.
+    num_tables: AtomicUsize,
+    /// Whether the file is deleted when it is closed.
+    obsolete: AtomicBool,
+}
+
+impl BlobFile {
+    fn open(file_path: String, file_number: u64) -> Result<BlobFile> {
+        let file = File::open(&file_path)?;
+        let file_size = file.metadata()?.len();
+        Ok(BlobFile {
+            file_number,
+            file_path,
+            file,
+            file_size,
+            num_tables: AtomicUsize::new(0),
+            obsolete: AtomicBool::new(false),
+        })
+    }
+
+    #[inline]
+    pub(crate) fn file_number(&self) -> u64 {
+        self.file_number
+    }
+
+    #[inline]
+    pub(crate) fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    pub(crate) fn read_value(&self, index: &BlobIndex) -> Result<Value> {
+        debug_assert_eq!(index.file_number, self.file_number);
+        if index.offset + index.length > self.file_size {
+            return Err(KVLiteError::Custom(format!(
+                "blob index {:?} is beyond blob file of {} bytes",
+                index, self.file_size
+            )));
+        }
+        let mut value = vec![0; index.length as usize];
+        self.file.read_exact_at(&mut value, index.offset)?;
+        Ok(value)
+    }
+
+    /// Called when an sstable handle referencing the file is created.
+    #[inline]
+    pub(crate) fn add_table(&self) {
+        self.num_tables.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Called when an sstable referencing the file is deleted. Once no sstable references
+    /// it, the file is deleted after the readers holding it finish.
+    #[inline]
+    pub(crate) fn remove_table(&self) {
+        if self.num_tables.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.obsolete.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Drop for BlobFile {
+    fn drop(&mut self) {
+        if self.obsolete.load(Ordering::SeqCst) {
+            info!("remove blob file {}", self.file_path);
+            if let Err(e) = std::fs::remove_file(&self.file_path) {
+                warn!("failed to remove blob file {}: {}", self.file_path, e);
+            }
+        }
+    }
+}
+
+/// Writer of a new blob file, whose values are appended.
+pub(crate) struct BlobFileWriter {
+    file_number: u64,
+    writer: BufWriterWithPos<File>,
+}
+
+impl BlobFileWriter {
+    pub(crate) fn add_value(&mut self, value: &[u8]) -> Result<BlobIndex> {
+        let index = BlobIndex {
+            file_number: self.file_number,
+            offset: self.writer.pos,
+            length: value.len() as u64,
+        };
+        self.writer.write_all(value)?;
+        Ok(index)
+    }
+
+    pub(crate) fn finish(mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer.sync_data()
+    }
+}
+
+/// Blob files of a database.
+pub struct BlobStore {
+    dir: String,
+    /// Blob files whose numbers are less than it exist when the store is opened.
+    first_file_number: u64,
+    next_file_number: AtomicU64,
+    /// Opened blob files, a file is closed once no sstable handle holds it.
+    files: Mutex<HashMap<u64, Weak<BlobFile>>>,
+}
+
+impl BlobStore {
+    pub(crate) fn open(db_path: &str) -> Result<BlobStore> {
+        let dir = format!("{}/{}", db_path, BLOB_DIR);
+        std::fs::create_dir_all(&dir)?;
+        let mut next_file_number = 1;
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            if let Some(file_number) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<u64>().ok())
+            {
+                next_file_number = next_file_number.max(file_number + 1);
+            }
+        }
+        Ok(BlobStore {
+            dir,
+            first_file_number: next_file_number,
+            next_file_number: AtomicU64::new(next_file_number),
+            files: Mutex::default(),
+        })
+    }
+
+    #[inline]
+    fn file_path(&self, file_number: u64) -> String {
+        format!("{}/{}", self.dir, file_number)
+    }
+
+    pub(crate) fn new_writer(&self) -> Result<BlobFileWriter> {
+        let file_number = self.next_file_number.fetch_add(1, Ordering::SeqCst);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.file_path(file_number))?;
+        Ok(BlobFileWriter {
+            file_number,
+            writer: BufWriterWithPos::new(file)?,
+        })
+    }
+
+    /// Get the opened blob file, or open it if no sstable handle holds it.
+    pub(crate) fn get_file(&self, file_number: u64) -> Result<Arc<BlobFile>> {
+        let mut files = self.files.lock().unwrap();
+        if let Some(file) = files.get(&file_number).and_then(Weak::upgrade) {
+            return Ok(file);
+        }
+        files.retain(|_, file| file.strong_count() > 0);
+        let file = Arc::new(BlobFile::open(self.file_path(file_number), file_number)?);
+        files.insert(file_number, Arc::downgrade(&file));
+        Ok(file)
+    }
+
+    /// Remove the blob files that exist when the store is opened but are not referenced by
+    /// the opened sstables, which are left by unfinished flushes and compactions.
+    pub(crate) fn remove_unreferenced_files(&self) -> Result<()> {
+        let files = self.files.lock().unwrap();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let file_number = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.parse::<u64>().ok());
+            if let Some(file_number) = file_number {
+                if file_number < self.first_file_number
+                    && files.get(&file_number).and_then(Weak::upgrade).is_none()
+                {
+                    info!("remove unreferenced blob file {:?}", path);
+                    std::fs::remove_file(path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sstable::blob_file::{tag_value, untag_value, BlobIndex, BlobStore, ValueType};
+
+    #[test]
+    fn test_blob_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let db_path = temp_dir.path().to_str().unwrap();
+        let store = BlobStore::open(db_path).unwrap();
+
+        let mut writer = store.new_writer().unwrap();
+        let indexes: Vec<BlobIndex> = (0..100)
+            .map(|i| {
+                let value = format!("value{}", i).repeat(i + 1);
+                writer.add_value(value.as_bytes()).unwrap()
+            })
+            .collect();
+        writer.finish().unwrap();
+
+        for index in &indexes {
+            assert_eq!(BlobIndex::decode(&index.encode()).unwrap(), *index);
+        }
+        let file = store.get_file(indexes[0].file_number).unwrap();
+        for (i, index) in indexes.iter().enumerate() {
+            assert_eq!(
+                file.read_value(index).unwrap(),
+                format!("value{}", i).repeat(i + 1).into_bytes()
+            );
+        }
+        let beyond = BlobIndex {
+            offset: file.file_size(),
+            ..indexes[0]
+        };
+        assert!(file.read_value(&beyond).is_err());
+
+        assert_eq!(
+            untag_value(tag_value(b"value".to_vec(), ValueType::BlobIndex)),
+            (b"value".to_vec(), ValueType::BlobIndex)
+        );
+        assert_eq!(
+            untag_value(tag_value(b"value".to_vec(), ValueType::Value)),
+            (b"value".to_vec(), ValueType::Value)
+        );
+        assert!(tag_value(vec![], ValueType::Value).is_empty());
+
+        // referenced files are kept, and unreferenced files are removed after reopening
+        let file_path = format!("{}/blob/{}", db_path, file.file_number());
+        drop(store);
+        let store = BlobStore::open(db_path).unwrap();
+        let file2 = store.get_file(file.file_number()).unwrap();
+        file2.add_table();
+        store.remove_unreferenced_files().unwrap();
+        assert!(std::path::Path::new(&file_path).exists());
+        drop(file2);
+        store.remove_unreferenced_files().unwrap();
+        assert!(!std::path::Path::new(&file_path).exists());
+
+        // files are removed once no sstable references them and no reader holds them
+        let store = BlobStore::open(db_path).unwrap();
+        let mut writer = store.new_writer().unwrap();
+        let index = writer.add_value(b"value").unwrap();
+        writer.finish().unwrap();
+        let file = store.get_file(index.file_number).unwrap();
+        file.add_table();
+        file.add_table();
+        file.remove_table();
+        let reader = file.clone();
+        file.remove_table();
+        drop(file);
+        assert_eq!(reader.read_value(&index).unwrap(), b"value");
+        drop(reader);
+        assert!(!std::path::Path::new(&format!("{}/blob/{}", db_path, index.file_number)).exists());
+    }
+}
@@ -0,0 +1,384 @@
+use crate::cache::{CachePolicy, CachePriority, CacheStatistics, EntryTracker, ShardLRUCache};
+use crate::hash::murmur_hash;
+use crate::sstable::data_block::DataBlock;
+use crate::sstable::secondary_cache::SecondaryCache;
+use crate::sstable::table_cache::TableCache;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Default capacity of block cache (8MB).
+pub const DEFAULT_BLOCK_CACHE_CAPACITY: usize = 8 << 20;
+
+/// Key of a data block in [BlockCache].
+///
+/// Table keys are only unique in a database, so each sstable handle is assigned a
+/// `table_cache_id` by the block cache, which is unique among all the databases sharing it.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct BlockCacheKey {
+    table_cache_id: u64,
+    block_offset: u64,
+}
+
+impl BlockCacheKey {
+    #[inline]
+    pub(crate) fn new(table_cache_id: u64, block_offset: u64) -> BlockCacheKey {
+        BlockCacheKey {
+            table_cache_id,
+            block_offset,
+        }
+    }
+
+    /// Key of the filter, index and compression dictionary of a sstable, no data block
+    /// starts at offset `u64::MAX`.
+    #[inline]
+    pub(crate) fn table(table_cache_id: u64) -> BlockCacheKey {
+        BlockCacheKey::new(table_cache_id, u64::MAX)
+    }
+
+    #[inline]
+    fn to_bytes(self) -> [u8; 16] {
+        let mut buf = [0u8; 16];
+        buf[..8].copy_from_slice(&self.table_cache_id.to_le_bytes());
+        buf[8..].copy_from_slice(&self.block_offset.to_le_bytes());
+        buf
+    }
+
+    #[inline]
+    fn hash(&self) -> u32 {
+        const SEED: u32 = 0x2f8b3a57;
+        murmur_hash(&self.to_bytes(), SEED)
+    }
+}
+
+/// Entry of [BlockCache].
+pub(crate) enum CachedBlock {
+    Data(DataBlock),
+    /// Filter, index and compression dictionary of a sstable.
+    Table(Arc<TableCache>),
+}
+
+/// LRU cache of decompressed data blocks and the filters and indexes of sstables, whose
+/// capacity is the total memory usage of blocks in bytes.
+///
+/// Filters and indexes are inserted with [CachePriority::High] and data blocks with
+/// [CachePriority::Low], so the former are protected by the high priority pool of
+/// [CachePolicy::LruWithHighPriPool] from scans.
+///
+/// It can be shared by multiple databases in one process via
+/// [crate::db::options::Options::block_cache].
+pub struct BlockCache {
+    cache: ShardLRUCache<BlockCacheKey, CachedBlock>,
+    secondary_cache: Option<Arc<dyn SecondaryCache>>,
+    next_table_cache_id: AtomicU64,
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        BlockCache::new(DEFAULT_BLOCK_CACHE_CAPACITY)
+    }
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> BlockCache {
+        BlockCache::with_policy(capacity, CachePolicy::Lru)
+    }
+
+    /// Create a block cache that evicts blocks by `policy`.
+    pub fn with_policy(capacity: usize, policy: CachePolicy) -> BlockCache {
+        BlockCache {
+            cache: ShardLRUCache::with_policy(capacity, policy),
+            secondary_cache: None,
+            next_table_cache_id: AtomicU64::default(),
+        }
+    }
+
+    /// Create a block cache whose evicted blocks are inserted into `secondary_cache`, which
+    /// is looked up when a block is missing in memory.
+    pub fn with_secondary_cache(
+        capacity: usize,
+        policy: CachePolicy,
+        secondary_cache: Arc<dyn SecondaryCache>,
+    ) -> BlockCache {
+        let tier = secondary_cache.clone();
+        let eviction_callback = Arc::new(move |key: &BlockCacheKey, block: &CachedBlock| {
+            if let CachedBlock::Data(data_block) = block {
+                tier.insert(&key.to_bytes(), &data_block.encode())
+            }
+        });
+        BlockCache {
+            cache: ShardLRUCache::with_eviction_callback(capacity, policy, Some(eviction_callback)),
+            secondary_cache: Some(secondary_cache),
+            next_table_cache_id: AtomicU64::default(),
+        }
+    }
+
+    /// Allocate a unique id for the blocks of a sstable handle.
+    pub(crate) fn new_table_cache_id(&self) -> u64 {
+        self.next_table_cache_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn look_up(&self, key: &BlockCacheKey) -> EntryTracker<BlockCacheKey, CachedBlock> {
+        self.cache.look_up(key, key.hash())
+    }
+
+    /// Look up the block evicted to secondary cache, a corrupted block is ignored so that
+    /// it is read from sstable again.
+    pub(crate) fn look_up_secondary(&self, key: &BlockCacheKey) -> Option<DataBlock> {
+        let secondary_cache = self.secondary_cache.as_ref()?;
+        let buf = secondary_cache.look_up(&key.to_bytes())?;
+        let data_block = DataBlock::decode(&buf);
+        if data_block.is_none() {
+            warn!("corrupted data block in secondary cache");
+        }
+        data_block
+    }
+
+    pub(crate) fn insert(&self, key: BlockCacheKey, data_block: DataBlock) {
+        let charge = data_block.charge();
+        self.cache.insert_with_charge(
+            key,
+            CachedBlock::Data(data_block),
+            charge,
+            CachePriority::Low,
+            key.hash(),
+        );
+    }
+
+    /// Insert the filter, index and compression dictionary of a sstable charged `charge`.
+    pub(crate) fn insert_table(
+        &self,
+        table_cache_id: u64,
+        table_cache: Arc<TableCache>,
+        charge: usize,
+    ) {
+        let key = BlockCacheKey::table(table_cache_id);
+        self.cache.insert_with_charge(
+            key,
+            CachedBlock::Table(table_cache),
+            charge,
+            CachePriority::High,
+            key.hash(),
+        );
+    }
+
+    /// Erase the filter, index and compression dictionary of a deleted sstable, its data
+    /// blocks are left to be evicted.
+    pub(crate) fn erase_table(&self, table_cache_id: u64) {
+        let key = BlockCacheKey::table(table_cache_id);
+        self.cache.erase(&key, key.hash());
+    }
+
+    /// Memory usage of the cached blocks in bytes.
+    pub fn usage(&self) -> usize {
+        self.cache.usage()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cache.capacity()
+    }
+
+    pub fn statistics(&self) -> CacheStatistics {
+        self.cache.statistics()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::CachePolicy;
+    use crate::db::key_types::SequenceNumber;
+    use crate::db::options::Options;
+    use crate::sstable::block_cache::{BlockCache, BlockCacheKey};
+    use crate::sstable::secondary_cache::SecondaryCache;
+    use crate::sstable::table_handle::{TableReadHandle, TableWriteHandle};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct MockSecondaryCache {
+        blocks: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+        hits: AtomicUsize,
+    }
+
+    impl SecondaryCache for MockSecondaryCache {
+        fn insert(&self, key: &[u8], value: &[u8]) {
+            self.blocks
+                .lock()
+                .unwrap()
+                .insert(key.to_vec(), value.to_vec());
+        }
+
+        fn look_up(&self, key: &[u8]) -> Option<Vec<u8>> {
+            let value = self.blocks.lock().unwrap().get(key).cloned();
+            if value.is_some() {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+            }
+            value
+        }
+    }
+
+    #[test]
+    fn test_shared_block_cache() {
+        let options = Options {
+            block_cache: Arc::new(BlockCache::new(64 << 10)),
+            ..Options::default()
+        };
+        let temp_dirs = [
+            tempfile::TempDir::new().unwrap(),
+            tempfile::TempDir::new().unwrap(),
+        ];
+
+        // sstables of the same table key in two databases
+        let mut read_handles = vec![];
+        for (db, temp_dir) in temp_dirs.iter().enumerate() {
+            std::fs::create_dir_all(temp_dir.path().join("1")).unwrap();
+            let path = temp_dir.path().to_str().unwrap();
+            let mut write_handle = TableWriteHandle::new(path, 1, 1, 2000, &options);
+            let kvs = (0..2000)
+                .map(|i| {
+                    (
+                        format!("key{:04}", i).into_bytes(),
+                        format!("value{:04}_{}", i, db).into_bytes(),
+                    )
+                })
+                .collect();
+            write_handle.write_sstable_from_vec(kvs).unwrap();
+            write_handle.rename();
+            read_handles.push(TableReadHandle::open(path, 1, 1, &options).unwrap());
+        }
+        assert_eq!(read_handles[0].table_key(), read_handles[1].table_key());
+
+        for _ in 0..2 {
+            for (db, read_handle) in read_handles.iter().enumerate() {
+                for i in (0..2000).step_by(7) {
+                    let value = read_handle
+                        .query(&format!("key{:04}", i).into_bytes(), SequenceNumber::MAX)
+                        .unwrap();
+                    assert_eq!(value, Some(format!("value{:04}_{}", i, db).into_bytes()));
+                }
+            }
+        }
+        let block_cache = &options.block_cache;
+        assert!(block_cache.usage() > 0);
+        assert!(block_cache.usage() <= block_cache.capacity());
+    }
+
+    #[test]
+    fn test_high_pri_pool() {
+        let policies = [
+            (CachePolicy::Lru, false),
+            (
+                CachePolicy::LruWithHighPriPool {
+                    high_pri_pool_ratio: 0.5,
+                },
+                true,
+            ),
+        ];
+        for (policy, retained) in policies {
+            let options = Options {
+                block_cache: Arc::new(BlockCache::with_policy(256 << 10, policy)),
+                ..Options::default()
+            };
+            let temp_dir = tempfile::TempDir::new().unwrap();
+            std::fs::create_dir_all(temp_dir.path().join("1")).unwrap();
+            let path = temp_dir.path().to_str().unwrap();
+            // a small sstable, and a large one with a key in each data block, whose data blocks
+            // overflow the cache
+            let read_handles: Vec<TableReadHandle> = [(1, 10), (2, 8000)]
+                .iter()
+                .map(|&(table_id, value_size)| {
+                    let mut write_handle = TableWriteHandle::new(path, 1, table_id, 200, &options);
+                    let kvs = (0..200)
+                        .map(|i| (format!("key{:03}", i).into_bytes(), vec![b'v'; value_size]))
+                        .collect();
+                    write_handle.write_sstable_from_vec(kvs).unwrap();
+                    TableReadHandle::from_table_write_handle(write_handle)
+                })
+                .collect();
+
+            let key = b"key000".to_vec();
+            assert!(read_handles[0]
+                .query(&key, SequenceNumber::MAX)
+                .unwrap()
+                .is_some());
+            for i in 0..200 {
+                let key = format!("key{:03}", i).into_bytes();
+                assert!(read_handles[1]
+                    .query(&key, SequenceNumber::MAX)
+                    .unwrap()
+                    .is_some());
+            }
+            // the filter and index blocks of the small sstable survive the scan only in the
+            // high priority pool
+            let table_key = BlockCacheKey::table(read_handles[0].table_cache_id());
+            assert_eq!(
+                options.block_cache.look_up(&table_key).value().is_some(),
+                retained,
+                "{:?}",
+                policy
+            );
+        }
+    }
+
+    #[test]
+    fn test_secondary_cache() {
+        let secondary_cache = Arc::new(MockSecondaryCache::default());
+        let options = Options {
+            block_cache: Arc::new(BlockCache::with_secondary_cache(
+                16 << 10,
+                CachePolicy::Lru,
+                secondary_cache.clone(),
+            )),
+            ..Options::default()
+        };
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("1")).unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let mut write_handle = TableWriteHandle::new(path, 1, 1, 20000, &options);
+        // more blocks than the shards of cache, so some of them are evicted
+        let kvs = (0..20000)
+            .map(|i| {
+                (
+                    format!("key{:05}", i).into_bytes(),
+                    format!("value{:05}", i).into_bytes(),
+                )
+            })
+            .collect();
+        write_handle.write_sstable_from_vec(kvs).unwrap();
+        let read_handle = TableReadHandle::from_table_write_handle(write_handle);
+
+        for _ in 0..2 {
+            for i in 0..20000 {
+                let value = read_handle
+                    .query(&format!("key{:05}", i).into_bytes(), SequenceNumber::MAX)
+                    .unwrap();
+                assert_eq!(value, Some(format!("value{:05}", i).into_bytes()));
+            }
+        }
+        // blocks evicted from memory are read from the secondary cache in the second pass
+        assert!(!secondary_cache.blocks.lock().unwrap().is_empty());
+        assert!(secondary_cache.hits.load(Ordering::Relaxed) > 0);
+
+        // corrupted blocks in the secondary cache are read from the sstable again
+        for (i, block) in secondary_cache
+            .blocks
+            .lock()
+            .unwrap()
+            .values_mut()
+            .enumerate()
+        {
+            if i % 2 == 0 {
+                let last = block.len() - 1;
+                block[last / 2] ^= 1;
+            } else {
+                block.truncate(i % 20);
+            }
+        }
+        for i in 0..20000 {
+            let value = read_handle
+                .query(&format!("key{:05}", i).into_bytes(), SequenceNumber::MAX)
+                .unwrap();
+            assert_eq!(value, Some(format!("value{:05}", i).into_bytes()));
+        }
+    }
+}
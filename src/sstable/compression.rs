@@ -0,0 +1,276 @@
+//! Compression of data blocks.
+//!
+//! Since format version 3, each data block ends with a [CompressionType] byte, so sstables
+//! written with different codecs can be read by the same build.
+//!
+//! Since format version 4, data blocks compressed with Zstd may use a dictionary trained
+//! from the values of the sstable, or shared by the sstables of a level, which is stored in
+//! the compression dictionary block of each sstable.
+
+use crate::error::KVLiteError;
+use crate::ioutils::read_bytes_exact;
+use crate::sstable::footer::Footer;
+use crate::Result;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+
+/// Blocks are stored uncompressed unless compression saves at least 1/8 of the size.
+const MIN_COMPRESSION_RATIO_SHIFT: usize = 3;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CompressionType {
+    #[default]
+    None = 0,
+    Snappy = 1,
+    Lz4 = 2,
+    Zstd = 3,
+}
+
+impl CompressionType {
+    pub fn from_u8(value: u8) -> Result<CompressionType> {
+        match value {
+            0 => Ok(CompressionType::None),
+            1 => Ok(CompressionType::Snappy),
+            2 => Ok(CompressionType::Lz4),
+            3 => Ok(CompressionType::Zstd),
+            _ => Err(KVLiteError::Custom(format!(
+                "unknown compression type {}",
+                value
+            ))),
+        }
+    }
+
+    fn compress(self, data: &[u8], compressor: Option<&mut DictCompressor>) -> Result<Vec<u8>> {
+        Ok(match (self, compressor) {
+            (CompressionType::Zstd, Some(compressor)) => compressor.0.compress(data)?,
+            (CompressionType::None, _) => data.to_vec(),
+            (CompressionType::Snappy, _) => snap::raw::Encoder::new()
+                .compress_vec(data)
+                .map_err(|e| KVLiteError::Custom(e.to_string()))?,
+            // the uncompressed size is prepended as u32
+            (CompressionType::Lz4, _) if data.len() > u32::MAX as usize => {
+                return Err(KVLiteError::Custom("block is too large for lz4".into()));
+            }
+            (CompressionType::Lz4, _) => lz4_flex::compress_prepend_size(data),
+            (CompressionType::Zstd, None) => zstd::bulk::compress(data, 0)?,
+        })
+    }
+
+    fn decompress(self, data: &[u8], dict: Option<&CompressionDict>) -> Result<Vec<u8>> {
+        Ok(match (self, dict) {
+            (CompressionType::Zstd, Some(dict)) => dict.decompress(data)?,
+            (CompressionType::None, _) => data.to_vec(),
+            (CompressionType::Snappy, _) => snap::raw::Decoder::new()
+                .decompress_vec(data)
+                .map_err(|e| KVLiteError::Custom(e.to_string()))?,
+            (CompressionType::Lz4, _) => lz4_flex::decompress_size_prepended(data)
+                .map_err(|e| KVLiteError::Custom(e.to_string()))?,
+            (CompressionType::Zstd, None) => zstd::stream::decode_all(data)?,
+        })
+    }
+}
+
+/// Zstd compression context loaded with the dictionary of a sstable, which is created once
+/// by the writer of the sstable and reused by all its data blocks.
+pub(crate) struct DictCompressor(zstd::bulk::Compressor<'static>);
+
+impl DictCompressor {
+    pub(crate) fn new(dict: &[u8]) -> Result<DictCompressor> {
+        Ok(DictCompressor(zstd::bulk::Compressor::with_dictionary(
+            0, dict,
+        )?))
+    }
+}
+
+/// Zstd dictionary of a sstable read from its compression dictionary block, which is held
+/// by the table cache with the decompression contexts loaded with it.
+pub(crate) struct CompressionDict {
+    dict: Vec<u8>,
+    /// Idle decompression contexts, a new one is created when all of them are in use.
+    decompressors: Mutex<Vec<zstd::bulk::Decompressor<'static>>>,
+}
+
+impl CompressionDict {
+    pub(crate) fn new(dict: Vec<u8>) -> CompressionDict {
+        CompressionDict {
+            dict,
+            decompressors: Mutex::default(),
+        }
+    }
+
+    /// Size of the dictionary in bytes.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.dict.len()
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        // the content size is always written by `DictCompressor`
+        let capacity = match zstd::zstd_safe::get_frame_content_size(data) {
+            Ok(Some(size)) => size as usize,
+            _ => return Err(KVLiteError::Custom("unknown size of zstd frame".into())),
+        };
+        let idle = self.decompressors.lock().unwrap().pop();
+        let mut decompressor = match idle {
+            Some(decompressor) => decompressor,
+            None => zstd::bulk::Decompressor::with_dictionary(&self.dict)?,
+        };
+        let decompressed = decompressor.decompress(data, capacity);
+        self.decompressors.lock().unwrap().push(decompressor);
+        Ok(decompressed?)
+    }
+}
+
+/// Zstd dictionary shared by the sstables of a level, which is trained by the first sstable
+/// written to the level after the database is opened.
+pub(crate) type LevelDict = Arc<Mutex<Option<Vec<u8>>>>;
+
+/// Compress `block` with `compression_type` and append the type byte, the block is stored
+/// uncompressed if the compression ratio is poor. `compressor` is only used by Zstd.
+///
+/// ```text
+/// +-----------------------------+
+/// | block | compression type u8 |
+/// +-----------------------------+
+/// ```
+pub(crate) fn compress_block(
+    mut block: Vec<u8>,
+    compression_type: CompressionType,
+    compressor: Option<&mut DictCompressor>,
+) -> Vec<u8> {
+    if compression_type != CompressionType::None {
+        match compression_type.compress(&block, compressor) {
+            Ok(mut compressed)
+                if compressed.len()
+                    < block.len() - (block.len() >> MIN_COMPRESSION_RATIO_SHIFT) =>
+            {
+                compressed.push(compression_type as u8);
+                return compressed;
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                "failed to compress block with {:?}: {}",
+                compression_type, e
+            ),
+        }
+    }
+    block.push(CompressionType::None as u8);
+    block
+}
+
+/// Decompress the block written by [compress_block] with the dictionary of the same sstable.
+pub(crate) fn decompress_block(
+    mut block: Vec<u8>,
+    dict: Option<&CompressionDict>,
+) -> Result<Vec<u8>> {
+    let compression_type = match block.last() {
+        Some(&ty) => CompressionType::from_u8(ty)?,
+        None => return Err(KVLiteError::Custom("empty block".into())),
+    };
+    block.pop();
+    match compression_type {
+        CompressionType::None => Ok(block),
+        ty => ty.decompress(&block, dict),
+    }
+}
+
+/// Load the compression dictionary block after the range deletion block, if any.
+pub(crate) fn load_compression_dict(
+    reader: &mut (impl Read + Seek),
+    footer: &Footer,
+) -> Option<CompressionDict> {
+    if footer.compression_dict_length == 0 {
+        return None;
+    }
+    let offset = footer.index_block_offset
+        + footer.index_block_length
+        + footer.filter_length
+        + footer.range_del_length;
+    reader.seek(SeekFrom::Start(offset)).unwrap();
+    let dict = read_bytes_exact(reader, footer.compression_dict_length).unwrap();
+    Some(CompressionDict::new(dict))
+}
+
+/// Train a Zstd dictionary of at most `max_dict_bytes` from the concatenated `samples`,
+/// returns `None` if the samples are too few to train.
+pub(crate) fn train_zstd_dict(
+    samples: &[u8],
+    sample_sizes: &[usize],
+    max_dict_bytes: usize,
+) -> Option<Vec<u8>> {
+    match zstd::dict::from_continuous(samples, sample_sizes, max_dict_bytes) {
+        Ok(dict) => Some(dict),
+        Err(e) => {
+            debug!("failed to train zstd dictionary: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sstable::compression::{
+        compress_block, decompress_block, train_zstd_dict, CompressionDict, CompressionType,
+        DictCompressor,
+    };
+
+    #[test]
+    fn test_compress_block() {
+        let block: Vec<u8> = (0..4096).map(|i| (i % 7) as u8).collect();
+        for ty in [
+            CompressionType::None,
+            CompressionType::Snappy,
+            CompressionType::Lz4,
+            CompressionType::Zstd,
+        ] {
+            let compressed = compress_block(block.clone(), ty, None);
+            assert_eq!(*compressed.last().unwrap(), ty as u8);
+            if ty != CompressionType::None {
+                assert!(compressed.len() < block.len());
+            }
+            assert_eq!(decompress_block(compressed, None).unwrap(), block);
+        }
+
+        // incompressible block is stored uncompressed
+        let block: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let compressed = compress_block(block.clone(), CompressionType::Zstd, None);
+        assert_eq!(*compressed.last().unwrap(), CompressionType::None as u8);
+        assert_eq!(decompress_block(compressed, None).unwrap(), block);
+    }
+
+    #[test]
+    fn test_zstd_dict() {
+        let mut samples = vec![];
+        let mut sample_sizes = vec![];
+        for i in 0..1000 {
+            let value = format!(r#"{{"id":{},"name":"user{}","active":true}}"#, i, i % 13);
+            samples.extend_from_slice(value.as_bytes());
+            sample_sizes.push(value.len());
+        }
+        let dict = train_zstd_dict(&samples, &sample_sizes, 4096).unwrap();
+        assert!(dict.len() <= 4096);
+
+        // contexts are reused by the blocks
+        let mut compressor = DictCompressor::new(&dict).unwrap();
+        let compression_dict = CompressionDict::new(dict);
+        for i in 0..3 {
+            let block = format!(r#"{{"id":{},"name":"user3","active":true}}"#, i).into_bytes();
+            let compressed =
+                compress_block(block.clone(), CompressionType::Zstd, Some(&mut compressor));
+            assert_eq!(*compressed.last().unwrap(), CompressionType::Zstd as u8);
+            assert_eq!(
+                decompress_block(compressed, Some(&compression_dict)).unwrap(),
+                block
+            );
+        }
+        assert_eq!(compression_dict.decompressors.lock().unwrap().len(), 1);
+
+        let block = br#"{"id":42,"name":"user3","active":true}"#.to_vec();
+        // too small to compress without dictionary
+        let compressed = compress_block(block.clone(), CompressionType::Zstd, None);
+        assert_eq!(*compressed.last().unwrap(), CompressionType::None as u8);
+
+        assert!(train_zstd_dict(&samples[..10], &[10], 4096).is_none());
+    }
+}
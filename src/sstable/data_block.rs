@@ -1,177 +1,410 @@
-use crate::byteutils::u32_from_le_bytes;
-use crate::collections::skip_list::skipmap::SrSwSkipMap;
-use crate::db::key_types::{DBKey, RawUserKey};
+use crate::byteutils::{u32_from_le_bytes, u64_from_le_bytes};
+use crate::db::comparator::Comparator;
+use crate::db::key_types::{unpack_seq_num_and_type, RawUserKey, SequenceNumber};
 use crate::db::Value;
+use crate::hash::murmur_hash;
+use crate::ioutils::decode_varint;
+use crate::sstable::blob_file::ValueType;
+use crate::sstable::compression::{decompress_block, CompressionDict};
+use crate::sstable::footer::FORMAT_VERSION;
 use std::cmp::Ordering;
 use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
 
+const CHECKSUM_SEED: u32 = 0x5d1e3c27;
+
+/// Decompressed data block, see [crate::sstable] for its formats.
+///
+/// Records of format version 0 store full keys, and each of them has an offset. Records of
+/// later versions store the suffixes of keys after the prefixes shared with the previous
+/// keys, except the restart points, which store full keys and have offsets. Values of
+/// records are returned with their [ValueType], which is always [ValueType::Value] before
+/// format version 7, and their sequence numbers, which are always 0 before format version 8.
+///
+/// Since format version 8, a key may have several records, which are ordered by sequence
+/// number in descending order.
+#[derive(Default)]
 pub struct DataBlock {
     data: Vec<u8>,
-    num_records: i64,
-    data_idx_offset: usize,
+    format_version: u32,
+    /// Start of the offsets of records (version 0) or restart points.
+    offsets_start: usize,
+    /// Number of records (version 0) or restart points.
+    num_offsets: usize,
 }
 
 impl DataBlock {
     pub(super) fn from_reader(
         reader: &mut (impl Read + Seek),
-        start: u32,
-        length: u32,
-        index_offset_uncompressed: u32,
+        start: u64,
+        length: u64,
+        index_offset_uncompressed: u64,
+        format_version: u32,
+        compression_dict: Option<&CompressionDict>,
     ) -> DataBlock {
         debug_assert!(start < index_offset_uncompressed);
-        reader.seek(SeekFrom::Start(start as u64)).unwrap();
+        reader.seek(SeekFrom::Start(start)).unwrap();
         let mut data_block = vec![0u8; length as usize];
         reader.read_exact(data_block.as_mut_slice()).unwrap();
-        #[cfg(feature = "snappy_compression")]
-        {
-            let mut decoder = snap::raw::Decoder::new();
-            data_block = decoder.decompress_vec(&data_block).unwrap();
+        if format_version >= 3 {
+            data_block =
+                decompress_block(data_block, compression_dict).expect("corrupted data block");
+        } else if cfg!(feature = "snappy_compression") {
+            // data blocks of older versions are compressed with snappy by builds with
+            // `snappy_compression` feature
+            data_block = snap::raw::Decoder::new()
+                .decompress_vec(&data_block)
+                .unwrap();
         }
 
-        debug_assert_eq!(
-            (start as usize + data_block.len() - index_offset_uncompressed as usize)
-                % std::mem::size_of::<u32>(),
-            0
-        );
-        let data_block_length = data_block.len() as u32;
+        let offsets_start = (index_offset_uncompressed - start) as usize;
+        let num_offsets = if format_version == 0 {
+            debug_assert_eq!(
+                (data_block.len() - offsets_start) % std::mem::size_of::<u32>(),
+                0
+            );
+            (data_block.len() - offsets_start) / std::mem::size_of::<u32>()
+        } else {
+            let num_restarts = u32_from_le_bytes(&data_block[data_block.len() - 4..]) as usize;
+            debug_assert_eq!(offsets_start + num_restarts * 4 + 4, data_block.len());
+            num_restarts
+        };
         DataBlock {
             data: data_block,
-            num_records: (start + data_block_length - index_offset_uncompressed) as i64
-                / std::mem::size_of::<u32>() as i64,
-            data_idx_offset: (index_offset_uncompressed - start) as usize,
+            format_version,
+            offsets_start,
+            num_offsets,
+        }
+    }
+
+    #[inline]
+    fn offset_at(&self, idx: usize) -> usize {
+        let start = self.offsets_start + idx * 4;
+        u32_from_le_bytes(&self.data[start..start + 4]) as usize
+    }
+
+    /// Decode the header of the record at `offset`.
+    /// Returns (shared key length, unshared key length, value length, value type, start of
+    /// key).
+    fn decode_header(&self, offset: usize) -> (usize, usize, usize, ValueType, usize) {
+        let data = &self.data[offset..];
+        match self.format_version {
+            0 => {
+                let key_length = u32_from_le_bytes(&data[0..4]) as usize;
+                let value_length = u32_from_le_bytes(&data[4..8]) as usize;
+                (0, key_length, value_length, ValueType::Value, offset + 8)
+            }
+            1 => {
+                let shared = u32_from_le_bytes(&data[0..4]) as usize;
+                let unshared = u32_from_le_bytes(&data[4..8]) as usize;
+                let value_length = u32_from_le_bytes(&data[8..12]) as usize;
+                (
+                    shared,
+                    unshared,
+                    value_length,
+                    ValueType::Value,
+                    offset + 12,
+                )
+            }
+            _ => {
+                let (shared, n1) = decode_varint(data).expect("corrupted data block");
+                let (unshared, n2) = decode_varint(&data[n1..]).expect("corrupted data block");
+                let (value_length, n3) =
+                    decode_varint(&data[n1 + n2..]).expect("corrupted data block");
+                let (value_length, value_type) = if self.format_version >= 7 {
+                    let value_type = if value_length & 1 == 1 {
+                        ValueType::BlobIndex
+                    } else {
+                        ValueType::Value
+                    };
+                    (value_length >> 1, value_type)
+                } else {
+                    (value_length, ValueType::Value)
+                };
+                (
+                    shared as usize,
+                    unshared as usize,
+                    value_length as usize,
+                    value_type,
+                    offset + n1 + n2 + n3,
+                )
+            }
         }
     }
 
-    #[allow(clippy::ptr_arg)]
-    pub(super) fn get_value(&self, key: &RawUserKey) -> Option<Value> {
+    /// Full key of the record at the `idx`-th offset.
+    fn restart_key(&self, idx: usize) -> &[u8] {
+        let (shared, key_length, _, _, key_start) = self.decode_header(self.offset_at(idx));
+        debug_assert_eq!(shared, 0);
+        &self.data[key_start..key_start + key_length]
+    }
+
+    /// Decode the record at `offset`, `key` holds the previous key and is replaced with
+    /// the key of the record. Returns the sequence number, the range and type of value and
+    /// the offset of the next record.
+    fn decode_record(
+        &self,
+        offset: usize,
+        key: &mut RawUserKey,
+    ) -> (SequenceNumber, Range<usize>, ValueType, usize) {
+        let (shared, unshared, value_length, value_type, key_start) = self.decode_header(offset);
+        let mut value_start = key_start + unshared;
+        key.truncate(shared);
+        key.extend_from_slice(&self.data[key_start..value_start]);
+        let seq_num = if self.format_version >= 8 {
+            let packed = u64_from_le_bytes(&self.data[value_start..value_start + 8]);
+            value_start += 8;
+            unpack_seq_num_and_type(packed).0
+        } else {
+            0
+        };
+        (
+            seq_num,
+            value_start..value_start + value_length,
+            value_type,
+            value_start + value_length,
+        )
+    }
+
+    /// Offset of the last restart point whose key is less than `key`, or the first one if
+    /// `key` is less than or equal to all the keys, so that the records of `key` are all
+    /// after it.
+    fn seek_restart(&self, key: &[u8], comparator: &dyn Comparator) -> usize {
         let mut left = 0;
-        let mut right = self.num_records;
-        while left <= right {
+        let mut right = self.num_offsets;
+        while left < right {
             let mid = (left + right) / 2;
-            let record_start_offset = self.data_idx_offset + mid as usize * 4;
-
-            debug_assert!(
-                record_start_offset < self.data.len(),
-                "{}, {}",
-                record_start_offset,
-                self.data.len()
-            );
-            let record_start =
-                u32_from_le_bytes(&self.data[record_start_offset..record_start_offset + 4])
-                    as usize;
-            let key_length = u32_from_le_bytes(&self.data[record_start..record_start + 4]) as usize;
-            let key_start = record_start + 8;
-            let value_length = u32_from_le_bytes(&self.data[record_start + 4..key_start]) as usize;
-            let value_start = key_start + key_length;
-            let key_read = &self.data[key_start..value_start];
-            match key_read.cmp(key) {
+            match comparator.compare(self.restart_key(mid), key) {
                 Ordering::Less => left = mid + 1,
-                Ordering::Equal => {
-                    return Some(Value::from(
-                        &self.data[value_start..value_start + value_length],
-                    ))
-                }
-                Ordering::Greater => right = mid - 1,
+                _ => right = mid,
             }
         }
-        None
+        self.offset_at(left.saturating_sub(1))
     }
 
-    /// Return whether the data block remains keys.
-    pub(super) fn get_all_record_le<UK: DBKey>(
+    /// Get the newest record of `key` whose sequence number is less than or equal to
+    /// `seq_num`.
+    pub(super) fn get_value(
         &self,
-        key: &RawUserKey,
-        kvs: &mut SrSwSkipMap<UK, Value>,
-    ) -> bool {
-        let mut left = 0;
-        let mut right = self.num_records;
-        while left <= right {
-            let mid = (left + right + 1) / 2;
-            let record_start_offset = self.data_idx_offset + mid as usize * 4;
-
-            debug_assert!(
-                record_start_offset < self.data.len(),
-                "{}, {}",
-                record_start_offset,
-                self.data.len()
-            );
-            let record_start =
-                u32_from_le_bytes(&self.data[record_start_offset..record_start_offset + 4])
-                    as usize;
-            let key_length = u32_from_le_bytes(&self.data[record_start..record_start + 4]) as usize;
-            let key_start = record_start + 8;
-            let value_start = key_start + key_length;
-            let key_read = &self.data[key_start..value_start];
-
-            match key_read.cmp(key) {
-                Ordering::Less => left = mid,
-                Ordering::Equal => {
-                    left = mid;
-                    break;
+        key: &[u8],
+        seq_num: SequenceNumber,
+        comparator: &dyn Comparator,
+    ) -> Option<(Value, ValueType)> {
+        if self.num_offsets == 0 {
+            return None;
+        }
+        let mut offset = self.seek_restart(key, comparator);
+        let mut record_key = RawUserKey::new();
+        while offset < self.offsets_start {
+            let (record_seq_num, value_range, value_type, next_offset) =
+                self.decode_record(offset, &mut record_key);
+            match comparator.compare(&record_key, key) {
+                Ordering::Equal if record_seq_num <= seq_num => {
+                    return Some((Value::from(&self.data[value_range]), value_type));
                 }
-                Ordering::Greater => right = mid - 1,
+                Ordering::Less | Ordering::Equal => offset = next_offset,
+                Ordering::Greater => return None,
             }
         }
-        for i in 0..=(left as usize) {
-            let (key_read, value_read) = self.key_value_at(i);
-            kvs.insert(key_read.into(), value_read);
-        }
-        right < self.num_records
+        None
     }
 
-    fn key_value_at(&self, idx: usize) -> (RawUserKey, Value) {
-        let record_start_offset = self.data_idx_offset + idx as usize * 4;
-
-        debug_assert!(
-            record_start_offset < self.data.len(),
-            "{}, {}",
-            record_start_offset,
-            self.data.len()
-        );
-        let record_start =
-            u32_from_le_bytes(&self.data[record_start_offset..record_start_offset + 4]) as usize;
-        let key_length = u32_from_le_bytes(&self.data[record_start..record_start + 4]) as usize;
-        let key_start = record_start + 8;
-        let value_length = u32_from_le_bytes(&self.data[record_start + 4..key_start]) as usize;
-        let value_start = key_start + key_length;
-        let key_read = RawUserKey::from(&self.data[key_start..value_start]);
-        let value_read = Value::from(&self.data[value_start..value_start + value_length]);
-        (key_read, value_read)
+    /// Iterate the records whose keys are greater than or equal to `key`.
+    pub(super) fn seek(self, key: &[u8], comparator: &dyn Comparator) -> DataBlockIter {
+        let offset = if self.num_offsets == 0 {
+            0
+        } else {
+            self.seek_restart(key, comparator)
+        };
+        let mut iter = DataBlockIter {
+            data_block: self,
+            offset,
+            key: RawUserKey::new(),
+            peeked: None,
+        };
+        while let Some((record_key, seq_num, value, value_type)) = iter.next() {
+            if comparator.compare(&record_key, key) != Ordering::Less {
+                iter.peeked = Some((record_key, seq_num, value, value_type));
+                break;
+            }
+        }
+        iter
     }
 
+    /// Memory usage of the data block, which is charged in block cache.
     #[inline]
-    pub fn len(&self) -> usize {
-        self.num_records as usize
+    pub(super) fn charge(&self) -> usize {
+        std::mem::size_of::<DataBlock>() + self.data.capacity()
+    }
+
+    /// Encode the decompressed block for [crate::sstable::secondary_cache::SecondaryCache],
+    /// the checksum is the murmur hash of the preceding bytes.
+    ///
+    /// ```text
+    /// +----------------------------------------------------------------+
+    /// | format version | offsets start | num offsets | data | checksum |
+    /// +----------------------------------------------------------------+
+    ///   u32              u64             u32                  u32
+    /// ```
+    pub(super) fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(20 + self.data.len());
+        buf.extend_from_slice(&self.format_version.to_le_bytes());
+        buf.extend_from_slice(&(self.offsets_start as u64).to_le_bytes());
+        buf.extend_from_slice(&(self.num_offsets as u32).to_le_bytes());
+        buf.extend_from_slice(&self.data);
+        let checksum = murmur_hash(&buf, CHECKSUM_SEED);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+        buf
+    }
+
+    /// Decode the block encoded by [DataBlock::encode], return `None` if the checksum or
+    /// the offsets of the block mismatch.
+    pub(super) fn decode(buf: &[u8]) -> Option<DataBlock> {
+        if buf.len() < 20 {
+            return None;
+        }
+        let (buf, checksum) = buf.split_at(buf.len() - 4);
+        if murmur_hash(buf, CHECKSUM_SEED) != u32_from_le_bytes(checksum) {
+            return None;
+        }
+        let format_version = u32_from_le_bytes(&buf[0..4]);
+        let offsets_start = u64_from_le_bytes(&buf[4..12]) as usize;
+        let num_offsets = u32_from_le_bytes(&buf[12..16]) as usize;
+        let data = &buf[16..];
+        // the number of restart points follows the offsets since format version 1
+        let trailer = if format_version == 0 { 0 } else { 4 };
+        let offsets_end = offsets_start
+            .checked_add(num_offsets * 4 + trailer)
+            .filter(|&end| end == data.len());
+        if format_version > FORMAT_VERSION || offsets_end.is_none() {
+            return None;
+        }
+        Some(DataBlock {
+            format_version,
+            offsets_start,
+            num_offsets,
+            data: data.to_vec(),
+        })
     }
 }
 
 impl IntoIterator for DataBlock {
-    type Item = (RawUserKey, Value);
+    type Item = (RawUserKey, SequenceNumber, Value, ValueType);
     type IntoIter = DataBlockIter;
 
     fn into_iter(self) -> Self::IntoIter {
         DataBlockIter {
             data_block: self,
-            idx: 0,
+            offset: 0,
+            key: RawUserKey::new(),
+            peeked: None,
         }
     }
 }
 
 pub struct DataBlockIter {
     data_block: DataBlock,
-    idx: usize,
+    /// Offset of the next record.
+    offset: usize,
+    /// Key of the last decoded record.
+    key: RawUserKey,
+    /// Record decoded by [DataBlock::seek].
+    peeked: Option<(RawUserKey, SequenceNumber, Value, ValueType)>,
 }
 
 impl Iterator for DataBlockIter {
-    type Item = (RawUserKey, Value);
+    type Item = (RawUserKey, SequenceNumber, Value, ValueType);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.idx < self.data_block.len() {
-            let record = self.data_block.key_value_at(self.idx);
-            self.idx += 1;
-            Some(record)
+        if let Some(record) = self.peeked.take() {
+            return Some(record);
+        }
+        if self.offset < self.data_block.offsets_start {
+            let (seq_num, value_range, value_type, next_offset) =
+                self.data_block.decode_record(self.offset, &mut self.key);
+            self.offset = next_offset;
+            Some((
+                self.key.clone(),
+                seq_num,
+                Value::from(&self.data_block.data[value_range]),
+                value_type,
+            ))
         } else {
             None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::db::key_types::{RawUserKey, SequenceNumber};
+    use crate::db::options::Options;
+    use crate::sstable::compression::CompressionType;
+    use crate::sstable::data_block::DataBlock;
+    use crate::sstable::footer::{Footer, FORMAT_VERSION};
+    use crate::sstable::index_block::IndexBlock;
+    use crate::sstable::table_handle::{TableReadHandle, TableWriteHandle};
+
+    #[test]
+    #[ignore = "holds values of more than 4GB in memory"]
+    fn test_value_beyond_u32() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("1")).unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        let value_length = u32::MAX as usize + 16;
+
+        // blocks too large for lz4 are stored uncompressed
+        for (table_id, compression) in [CompressionType::Zstd, CompressionType::Lz4]
+            .iter()
+            .enumerate()
+        {
+            let options = Options {
+                compression: *compression,
+                ..Options::default()
+            };
+            let mut write_handle = TableWriteHandle::new(&path, 1, table_id as u64, 2, &options);
+            let kvs = vec![
+                (b"key0".to_vec(), vec![0u8; value_length]),
+                (b"key1".to_vec(), b"value1".to_vec()),
+            ];
+            write_handle.write_sstable_from_vec(kvs).unwrap();
+            let read_handle = TableReadHandle::from_table_write_handle(write_handle);
+
+            let mut reader = read_handle.create_buf_reader_with_pos();
+            let footer = Footer::load_footer(&mut reader).unwrap();
+            let index_block = IndexBlock::load_index(&mut reader, &footer);
+            assert_eq!(index_block.indexes.len(), 2);
+            let (offset, length, index_offset, ..) = index_block.indexes[0];
+            assert!(index_offset - offset > u32::MAX as u64);
+            if *compression == CompressionType::Lz4 {
+                assert!(length > u32::MAX as u64);
+            }
+
+            // values are not copied out of the block to keep the memory usage low
+            let data_block = DataBlock::from_reader(
+                &mut reader,
+                offset,
+                length,
+                index_offset,
+                FORMAT_VERSION,
+                None,
+            );
+            let mut key = RawUserKey::new();
+            let (_, value_range, _, next_offset) = data_block.decode_record(0, &mut key);
+            assert_eq!(key, b"key0".to_vec());
+            assert_eq!(value_range.len(), value_length);
+            assert_eq!(next_offset, data_block.offsets_start);
+            assert!(data_block.data[value_range.end - 16..value_range.end]
+                .iter()
+                .all(|b| *b == 0));
+            drop(data_block);
+
+            assert_eq!(
+                read_handle
+                    .query(&b"key1".to_vec(), SequenceNumber::MAX)
+                    .unwrap(),
+                Some(b"value1".to_vec())
+            );
+        }
+    }
+}
@@ -1,75 +1,211 @@
+use crate::byteutils::{u32_from_le_bytes, u64_from_le_bytes};
 use crate::error::KVLiteError;
-use crate::ioutils::BufWriterWithPos;
+use crate::filter::{BlockedBloomFilterPolicy, FilterPolicy};
 use crate::Result;
-use std::fs::File;
 use std::io::{Read, Seek, SeekFrom, Write};
 
-pub const FOOTER_MAGIC_NUMBER: u32 = 0xdb991122;
-pub const FOOTER_BYTE_SIZE: i64 = 20;
+/// Magic number of the footer without filter policy name, whose filter block is always
+/// built by [BlockedBloomFilterPolicy].
+pub const LEGACY_FOOTER_MAGIC_NUMBER: u32 = 0xdb991122;
+/// Magic number of the footer without format version, whose format version is 0.
+pub const UNVERSIONED_FOOTER_MAGIC_NUMBER: u32 = 0xdb991123;
+pub const FOOTER_MAGIC_NUMBER: u32 = 0xdb991124;
+/// Byte size of the footer except the filter policy name.
+pub const FOOTER_BYTE_SIZE: i64 = 68;
+/// Byte size of the footer without properties length, since format version 5.
+const FOOTER_V5_BYTE_SIZE: i64 = 60;
+/// Byte size of the footer of u32 fields, since format version 4.
+const FOOTER_V4_BYTE_SIZE: i64 = 36;
+/// Byte size of the footer without compression dictionary length, before format version 4.
+const FOOTER_V3_BYTE_SIZE: i64 = 32;
+const UNVERSIONED_FOOTER_BYTE_SIZE: i64 = 28;
+const LEGACY_FOOTER_BYTE_SIZE: i64 = 24;
+
+/// Format version of the sstables written, see [crate::sstable] for the formats.
+///
+/// * 0: data blocks store full keys and the offset of each record.
+/// * 1: data blocks store keys sharing prefixes with the previous keys, and the offsets of
+///   restart points.
+/// * 2: lengths in data block entries and index block are varints.
+/// * 3: data blocks end with the type of their compression.
+/// * 4: Zstd compression dictionary block after the range deletion block.
+/// * 5: offsets, lengths and kv_total in the footer are u64.
+/// * 6: properties block after the compression dictionary block.
+/// * 7: the lowest bit of value lengths in data blocks marks blob indexes, and the properties
+///   block records the referenced blob files.
+/// * 8: keys in data blocks are followed by their sequence numbers and types, and a key may
+///   have several records.
+/// * 9: filter blocks written with a prefix extractor record whether any key is not in its
+///   domain.
+/// * 10: range tombstones are followed by their sequence numbers.
+pub const FORMAT_VERSION: u32 = 10;
 
 pub(crate) struct Footer {
-    pub(crate) index_block_offset: u32,
-    pub(crate) index_block_length: u32,
-    pub(crate) filter_length: u32,
-    pub(crate) kv_total: u32,
+    pub(crate) index_block_offset: u64,
+    pub(crate) index_block_length: u64,
+    pub(crate) filter_length: u64,
+    pub(crate) range_del_length: u64,
+    pub(crate) kv_total: u64,
+    /// Length of the compression dictionary block, 0 if data blocks are compressed
+    /// without dictionary.
+    pub(crate) compression_dict_length: u64,
+    /// Length of the properties block, 0 before format version 6.
+    pub(crate) properties_length: u64,
+    /// Name of the filter policy that builds the filter block.
+    pub(crate) filter_policy: String,
+    pub(crate) format_version: u32,
 }
 
 impl Footer {
     pub(crate) fn write_to_file(&self, writer: &mut (impl Write + Seek)) -> Result<()> {
+        writer.write_all(self.filter_policy.as_bytes())?;
         writer.write_all(&self.index_block_offset.to_le_bytes())?;
         writer.write_all(&self.index_block_length.to_le_bytes())?;
         writer.write_all(&self.filter_length.to_le_bytes())?;
+        writer.write_all(&self.range_del_length.to_le_bytes())?;
         writer.write_all(&self.kv_total.to_le_bytes())?;
+        writer.write_all(&self.compression_dict_length.to_le_bytes())?;
+        writer.write_all(&self.properties_length.to_le_bytes())?;
+        writer.write_all(&self.format_version.to_le_bytes())?;
+        writer.write_all(&(self.filter_policy.len() as u32).to_le_bytes())?;
         writer.write_all(&FOOTER_MAGIC_NUMBER.to_le_bytes())?;
         Ok(())
     }
 
+    /// Offset of the properties block, which is after the compression dictionary block.
+    pub(crate) fn properties_offset(&self) -> u64 {
+        self.index_block_offset
+            + self.index_block_length
+            + self.filter_length
+            + self.range_del_length
+            + self.compression_dict_length
+    }
+
     pub(crate) fn load_footer(reader: &mut (impl Read + Seek)) -> Result<Footer> {
-        reader.seek(SeekFrom::End(-FOOTER_BYTE_SIZE))?;
+        // footers of older versions are shorter, so the tail of the file is read into the
+        // end of the buffer
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        let read_size = file_size.min(FOOTER_BYTE_SIZE as u64) as usize;
+        let mut buffer = [0u8; FOOTER_BYTE_SIZE as usize];
+        reader.seek(SeekFrom::End(-(read_size as i64)))?;
+        reader.read_exact(&mut buffer[FOOTER_BYTE_SIZE as usize - read_size..])?;
+
+        // validate magic number
+        let magic_number = u32_from_le_bytes(&buffer[64..68]);
+        let (fields, format_version, filter_policy) = if magic_number == FOOTER_MAGIC_NUMBER {
+            let format_version = u32_from_le_bytes(&buffer[56..60]);
+            if format_version > FORMAT_VERSION {
+                return Err(KVLiteError::Custom(format!(
+                    "unsupported sstable format version {}",
+                    format_version
+                )));
+            }
+            let footer_length = if format_version >= 6 {
+                FOOTER_BYTE_SIZE
+            } else if format_version == 5 {
+                FOOTER_V5_BYTE_SIZE
+            } else if format_version == 4 {
+                FOOTER_V4_BYTE_SIZE
+            } else {
+                FOOTER_V3_BYTE_SIZE
+            };
+            let filter_policy = Self::load_filter_policy(reader, &buffer[60..64], footer_length)?;
+            let fields_start = (FOOTER_BYTE_SIZE - footer_length) as usize;
+            let fields = if format_version >= 5 {
+                buffer[fields_start..56]
+                    .chunks(8)
+                    .map(u64_from_le_bytes)
+                    .collect()
+            } else {
+                u32_fields(&buffer[fields_start..56])
+            };
+            (fields, format_version, filter_policy)
+        } else if magic_number == UNVERSIONED_FOOTER_MAGIC_NUMBER {
+            let offset = (FOOTER_BYTE_SIZE - UNVERSIONED_FOOTER_BYTE_SIZE) as usize;
+            let filter_policy =
+                Self::load_filter_policy(reader, &buffer[60..64], UNVERSIONED_FOOTER_BYTE_SIZE)?;
+            (u32_fields(&buffer[offset..offset + 20]), 0, filter_policy)
+        } else if magic_number == LEGACY_FOOTER_MAGIC_NUMBER {
+            let offset = (FOOTER_BYTE_SIZE - LEGACY_FOOTER_BYTE_SIZE) as usize;
+            (
+                u32_fields(&buffer[offset..offset + 20]),
+                0,
+                BlockedBloomFilterPolicy.name().to_string(),
+            )
+        } else {
+            return Err(KVLiteError::Custom("invalid footer magic number".into()));
+        };
 
-        let mut buffer = [0u8; 20];
-        reader.read_exact(&mut buffer).unwrap();
+        Ok(Footer {
+            index_block_offset: fields[0],
+            index_block_length: fields[1],
+            filter_length: fields[2],
+            range_del_length: fields[3],
+            kv_total: fields[4],
+            // footers before format version 4 have no compression dictionary length
+            compression_dict_length: fields.get(5).copied().unwrap_or(0),
+            // footers before format version 6 have no properties length
+            properties_length: fields.get(6).copied().unwrap_or(0),
+            filter_policy,
+            format_version,
+        })
+    }
 
-        let mut index_block_offset = [0u8; 4];
-        index_block_offset.clone_from_slice(&buffer[0..4]);
+    /// Read the filter policy name before the `footer_length` bytes of fixed-length fields.
+    fn load_filter_policy(
+        reader: &mut (impl Read + Seek),
+        name_length: &[u8],
+        footer_length: i64,
+    ) -> Result<String> {
+        let mut filter_policy_length = [0u8; 4];
+        filter_policy_length.clone_from_slice(name_length);
+        let filter_policy_length = u32::from_le_bytes(filter_policy_length) as i64;
 
-        let mut index_block_length = [0u8; 4];
-        index_block_length.clone_from_slice(&buffer[4..8]);
+        let mut filter_policy = vec![0u8; filter_policy_length as usize];
+        reader.seek(SeekFrom::End(-footer_length - filter_policy_length))?;
+        reader.read_exact(&mut filter_policy)?;
+        String::from_utf8(filter_policy)
+            .map_err(|_| KVLiteError::Custom("invalid filter policy name".into()))
+    }
+}
 
-        let mut filter_length = [0u8; 4];
-        filter_length.clone_from_slice(&buffer[8..12]);
+/// Fields of the footers before format version 5.
+fn u32_fields(buf: &[u8]) -> Vec<u64> {
+    buf.chunks(4)
+        .map(|field| u32_from_le_bytes(field) as u64)
+        .collect()
+}
 
-        let mut kv_total = [0u8; 4];
-        kv_total.clone_from_slice(&buffer[12..16]);
+#[cfg(test)]
+mod tests {
+    use crate::sstable::footer::{Footer, FORMAT_VERSION};
+    use std::io::Cursor;
 
+    #[test]
+    fn test_footer_beyond_u32() {
         let footer = Footer {
-            index_block_offset: u32::from_le_bytes(index_block_offset),
-            index_block_length: u32::from_le_bytes(index_block_length),
-            filter_length: u32::from_le_bytes(filter_length),
-            kv_total: u32::from_le_bytes(kv_total),
+            index_block_offset: 6 << 30,
+            index_block_length: 5 << 30,
+            filter_length: 100,
+            range_del_length: 0,
+            kv_total: u32::MAX as u64 + 1,
+            compression_dict_length: 16,
+            properties_length: 80,
+            filter_policy: "bloom".to_string(),
+            format_version: FORMAT_VERSION,
         };
+        let mut file = Cursor::new(vec![0u8; 100]);
+        file.set_position(100);
+        footer.write_to_file(&mut file).unwrap();
 
-        // validate magic number
-        if buffer[16..20] != FOOTER_MAGIC_NUMBER.to_le_bytes() {
-            return Err(KVLiteError::Custom("invalid footer magic number".into()));
-        }
-
-        Ok(footer)
+        let loaded = Footer::load_footer(&mut file).unwrap();
+        assert_eq!(loaded.index_block_offset, 6 << 30);
+        assert_eq!(loaded.index_block_length, 5 << 30);
+        assert_eq!(loaded.filter_length, 100);
+        assert_eq!(loaded.kv_total, u32::MAX as u64 + 1);
+        assert_eq!(loaded.compression_dict_length, 16);
+        assert_eq!(loaded.properties_length, 80);
+        assert_eq!(loaded.filter_policy, "bloom");
+        assert_eq!(loaded.format_version, FORMAT_VERSION);
     }
 }
-
-pub(super) fn write_footer(
-    index_block_offset: u32,
-    index_block_length: u32,
-    writer: &mut BufWriterWithPos<File>,
-    filter_length: u32,
-    kv_total: u32,
-) {
-    let footer = Footer {
-        index_block_offset,
-        index_block_length,
-        filter_length,
-        kv_total,
-    };
-    footer.write_to_file(writer).unwrap();
-}
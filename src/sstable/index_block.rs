@@ -1,5 +1,7 @@
+use crate::byteutils::u32_from_le_bytes;
+use crate::db::comparator::Comparator;
 use crate::db::key_types::RawUserKey;
-use crate::ioutils::{read_bytes_exact, read_u32};
+use crate::ioutils::{decode_varint, put_varint, read_bytes_exact};
 use crate::sstable::footer::Footer;
 use crate::Result;
 use std::io::{Read, Seek, SeekFrom, Write};
@@ -8,15 +10,15 @@ use std::io::{Read, Seek, SeekFrom, Write};
 pub struct IndexBlock {
     pub(crate) min_key: RawUserKey,
     /// offset, length, index_offset_uncompressed, max key length, max key
-    pub(crate) indexes: Vec<(u32, u32, u32, u32, RawUserKey)>,
+    pub(crate) indexes: Vec<(u64, u64, u64, u32, RawUserKey)>,
 }
 
 impl IndexBlock {
     pub(crate) fn add_index(
         &mut self,
-        offset: u32,
-        length: u32,
-        index_offset_uncompressed: u32,
+        offset: u64,
+        length: u64,
+        index_offset_uncompressed: u64,
         max_key: RawUserKey,
     ) {
         debug_assert!(offset < index_offset_uncompressed);
@@ -29,41 +31,57 @@ impl IndexBlock {
         ));
     }
 
+    /// Write the index block in the current format version, in which all the lengths and
+    /// offsets are varints.
     pub(crate) fn write_to_file(&mut self, writer: &mut (impl Write + Seek)) -> Result<()> {
-        let min_key_len = self.min_key.len() as u32;
-        debug_assert_ne!(min_key_len, 0);
-        writer.write_all(&min_key_len.to_le_bytes()).unwrap();
-        writer.write_all(&self.min_key).unwrap();
+        let mut buf = Vec::new();
+        put_varint(&mut buf, self.min_key.len() as u64);
+        buf.extend_from_slice(&self.min_key);
         for index in &self.indexes {
-            writer.write_all(&index.0.to_le_bytes())?;
-            writer.write_all(&index.1.to_le_bytes())?;
-            writer.write_all(&index.2.to_le_bytes())?;
-            writer.write_all(&index.3.to_le_bytes())?;
-            writer.write_all(&index.4)?;
+            put_varint(&mut buf, index.0);
+            put_varint(&mut buf, index.1);
+            put_varint(&mut buf, index.2);
+            put_varint(&mut buf, index.3 as u64);
+            buf.extend_from_slice(&index.4);
         }
+        writer.write_all(&buf)?;
         Ok(())
     }
 
     pub(crate) fn load_index<R: Read + Seek>(reader: &mut R, footer: &Footer) -> IndexBlock {
         reader
-            .seek(SeekFrom::Start(footer.index_block_offset as u64))
+            .seek(SeekFrom::Start(footer.index_block_offset))
             .unwrap();
+        let data = read_bytes_exact(reader, footer.index_block_length).unwrap();
 
-        let mut index_block = IndexBlock::default();
+        // lengths and offsets are u32 before format version 2
+        let read_number = |offset: &mut usize| -> u64 {
+            if footer.format_version < 2 {
+                let n = u32_from_le_bytes(&data[*offset..*offset + 4]);
+                *offset += 4;
+                n as u64
+            } else {
+                let (n, length) = decode_varint(&data[*offset..]).expect("corrupted index block");
+                *offset += length;
+                n
+            }
+        };
 
-        let min_key_length = read_u32(reader).unwrap();
-        let min_key = read_bytes_exact(reader, min_key_length as u64).unwrap();
-        let mut offset: u32 = (std::mem::size_of::<u32>() + min_key.len()) as u32;
-        index_block.min_key = min_key;
-        debug_assert!(offset < footer.index_block_length);
-        while offset < footer.index_block_length {
-            let block_offset = read_u32(reader).unwrap();
-            let block_length = read_u32(reader).unwrap();
-            let index_offset_uncompressed = read_u32(reader).unwrap();
+        let mut index_block = IndexBlock::default();
+        let mut offset = 0;
+        let min_key_length = read_number(&mut offset) as usize;
+        index_block.min_key = data[offset..offset + min_key_length].to_vec();
+        offset += min_key_length;
+        debug_assert!(offset <= data.len());
+        while offset < data.len() {
+            let block_offset = read_number(&mut offset);
+            let block_length = read_number(&mut offset);
+            let index_offset_uncompressed = read_number(&mut offset);
             debug_assert!(block_offset < index_offset_uncompressed);
-            let max_key_length = read_u32(reader).unwrap();
+            let max_key_length = read_number(&mut offset) as u32;
 
-            let max_key = read_bytes_exact(reader, max_key_length as u64).unwrap();
+            let max_key = data[offset..offset + max_key_length as usize].to_vec();
+            offset += max_key_length as usize;
             index_block.indexes.push((
                 block_offset,
                 block_length,
@@ -71,36 +89,49 @@ impl IndexBlock {
                 max_key_length,
                 max_key,
             ));
-
-            offset += 16 + max_key_length;
         }
         index_block
     }
 
     /// Returns (offset, length)
-    pub(crate) fn may_contain_key(&self, key: &RawUserKey) -> Option<(u32, u32, u32)> {
-        self.binary_search(key)
+    pub(crate) fn may_contain_key(
+        &self,
+        key: &[u8],
+        comparator: &dyn Comparator,
+    ) -> Option<(u64, u64, u64)> {
+        self.binary_search(key, comparator)
     }
 
-    /// Get maximum key from [SSTableIndex]
-    pub(crate) fn max_key(&self) -> &RawUserKey {
-        let last = self.indexes.last().unwrap_or_else(|| unsafe {
-            std::hint::unreachable_unchecked();
-        });
-        &last.4
+    /// Get maximum key from [SSTableIndex], return `None` if there is no data block.
+    pub(crate) fn max_key(&self) -> Option<&RawUserKey> {
+        self.indexes.last().map(|last| &last.4)
     }
 
     /// Find the first data block whose max key is greater or equal to `key`
     /// Returns (offset, length, index_offset)
-    pub(crate) fn binary_search(&self, key: &RawUserKey) -> Option<(u32, u32, u32)> {
-        match self.indexes.binary_search_by(|probe| probe.4.cmp(key)) {
+    pub(crate) fn binary_search(
+        &self,
+        key: &[u8],
+        comparator: &dyn Comparator,
+    ) -> Option<(u64, u64, u64)> {
+        match self
+            .indexes
+            .binary_search_by(|probe| comparator.compare(&probe.4, key))
+        {
             Ok(i) | Err(i) => self.indexes.get(i).map(|e| (e.0, e.1, e.2)),
         }
     }
 
     /// Find all the first data block whose max key is greater or equal to `key`
-    pub(crate) fn find_all_ge(&self, key: &RawUserKey) -> &[(u32, u32, u32, u32, RawUserKey)] {
-        match self.indexes.binary_search_by(|probe| probe.4.cmp(key)) {
+    pub(crate) fn find_all_ge(
+        &self,
+        key: &[u8],
+        comparator: &dyn Comparator,
+    ) -> &[(u64, u64, u64, u32, RawUserKey)] {
+        match self
+            .indexes
+            .binary_search_by(|probe| comparator.compare(&probe.4, key))
+        {
             Ok(i) | Err(i) => &self.indexes[i..],
         }
     }
@@ -108,10 +139,12 @@ impl IndexBlock {
 
 #[test]
 fn test_may_contain_key() {
+    use crate::db::comparator::BytewiseComparator;
+
     let mut index = IndexBlock::default();
     index.indexes.push((1, 1, 1, 1, "key298".into()));
-    let option = index.may_contain_key(&Vec::from("key299"));
+    let option = index.may_contain_key(b"key299", &BytewiseComparator);
     assert!(option.is_none());
-    let option = index.may_contain_key(&Vec::from("key298"));
+    let option = index.may_contain_key(b"key298", &BytewiseComparator);
     assert!(option.is_some());
 }
@@ -1,13 +1,13 @@
-use crate::cache::{LRUEntry, ShardLRUCache};
 use crate::collections::skip_list::skipmap::SrSwSkipMap;
 use crate::compaction::level_0::{compact_and_insert, LEVEL0_FILES_THRESHOLD};
-use crate::db::key_types::{DBKey, RawUserKey};
+use crate::db::db_iter::{InternalKeyValue, IteratorStatus};
+use crate::db::key_types::{DBKey, RawUserKey, SequenceNumber};
+use crate::db::slice_transform::KeyPrefix;
 use crate::db::Value;
 use crate::memory::MemTable;
-use crate::sstable::manager::level_iter::Level0Iterator;
+use crate::sstable::manager::level_iter::{Level0Iterator, MergingIterator};
 use crate::sstable::manager::level_n::LevelNManager;
-use crate::sstable::table_cache::TableCache;
-use crate::sstable::table_handle::{TableReadHandle, TableWriteHandle};
+use crate::sstable::table_handle::{TableIterator, TableReadHandle, TableWriteHandle};
 use crate::sstable::{TableID, NUM_LEVEL0_TABLE_TO_COMPACT};
 use crate::wal::WAL;
 use crate::Result;
@@ -29,6 +29,8 @@ pub struct Level0Manager<SK: DBKey, UK: DBKey, M: MemTable<SK, UK>, L: WAL<SK, U
 
     level0_tables: std::sync::RwLock<BTreeMap<TableID, Arc<TableReadHandle>>>,
     file_size: AtomicU64,
+    /// Table IDs are increasing, newer sstables have larger IDs.
+    next_table_id: AtomicU64,
 
     table_manager: std::sync::Arc<LevelNManager>,
     sender: crossbeam_channel::Sender<bool>,
@@ -37,7 +39,6 @@ pub struct Level0Manager<SK: DBKey, UK: DBKey, M: MemTable<SK, UK>, L: WAL<SK, U
     wal: Arc<Mutex<L>>,
 
     handle: Arc<Mutex<Option<JoinHandle<()>>>>,
-    table_cache: Arc<ShardLRUCache<TableID, TableCache>>,
 
     background_task_write_to_level0_is_running: Arc<AtomicBool>,
     _phantom_key: PhantomData<SK>,
@@ -54,7 +55,6 @@ where
         db_path: String,
         table_manager: Arc<LevelNManager>,
         wal: Arc<Mutex<L>>,
-        index_cache: Arc<ShardLRUCache<TableID, TableCache>>,
         background_task_write_to_level0_is_running: Arc<AtomicBool>,
     ) -> Result<Arc<Level0Manager<SK, UK, M, L>>> {
         std::fs::create_dir_all(format!("{}/0", db_path)).unwrap();
@@ -74,7 +74,8 @@ where
             if let Ok(table_id) = table_id {
                 file_size += d.metadata().unwrap().len();
 
-                let handle = TableReadHandle::open(&db_path, 0, table_id);
+                let handle = TableReadHandle::open(&db_path, 0, table_id, table_manager.options())?
+                    .with_blob_store(table_manager.blob_store())?;
                 level0_tables.insert(handle.table_id(), Arc::new(handle));
             } else {
                 // remove temporary file.
@@ -82,16 +83,21 @@ where
             }
         }
 
+        // sstables of all the levels are opened
+        table_manager.blob_store().remove_unreferenced_files()?;
+
         let (sender, receiver) = crossbeam_channel::unbounded();
         let level0_manager = Arc::new(Level0Manager {
             db_path,
+            next_table_id: AtomicU64::new(
+                level0_tables.last_key_value().map_or(1, |(id, _)| id + 1),
+            ),
             level0_tables: std::sync::RwLock::new(level0_tables),
             file_size: AtomicU64::new(file_size),
             table_manager,
             sender,
             wal,
             handle: Arc::new(Mutex::new(None)),
-            table_cache: index_cache,
             background_task_write_to_level0_is_running,
             _phantom_table: PhantomData,
             _phantom_uk: PhantomData,
@@ -112,7 +118,6 @@ where
         leveln_manager: Arc<LevelNManager>,
         wal: Arc<Mutex<L>>,
         imm_mem_table: Arc<ArcSwap<M>>,
-        index_cache: Arc<ShardLRUCache<TableID, TableCache>>,
         recv: Receiver<()>,
         background_task_write_to_level0_is_running: Arc<AtomicBool>,
     ) -> (Arc<Level0Manager<SK, UK, M, L>>, JoinHandle<()>) {
@@ -120,7 +125,6 @@ where
             db_path,
             leveln_manager,
             wal,
-            index_cache,
             background_task_write_to_level0_is_running,
         )
         .unwrap();
@@ -157,10 +161,13 @@ where
         (manager, handle)
     }
 
-    /// Persistently write the `table` to disk.
+    /// Persistently write the `table` to disk, the versions read by the alive snapshots
+    /// are retained.
     fn write_to_table(&self, table: Arc<M>) -> Result<()> {
-        let mut handle = self.create_table_write_handle(table.len() as u32);
-        handle.write_sstable(table.deref())?;
+        let snapshots = self.table_manager.snapshots().to_vec();
+        let mut handle = self.create_table_write_handle(table.len() as u64);
+        handle.add_range_tombstones(table.range_tombstones().to_vec());
+        handle.write_sstable(table.deref(), &snapshots)?;
         self.insert_table_handle(handle);
         self.delete_imm_table_log()?;
         self.may_compact();
@@ -225,68 +232,143 @@ where
     pub fn get_level0_iterator(&self) -> Level0Iterator {
         let guard = self.level0_tables.read().unwrap();
         let tables = &*guard;
-        Level0Iterator::new(tables)
+        Level0Iterator::new(tables, self.table_manager.comparator().clone())
+    }
+
+    /// Iterate the key-value pairs in level0, the sstables without keys of `key_prefix`
+    /// are not read, but their range tombstones are kept.
+    pub fn get_level0_prefix_iterator(
+        &self,
+        key_prefix: &KeyPrefix,
+    ) -> MergingIterator<Box<dyn Iterator<Item = InternalKeyValue>>> {
+        let guard = self.level0_tables.read().unwrap();
+        let mut iterators: Vec<Box<dyn Iterator<Item = InternalKeyValue>>> =
+            Vec::with_capacity(guard.len());
+        let mut range_tombstones = Vec::with_capacity(guard.len());
+        let status = IteratorStatus::default();
+        for table in guard.values() {
+            let iterator = if table.may_contain_prefix(key_prefix) {
+                TableIterator::new(table.clone(), status.clone())
+                    .map_err(|e| status.set_error(e))
+                    .ok()
+            } else {
+                None
+            };
+            match iterator {
+                Some(iterator) => iterators.push(Box::new(iterator)),
+                None => iterators.push(Box::new(std::iter::empty())),
+            }
+            range_tombstones.push(table.range_tombstones().to_vec());
+        }
+        MergingIterator::with_range_tombstones(
+            iterators,
+            range_tombstones,
+            vec![status],
+            self.table_manager.comparator().clone(),
+        )
     }
 
+    /// Query the newest key-values visible at `seq_num` in [`key_start`, `key_end`].
     pub fn range_query(
         &self,
         key_start: &RawUserKey,
         key_end: &RawUserKey,
+        seq_num: SequenceNumber,
         kvs: &mut SrSwSkipMap<UK, Value>,
-    ) {
+    ) -> Result<()> {
+        let range_prefix = self.table_manager.range_prefix(key_start, key_end);
         let tables_guard = self.level0_tables.read().unwrap();
 
-        // query the latest table first
-        for table in tables_guard.values().rev() {
-            table.range_query(key_start, key_end, kvs);
+        // query the oldest table first, so that newer key-values and range tombstones
+        // overwrite the older ones.
+        for table in tables_guard.values() {
+            table.range_query(key_start, key_end, seq_num, range_prefix.as_ref(), kvs)?;
         }
+        Ok(())
     }
 
-    pub fn query(&self, key: &RawUserKey) -> Result<Option<Value>> {
+    /// Query the newest value of `key` visible at `seq_num`.
+    pub fn query(&self, key: &RawUserKey, seq_num: SequenceNumber) -> Result<Option<Value>> {
         let tables_guard = self.level0_tables.read().unwrap();
 
-        // query the latest table first
+        // query the latest table first, the first table having the key holds its newest
+        // version. Tables are ordered by recency rather than sequence number, since plain
+        // writes are at sequence number 0 while ingested tables have greater ones.
         for table in tables_guard.values().rev() {
-            // get cache
-            let entry_tracker = self.table_cache.look_up(&table.table_key(), table.hash());
-            let option = if !entry_tracker.0.is_null() {
-                let mut table_cache =
-                    unsafe { (*(entry_tracker.0 as *mut LRUEntry<u64, TableCache>)).value_mut() };
-                table.query_sstable_with_cache(key, &mut table_cache)
-            } else {
-                table.query_sstable(key, &self.table_cache)
-            };
+            let option = table.query(key, seq_num)?;
 
             if option.is_some() {
                 return Ok(option);
             }
+            if table.range_tombstones_cover(key, seq_num) {
+                return Ok(Some(Value::default()));
+            }
         }
         Ok(None)
     }
 
     fn get_next_table_id(&self) -> TableID {
-        let table_guard = self.level0_tables.read().unwrap();
-        match table_guard.last_key_value() {
-            Some((k, _v)) => k + 1,
-            None => 1,
-        }
+        self.next_table_id.fetch_add(1, Ordering::SeqCst)
     }
 
     fn insert_table_handle(&self, handle: TableWriteHandle) {
-        let file_size = handle.writer.writer.pos;
-        debug_assert!(file_size > 0);
+        debug_assert!(handle.writer.writer.pos > 0);
         debug_assert_eq!(handle.level(), 0);
+        self.insert_read_handle(TableReadHandle::from_table_write_handle(handle));
+    }
 
-        let handle = Arc::new(TableReadHandle::from_table_write_handle(handle));
+    fn insert_read_handle(&self, handle: TableReadHandle) {
+        let file_size = handle.file_size();
         let mut table_guard = self.level0_tables.write().unwrap();
-
-        table_guard.insert(handle.table_id(), handle);
+        table_guard.insert(handle.table_id(), Arc::new(handle));
         self.file_size.fetch_add(file_size, Ordering::Release);
     }
 
-    pub fn create_table_write_handle(&self, kv_total: u32) -> TableWriteHandle {
+    /// Whether any sstable in level0 may have keys in [`min_key`, `max_key`].
+    pub(crate) fn is_overlapping(&self, min_key: &[u8], max_key: &[u8]) -> bool {
+        let guard = self.level0_tables.read().unwrap();
+        guard
+            .values()
+            .any(|table| table.is_overlapping(min_key, max_key))
+    }
+
+    /// Move the external sstable staged at `staged_path` by
+    /// [crate::sstable::sst_file_writer::copy_with_global_seq_num] into
+    /// level0 as the newest sstable.
+    pub(crate) fn ingest_file(&self, staged_path: &str) -> Result<()> {
+        let table_id = self.get_next_table_id();
+        let table_path = format!("{}/0/{}", self.db_path, table_id);
+        std::fs::rename(staged_path, &table_path)?;
+        let handle = TableReadHandle::try_open_file(
+            table_path.clone(),
+            0,
+            table_id,
+            self.table_manager.options(),
+        )
+        .and_then(|handle| handle.with_blob_store(self.table_manager.blob_store()));
+        let handle = match handle {
+            Ok(handle) => handle,
+            Err(e) => {
+                let _ = std::fs::remove_file(&table_path);
+                return Err(e);
+            }
+        };
+        self.insert_read_handle(handle);
+        self.may_compact();
+        Ok(())
+    }
+
+    pub fn create_table_write_handle(&self, kv_total: u64) -> TableWriteHandle {
         let next_table_id = self.get_next_table_id();
-        TableWriteHandle::new(&self.db_path, 0, next_table_id, kv_total)
+        TableWriteHandle::new(
+            &self.db_path,
+            0,
+            next_table_id,
+            kv_total,
+            self.table_manager.options(),
+        )
+        .with_blob_store(self.table_manager.blob_store().clone())
+        .with_level_dict(self.table_manager.level_dict(0))
     }
 
     /// Get sstable file count of level 0, used for judging whether need compacting.
@@ -303,8 +385,6 @@ where
             .fetch_sub(table_handle.file_size(), Ordering::Release);
 
         table_handle.ready_to_delete();
-        self.table_cache
-            .erase(&table_handle.table_key(), table_handle.hash());
     }
 
     pub fn random_handle(&self) -> Arc<TableReadHandle> {
@@ -324,26 +404,28 @@ where
         let mut tables = Vec::new();
         tables.reserve(NUM_LEVEL0_TABLE_TO_COMPACT);
 
+        let comparator = self.table_manager.comparator();
         let mut count = 0;
-        let mut min_key: Option<&RawUserKey> = None;
-        let max = RawUserKey::default();
-        let mut max_key: &RawUserKey = &max;
+        let mut min_max_key: Option<(&RawUserKey, &RawUserKey)> = None;
         for (_id, table) in guard.iter() {
             if table.test_and_set_compacting() {
                 tables.push(table.clone());
                 count += 1;
                 let keys = table.min_max_key();
-                min_key = match min_key {
-                    Some(m) => Some(m.min(keys.0)),
-                    None => Some(keys.0),
+                min_max_key = match min_max_key {
+                    Some((min_key, max_key)) => Some((
+                        std::cmp::min_by(min_key, keys.0, |a, b| comparator.compare(a, b)),
+                        std::cmp::max_by(max_key, keys.1, |a, b| comparator.compare(a, b)),
+                    )),
+                    None => Some(keys),
                 };
-                max_key = max_key.max(keys.1);
                 if count >= NUM_LEVEL0_TABLE_TO_COMPACT {
                     break;
                 }
             }
         }
-        (tables, min_key.unwrap().clone(), max_key.clone())
+        let (min_key, max_key) = min_max_key.unwrap();
+        (tables, min_key.clone(), max_key.clone())
     }
 
     pub(crate) fn close(&self) {
@@ -356,7 +438,7 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::db::key_types::RawUserKey;
+    use crate::db::key_types::{RawUserKey, SequenceNumber};
     use crate::db::DBCommand;
     use crate::memory::{InternalKeyValueIterator, MutexSkipMapMemTable};
     use crate::sstable::manager::level_0::Level0Manager;
@@ -401,7 +483,6 @@ mod tests {
             leveln_manager.clone(),
             Arc::new(Mutex::new(wal)),
             imm_mem.clone(),
-            leveln_manager.index_cache.clone(),
             receiver,
             background,
         );
@@ -428,9 +509,14 @@ mod tests {
         for i in 0..NUM_KEYS {
             let key = format!("key{}", i).into_bytes();
             let v = manager
-                .query(&key)
+                .query(&key, SequenceNumber::MAX)
                 .unwrap()
-                .unwrap_or_else(|| leveln_manager.query(&key).unwrap().unwrap());
+                .unwrap_or_else(|| {
+                    leveln_manager
+                        .query(&key, SequenceNumber::MAX)
+                        .unwrap()
+                        .unwrap()
+                });
             assert_eq!(format!("value{}", i).into_bytes(), v);
         }
 
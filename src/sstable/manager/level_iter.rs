@@ -1,49 +1,111 @@
-use crate::db::db_iter::{InternalKeyValue, KeyValueIterItem};
-use crate::db::key_types::RawUserKey;
+use crate::db::comparator::Comparator;
+use crate::db::db_iter::{InternalKeyValue, IteratorStatus, KeyValueIterItem};
+use crate::db::key_types::{RawUserKey, SequenceNumber};
+use crate::db::range_tombstone::{is_covered, RangeTombstone};
+use crate::sstable::manager::level_n::LevelTables;
 use crate::sstable::table_handle::{TableIterator, TableReadHandle};
 use crate::sstable::TableID;
+use std::cmp::Ordering;
 use std::collections::{BTreeMap, BinaryHeap};
 use std::sync::Arc;
 
 pub type Level0Iterator = MergingIterator<TableIterator>;
 
 impl Level0Iterator {
-    pub(super) fn new(tables: &BTreeMap<TableID, Arc<TableReadHandle>>) -> Level0Iterator {
-        let iterators: Vec<_> = tables
-            .values()
-            .map(|handle| TableIterator::new(handle.clone()))
-            .collect();
-        Self::from_iterators(iterators)
+    pub(super) fn new(
+        tables: &BTreeMap<TableID, Arc<TableReadHandle>>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Level0Iterator {
+        let status = IteratorStatus::default();
+        let mut iterators = Vec::with_capacity(tables.len());
+        let mut range_tombstones = Vec::with_capacity(tables.len());
+        for handle in tables.values() {
+            match TableIterator::new(handle.clone(), status.clone()) {
+                Ok(iterator) => {
+                    iterators.push(iterator);
+                    range_tombstones.push(handle.range_tombstones().to_vec());
+                }
+                Err(e) => status.set_error(e),
+            }
+        }
+        Self::with_range_tombstones(iterators, range_tombstones, vec![status], comparator)
     }
 }
 
 pub struct MergingIterator<It: Iterator<Item = InternalKeyValue>> {
     pub(crate) iterators: Vec<It>,
     priority_queue: BinaryHeap<KeyValueIterItem>,
+    /// Range tombstones of the `i`th iterator delete keys in the iterators before it.
+    range_tombstones: Vec<Vec<RangeTombstone>>,
+    /// Statuses of the sstable iterators, the merging stops at their first error.
+    statuses: Vec<IteratorStatus>,
+    comparator: Arc<dyn Comparator>,
     #[cfg(debug_assertions)]
-    prev_key: RawUserKey,
+    prev_key: Option<RawUserKey>,
 }
 
 impl<It: Iterator<Item = InternalKeyValue>> MergingIterator<It> {
-    pub(crate) fn from_iterators(mut iterators: Vec<It>) -> MergingIterator<It> {
+    pub(crate) fn with_range_tombstones(
+        mut iterators: Vec<It>,
+        range_tombstones: Vec<Vec<RangeTombstone>>,
+        statuses: Vec<IteratorStatus>,
+        comparator: Arc<dyn Comparator>,
+    ) -> MergingIterator<It> {
+        debug_assert_eq!(iterators.len(), range_tombstones.len());
         let mut priority_queue = BinaryHeap::with_capacity(iterators.len());
         for (iter_id, iter) in iterators.iter_mut().enumerate() {
             if let Some((k, v)) = iter.next() {
-                priority_queue.push(KeyValueIterItem::new(k, v, iter_id));
+                priority_queue.push(KeyValueIterItem::new(k, v, iter_id, comparator.clone()));
             }
         }
         MergingIterator {
             iterators,
             priority_queue,
+            range_tombstones,
+            statuses,
+            comparator,
             #[cfg(debug_assertions)]
-            prev_key: RawUserKey::default(),
+            prev_key: None,
         }
     }
 
+    /// Get all the range tombstones of the iterators.
+    pub(crate) fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones.concat()
+    }
+
+    /// Return the error which stops the iteration, if the blobs or blocks of a sstable
+    /// can't be read.
+    pub fn status(&self) -> crate::Result<()> {
+        self.statuses.iter().try_for_each(IteratorStatus::check)
+    }
+
+    pub(crate) fn shared_statuses(&self) -> Vec<IteratorStatus> {
+        self.statuses.clone()
+    }
+
+    /// Whether `key` in the `iter_id`th iterator is deleted by the newer iterators.
+    fn is_range_deleted(&self, key: &[u8], iter_id: usize) -> bool {
+        self.range_tombstones[iter_id + 1..]
+            .iter()
+            .any(|range_tombstones| {
+                is_covered(
+                    range_tombstones,
+                    key,
+                    SequenceNumber::MAX,
+                    self.comparator.as_ref(),
+                )
+            })
+    }
+
     fn try_pop_ith_elem_to_queue(&mut self, iter_id: usize) {
         if let Some((k, v)) = self.iterators[iter_id].next() {
-            self.priority_queue
-                .push(KeyValueIterItem::new(k, v, iter_id));
+            self.priority_queue.push(KeyValueIterItem::new(
+                k,
+                v,
+                iter_id,
+                self.comparator.clone(),
+            ));
         }
     }
 }
@@ -52,11 +114,11 @@ impl<It: Iterator<Item = InternalKeyValue>> Iterator for MergingIterator<It> {
     type Item = InternalKeyValue;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.priority_queue.pop().map(|item| {
+        while let Some(item) = self.priority_queue.pop() {
             self.try_pop_ith_elem_to_queue(item.iter_id);
 
             while let Some(next_item) = self.priority_queue.peek() {
-                if next_item.key == item.key {
+                if self.comparator.compare(&next_item.key, &item.key) == Ordering::Equal {
                     let next_item_iter_id = next_item.iter_id;
                     debug_assert!(item.iter_id > next_item_iter_id);
                     self.priority_queue.pop();
@@ -65,58 +127,116 @@ impl<It: Iterator<Item = InternalKeyValue>> Iterator for MergingIterator<It> {
                     break;
                 }
             }
+            // a newer version of the key may be lost with the error
+            if !self.statuses.iter().all(IteratorStatus::is_ok) {
+                return None;
+            }
+            if self.is_range_deleted(&item.key, item.iter_id) {
+                continue;
+            }
             #[cfg(debug_assertions)]
             {
-                assert!(self.prev_key < item.key);
-                self.prev_key = item.key.clone();
+                if let Some(prev_key) = &self.prev_key {
+                    assert_eq!(self.comparator.compare(prev_key, &item.key), Ordering::Less);
+                }
+                self.prev_key = Some(item.key.clone());
             }
-            (item.key, item.value)
-        })
+            return Some((item.key, item.value));
+        }
+        None
     }
 }
 
 pub struct LevelNIterator {
     iterators: Vec<TableIterator>,
+    range_tombstones: Vec<RangeTombstone>,
+    /// Status shared by the sstable iterators, the iteration stops at the first error.
+    status: IteratorStatus,
     idx: usize,
     #[cfg(debug_assertions)]
-    prev_key: RawUserKey,
+    comparator: Arc<dyn Comparator>,
+    #[cfg(debug_assertions)]
+    prev_key: Option<RawUserKey>,
     #[cfg(debug_assertions)]
     prev_idx: usize,
 }
 
 impl LevelNIterator {
     pub(super) fn new(
-        table_handles: &BTreeMap<(RawUserKey, TableID), Arc<TableReadHandle>>,
+        table_handles: &LevelTables,
+        comparator: Arc<dyn Comparator>,
+    ) -> LevelNIterator {
+        Self::with_table_filter(table_handles, comparator, |_| true)
+    }
+
+    /// Create an iterator that only reads the sstables satisfying `table_filter`, range
+    /// tombstones of all the sstables are kept.
+    pub(super) fn with_table_filter(
+        table_handles: &LevelTables,
+        #[allow(unused_variables)] comparator: Arc<dyn Comparator>,
+        table_filter: impl Fn(&TableReadHandle) -> bool,
     ) -> LevelNIterator {
         #[cfg(debug_assertions)]
         {
-            let mut last_max_key = RawUserKey::default();
+            let mut last_max_key: Option<&RawUserKey> = None;
             for table in table_handles.values() {
-                assert!(last_max_key.lt(table.min_key()));
-                last_max_key = table.max_key().clone();
+                if let Some(last_max_key) = last_max_key {
+                    assert_eq!(
+                        comparator.compare(last_max_key, table.min_key()),
+                        Ordering::Less
+                    );
+                }
+                last_max_key = Some(table.max_key());
             }
         }
 
+        let status = IteratorStatus::default();
         let iterators: Vec<_> = table_handles
             .values()
-            .map(|handle| TableIterator::new(handle.clone()))
+            .filter(|handle| table_filter(handle))
+            .filter_map(
+                |handle| match TableIterator::new(handle.clone(), status.clone()) {
+                    Ok(iterator) => Some(iterator),
+                    Err(e) => {
+                        status.set_error(e);
+                        None
+                    }
+                },
+            )
+            .collect();
+        let range_tombstones = table_handles
+            .values()
+            .flat_map(|handle| handle.range_tombstones().iter().cloned())
             .collect();
         LevelNIterator {
             iterators,
+            range_tombstones,
+            status,
             idx: 0,
             #[cfg(debug_assertions)]
-            prev_key: RawUserKey::default(),
+            comparator,
+            #[cfg(debug_assertions)]
+            prev_key: None,
             #[cfg(debug_assertions)]
             prev_idx: 0,
         }
     }
+
+    /// Get all the range tombstones of the sstables in this level.
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones.clone()
+    }
+
+    pub(crate) fn shared_status(&self) -> IteratorStatus {
+        self.status.clone()
+    }
 }
 
 impl Iterator for LevelNIterator {
     type Item = InternalKeyValue;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.idx == self.iterators.len() {
+        if self.idx == self.iterators.len() || !self.status.is_ok() {
             return None;
         }
         #[cfg(debug_assertions)]
@@ -133,18 +253,21 @@ impl Iterator for LevelNIterator {
         });
         #[cfg(debug_assertions)]
         if !reenter {
-            if let Some((k, _v)) = &item {
-                assert!(
-                    self.prev_key.lt(k),
+            if let (Some(prev_key), Some((k, _v))) = (&self.prev_key, &item) {
+                assert_eq!(
+                    self.comparator.compare(prev_key, k),
+                    Ordering::Less,
                     r#"prev_key: {:?} key: {:?}
                        prev_idx: {} idx: {}
                     "#,
-                    self.prev_key,
+                    prev_key,
                     k,
                     self.prev_idx,
                     self.idx
                 );
-                self.prev_key = k.clone();
+            }
+            if let Some((k, _v)) = &item {
+                self.prev_key = Some(k.clone());
             }
         }
         item
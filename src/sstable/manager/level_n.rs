@@ -1,11 +1,14 @@
-use crate::cache::{LRUEntry, ShardLRUCache};
 use crate::collections::skip_list::skipmap::SrSwSkipMap;
 use crate::compaction::level_n::start_compact;
-use crate::db::db_iter::InternalKeyValue;
-use crate::db::key_types::{DBKey, RawUserKey};
+use crate::db::comparator::{ComparableKey, Comparator};
+use crate::db::key_types::{DBKey, RawUserKey, SequenceNumber};
+use crate::db::options::Options;
+use crate::db::slice_transform::{KeyPrefix, RangePrefix};
+use crate::db::snapshot::SnapshotList;
 use crate::db::{Value, MAX_LEVEL};
+use crate::sstable::blob_file::BlobStore;
+use crate::sstable::compression::LevelDict;
 use crate::sstable::manager::level_iter::LevelNIterator;
-use crate::sstable::table_cache::TableCache;
 use crate::sstable::table_handle::{TableReadHandle, TableWriteHandle};
 use crate::sstable::TableID;
 use crate::Result;
@@ -13,22 +16,32 @@ use crossbeam_channel::{Receiver, Sender};
 use std::collections::{BTreeMap, VecDeque};
 use std::num::NonZeroUsize;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
 use std::thread::JoinHandle;
 
+/// map: <(max key, tableID), sstable>, max keys are ordered by the comparator of database.
+pub type LevelTables = BTreeMap<(ComparableKey, TableID), Arc<TableReadHandle>>;
+
 /// Struct for adding and removing sstable files.
 pub struct LevelNManager {
     db_path: String,
-    /// map: <max key, tableID>
-    level_tables:
-        [std::sync::RwLock<BTreeMap<(RawUserKey, TableID), Arc<TableReadHandle>>>; MAX_LEVEL],
+    options: Options,
+    /// Blob files of all the levels, including level 0.
+    blob_store: Arc<BlobStore>,
+    level_tables: [std::sync::RwLock<LevelTables>; MAX_LEVEL],
     level_sizes: [AtomicU64; MAX_LEVEL],
     next_table_id: [AtomicU64; MAX_LEVEL],
 
-    pub(crate) index_cache: Arc<ShardLRUCache<TableID, TableCache>>,
     senders: Vec<Sender<bool>>,
     handles: RwLock<Vec<JoinHandle<()>>>,
     next_to_compact: AtomicUsize,
+    /// Snapshots whose versions are retained by flushes and compactions.
+    snapshots: SnapshotList,
+    /// Zstd dictionaries of level 0 to `MAX_LEVEL` if [Options::zstd_dict_per_level].
+    level_dicts: Vec<LevelDict>,
+    /// Held by compactions while inserting their outputs and deleting their inputs, and by
+    /// ingestions while choosing the levels of sstables and inserting them.
+    version_lock: Mutex<()>,
 }
 
 unsafe impl Sync for LevelNManager {}
@@ -36,16 +49,16 @@ unsafe impl Send for LevelNManager {}
 
 impl LevelNManager {
     /// Open all the sstables at `db_path` when initializing DB.
-    pub fn open_tables(
-        db_path: String,
-        index_cache: Arc<ShardLRUCache<u64, TableCache>>,
-    ) -> Arc<LevelNManager> {
+    pub fn open_tables(db_path: String, options: Options) -> crate::Result<Arc<LevelNManager>> {
         for i in 1..=MAX_LEVEL {
-            std::fs::create_dir_all(format!("{}/{}", db_path, i)).unwrap();
+            std::fs::create_dir_all(format!("{}/{}", db_path, i))?;
         }
 
+        let blob_store = Arc::new(BlobStore::open(&db_path)?);
         let mut manager = LevelNManager {
             db_path,
+            options,
+            blob_store,
             level_tables: [
                 std::sync::RwLock::default(),
                 std::sync::RwLock::default(),
@@ -76,7 +89,9 @@ impl LevelNManager {
             senders: Vec::with_capacity(MAX_LEVEL - 1),
             handles: RwLock::new(Vec::with_capacity(MAX_LEVEL - 1)),
             next_to_compact: AtomicUsize::default(),
-            index_cache,
+            snapshots: SnapshotList::default(),
+            level_dicts: (0..=MAX_LEVEL).map(|_| LevelDict::default()).collect(),
+            version_lock: Mutex::default(),
         };
 
         let mut receivers = VecDeque::with_capacity(MAX_LEVEL - 1);
@@ -91,7 +106,14 @@ impl LevelNManager {
                 // The file whose file_name is a number is considered as sstable.
                 if let Ok(table_id) = path.file_name().unwrap().to_str().unwrap().parse::<u64>() {
                     next_table_id = next_table_id.max(table_id);
-                    let handle = TableReadHandle::open(&manager.db_path, i as _, table_id);
+                    let handle = TableReadHandle::open(
+                        &manager.db_path,
+                        i as _,
+                        table_id,
+                        &manager.options,
+                    )?
+                    .with_blob_store(&manager.blob_store)?;
+                    let key = manager.level_key(handle.max_key(), handle.table_id());
 
                     // Safety: i is in range [1, MAX_LEVEL]
                     unsafe {
@@ -100,10 +122,7 @@ impl LevelNManager {
                             .get_unchecked_mut(i - 1)
                             .write()
                             .unwrap();
-                        guard.insert(
-                            (handle.max_key().clone(), handle.table_id()),
-                            Arc::new(handle),
-                        );
+                        guard.insert(key, Arc::new(handle));
                     }
 
                     file_size += d.metadata().unwrap().len();
@@ -140,7 +159,7 @@ impl LevelNManager {
                 receivers.pop_front().unwrap(),
             ));
         }
-        manager
+        Ok(manager)
     }
 
     fn start_compacting_task(
@@ -165,68 +184,124 @@ impl LevelNManager {
         })
     }
 
-    pub fn get_level_tables_lock(
-        &self,
-        level: NonZeroUsize,
-    ) -> &std::sync::RwLock<BTreeMap<(RawUserKey, u64), Arc<TableReadHandle>>> {
+    #[inline]
+    pub fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.options.comparator
+    }
+
+    #[inline]
+    pub(crate) fn snapshots(&self) -> &SnapshotList {
+        &self.snapshots
+    }
+
+    #[inline]
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    #[inline]
+    pub(crate) fn blob_store(&self) -> &Arc<BlobStore> {
+        &self.blob_store
+    }
+
+    /// Key of the sstable whose max key is `max_key` in [LevelTables].
+    #[inline]
+    fn level_key(&self, max_key: &[u8], table_id: TableID) -> (ComparableKey, TableID) {
+        (
+            ComparableKey::new(max_key.to_vec(), self.comparator().clone()),
+            table_id,
+        )
+    }
+
+    pub fn get_level_tables_lock(&self, level: NonZeroUsize) -> &std::sync::RwLock<LevelTables> {
         let lock = self.level_tables.get(level.get() - 1).unwrap();
         lock
     }
 
-    pub fn get_iterators(&self) -> Vec<Box<dyn Iterator<Item = InternalKeyValue>>> {
+    pub fn get_iterators(&self) -> Vec<LevelNIterator> {
+        self.level_tables
+            .iter()
+            .map(|tables| {
+                let guard = tables.read().unwrap();
+                LevelNIterator::new(&*guard, self.comparator().clone())
+            })
+            .collect()
+    }
+
+    /// Get iterators which skip the sstables without keys of `key_prefix`.
+    pub fn get_prefix_iterators(&self, key_prefix: &KeyPrefix) -> Vec<LevelNIterator> {
         self.level_tables
             .iter()
             .map(|tables| {
                 let guard = tables.read().unwrap();
-                let elem: Box<dyn Iterator<Item = InternalKeyValue>> =
-                    Box::new(LevelNIterator::new(&*guard));
-                elem
+                LevelNIterator::with_table_filter(&guard, self.comparator().clone(), |table| {
+                    table.may_contain_prefix(key_prefix)
+                })
             })
             .collect()
     }
 
+    /// Query the newest key-values visible at `seq_num` in [`key_start`, `key_end`].
     pub fn range_query<UK: DBKey>(
         &self,
         key_start: &RawUserKey,
         key_end: &RawUserKey,
+        seq_num: SequenceNumber,
         kvs: &mut SrSwSkipMap<UK, Value>,
-    ) {
+    ) -> Result<()> {
+        let range_prefix = self.range_prefix(key_start, key_end);
         for level in (1..=MAX_LEVEL).rev() {
             let tables_lock =
                 self.get_level_tables_lock(unsafe { NonZeroUsize::new_unchecked(level) });
             let tables_guard = tables_lock.read().unwrap();
-            for (_k, table_read_handle) in tables_guard.range((key_start.clone(), 0)..) {
-                if !table_read_handle.range_query(key_start, key_end, kvs) {
+            for (_k, table_read_handle) in tables_guard.range(self.level_key(key_start, 0)..) {
+                if !table_read_handle.range_query(
+                    key_start,
+                    key_end,
+                    seq_num,
+                    range_prefix.as_ref(),
+                    kvs,
+                )? {
                     break;
                 }
             }
         }
+        Ok(())
     }
 
-    pub fn query(&self, key: &RawUserKey) -> Result<Option<Value>> {
+    /// Get the prefixes of the keys in [`key_start`, `key_end`], which are used to skip
+    /// sstables by the prefix filters.
+    pub(crate) fn range_prefix(
+        &self,
+        key_start: &RawUserKey,
+        key_end: &RawUserKey,
+    ) -> Option<RangePrefix> {
+        let prefix_extractor = self.options.prefix_extractor.clone()?;
+        RangePrefix::from_range(prefix_extractor, key_start, key_end)
+    }
+
+    /// Query the newest value of `key` visible at `seq_num`.
+    pub fn query(&self, key: &RawUserKey, seq_num: SequenceNumber) -> Result<Option<Value>> {
         for level in 1..=MAX_LEVEL {
             let tables_lock =
                 self.get_level_tables_lock(unsafe { NonZeroUsize::new_unchecked(level) });
             let tables_guard = tables_lock.read().unwrap();
 
-            if let Some((k, table_read_handle)) = tables_guard.range((key.clone(), 0)..).next() {
-                debug_assert!(key.le(&k.0));
+            if let Some((k, table_read_handle)) =
+                tables_guard.range(self.level_key(key, 0)..).next()
+            {
+                debug_assert_ne!(
+                    self.comparator().compare(key, &k.0.key),
+                    std::cmp::Ordering::Greater
+                );
                 debug_assert!(table_read_handle.readable());
-                let entry_tracker = self
-                    .index_cache
-                    .look_up(&table_read_handle.table_key(), table_read_handle.hash());
-
-                let option = if entry_tracker.0.is_null() {
-                    table_read_handle.query_sstable(key, &self.index_cache)
-                } else {
-                    let mut table_cache = unsafe {
-                        (*(entry_tracker.0 as *mut LRUEntry<u64, TableCache>)).value_mut()
-                    };
-                    table_read_handle.query_sstable_with_cache(key, &mut table_cache)
-                };
+                let option = table_read_handle.query(key, seq_num)?;
                 if option.is_some() {
                     return Ok(option);
                 }
+                if table_read_handle.range_tombstones_cover(key, seq_num) {
+                    return Ok(Some(Value::default()));
+                }
             }
         }
         Ok(None)
@@ -240,19 +315,25 @@ impl LevelNManager {
         }
     }
 
+    /// Lock the sstables of all the levels against compactions and ingestions.
+    pub(crate) fn lock_version(&self) -> MutexGuard<'_, ()> {
+        self.version_lock.lock().unwrap()
+    }
+
     pub fn upsert_table_handle(&self, handle: TableWriteHandle) {
-        let file_size = handle.writer.writer.pos;
-        debug_assert!(file_size > 0);
+        debug_assert!(handle.writer.writer.pos > 0);
+        self.insert_read_handle(TableReadHandle::from_table_write_handle(handle));
+    }
 
+    fn insert_read_handle(&self, handle: TableReadHandle) {
+        let file_size = handle.file_size();
         let level = NonZeroUsize::new(handle.level()).unwrap();
 
-        let handle = TableReadHandle::from_table_write_handle(handle);
-
         let lock = self.get_level_tables_lock(level);
         let mut table_guard = lock.write().unwrap();
 
         let option = table_guard.insert(
-            (handle.max_key().clone(), handle.table_id()),
+            self.level_key(handle.max_key(), handle.table_id()),
             Arc::new(handle),
         );
 
@@ -279,22 +360,80 @@ impl LevelNManager {
             .write()
             .unwrap();
         let t = guard
-            .remove(&(table_handle.max_key().clone(), table_handle.table_id()))
+            .remove(&self.level_key(table_handle.max_key(), table_handle.table_id()))
             .unwrap();
 
         t.ready_to_delete();
-        self.index_cache
-            .erase(&table_handle.table_key(), table_handle.hash());
     }
 
     /// Create a new sstable without `min_key` or `max_key`
     pub fn create_table_write_handle(
         &self,
         level: NonZeroUsize,
-        kv_total: u32,
+        kv_total: u64,
     ) -> TableWriteHandle {
         let next_table_id = self.get_next_table_id(level);
-        TableWriteHandle::new(&self.db_path, level.get(), next_table_id, kv_total)
+        TableWriteHandle::new(
+            &self.db_path,
+            level.get(),
+            next_table_id,
+            kv_total,
+            &self.options,
+        )
+        .with_blob_store(self.blob_store.clone())
+        .with_level_dict(self.level_dict(level.get()))
+    }
+
+    /// Zstd dictionary shared by the sstables of `level`, if trained per level.
+    pub(crate) fn level_dict(&self, level: usize) -> Option<LevelDict> {
+        if self.options.zstd_dict_per_level {
+            Some(self.level_dicts[level].clone())
+        } else {
+            None
+        }
+    }
+
+    /// Whether any sstable in `level` may have keys in [`min_key`, `max_key`].
+    pub(crate) fn is_overlapping(
+        &self,
+        level: NonZeroUsize,
+        min_key: &[u8],
+        max_key: &[u8],
+    ) -> bool {
+        let tables_lock = self.get_level_tables_lock(level);
+        let tables_guard = tables_lock.read().unwrap();
+        // sstables in the level do not overlap, only the first one whose max key is not less
+        // than `min_key` may overlap
+        match tables_guard.range(self.level_key(min_key, 0)..).next() {
+            Some((_, handle)) => handle.is_overlapping(min_key, max_key),
+            None => false,
+        }
+    }
+
+    /// Move the external sstable staged at `staged_path` by
+    /// [crate::sstable::sst_file_writer::copy_with_global_seq_num] into
+    /// `level`.
+    pub(crate) fn ingest_file(&self, level: NonZeroUsize, staged_path: &str) -> Result<()> {
+        let table_id = self.get_next_table_id(level);
+        let table_path = format!("{}/{}/{}", self.db_path, level, table_id);
+        std::fs::rename(staged_path, &table_path)?;
+        let handle = TableReadHandle::try_open_file(
+            table_path.clone(),
+            level.get(),
+            table_id,
+            &self.options,
+        )
+        .and_then(|handle| handle.with_blob_store(&self.blob_store));
+        let handle = match handle {
+            Ok(handle) => handle,
+            Err(e) => {
+                let _ = std::fs::remove_file(&table_path);
+                return Err(e);
+            }
+        };
+        self.insert_read_handle(handle);
+        self.may_compact(level);
+        Ok(())
     }
 
     /// Get sstable file count of `level`, used for judging whether need compacting.
@@ -320,7 +459,7 @@ impl LevelNManager {
         // min_key:       "3"
         //                 |-------------->
         // max_key:  "1", "3", "5", "7" ...
-        for (_key, handle) in tables_guard.range((min_key.clone(), 0)..) {
+        for (_key, handle) in tables_guard.range(self.level_key(min_key, 0)..) {
             if handle.is_overlapping(min_key, max_key) {
                 if handle.test_and_set_compacting() {
                     let handle = handle.clone();
@@ -394,14 +533,13 @@ impl LevelNManager {
 pub(crate) mod tests {
     use std::sync::Arc;
 
-    use crate::cache::ShardLRUCache;
+    use crate::db::options::Options;
     use crate::db::MAX_LEVEL;
     use crate::sstable::manager::level_n::LevelNManager;
     use crate::sstable::table_handle::tests::create_read_handle;
 
     pub(crate) fn create_manager(db_path: &str) -> Arc<LevelNManager> {
-        let index_cache = Arc::new(ShardLRUCache::default());
-        LevelNManager::open_tables(db_path.to_string(), index_cache)
+        LevelNManager::open_tables(db_path.to_string(), Options::default()).unwrap()
     }
 
     #[test]
@@ -17,33 +17,73 @@
 //! +-------------------------+       |
 //! | Filter Block            |<-+    |
 //! +-------------------------+  |    |
+//! | Range Deletion Block    |<-+    |
+//! +-------------------------+  |    |
+//! | Compression Dictionary  |<-+    |
+//! +-------------------------+  |    |
+//! | Properties Block        |<-+    |
+//! +-------------------------+  |    |
 //! | Footer                  |--+----+
 //! +-------------------------+
 //! ```
 //!
 //! ## Data Block
 //!
+//! Since format version 3, each data block on disk is followed by its
+//! [compression::CompressionType] byte, and the block before it is compressed with that
+//! codec, unless the compression ratio is poor. Data blocks of older versions are compressed
+//! with snappy if the `snappy_compression` feature is enabled. The layouts below are of
+//! decompressed data blocks.
+//!
+//! Keys of a data block are stored after the prefixes shared with the previous keys. Every
+//! [crate::db::options::Options::block_restart_interval] keys there is a restart point, which
+//! stores the full key, so point lookups binary search the restart points and then scan
+//! the keys after the found one.
+//!
 //! ```text
 //! +-----------------------------------------------------------------+
 //! | Key/Value Entry 1 | Key/Value Entry 2 | ... | Key/Value Entry n |
 //! +-----------------------------------------------------------------+
+//! | restart offset 1 | ... | restart offset m | number of restarts  |
+//! +-----------------------------------------------------------------+
+//! \-----------------/\-----/\----------------/\-------------------/
+//!        u32                       u32                 u32
 //! ```
 //!
 //! ### Key/Value Entry
 //!
 //! ```text
-//! +-----------------------------------------+
-//! | key length | value length | key | value |
-//! +-----------------------------------------+
-//! \-----------/\-------------/\-----/\------/
-//!      u32           u32      var-len var-len
+//! +-------------------------------------------------------------------------------+
+//! | shared key length | unshared key length | value length | unshared key | value |
+//! +-------------------------------------------------------------------------------+
+//! \------------------/\--------------------/\-------------/\-------------/\------/
+//!       varint               varint             varint          var-len    var-len
+//! ```
+//!
+//! Since format version 7, the value length is `length << 1 | value type`, and values of
+//! type 1 are [blob_file::BlobIndex]es of the values separated into blob files.
+//!
+//! Since format version 8, the unshared key is followed by `sequence number << 8 | key type`
+//! (u64) of the record, see [crate::db::key_types::KeyType]. A key has a record for each of
+//! its versions that is still needed by snapshots, ordered by sequence number in descending
+//! order, and the records of a key are never split across data blocks. Shared prefixes,
+//! restart points, the index block and the filter block only refer to the user keys.
+//! Records of older versions have sequence number 0.
+//!
+//! The lengths of entries are u32 in format version 1. Data blocks of format version 0 store full keys without shared key length, and the
+//! offset of every entry instead of restart points:
+//!
+//! ```text
+//! +--------------------------------------------------------------------------------+
+//! | key length | value length | key | value | ... | entry offset 1 | ... | offset n |
+//! +--------------------------------------------------------------------------------+
 //! ```
 //!
 //! ## Index Block
 //!
 //! ```text
 //! +-------------------------------+
-//! | min_key length(u32) | min_key |
+//! | min_key length      | min_key |
 //! +---------------------------------------------------------+
 //! | offset | length | index_offset | key1 length | max key1 | -> Data Block1
 //! +---------------------------------------------------------+
@@ -52,42 +92,115 @@
 //! |                            ...                          |
 //! +---------------------------------------------------------+
 //! \-------/\-------/\------------/\-------------/\----------/
-//!    u32      u32         u32            u32       var-len
+//!  varint   varint      varint         varint      var-len
 //! ```
 //!
+//! The lengths and offsets of index blocks are u32 before format version 2. Offsets and
+//! lengths of data blocks are decoded as u64, so an sstable may exceed 4 GiB.
+//!
 //! ## Filter Block
 //!
+//! Filter of the key hashes built by the filter policy recorded in the footer, see
+//! [crate::filter] for the format of each policy. If a prefix extractor is configured,
+//! prefixes of the keys are also inserted, so that prefix iterators and range queries
+//! within a prefix can skip the sstable without reading the index block and data blocks.
+//! Since format version 9, the hash of a marker is also inserted if any key is not in the
+//! domain of the prefix extractor, so that range queries across a few prefixes can skip the
+//! sstable if it has none of the prefixes and no such key.
+//!
+//! ## Range Deletion Block
+//!
+//! Range tombstones of the sstable, each one deletes the versions of the keys in
+//! [start key, end key) of older sstables whose sequence numbers are smaller than its own.
+//! A range tombstone is only visible to the readers at its sequence number or later.
+//! Compactions keep the covered versions still read by snapshots older than it, and add
+//! deletions at its sequence number before them.
+//!
+//! ```text
+//! +---------------------------------------------------------------------------+
+//! | start key length | end key length | start key | end key | sequence number | -> Range Tombstone 1
+//! +---------------------------------------------------------------------------+
+//! |                                    ...                                    |
+//! +---------------------------------------------------------------------------+
+//! \-----------------/\---------------/\----------/\--------/\----------------/
+//!         u32               u32         var-len    var-len         u64
+//! ```
+//!
+//! Range tombstones before format version 10 have no sequence numbers, they are read at
+//! the largest sequence number and delete all the versions of the keys in older sstables.
+//!
+//! ## Compression Dictionary Block
+//!
+//! Zstd dictionary trained from the values of the sstable if
+//! [crate::db::options::Options::zstd_max_dict_bytes] is positive, or shared by the
+//! sstables of the level if [crate::db::options::Options::zstd_dict_per_level] is set. All
+//! the data blocks compressed with Zstd use it. It is cached with the filter and index
+//! blocks, together with the decompression contexts loaded with it.
+//!
+//! ## Properties Block
+//!
+//! Statistics of the sstable, see [properties_block::TableProperties]. The sequence numbers
+//! are the smallest and largest ones of the records. If they are equal, all the records
+//! have that sequence number, which is how ingested sstables are assigned a global one.
+//!
 //! ```text
-//! +---------------------------------+
-//! | FilterBlock length | bit vector |
-//! +---------------------------------+
+//! +---------------------------------------------------------------------------------------------------------+
+//! | raw key size | raw value size | num entries | num deletions | num range deletions | num data blocks |
+//! +---------------------------------------------------------------------------------------------------------+
+//! | data size | creation time | smallest seq num | largest seq num | compression type | min key length |
+//! +---------------------------------------------------------------------------------------------------------+
+//! | min key | max key length | max key | num blob files | (blob file number | referenced length)* |
+//! +---------------------------------------------------------------------------------------------------------+
 //! ```
 //!
+//! Properties blocks before format version 7 have no blob files.
+//!
+//! All the numbers are varints except the compression type (u8).
+//!
 //! ## Footer
 //!
-//! Length of Footer is fixed (64bit).
+//! Footer records the name of the [crate::filter::FilterPolicy] that builds the filter block,
+//! followed by fixed-length fields. The format version of the sstable is described in
+//! `footer::FORMAT_VERSION`.
 //!
 //! ```text
-//! +--------------------------------------------------------------------------------------------+
-//! | IndexBlock offset | IndexBlock length | filter length | kv_total | Magic Number 0xdb991122 |
-//! +--------------------------------------------------------------------------------------------+
-//! \------------------/\-------------------/\-------------/\----------/\------------------------/
-//!         u32                  u32             u32            u32               u32
+//! +--------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------+
+//! | filter policy name | IndexBlock offset | IndexBlock length | filter length | range deletion length | kv_total | compression dictionary length | properties length | format version | name length | Magic Number 0xdb991124 |
+//! +--------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------+
+//! \-------------------/\------------------/\-------------------/\-------------/\-----------------------/\----------/\------------------------------/\------------------/\---------------/\------------/\------------------------/
+//!       var-len                u64                  u64             u64                  u64                u64                    u64                       u64                u32             u32                 u32
 //! ```
 //!
+//! Footers before format version 6 have no properties length, footers before format version
+//! 5 have u32 offsets, lengths and kv_total, and footers before format version 4 have no
+//! compression dictionary length.
+//!
+//! SSTables of format version 0 have footers without format version (magic number
+//! 0xdb991123). The legacy footer (magic number 0xdb991122) has no filter policy name and
+//! name length either, whose filter block is built by [crate::filter::BlockedBloomFilterPolicy].
+//!
 //! NOTE: All fixed-length integer are little-endian.
 
+pub mod blob_file;
+pub mod block_cache;
+pub mod compression;
 pub(super) mod data_block;
 pub(super) mod filter_block;
 pub(crate) mod footer;
 pub(crate) mod index_block;
 pub mod manager;
+pub mod properties_block;
+pub(super) mod range_del_block;
+pub mod secondary_cache;
+pub mod sst_file_writer;
 mod table_cache;
 pub mod table_handle;
 
 pub type TableID = u64;
 
 pub const DATA_BLOCK_SIZE: usize = 4096;
+/// Default number of keys between restart points of data blocks.
+pub const DEFAULT_BLOCK_RESTART_INTERVAL: usize = 16;
 pub const NUM_LEVEL0_TABLE_TO_COMPACT: usize = 4;
 
 pub fn sstable_file(db_path: &str, level: u32, table_id: u128) -> String {
@@ -0,0 +1,203 @@
+//! Statistics of an sstable recorded by the table writer.
+
+use crate::db::key_types::RawUserKey;
+use crate::error::KVLiteError;
+use crate::ioutils::{decode_varint, put_varint, read_bytes_exact};
+use crate::sstable::compression::CompressionType;
+use crate::sstable::footer::Footer;
+use crate::Result;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Properties of an sstable, see [crate::db::dbimpl::DBImpl::get_properties_of_all_tables].
+///
+/// SSTables before format version 6 have no properties block, their properties are derived
+/// from the footer and the index block, so the raw sizes, the number of deletions and the
+/// creation time are 0.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    /// Total length of the keys before prefix compression.
+    pub raw_key_size: u64,
+    /// Total length of the values before compression.
+    pub raw_value_size: u64,
+    pub num_entries: u64,
+    /// Number of point deletions, which are stored as empty values.
+    pub num_deletions: u64,
+    pub num_range_deletions: u64,
+    pub num_data_blocks: u64,
+    /// Total length of the data blocks on disk.
+    pub data_size: u64,
+    pub index_size: u64,
+    pub filter_size: u64,
+    /// Codec configured for the data blocks, blocks that compress poorly are stored
+    /// uncompressed.
+    pub compression: CompressionType,
+    /// Name of the filter policy that builds the filter block.
+    pub filter_policy: String,
+    pub format_version: u32,
+    /// Seconds since the Unix epoch when the sstable was written.
+    pub creation_time: u64,
+    /// Sequence numbers of the entries, 0 if the sstable does not record them.
+    pub smallest_seq_num: u64,
+    pub largest_seq_num: u64,
+    pub min_key: RawUserKey,
+    pub max_key: RawUserKey,
+    /// Numbers of the blob files referenced by the blob indexes of the sstable, and the
+    /// total length of the referenced values in each of them, ordered by file number.
+    pub blob_references: Vec<(u64, u64)>,
+}
+
+impl TableProperties {
+    /// Write the properties block, see [crate::sstable] for the format. The sizes of the index
+    /// and filter blocks, the filter policy and the format version are recorded in the footer.
+    pub(crate) fn write_to_file(&self, writer: &mut impl Write) -> Result<()> {
+        let mut buf = vec![];
+        for number in [
+            self.raw_key_size,
+            self.raw_value_size,
+            self.num_entries,
+            self.num_deletions,
+            self.num_range_deletions,
+            self.num_data_blocks,
+            self.data_size,
+            self.creation_time,
+            self.smallest_seq_num,
+            self.largest_seq_num,
+        ] {
+            put_varint(&mut buf, number);
+        }
+        buf.push(self.compression as u8);
+        put_varint(&mut buf, self.min_key.len() as u64);
+        buf.extend_from_slice(&self.min_key);
+        put_varint(&mut buf, self.max_key.len() as u64);
+        buf.extend_from_slice(&self.max_key);
+        put_varint(&mut buf, self.blob_references.len() as u64);
+        for (file_number, length) in &self.blob_references {
+            put_varint(&mut buf, *file_number);
+            put_varint(&mut buf, *length);
+        }
+        writer.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Load the properties block after the compression dictionary block, return `None` if
+    /// the sstable has no properties block.
+    pub(crate) fn load_properties(
+        reader: &mut (impl Read + Seek),
+        footer: &Footer,
+    ) -> Result<Option<TableProperties>> {
+        if footer.properties_length == 0 {
+            return Ok(None);
+        }
+        reader.seek(SeekFrom::Start(footer.properties_offset()))?;
+        let data = read_bytes_exact(reader, footer.properties_length)?;
+
+        let corrupted = || KVLiteError::Custom("corrupted properties block".into());
+        let mut pos = 0;
+        let mut read_number = || -> Result<u64> {
+            let (number, length) = decode_varint(&data[pos..]).ok_or_else(corrupted)?;
+            pos += length;
+            Ok(number)
+        };
+        let mut properties = TableProperties {
+            raw_key_size: read_number()?,
+            raw_value_size: read_number()?,
+            num_entries: read_number()?,
+            num_deletions: read_number()?,
+            num_range_deletions: read_number()?,
+            num_data_blocks: read_number()?,
+            data_size: read_number()?,
+            creation_time: read_number()?,
+            smallest_seq_num: read_number()?,
+            largest_seq_num: read_number()?,
+            index_size: footer.index_block_length,
+            filter_size: footer.filter_length,
+            filter_policy: footer.filter_policy.clone(),
+            format_version: footer.format_version,
+            ..TableProperties::default()
+        };
+        let compression = *data.get(pos).ok_or_else(corrupted)?;
+        properties.compression = CompressionType::from_u8(compression)?;
+        pos += 1;
+        for key in [&mut properties.min_key, &mut properties.max_key] {
+            let (length, varint_length) = decode_varint(&data[pos..]).ok_or_else(corrupted)?;
+            pos += varint_length;
+            let end = pos + length as usize;
+            *key = data.get(pos..end).ok_or_else(corrupted)?.to_vec();
+            pos = end;
+        }
+        if footer.format_version >= 7 {
+            let mut read_number = || -> Result<u64> {
+                let (number, length) = decode_varint(&data[pos..]).ok_or_else(corrupted)?;
+                pos += length;
+                Ok(number)
+            };
+            let num_blob_files = read_number()?;
+            for _ in 0..num_blob_files {
+                let file_number = read_number()?;
+                properties
+                    .blob_references
+                    .push((file_number, read_number()?));
+            }
+        }
+        Ok(Some(properties))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sstable::compression::CompressionType;
+    use crate::sstable::footer::{Footer, FORMAT_VERSION};
+    use crate::sstable::properties_block::TableProperties;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_properties_block() {
+        let properties = TableProperties {
+            raw_key_size: 1000,
+            raw_value_size: u32::MAX as u64 + 1,
+            num_entries: 100,
+            num_deletions: 3,
+            num_range_deletions: 1,
+            num_data_blocks: 5,
+            data_size: 4000,
+            index_size: 200,
+            filter_size: 128,
+            compression: CompressionType::Lz4,
+            filter_policy: "bloom".to_string(),
+            format_version: FORMAT_VERSION,
+            creation_time: 1700000000,
+            smallest_seq_num: 0,
+            largest_seq_num: 0,
+            min_key: "key000".into(),
+            max_key: "key099".into(),
+            blob_references: vec![(1, 4000), (u32::MAX as u64 + 1, 100)],
+        };
+        let mut file = Cursor::new(vec![]);
+        properties.write_to_file(&mut file).unwrap();
+        let footer = Footer {
+            index_block_offset: 0,
+            index_block_length: 200,
+            filter_length: 128,
+            range_del_length: 0,
+            kv_total: 100,
+            compression_dict_length: 0,
+            properties_length: file.get_ref().len() as u64,
+            filter_policy: "bloom".to_string(),
+            format_version: FORMAT_VERSION,
+        };
+        // the index and filter blocks are not written
+        let mut file = Cursor::new([vec![0u8; 328], file.into_inner()].concat());
+        assert_eq!(
+            TableProperties::load_properties(&mut file, &footer).unwrap(),
+            Some(properties)
+        );
+
+        let footer = Footer {
+            properties_length: 0,
+            ..footer
+        };
+        assert!(TableProperties::load_properties(&mut file, &footer)
+            .unwrap()
+            .is_none());
+    }
+}
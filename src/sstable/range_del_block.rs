@@ -0,0 +1,105 @@
+use crate::db::key_types::SequenceNumber;
+use crate::db::range_tombstone::RangeTombstone;
+use crate::ioutils::{read_bytes_exact, read_u32, read_u64};
+use crate::Result;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+pub(super) fn write_range_del_block(
+    range_tombstones: &[RangeTombstone],
+    writer: &mut (impl Write + Seek),
+) -> Result<()> {
+    for tombstone in range_tombstones {
+        writer.write_all(&(tombstone.start.len() as u32).to_le_bytes())?;
+        writer.write_all(&(tombstone.end.len() as u32).to_le_bytes())?;
+        writer.write_all(&tombstone.start)?;
+        writer.write_all(&tombstone.end)?;
+        writer.write_all(&tombstone.seq_num.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Load the range tombstones of a sstable of `format_version`, the tombstones without
+/// sequence numbers are at [SequenceNumber::MAX].
+pub(super) fn load_range_del_block(
+    offset: u64,
+    length: u64,
+    format_version: u32,
+    reader: &mut (impl Read + Seek),
+) -> Result<Vec<RangeTombstone>> {
+    let mut range_tombstones = vec![];
+    if length == 0 {
+        return Ok(range_tombstones);
+    }
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut read_length = 0;
+    while read_length < length {
+        let start_length = read_u32(reader)?;
+        let end_length = read_u32(reader)?;
+        let start = read_bytes_exact(reader, start_length as u64)?;
+        let end = read_bytes_exact(reader, end_length as u64)?;
+        read_length += 8 + start_length as u64 + end_length as u64;
+        let seq_num = if format_version >= 10 {
+            read_length += 8;
+            read_u64(reader)?
+        } else {
+            SequenceNumber::MAX
+        };
+        range_tombstones.push(RangeTombstone::new(start, end, seq_num));
+    }
+    debug_assert_eq!(read_length, length);
+    Ok(range_tombstones)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::key_types::SequenceNumber;
+    use crate::db::range_tombstone::RangeTombstone;
+    use crate::ioutils::{BufReaderWithPos, BufWriterWithPos};
+    use crate::sstable::footer::FORMAT_VERSION;
+    use crate::sstable::range_del_block::{load_range_del_block, write_range_del_block};
+    use std::io::{Seek, SeekFrom, Write};
+
+    #[test]
+    fn test_load_range_del_block() {
+        let range_tombstones = vec![
+            RangeTombstone::new("key1".into(), "key3".into(), 7),
+            RangeTombstone::new("key5".into(), "key50".into(), SequenceNumber::MAX),
+        ];
+
+        let temp_file = tempfile::tempfile().unwrap();
+        let mut temp_file2 = temp_file.try_clone().unwrap();
+        let mut writer = BufWriterWithPos::new(temp_file).unwrap();
+        write_range_del_block(&range_tombstones, &mut writer).unwrap();
+        writer.flush().unwrap();
+        let length = writer.pos;
+
+        temp_file2.seek(SeekFrom::Start(0)).unwrap();
+        let mut reader = BufReaderWithPos::new(temp_file2).unwrap();
+        assert_eq!(
+            load_range_del_block(0, length, FORMAT_VERSION, &mut reader).unwrap(),
+            range_tombstones
+        );
+        assert!(load_range_del_block(0, 0, FORMAT_VERSION, &mut reader)
+            .unwrap()
+            .is_empty());
+
+        // range tombstones without sequence numbers
+        let temp_file = tempfile::tempfile().unwrap();
+        let mut temp_file2 = temp_file.try_clone().unwrap();
+        let mut writer = BufWriterWithPos::new(temp_file).unwrap();
+        writer.write_all(&4u32.to_le_bytes()).unwrap();
+        writer.write_all(&4u32.to_le_bytes()).unwrap();
+        writer.write_all(b"key1key3").unwrap();
+        writer.flush().unwrap();
+        temp_file2.seek(SeekFrom::Start(0)).unwrap();
+        let mut reader = BufReaderWithPos::new(temp_file2).unwrap();
+        assert_eq!(
+            load_range_del_block(0, writer.pos, 9, &mut reader).unwrap(),
+            vec![RangeTombstone::new(
+                "key1".into(),
+                "key3".into(),
+                SequenceNumber::MAX
+            )]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! Secondary cache tier behind [crate::sstable::block_cache::BlockCache].
+//!
+//! Data blocks evicted from memory are inserted into the secondary cache, and it is looked
+//! up before reading the block from sstable again.
+
+use crate::Result;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+pub trait SecondaryCache: Send + Sync {
+    /// Insert the encoded value evicted from the primary cache, which may be dropped later
+    /// without notification.
+    fn insert(&self, key: &[u8], value: &[u8]);
+
+    fn look_up(&self, key: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Location of a cached value in the cache file.
+struct Record {
+    offset: u64,
+    length: u64,
+}
+
+struct FileCacheState {
+    file: File,
+    index: HashMap<Vec<u8>, Record>,
+    /// Keys of records from the oldest to the newest.
+    queue: VecDeque<(Vec<u8>, u64)>,
+    write_offset: u64,
+}
+
+impl FileCacheState {
+    /// Remove the record of `key` at `offset` from index, unless `key` is inserted again.
+    fn remove(&mut self, key: &[u8], offset: u64) {
+        if matches!(self.index.get(key), Some(record) if record.offset == offset) {
+            self.index.remove(key);
+        }
+    }
+}
+
+/// Size-bounded secondary cache in a local file, which is written as a ring buffer, so the
+/// oldest records are overwritten when the file is full.
+///
+/// The file is truncated on open, because the keys of block cache are only unique in one
+/// process.
+///
+/// ```text
+///               oldest record                   write_offset
+///               v                               v
+/// +------------------------------------------------------------+
+/// | new records | old records | ... | new records |            |
+/// +------------------------------------------------------------+
+/// \----------------------------- capacity ---------------------/
+/// ```
+pub struct FileSecondaryCache {
+    capacity: u64,
+    state: Mutex<FileCacheState>,
+}
+
+impl FileSecondaryCache {
+    /// Create a cache file of at most `capacity` bytes at `path`.
+    pub fn open(path: impl AsRef<Path>, capacity: u64) -> Result<FileSecondaryCache> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(FileSecondaryCache {
+            capacity,
+            state: Mutex::new(FileCacheState {
+                file,
+                index: HashMap::new(),
+                queue: VecDeque::new(),
+                write_offset: 0,
+            }),
+        })
+    }
+
+    /// Total size of the cached values in bytes.
+    pub fn usage(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.index.values().map(|record| record.length).sum()
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+}
+
+impl SecondaryCache for FileSecondaryCache {
+    fn insert(&self, key: &[u8], value: &[u8]) {
+        let length = value.len() as u64;
+        if length > self.capacity {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let mut offset = state.write_offset;
+        if offset + length > self.capacity {
+            // wrap around and drop the records at the end of file
+            while let Some((key, old_offset)) = state.queue.pop_front() {
+                if old_offset < offset {
+                    state.queue.push_front((key, old_offset));
+                    break;
+                }
+                state.remove(&key, old_offset);
+            }
+            offset = 0;
+        }
+        // drop the oldest records overwritten by the value, records written after wrapping
+        // around are always before `offset`
+        while let Some((key, old_offset)) = state.queue.pop_front() {
+            if old_offset < offset || old_offset >= offset + length {
+                state.queue.push_front((key, old_offset));
+                break;
+            }
+            state.remove(&key, old_offset);
+        }
+
+        let written = state
+            .file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| state.file.write_all(value));
+        if let Err(e) = written {
+            warn!("failed to write secondary cache: {}", e);
+            return;
+        }
+        state.write_offset = offset + length;
+        state.queue.push_back((key.to_vec(), offset));
+        state.index.insert(key.to_vec(), Record { offset, length });
+    }
+
+    fn look_up(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        let record = state.index.get(key)?;
+        let (offset, length) = (record.offset, record.length);
+        let mut buf = vec![0; length as usize];
+        let read = state
+            .file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| state.file.read_exact(&mut buf));
+        match read {
+            Ok(()) => Some(buf),
+            Err(e) => {
+                warn!("failed to read secondary cache: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sstable::secondary_cache::{FileSecondaryCache, SecondaryCache};
+
+    #[test]
+    fn test_file_secondary_cache() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cache = FileSecondaryCache::open(temp_dir.path().join("cache"), 1000).unwrap();
+
+        for i in 0..10u8 {
+            cache.insert(&[i], &[i; 100]);
+        }
+        assert_eq!(cache.usage(), 1000);
+        for i in 0..10u8 {
+            assert_eq!(cache.look_up(&[i]), Some(vec![i; 100]));
+        }
+
+        // overwrite the oldest 3 records
+        cache.insert(&[10], &[10; 250]);
+        assert_eq!(cache.usage(), 950);
+        for i in 0..3u8 {
+            assert!(cache.look_up(&[i]).is_none());
+        }
+        for i in 3..10u8 {
+            assert_eq!(cache.look_up(&[i]), Some(vec![i; 100]));
+        }
+        assert_eq!(cache.look_up(&[10]), Some(vec![10; 250]));
+
+        // wrap around again and drop the records at the end of file
+        cache.insert(&[11], &[11; 800]);
+        assert_eq!(cache.usage(), 800);
+        assert_eq!(cache.look_up(&[11]), Some(vec![11; 800]));
+        assert!(cache.look_up(&[10]).is_none());
+
+        // a value larger than the cache is not inserted
+        cache.insert(&[12], &[12; 1001]);
+        assert!(cache.look_up(&[12]).is_none());
+    }
+}
@@ -0,0 +1,246 @@
+//! Write sstables outside the database, which are bulk loaded by
+//! [crate::db::dbimpl::DBImpl::ingest_external_files].
+
+use crate::db::comparator::Comparator;
+use crate::db::key_types::RawUserKey;
+use crate::db::options::Options;
+use crate::db::{Value, MAX_LEVEL};
+use crate::error::KVLiteError;
+use crate::ioutils::{BufReaderWithPos, BufWriterWithPos};
+use crate::sstable::footer::Footer;
+use crate::sstable::properties_block::TableProperties;
+use crate::sstable::table_handle::{temp_file_name, TableReadHandle, TableWriter};
+use crate::sstable::DATA_BLOCK_SIZE;
+use crate::Result;
+use std::cmp::Ordering;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Writer of an sstable whose keys are added in increasing order.
+///
+/// The sstable is written with the filter policy and the compression of the last level,
+/// where the ingested sstables are usually placed. It is written to a temporary file until
+/// [SstFileWriter::finish] is called.
+pub struct SstFileWriter {
+    file_path: String,
+    writer: TableWriter,
+    comparator: Arc<dyn Comparator>,
+    last_key: RawUserKey,
+    kv_count: u64,
+}
+
+impl SstFileWriter {
+    /// Create a writer of the sstable at `file_path`, which should not exist.
+    pub fn create(file_path: impl AsRef<Path>, options: &Options) -> Result<SstFileWriter> {
+        let file_path = file_path
+            .as_ref()
+            .to_str()
+            .ok_or_else(|| KVLiteError::Custom("invalid sstable path".into()))?
+            .to_string();
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(temp_file_name(&file_path))?;
+        let writer = TableWriter::new(
+            BufWriterWithPos::new(file)?,
+            0,
+            options.prefix_extractor.clone(),
+            options.level_filter_policy(MAX_LEVEL).clone(),
+            options.block_restart_interval,
+            options.level_compression(MAX_LEVEL),
+            (options.zstd_max_dict_bytes, options.zstd_max_train_bytes),
+        );
+        Ok(SstFileWriter {
+            file_path,
+            writer,
+            comparator: options.comparator.clone(),
+            last_key: RawUserKey::new(),
+            kv_count: 0,
+        })
+    }
+
+    /// Add `key` with a non-empty `value`, `key` should be greater than the keys added.
+    pub fn put(&mut self, key: RawUserKey, value: Value) -> Result<()> {
+        if value.is_empty() {
+            return Err(KVLiteError::Custom(
+                "empty value is a deletion, use `delete` instead".into(),
+            ));
+        }
+        self.add(key, value)
+    }
+
+    /// Add a deletion of `key`, which deletes the key in older sstables once ingested.
+    pub fn delete(&mut self, key: RawUserKey) -> Result<()> {
+        self.add(key, Value::default())
+    }
+
+    fn add(&mut self, key: RawUserKey, value: Value) -> Result<()> {
+        if key.is_empty() {
+            return Err(KVLiteError::Custom("empty key".into()));
+        }
+        if self.kv_count > 0 && self.comparator.compare(&self.last_key, &key) != Ordering::Less {
+            return Err(KVLiteError::Custom(
+                "keys must be added in increasing order".into(),
+            ));
+        }
+        self.writer.add_key_value(key.clone(), 0, value)?;
+        if self.writer.data.len() >= DATA_BLOCK_SIZE {
+            self.writer.flush_data(key.clone())?;
+        }
+        self.last_key = key;
+        self.kv_count += 1;
+        Ok(())
+    }
+
+    /// Number of key-values added.
+    #[inline]
+    pub fn kv_count(&self) -> u64 {
+        self.kv_count
+    }
+
+    /// Write the index, filter and properties blocks, and move the temporary file to the
+    /// path of the sstable.
+    pub fn finish(mut self) -> Result<()> {
+        if self.kv_count == 0 {
+            return Err(KVLiteError::Custom("no key-value in sstable".into()));
+        }
+        if !self.writer.data.is_empty() {
+            self.writer.flush_data(self.last_key.clone())?;
+        }
+        self.writer.kv_total = self.kv_count;
+        self.writer.write_index_filter_footer()?;
+        std::fs::rename(temp_file_name(&self.file_path), &self.file_path)?;
+        Ok(())
+    }
+}
+
+impl Drop for SstFileWriter {
+    fn drop(&mut self) {
+        // remove the temporary file if the sstable is not finished
+        let _ = std::fs::remove_file(temp_file_name(&self.file_path));
+    }
+}
+
+/// Check whether the keys of the sstable are in increasing order.
+pub(crate) fn validate_key_order(handle: Arc<TableReadHandle>) -> Result<()> {
+    let comparator = handle.comparator().clone();
+    let mut last_key: Option<RawUserKey> = None;
+    for (key, ..) in TableReadHandle::raw_iter(handle)? {
+        if let Some(last_key) = &last_key {
+            if comparator.compare(last_key, &key) != Ordering::Less {
+                return Err(KVLiteError::Custom(
+                    "keys of sstable are not in increasing order".into(),
+                ));
+            }
+        }
+        last_key = Some(key);
+    }
+    Ok(())
+}
+
+/// Copy the sstable at `src` to `dst`, and record `global_seq_num` as the sequence number of
+/// all its keys in the properties block. SSTables without properties block cannot record it,
+/// so they are rejected.
+pub(crate) fn copy_with_global_seq_num(src: &str, dst: &str, global_seq_num: u64) -> Result<()> {
+    let mut reader = BufReaderWithPos::new(File::open(src)?)?;
+    let mut footer = Footer::load_footer(&mut reader)?;
+    let mut properties =
+        TableProperties::load_properties(&mut reader, &footer)?.ok_or_else(|| {
+            KVLiteError::Custom(format!(
+                "sstable `{}` has no properties block to record the global sequence number",
+                src
+            ))
+        })?;
+
+    let file = OpenOptions::new().write(true).create_new(true).open(dst)?;
+    let mut writer = BufWriterWithPos::new(file)?;
+    reader.seek(SeekFrom::Start(0))?;
+    std::io::copy(
+        &mut (&mut reader).take(footer.properties_offset()),
+        &mut writer,
+    )?;
+    properties.smallest_seq_num = global_seq_num;
+    properties.largest_seq_num = global_seq_num;
+    let properties_offset = writer.pos;
+    properties.write_to_file(&mut writer)?;
+    footer.properties_length = writer.pos - properties_offset;
+    footer.write_to_file(&mut writer)?;
+    writer.flush()?;
+    writer.sync_data()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::options::Options;
+    use crate::sstable::sst_file_writer::{
+        copy_with_global_seq_num, validate_key_order, SstFileWriter,
+    };
+    use crate::sstable::table_handle::{temp_file_name, TableReadHandle};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_sst_file_writer() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("external.sst");
+        let options = Options::default();
+
+        let mut writer = SstFileWriter::create(&path, &options).unwrap();
+        for i in 0..1000 {
+            let key = format!("key{:04}", i).into_bytes();
+            if i % 10 == 0 {
+                writer.delete(key).unwrap();
+            } else {
+                writer
+                    .put(key, format!("value{:04}", i).into_bytes())
+                    .unwrap();
+            }
+        }
+        assert!(writer.put(b"key0500".to_vec(), b"value".to_vec()).is_err());
+        assert!(writer.put(b"key1000".to_vec(), vec![]).is_err());
+        assert_eq!(writer.kv_count(), 1000);
+        writer.finish().unwrap();
+
+        let path = path.to_str().unwrap().to_string();
+        assert!(!std::path::Path::new(&temp_file_name(&path)).exists());
+        let handle =
+            Arc::new(TableReadHandle::try_open_file(path.clone(), 0, 0, &options).unwrap());
+        assert_eq!(handle.kv_total(), 1000);
+        assert_eq!(handle.min_key(), b"key0000");
+        assert_eq!(handle.max_key(), b"key0999");
+        assert_eq!(handle.largest_seq_num(), 0);
+        validate_key_order(handle.clone()).unwrap();
+        for (i, (key, value)) in TableReadHandle::iter(handle).unwrap().enumerate() {
+            assert_eq!(key, format!("key{:04}", i).into_bytes());
+            if i % 10 == 0 {
+                assert!(value.is_empty());
+            } else {
+                assert_eq!(value, format!("value{:04}", i).into_bytes());
+            }
+        }
+
+        let copy = temp_dir.path().join("copy.sst");
+        let copy = copy.to_str().unwrap().to_string();
+        copy_with_global_seq_num(&path, &copy, 42).unwrap();
+        let handle = Arc::new(TableReadHandle::try_open_file(copy, 0, 0, &options).unwrap());
+        assert_eq!(handle.largest_seq_num(), 42);
+        let properties = handle.properties().unwrap();
+        assert_eq!(properties.smallest_seq_num, 42);
+        assert_eq!(properties.num_deletions, 100);
+        assert_eq!(TableReadHandle::iter(handle).unwrap().count(), 1000);
+
+        // unfinished sstable is removed
+        let path = temp_dir.path().join("unfinished.sst");
+        let mut writer = SstFileWriter::create(&path, &options).unwrap();
+        writer.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+        drop(writer);
+        assert!(!path.exists());
+        assert!(!std::path::Path::new(&temp_file_name(path.to_str().unwrap())).exists());
+        assert!(SstFileWriter::create(&path, &options)
+            .unwrap()
+            .finish()
+            .is_err());
+    }
+}
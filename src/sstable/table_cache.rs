@@ -1,20 +1,25 @@
-use crate::filter::DefaultBloomFilter;
-use crate::sstable::data_block::DataBlock;
+use crate::filter::Filter;
+use crate::sstable::compression::CompressionDict;
 use crate::sstable::index_block::IndexBlock;
-use std::collections::HashMap;
 
+/// Filter, index and compression dictionary of a sstable, data blocks are cached in
+/// [crate::sstable::block_cache::BlockCache].
 pub struct TableCache {
-    pub filter: DefaultBloomFilter,
+    pub filter: Box<dyn Filter>,
     pub index: IndexBlock,
-    pub start_data_block_map: HashMap<u32, DataBlock>,
+    pub compression_dict: Option<CompressionDict>,
 }
 
 impl TableCache {
-    pub fn new(filter: DefaultBloomFilter, index: IndexBlock) -> TableCache {
+    pub fn new(
+        filter: Box<dyn Filter>,
+        index: IndexBlock,
+        compression_dict: Option<CompressionDict>,
+    ) -> TableCache {
         TableCache {
             filter,
             index,
-            start_data_block_map: HashMap::with_capacity(10),
+            compression_dict,
         }
     }
 }
@@ -1,23 +1,44 @@
-use crate::cache::ShardLRUCache;
+use crate::cache::EntryTracker;
 use crate::collections::skip_list::skipmap::SrSwSkipMap;
-use crate::db::db_iter::InternalKeyValue;
-use crate::db::key_types::{DBKey, RawUserKey};
+use crate::db::comparator::Comparator;
+use crate::db::db_iter::{InternalKeyValue, IteratorStatus, VersionedKeyValue};
+use crate::db::key_types::{pack_seq_num_and_type, DBKey, KeyType, RawUserKey, SequenceNumber};
+use crate::db::options::Options;
+use crate::db::range_tombstone::{is_covered, remove_covered_keys, RangeTombstone};
+use crate::db::slice_transform::{
+    out_of_domain_hash, prefix_hash, KeyPrefix, RangePrefix, SliceTransform,
+};
+use crate::db::snapshot::VersionFilter;
 use crate::db::{max_level_shift, Value, WRITE_BUFFER_SIZE};
 use crate::env::file_system::{FileSystem, SequentialReadableFile};
-use crate::filter::{DefaultBloomFilter, SEED};
+use crate::error::KVLiteError;
+use crate::filter::{Filter, FilterPolicy, SEED};
 use crate::hash::murmur_hash;
-use crate::ioutils::{BufReaderWithPos, BufWriterWithPos};
+use crate::ioutils::{put_varint, BufReaderWithPos, BufWriterWithPos};
 use crate::memory::InternalKeyValueIterator;
+use crate::sstable::blob_file::{
+    tag_value, untag_value, BlobFile, BlobFileWriter, BlobIndex, BlobStore, ValueType,
+};
+use crate::sstable::block_cache::{BlockCache, BlockCacheKey, CachedBlock};
+use crate::sstable::compression::{
+    compress_block, load_compression_dict, train_zstd_dict, CompressionDict, CompressionType,
+    DictCompressor, LevelDict,
+};
 use crate::sstable::data_block::{DataBlock, DataBlockIter};
 use crate::sstable::filter_block::{load_filter_block, write_filter_block};
-use crate::sstable::footer::{write_footer, Footer};
+use crate::sstable::footer::{Footer, FORMAT_VERSION};
 use crate::sstable::index_block::IndexBlock;
+use crate::sstable::properties_block::TableProperties;
+use crate::sstable::range_del_block::{load_range_del_block, write_range_del_block};
 use crate::sstable::table_cache::TableCache;
 use crate::sstable::{TableID, DATA_BLOCK_SIZE};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fs::{File, OpenOptions};
 use std::io::{Seek, SeekFrom, Write};
 use std::ops::Deref;
 use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum TableStatus {
@@ -35,10 +56,19 @@ pub struct TableWriteHandle {
     level: usize,
     table_id: u64,
     pub(crate) writer: TableWriter,
+    comparator: Arc<dyn Comparator>,
+    block_cache: Arc<BlockCache>,
+    pin_filter_and_index_blocks: bool,
 }
 
 impl TableWriteHandle {
-    pub fn new(db_path: &str, level: usize, table_id: u64, kv_total: u32) -> TableWriteHandle {
+    pub fn new(
+        db_path: &str,
+        level: usize,
+        table_id: u64,
+        kv_total: u64,
+        options: &Options,
+    ) -> TableWriteHandle {
         let file_path = format!("{}/{}/{}", db_path, level, table_id);
         let writer = {
             let mut file = OpenOptions::new()
@@ -50,7 +80,17 @@ impl TableWriteHandle {
             debug_assert!(std::path::Path::new(&temp_file_name(&file_path)).exists());
             file.seek(SeekFrom::Start(0)).unwrap();
             let buf_writer = BufWriterWithPos::new(file).unwrap();
-            TableWriter::new(buf_writer, kv_total)
+            let mut writer = TableWriter::new(
+                buf_writer,
+                kv_total,
+                options.prefix_extractor.clone(),
+                options.level_filter_policy(level).clone(),
+                options.block_restart_interval,
+                options.level_compression(level),
+                (options.zstd_max_dict_bytes, options.zstd_max_train_bytes),
+            );
+            writer.min_blob_size = options.min_blob_size;
+            writer
         };
 
         TableWriteHandle {
@@ -58,32 +98,80 @@ impl TableWriteHandle {
             level,
             table_id,
             writer,
+            comparator: options.comparator.clone(),
+            block_cache: options.block_cache.clone(),
+            pin_filter_and_index_blocks: options.pin_filter_and_index_blocks.pins(level),
         }
     }
 
-    pub fn write_sstable(&mut self, table: &impl InternalKeyValueIterator) -> crate::Result<()> {
-        // write Data Blocks
-        for (i, (k, v)) in table.kv_iter().enumerate() {
-            self.writer.add_key_value(k.clone(), v.clone());
-            if self.writer.data.len() >= DATA_BLOCK_SIZE || i == table.len() - 1 {
-                self.writer.flush_data(k.clone());
-            }
-        }
-        self.writer.write_index_filter_footer();
-        Ok(())
+    /// Write the versions of the keys in `table`, the versions read by neither the latest
+    /// reads nor the snapshots at `snapshots` are dropped.
+    pub fn write_sstable(
+        &mut self,
+        table: &impl InternalKeyValueIterator,
+        snapshots: &[SequenceNumber],
+    ) -> crate::Result<()> {
+        let mut version_filter = VersionFilter::new(snapshots.to_vec());
+        let versions = table
+            .versions_iter()
+            .filter(|(k, seq_num, _)| version_filter.retain(k, *seq_num))
+            .map(|(k, seq_num, v)| (k.clone(), seq_num, v.clone()));
+        self.write_versions(versions, TableWriter::add_key_value)
     }
 
+    /// Write the key-values with sequence number 0.
     pub fn write_sstable_from_vec(&mut self, kvs: Vec<(RawUserKey, Value)>) -> crate::Result<()> {
-        // write Data Blocks
-        let length = kvs.len();
-        for (i, (k, v)) in kvs.into_iter().enumerate() {
-            self.writer.add_key_value(k.clone(), v);
-            if self.writer.data.len() >= DATA_BLOCK_SIZE || i == length - 1 {
-                self.writer.flush_data(k);
+        self.write_versions(
+            kvs.into_iter().map(|(k, v)| (k, 0, v)),
+            TableWriter::add_key_value,
+        )
+    }
+
+    /// Write the versions read by [TableReadHandle::raw_iter], whose blob indexes are
+    /// written without reading the blobs.
+    pub(crate) fn write_sstable_from_raw_vec(
+        &mut self,
+        kvs: Vec<VersionedKeyValue>,
+    ) -> crate::Result<()> {
+        self.write_versions(kvs.into_iter(), TableWriter::add_raw_key_value)
+    }
+
+    /// Write `versions` ordered by key and then by sequence number in descending order, a
+    /// data block is flushed only after the last version of a key.
+    fn write_versions(
+        &mut self,
+        versions: impl Iterator<Item = VersionedKeyValue>,
+        add: fn(&mut TableWriter, RawUserKey, SequenceNumber, Value) -> crate::Result<()>,
+    ) -> crate::Result<()> {
+        let mut versions = versions.peekable();
+        let mut count = 0;
+        while let Some((k, seq_num, v)) = versions.next() {
+            add(&mut self.writer, k.clone(), seq_num, v)?;
+            count += 1;
+            match versions.peek() {
+                Some((next_key, ..)) if next_key == &k => {}
+                Some(_) if self.writer.data.len() < DATA_BLOCK_SIZE => {}
+                _ => self.writer.flush_data(k)?,
             }
         }
-        self.writer.write_index_filter_footer();
-        Ok(())
+        self.writer.kv_total = count;
+        self.writer.write_index_filter_footer()
+    }
+
+    /// Write the values of at least [Options::min_blob_size] bytes to a new blob file of
+    /// `blob_store`, and store their blob indexes in the sstable.
+    pub(crate) fn with_blob_store(mut self, blob_store: Arc<BlobStore>) -> TableWriteHandle {
+        self.writer.blob_store = Some(blob_store);
+        self
+    }
+
+    /// Compress the data blocks with the Zstd dictionary shared by the sstables of the
+    /// level, see [Options::zstd_dict_per_level].
+    pub(crate) fn with_level_dict(mut self, level_dict: Option<LevelDict>) -> TableWriteHandle {
+        if let Some(level_dict) = level_dict {
+            self.writer.set_level_dict(level_dict);
+        }
+        self
     }
 
     pub(crate) fn rename(&self) {
@@ -101,6 +189,18 @@ impl TableWriteHandle {
             .unwrap_or_else(|e| panic!("{:#?}, file_path: {}", e, &self.file_path));
     }
 
+    /// Remove the temporary file of the sstable which fails to be written or is not
+    /// inserted. The blob file it writes is removed when the database is opened again.
+    pub(crate) fn abandon(self) {
+        if let Err(e) = std::fs::remove_file(temp_file_name(&self.file_path)) {
+            warn!(
+                "failed to remove {}: {}",
+                temp_file_name(&self.file_path),
+                e
+            );
+        }
+    }
+
     #[inline]
     pub fn level(&self) -> usize {
         self.level
@@ -111,33 +211,121 @@ impl TableWriteHandle {
         self.table_id
     }
 
-    #[inline]
-    pub fn take_min_key(&mut self) -> RawUserKey {
-        debug_assert_ne!(self.writer.index_block.min_key.len(), 0);
-        std::mem::take(&mut self.writer.index_block.min_key)
+    /// Add range tombstones to the sstable, should be called before writing key-values.
+    pub fn add_range_tombstones(&mut self, range_tombstones: Vec<RangeTombstone>) {
+        self.writer.range_tombstones.extend(range_tombstones);
     }
 
+    /// Take the minimum and maximum key of the sstable, keys deleted by range tombstones
+    /// are included.
+    pub fn take_min_max_key(&mut self) -> (RawUserKey, RawUserKey) {
+        let min_key = std::mem::take(&mut self.writer.index_block.min_key);
+        let bounds = key_bounds(
+            min_key,
+            self.writer.index_block.max_key(),
+            &self.writer.range_tombstones,
+            self.comparator.as_ref(),
+        );
+        debug_assert_ne!(bounds.0.len(), 0);
+        bounds
+    }
+
+    /// Get the maximum key of key-values written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no key-value is written.
     #[inline]
     pub fn max_key(&self) -> &RawUserKey {
         self.writer.max_key()
     }
 }
 
+/// Extend [`min_key`, `max_key`] to include the keys deleted by `range_tombstones`.
+fn key_bounds(
+    mut min_key: RawUserKey,
+    max_key: Option<&RawUserKey>,
+    range_tombstones: &[RangeTombstone],
+    comparator: &dyn Comparator,
+) -> (RawUserKey, RawUserKey) {
+    let mut max_key = max_key.cloned().unwrap_or_default();
+    for tombstone in range_tombstones {
+        if min_key.is_empty() || comparator.compare(&tombstone.start, &min_key) == Ordering::Less {
+            min_key = tombstone.start.clone();
+        }
+        let max_covered_key = tombstone.max_covered_key(comparator);
+        if max_key.is_empty() || comparator.compare(&max_key, &max_covered_key) == Ordering::Less {
+            max_key = max_covered_key;
+        }
+    }
+    (min_key, max_key)
+}
+
 pub(crate) struct TableWriter {
-    pub(crate) kv_total: u32,
+    pub(crate) kv_total: u64,
     #[cfg(debug_assertions)]
-    kv_count: u32,
-    data: Vec<u8>,
+    kv_count: u64,
+    pub(super) data: Vec<u8>,
     pub(crate) index_block: IndexBlock,
     pub(crate) writer: BufWriterWithPos<File>,
-    record_offsets: Vec<u8>,
-    filter: DefaultBloomFilter,
-    #[cfg(feature = "snappy_compression")]
-    snappy_encoder: snap::raw::Encoder,
+    /// Offsets of restart points in the current data block.
+    restarts: Vec<u32>,
+    /// Number of keys after the last restart point.
+    restart_counter: usize,
+    block_restart_interval: usize,
+    last_key: RawUserKey,
+    /// Hashes of keys and prefixes to build the filter block.
+    filter_hashes: Vec<u32>,
+    filter_policy: Arc<dyn FilterPolicy>,
+    range_tombstones: Vec<RangeTombstone>,
+    prefix_extractor: Option<Arc<dyn SliceTransform>>,
+    /// Whether [out_of_domain_hash] is in `filter_hashes`.
+    has_out_of_domain_key: bool,
+    compression_type: CompressionType,
+    /// Zstd dictionary trained from the sampled values, used by all the data blocks.
+    compression_dict: Option<Vec<u8>>,
+    /// Compression context loaded with `compression_dict`.
+    compressor: Option<DictCompressor>,
+    /// Dictionary shared by the sstables of the level, if trained per level.
+    level_dict: Option<LevelDict>,
+    /// Whether values are sampled to train the dictionary, data blocks are buffered
+    /// until the dictionary is trained.
+    sampling: bool,
+    max_dict_bytes: usize,
+    max_train_bytes: usize,
+    samples: Vec<u8>,
+    sample_sizes: Vec<usize>,
+    /// Uncompressed data blocks, the length of their entries and their max keys.
+    buffered_blocks: Vec<(Vec<u8>, usize, RawUserKey)>,
+    pub(super) properties: TableProperties,
+    /// Values of at least this length are written to a blob file if `blob_store` is set.
+    min_blob_size: Option<usize>,
+    blob_store: Option<Arc<BlobStore>>,
+    blob_writer: Option<BlobFileWriter>,
+    /// Total length of the values referenced in each blob file.
+    blob_references: BTreeMap<u64, u64>,
 }
 
 impl TableWriter {
-    fn new(writer: BufWriterWithPos<File>, kv_total: u32) -> TableWriter {
+    pub(super) fn new(
+        writer: BufWriterWithPos<File>,
+        kv_total: u64,
+        prefix_extractor: Option<Arc<dyn SliceTransform>>,
+        filter_policy: Arc<dyn FilterPolicy>,
+        block_restart_interval: usize,
+        compression_type: CompressionType,
+        (max_dict_bytes, max_train_bytes): (usize, usize),
+    ) -> TableWriter {
+        assert!(
+            block_restart_interval > 0,
+            "block restart interval must be positive"
+        );
+        // prefixes of keys are also inserted into the filter.
+        let filter_entries = if prefix_extractor.is_some() {
+            kv_total as usize * 2
+        } else {
+            kv_total as usize
+        };
         TableWriter {
             kv_total,
             #[cfg(debug_assertions)]
@@ -145,90 +333,319 @@ impl TableWriter {
             data: Vec::with_capacity(WRITE_BUFFER_SIZE as usize + 500),
             index_block: IndexBlock::default(),
             writer,
-            record_offsets: Vec::with_capacity(kv_total as usize),
-            filter: DefaultBloomFilter::create_filter(kv_total as usize),
-            #[cfg(feature = "snappy_compression")]
-            snappy_encoder: snap::raw::Encoder::new(),
+            restarts: vec![],
+            restart_counter: block_restart_interval,
+            block_restart_interval,
+            last_key: RawUserKey::new(),
+            filter_hashes: Vec::with_capacity(filter_entries),
+            filter_policy,
+            range_tombstones: vec![],
+            prefix_extractor,
+            has_out_of_domain_key: false,
+            compression_type,
+            compression_dict: None,
+            compressor: None,
+            level_dict: None,
+            sampling: compression_type == CompressionType::Zstd && max_dict_bytes > 0,
+            max_dict_bytes,
+            max_train_bytes,
+            samples: vec![],
+            sample_sizes: vec![],
+            buffered_blocks: vec![],
+            properties: TableProperties {
+                compression: compression_type,
+                ..TableProperties::default()
+            },
+            min_blob_size: None,
+            blob_store: None,
+            blob_writer: None,
+            blob_references: BTreeMap::new(),
         }
     }
 
-    fn add_key_value(&mut self, mut k: RawUserKey, mut v: Value) {
-        debug_assert!(!k.is_empty(), "attempt to write empty key");
-        let h = murmur_hash(&k, SEED);
-        self.filter.add(h);
-        debug_assert!(self.filter.may_contain(h));
+    /// Share the Zstd dictionary of the level, the dictionary is trained by this sstable if
+    /// the level has none yet.
+    pub(super) fn set_level_dict(&mut self, level_dict: LevelDict) {
+        if !self.sampling {
+            return;
+        }
+        let dict = level_dict.lock().unwrap().clone();
+        match dict {
+            Some(dict) => {
+                self.sampling = false;
+                self.set_compression_dict(Some(dict));
+            }
+            None => self.level_dict = Some(level_dict),
+        }
+    }
 
-        #[cfg(debug_assertions)]
-        let excepted_data_len = self.data.len() + 8 + k.len() + v.len();
+    fn set_compression_dict(&mut self, dict: Option<Vec<u8>>) {
+        self.compressor = dict
+            .as_ref()
+            .and_then(|dict| match DictCompressor::new(dict) {
+                Ok(compressor) => Some(compressor),
+                Err(e) => {
+                    warn!("failed to load zstd dictionary: {}", e);
+                    None
+                }
+            });
+        if self.compressor.is_some() {
+            self.compression_dict = dict;
+        }
+    }
 
-        if unsafe { std::intrinsics::unlikely(self.index_block.min_key.is_empty()) } {
-            self.index_block.min_key = k.clone();
+    pub(super) fn add_key_value(
+        &mut self,
+        k: RawUserKey,
+        seq_num: SequenceNumber,
+        v: Value,
+    ) -> crate::Result<()> {
+        match (&self.blob_store, self.min_blob_size) {
+            (Some(_), Some(min_blob_size)) if !v.is_empty() && v.len() >= min_blob_size => {
+                let blob_index = self.write_blob(&v)?;
+                self.add_blob_index(k, seq_num, blob_index);
+            }
+            _ => self.add_record(k, seq_num, v, ValueType::Value),
         }
+        Ok(())
+    }
 
-        let record_offset = (self.data.len() as u32).to_le_bytes();
-        self.record_offsets.append(&mut Vec::from(record_offset));
+    /// Add a key-value whose value is tagged by [tag_value].
+    fn add_raw_key_value(
+        &mut self,
+        k: RawUserKey,
+        seq_num: SequenceNumber,
+        v: Value,
+    ) -> crate::Result<()> {
+        match untag_value(v) {
+            (v, ValueType::Value) => self.add_key_value(k, seq_num, v),
+            (v, ValueType::BlobIndex) => {
+                let blob_index = BlobIndex::decode(&v)?;
+                self.add_blob_index(k, seq_num, blob_index);
+                Ok(())
+            }
+        }
+    }
 
-        self.data
-            .append(&mut Vec::from((k.len() as u32).to_le_bytes()));
-        self.data
-            .append(&mut Vec::from((v.len() as u32).to_le_bytes()));
-        self.data.append(&mut k);
-        self.data.append(&mut v);
+    fn add_blob_index(&mut self, k: RawUserKey, seq_num: SequenceNumber, blob_index: BlobIndex) {
+        *self
+            .blob_references
+            .entry(blob_index.file_number)
+            .or_default() += blob_index.length;
+        self.add_record(k, seq_num, blob_index.encode(), ValueType::BlobIndex);
+    }
+
+    /// Append `value` to the blob file, which is created by the first large value.
+    fn write_blob(&mut self, value: &[u8]) -> crate::Result<BlobIndex> {
+        let blob_store = self.blob_store.as_ref().expect("blob store is not set");
+        let blob_writer = match &mut self.blob_writer {
+            Some(blob_writer) => blob_writer,
+            None => self.blob_writer.insert(blob_store.new_writer()?),
+        };
+        blob_writer.add_value(value)
+    }
+
+    fn add_record(
+        &mut self,
+        k: RawUserKey,
+        seq_num: SequenceNumber,
+        v: Value,
+        value_type: ValueType,
+    ) {
+        debug_assert!(!k.is_empty(), "attempt to write empty key");
+        // older versions of the previous key are already in the filter
+        if k != self.last_key {
+            self.filter_hashes.push(murmur_hash(&k, SEED));
+            if let Some(prefix_extractor) = &self.prefix_extractor {
+                if prefix_extractor.in_domain(&k) {
+                    self.filter_hashes
+                        .push(prefix_hash(prefix_extractor.transform(&k)));
+                } else if !self.has_out_of_domain_key {
+                    self.filter_hashes.push(out_of_domain_hash());
+                    self.has_out_of_domain_key = true;
+                }
+            }
+        }
+
+        if unsafe { std::intrinsics::unlikely(self.index_block.min_key.is_empty()) } {
+            self.index_block.min_key = k.clone();
+            self.properties.smallest_seq_num = seq_num;
+            self.properties.largest_seq_num = seq_num;
+        }
+        self.properties.smallest_seq_num = self.properties.smallest_seq_num.min(seq_num);
+        self.properties.largest_seq_num = self.properties.largest_seq_num.max(seq_num);
+
+        let shared = if self.restart_counter < self.block_restart_interval {
+            self.last_key
+                .iter()
+                .zip(k.iter())
+                .take_while(|(a, b)| a == b)
+                .count()
+        } else {
+            // data blocks are flushed once they exceed `DATA_BLOCK_SIZE`, so only the
+            // block length may exceed u32 because of huge values, but not the restarts
+            self.restarts.push(self.data.len() as u32);
+            self.restart_counter = 0;
+            0
+        };
+        self.restart_counter += 1;
+
+        put_varint(&mut self.data, shared as u64);
+        put_varint(&mut self.data, (k.len() - shared) as u64);
+        put_varint(&mut self.data, (v.len() as u64) << 1 | value_type as u64);
+        self.data.extend_from_slice(&k[shared..]);
+        self.data.extend_from_slice(
+            &pack_seq_num_and_type(seq_num, KeyType::of_value(&v)).to_le_bytes(),
+        );
+        self.data.extend_from_slice(&v);
+        if self.sampling
+            && value_type == ValueType::Value
+            && !v.is_empty()
+            && self.samples.len() < self.max_train_bytes
+        {
+            self.samples.extend_from_slice(&v);
+            self.sample_sizes.push(v.len());
+        }
+        self.properties.raw_key_size += k.len() as u64;
+        self.properties.raw_value_size += v.len() as u64;
+        if v.is_empty() {
+            self.properties.num_deletions += 1;
+        }
+        self.last_key = k;
         #[cfg(debug_assertions)]
         {
             self.kv_count += 1;
-            assert_eq!(excepted_data_len, self.data.len());
         }
     }
 
-    fn flush_data(&mut self, max_key: RawUserKey) {
-        let index_offset_uncompressed = self.writer.pos as u32 + self.data.len() as u32;
-        self.data.append(&mut self.record_offsets);
-
-        #[cfg(feature = "snappy_compression")]
-        {
-            #[cfg(debug_assertions)]
-            let before_length = self.data.len();
-            self.data = self.snappy_encoder.compress_vec(&self.data).unwrap();
-            #[cfg(debug_assertions)]
-            debug!(
-                "snappy before: {}, after: {}",
-                before_length,
-                self.data.len()
-            );
+    pub(super) fn flush_data(&mut self, max_key: RawUserKey) -> crate::Result<()> {
+        let entries_length = self.data.len();
+        for restart in &self.restarts {
+            self.data.extend_from_slice(&restart.to_le_bytes());
+        }
+        self.data
+            .extend_from_slice(&(self.restarts.len() as u32).to_le_bytes());
+        self.restarts.clear();
+        self.restart_counter = self.block_restart_interval;
+        self.last_key.clear();
+
+        let block = std::mem::take(&mut self.data);
+        if self.sampling {
+            self.buffered_blocks.push((block, entries_length, max_key));
+            if self.samples.len() >= self.max_train_bytes {
+                self.finish_sampling()?;
+            }
+            Ok(())
+        } else {
+            self.write_data_block(block, entries_length, max_key)
         }
+    }
+
+    fn write_data_block(
+        &mut self,
+        block: Vec<u8>,
+        entries_length: usize,
+        max_key: RawUserKey,
+    ) -> crate::Result<()> {
+        let index_offset_uncompressed = self.writer.pos + entries_length as u64;
+        let block = compress_block(block, self.compression_type, self.compressor.as_mut());
         self.index_block.add_index(
-            self.writer.pos as u32,
-            self.data.len() as u32,
+            self.writer.pos,
+            block.len() as u64,
             index_offset_uncompressed,
             max_key,
         );
-        self.writer.write_all(&self.data).unwrap();
-        self.data.clear();
+        self.properties.num_data_blocks += 1;
+        self.properties.data_size += block.len() as u64;
+        self.writer.write_all(&block)?;
+        Ok(())
+    }
+
+    /// Train the dictionary from the sampled values and write the buffered data blocks.
+    fn finish_sampling(&mut self) -> crate::Result<()> {
+        self.sampling = false;
+        let dict = train_zstd_dict(&self.samples, &self.sample_sizes, self.max_dict_bytes);
+        self.set_compression_dict(dict);
+        if let (Some(level_dict), Some(dict)) = (self.level_dict.take(), &self.compression_dict) {
+            // another sstable of the level may have trained one meanwhile
+            level_dict
+                .lock()
+                .unwrap()
+                .get_or_insert_with(|| dict.clone());
+        }
+        self.samples = vec![];
+        self.sample_sizes = vec![];
+        for (block, entries_length, max_key) in std::mem::take(&mut self.buffered_blocks) {
+            self.write_data_block(block, entries_length, max_key)?;
+        }
+        Ok(())
     }
 
-    fn write_index_filter_footer(&mut self) {
-        let index_block_offset = self.writer.pos as u32;
-        self.index_block.write_to_file(&mut self.writer).unwrap();
-        let index_block_length = self.writer.pos as u32 - index_block_offset;
-        write_filter_block(&mut self.filter, &mut self.writer);
-        write_footer(
+    pub(super) fn write_index_filter_footer(&mut self) -> crate::Result<()> {
+        if self.sampling {
+            self.finish_sampling()?;
+        }
+        if let Some(blob_writer) = self.blob_writer.take() {
+            blob_writer.finish()?;
+        }
+        let index_block_offset = self.writer.pos;
+        self.index_block.write_to_file(&mut self.writer)?;
+        let index_block_length = self.writer.pos - index_block_offset;
+        let filter = self.filter_policy.create_filter(&self.filter_hashes);
+        write_filter_block(&filter, &mut self.writer)?;
+        let range_del_offset = self.writer.pos;
+        write_range_del_block(&self.range_tombstones, &mut self.writer)?;
+        let range_del_length = self.writer.pos - range_del_offset;
+        let compression_dict_length = match &self.compression_dict {
+            Some(dict) => {
+                self.writer.write_all(dict)?;
+                dict.len() as u64
+            }
+            None => 0,
+        };
+        let properties_offset = self.writer.pos;
+        self.write_properties()?;
+        let properties_length = self.writer.pos - properties_offset;
+        let footer = Footer {
             index_block_offset,
             index_block_length,
-            &mut self.writer,
-            self.filter.len() as u32,
-            self.kv_total,
-        );
+            filter_length: filter.len() as u64,
+            range_del_length,
+            kv_total: self.kv_total,
+            compression_dict_length,
+            properties_length,
+            filter_policy: self.filter_policy.name().to_string(),
+            format_version: FORMAT_VERSION,
+        };
+        footer.write_to_file(&mut self.writer)?;
         #[cfg(debug_assertions)]
         debug_assert_eq!(self.kv_count, self.kv_total);
 
-        self.writer.flush().unwrap();
-        self.writer.sync_data().unwrap();
+        self.writer.flush()?;
+        self.writer.sync_data()?;
+        Ok(())
+    }
+
+    fn write_properties(&mut self) -> crate::Result<()> {
+        let properties = &mut self.properties;
+        properties.num_entries = self.kv_total;
+        properties.num_range_deletions = self.range_tombstones.len() as u64;
+        properties.creation_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        properties.min_key = self.index_block.min_key.clone();
+        properties.max_key = self.index_block.max_key().cloned().unwrap_or_default();
+        properties.blob_references = self
+            .blob_references
+            .iter()
+            .map(|(file_number, length)| (*file_number, *length))
+            .collect();
+        properties.write_to_file(&mut self.writer)
     }
 
     #[inline]
     pub(crate) fn max_key(&self) -> &RawUserKey {
-        self.index_block.max_key()
+        self.index_block.max_key().expect("no key-value in sstable")
     }
 }
 
@@ -241,31 +658,90 @@ pub struct TableReadHandle {
     status: RwLock<TableStatus>,
     min_key: RawUserKey,
     max_key: RawUserKey,
-    kv_total: u32,
+    range_tombstones: Vec<RangeTombstone>,
+    kv_total: u64,
+    /// Sequence numbers recorded in the properties block, 0 if not recorded.
+    smallest_seq_num: u64,
+    largest_seq_num: u64,
     file_size: u64,
+    comparator: Arc<dyn Comparator>,
+    filter_policy: Arc<dyn FilterPolicy>,
+    block_cache: Arc<BlockCache>,
+    table_cache_id: u64,
+    format_version: u32,
+    /// Filter and index blocks pinned in memory.
+    pinned_table_cache: Option<Arc<TableCache>>,
+    /// Blob files referenced by the sstable and the total length of the referenced values.
+    blob_references: Vec<(u64, u64)>,
+    /// Blob files opened by [TableReadHandle::with_blob_store].
+    blob_files: Vec<Arc<BlobFile>>,
 }
 
 unsafe impl Send for TableReadHandle {}
 unsafe impl Sync for TableReadHandle {}
 
 impl TableReadHandle {
-    /// Create a table handle for existing sstable.
-    pub fn open(db_path: &str, level: usize, table_id: u64) -> TableReadHandle {
+    /// Create a table handle for existing sstable, return an error if the filter policy of
+    /// the sstable is neither configured in `options` nor built-in.
+    pub fn open(
+        db_path: &str,
+        level: usize,
+        table_id: u64,
+        options: &Options,
+    ) -> crate::Result<TableReadHandle> {
         let file_path = format!("{}/{}/{}", db_path, level, table_id);
+        Self::try_open_file(file_path, level, table_id, options)
+    }
 
-        let file = File::open(&file_path).unwrap();
-        let file_size = file.metadata().unwrap().len();
+    /// Create a table handle for the sstable at `file_path`, return an error if the footer,
+    /// the range deletion block or the filter policy of the sstable is invalid, or the
+    /// pinned filter block is corrupted.
+    pub(crate) fn try_open_file(
+        file_path: String,
+        level: usize,
+        table_id: u64,
+        options: &Options,
+    ) -> crate::Result<TableReadHandle> {
+        let file = File::open(&file_path)?;
+        let file_size = file.metadata()?.len();
 
-        let mut buf_reader = BufReaderWithPos::new(file).unwrap();
+        let mut buf_reader = BufReaderWithPos::new(file)?;
 
-        let footer = Footer::load_footer(&mut buf_reader).unwrap();
+        let footer = Footer::load_footer(&mut buf_reader)?;
         let mut index_block = IndexBlock::load_index(&mut buf_reader, &footer);
+        let range_tombstones = load_range_del_block(
+            footer.index_block_offset + footer.index_block_length + footer.filter_length,
+            footer.range_del_length,
+            footer.format_version,
+            &mut buf_reader,
+        )?;
+        let (smallest_seq_num, largest_seq_num, blob_references) =
+            match TableProperties::load_properties(&mut buf_reader, &footer)? {
+                Some(properties) => (
+                    properties.smallest_seq_num,
+                    properties.largest_seq_num,
+                    properties.blob_references,
+                ),
+                None => (0, 0, vec![]),
+            };
 
+        let filter_policy = options
+            .find_filter_policy(&footer.filter_policy)
+            .ok_or_else(|| {
+                KVLiteError::Custom(format!("unknown filter policy `{}`", footer.filter_policy))
+            })?;
+
+        let comparator = options.comparator.clone();
         let min_key = std::mem::take(&mut index_block.min_key);
-        let max_key = index_block.max_key().clone();
+        let (min_key, max_key) = key_bounds(
+            min_key,
+            index_block.max_key(),
+            &range_tombstones,
+            comparator.as_ref(),
+        );
 
         let table_key = Self::calc_table_key(table_id, level);
-        TableReadHandle {
+        let mut handle = TableReadHandle {
             file_path,
             level,
             table_id,
@@ -274,9 +750,24 @@ impl TableReadHandle {
             status: RwLock::new(TableStatus::Store),
             min_key,
             max_key,
+            range_tombstones,
             kv_total: footer.kv_total,
+            smallest_seq_num,
+            largest_seq_num,
             file_size,
+            comparator,
+            filter_policy,
+            table_cache_id: options.block_cache.new_table_cache_id(),
+            block_cache: options.block_cache.clone(),
+            format_version: footer.format_version,
+            pinned_table_cache: None,
+            blob_references,
+            blob_files: vec![],
+        };
+        if options.pin_filter_and_index_blocks.pins(level) {
+            handle.pin_filter_and_index_blocks()?;
         }
+        Ok(handle)
     }
 
     #[inline]
@@ -305,13 +796,14 @@ impl TableReadHandle {
 
         table_write_handle.rename();
 
-        let min_key = table_write_handle.take_min_key();
-        let max_key: RawUserKey = table_write_handle.max_key().clone();
+        let blob_store = table_write_handle.writer.blob_store.take();
+        let (min_key, max_key) = table_write_handle.take_min_max_key();
+        let range_tombstones = std::mem::take(&mut table_write_handle.writer.range_tombstones);
 
         let table_id = table_write_handle.table_id;
         let level = table_write_handle.level;
         let table_key = Self::calc_table_key(table_id, level);
-        TableReadHandle {
+        let mut handle = TableReadHandle {
             file_path: table_write_handle.file_path,
             level,
             table_id,
@@ -320,11 +812,95 @@ impl TableReadHandle {
             status: RwLock::new(TableStatus::Store),
             min_key,
             max_key,
+            range_tombstones,
             kv_total: table_write_handle.writer.kv_total,
+            smallest_seq_num: table_write_handle.writer.properties.smallest_seq_num,
+            largest_seq_num: table_write_handle.writer.properties.largest_seq_num,
             file_size,
+            comparator: table_write_handle.comparator,
+            filter_policy: table_write_handle.writer.filter_policy,
+            table_cache_id: table_write_handle.block_cache.new_table_cache_id(),
+            block_cache: table_write_handle.block_cache,
+            format_version: FORMAT_VERSION,
+            pinned_table_cache: None,
+            blob_references: std::mem::take(
+                &mut table_write_handle.writer.properties.blob_references,
+            ),
+            blob_files: vec![],
+        };
+        if table_write_handle.pin_filter_and_index_blocks {
+            handle
+                .pin_filter_and_index_blocks()
+                .expect("failed to load the filter block written by the handle");
+        }
+        match blob_store {
+            Some(blob_store) => handle
+                .with_blob_store(&blob_store)
+                .expect("failed to open blob files"),
+            None => handle,
+        }
+    }
+
+    /// Open the blob files referenced by the sstable in `blob_store`, which are read to
+    /// resolve the blob indexes of the sstable.
+    pub(crate) fn with_blob_store(mut self, blob_store: &BlobStore) -> crate::Result<Self> {
+        self.blob_files = self
+            .blob_references
+            .iter()
+            .map(|(file_number, _)| blob_store.get_file(*file_number))
+            .collect::<crate::Result<_>>()?;
+        for blob_file in &self.blob_files {
+            blob_file.add_table();
+        }
+        Ok(self)
+    }
+
+    /// Read the value referenced by the blob index `value`, other values are returned as is.
+    pub(crate) fn resolve_value(
+        &self,
+        value: Value,
+        value_type: ValueType,
+    ) -> crate::Result<Value> {
+        match value_type {
+            ValueType::Value => Ok(value),
+            ValueType::BlobIndex => {
+                let blob_index = BlobIndex::decode(&value)?;
+                let blob_file = self
+                    .blob_files
+                    .iter()
+                    .find(|blob_file| blob_file.file_number() == blob_index.file_number)
+                    .ok_or_else(|| {
+                        KVLiteError::Custom(format!(
+                            "blob file {} of sstable {} is not opened",
+                            blob_index.file_number, self.file_path
+                        ))
+                    })?;
+                blob_file.read_value(&blob_index)
+            }
         }
     }
 
+    /// Load the filter and index blocks and hold them in the handle.
+    fn pin_filter_and_index_blocks(&mut self) -> crate::Result<()> {
+        let mut buf_reader = self.create_buf_reader_with_pos();
+        let footer = Footer::load_footer(&mut buf_reader)?;
+        let filter = self.load_filter(&mut buf_reader, &footer)?;
+        let index_block = IndexBlock::load_index(&mut buf_reader, &footer);
+        let compression_dict = load_compression_dict(&mut buf_reader, &footer);
+        self.pinned_table_cache = Some(Arc::new(TableCache::new(
+            filter,
+            index_block,
+            compression_dict,
+        )));
+        Ok(())
+    }
+
+    /// Whether the filter and index blocks are pinned in the handle.
+    #[inline]
+    pub fn is_pinned(&self) -> bool {
+        self.pinned_table_cache.is_some()
+    }
+
     /// Used for read sstable
     pub fn create_buf_reader_with_pos(&self) -> impl SequentialReadableFile {
         FileSystem::create_seq_readable_file((&self.file_path).as_ref()).unwrap()
@@ -346,111 +922,330 @@ impl TableReadHandle {
     }
 
     #[inline]
-    pub fn kv_total(&self) -> u32 {
+    pub fn kv_total(&self) -> u64 {
         self.kv_total
     }
 
+    #[inline]
+    pub fn smallest_seq_num(&self) -> u64 {
+        self.smallest_seq_num
+    }
+
+    #[inline]
+    pub fn largest_seq_num(&self) -> u64 {
+        self.largest_seq_num
+    }
+
+    /// Sequence number of all the records of the sstable, if they share one.
+    ///
+    /// The records of ingested sstables are assigned the global sequence number recorded in
+    /// the properties block, and sstables before format version 8 are only read after
+    /// reopening the database, so their records are treated as the largest sequence number.
+    #[inline]
+    fn global_seq_num(&self) -> Option<SequenceNumber> {
+        if self.format_version < 8 || self.smallest_seq_num == self.largest_seq_num {
+            Some(self.largest_seq_num)
+        } else {
+            None
+        }
+    }
+
+    /// Sequence number to read the records by readers at `seq_num`, `None` if no record is
+    /// visible to them.
+    #[inline]
+    fn record_seq_num(&self, seq_num: SequenceNumber) -> Option<SequenceNumber> {
+        match self.global_seq_num() {
+            Some(global_seq_num) if global_seq_num > seq_num => None,
+            Some(_) => Some(SequenceNumber::MAX),
+            None => Some(seq_num),
+        }
+    }
+
+    /// Numbers of the blob files referenced by the sstable, and the total length of the
+    /// referenced values in each of them.
+    #[inline]
+    pub fn blob_references(&self) -> &[(u64, u64)] {
+        &self.blob_references
+    }
+
+    #[inline]
+    pub(crate) fn blob_files(&self) -> &[Arc<BlobFile>] {
+        &self.blob_files
+    }
+
+    /// Read the properties of the sstable, which are derived from the footer and the index
+    /// block if the sstable has no properties block.
+    pub fn properties(&self) -> crate::Result<TableProperties> {
+        let mut buf_reader = self.create_buf_reader_with_pos();
+        let footer = Footer::load_footer(&mut buf_reader)?;
+        if let Some(properties) = TableProperties::load_properties(&mut buf_reader, &footer)? {
+            return Ok(properties);
+        }
+        let index_block = IndexBlock::load_index(&mut buf_reader, &footer);
+        Ok(TableProperties {
+            num_entries: footer.kv_total,
+            num_range_deletions: self.range_tombstones.len() as u64,
+            num_data_blocks: index_block.indexes.len() as u64,
+            data_size: footer.index_block_offset,
+            index_size: footer.index_block_length,
+            filter_size: footer.filter_length,
+            filter_policy: footer.filter_policy,
+            format_version: footer.format_version,
+            max_key: index_block.max_key().cloned().unwrap_or_default(),
+            min_key: index_block.min_key,
+            ..TableProperties::default()
+        })
+    }
+
     #[inline]
     pub fn table_key(&self) -> u64 {
         self.table_key
     }
 
+    /// Id of the sstable handle in block cache.
+    #[cfg(test)]
+    #[inline]
+    pub(crate) fn table_cache_id(&self) -> u64 {
+        self.table_cache_id
+    }
+
     #[inline]
     pub fn hash(&self) -> u32 {
         self.hash
     }
 
+    #[inline]
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// Whether `key` in older sstables is deleted by the range tombstones of this sstable
+    /// visible to the readers at `seq_num`.
+    #[inline]
+    pub fn range_tombstones_cover(&self, key: &[u8], seq_num: SequenceNumber) -> bool {
+        is_covered(
+            &self.range_tombstones,
+            key,
+            seq_num,
+            self.comparator.as_ref(),
+        )
+    }
+
+    #[inline]
+    pub fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.comparator
+    }
+
+    #[inline]
+    pub fn filter_policy(&self) -> &Arc<dyn FilterPolicy> {
+        &self.filter_policy
+    }
+
+    fn load_filter(
+        &self,
+        reader: &mut impl SequentialReadableFile,
+        footer: &Footer,
+    ) -> crate::Result<Box<dyn Filter>> {
+        let data = load_filter_block(
+            footer.index_block_offset + footer.index_block_length,
+            footer.filter_length as usize,
+            reader,
+        );
+        self.filter_policy.load_filter(data)
+    }
+
     pub fn status(&self) -> TableStatus {
         let guard = self.status.read().unwrap();
         *guard.deref()
     }
 
-    /// Query value by `key` with `cache`
-    pub fn query_sstable_with_cache(
+    /// Query the newest value of `key` visible at `seq_num` in the data block at `offset`,
+    /// the block is read and inserted into block cache if it is not cached.
+    fn query_data_block(
         &self,
-        #[allow(clippy::ptr_arg)] key: &RawUserKey,
-        cache: &mut TableCache,
-    ) -> Option<Value> {
-        let h = murmur_hash(key, SEED);
-        if cache.filter.may_contain(h) {
-            if let Some((offset, length, index_offset)) = cache.index.may_contain_key(key) {
-                return match cache.start_data_block_map.get(&offset) {
-                    Some(data_block) => data_block.get_value(key),
-                    None => {
-                        let mut buf_reader = self.create_buf_reader_with_pos();
-                        let data_block =
-                            DataBlock::from_reader(&mut buf_reader, offset, length, index_offset);
-                        let option = data_block.get_value(key);
-                        cache.start_data_block_map.insert(offset, data_block);
-                        option
-                    }
-                };
-            }
+        key: &[u8],
+        seq_num: SequenceNumber,
+        (offset, length, index_offset): (u64, u64, u64),
+        compression_dict: Option<&CompressionDict>,
+    ) -> crate::Result<Option<Value>> {
+        let cache_key = BlockCacheKey::new(self.table_cache_id, offset);
+        let entry_tracker = self.block_cache.look_up(&cache_key);
+        if let Some(CachedBlock::Data(data_block)) = entry_tracker.value() {
+            return data_block
+                .get_value(key, seq_num, self.comparator.as_ref())
+                .map(|(value, value_type)| self.resolve_value(value, value_type))
+                .transpose();
         }
-        None
+        let data_block = match self.block_cache.look_up_secondary(&cache_key) {
+            Some(data_block) => data_block,
+            None => DataBlock::from_reader(
+                &mut self.create_buf_reader_with_pos(),
+                offset,
+                length,
+                index_offset,
+                self.format_version,
+                compression_dict,
+            ),
+        };
+        let option = data_block.get_value(key, seq_num, self.comparator.as_ref());
+        self.block_cache.insert(cache_key, data_block);
+        option
+            .map(|(value, value_type)| self.resolve_value(value, value_type))
+            .transpose()
     }
 
-    /// Query value by `key` and insert cache into `lru_cache`.
-    pub fn query_sstable(
+    /// Query the newest value of `key` visible at `seq_num` with the pinned filter and
+    /// index blocks, or those in block cache if they are not pinned.
+    pub fn query(
         &self,
         #[allow(clippy::ptr_arg)] key: &RawUserKey,
-        lru_cache: &Arc<ShardLRUCache<u64, TableCache>>,
-    ) -> Option<Value> {
-        let mut buf_reader = self.create_buf_reader_with_pos();
-        let footer = Footer::load_footer(&mut buf_reader).unwrap();
-        let bloom_filter = load_filter_block(
-            footer.index_block_offset as u64 + footer.index_block_length as u64,
-            footer.filter_length as usize,
-            &mut buf_reader,
-        );
+        seq_num: SequenceNumber,
+    ) -> crate::Result<Option<Value>> {
+        let seq_num = match self.record_seq_num(seq_num) {
+            Some(seq_num) => seq_num,
+            None => return Ok(None),
+        };
+        let table_cache = self.table_cache()?;
+        if table_cache.filter.may_contain(murmur_hash(key, SEED)) {
+            if let Some(block_handle) = table_cache
+                .index
+                .may_contain_key(key, self.comparator.as_ref())
+            {
+                return self.query_data_block(
+                    key,
+                    seq_num,
+                    block_handle,
+                    table_cache.compression_dict.as_ref(),
+                );
+            }
+        }
+        Ok(None)
+    }
 
-        let h = murmur_hash(key, SEED);
-        if bloom_filter.may_contain(h) {
-            let index_block = IndexBlock::load_index(&mut buf_reader, &footer);
-            let may_contain_key = index_block.may_contain_key(key);
-            let mut cache = TableCache::new(bloom_filter, index_block);
-
-            let option = if let Some((offset, length, index_offset)) = may_contain_key {
-                let data_block =
-                    DataBlock::from_reader(&mut buf_reader, offset, length, index_offset);
-                let option = data_block.get_value(key);
-                cache.start_data_block_map.insert(offset, data_block);
-                option
-            } else {
-                None
-            };
-            lru_cache.insert_no_exists(self.table_key, cache, self.hash);
-            option
-        } else {
-            None
+    /// Whether the sstable may contain keys with `key_prefix`, the pinned filter or the
+    /// filter in block cache is used if exists.
+    ///
+    /// It is valid only if the sstable is written with the prefix extractor of `key_prefix`.
+    pub fn may_contain_prefix(&self, key_prefix: &KeyPrefix) -> bool {
+        self.check_filter(|filter| filter.may_contain(key_prefix.hash()))
+    }
+
+    /// Whether the sstable may contain keys in the range of `range_prefix`.
+    pub fn may_contain_range_prefix(&self, range_prefix: &RangePrefix) -> bool {
+        match range_prefix {
+            RangePrefix::Single(key_prefix) => self.may_contain_prefix(key_prefix),
+            // keys not in domain are recorded since format version 9
+            RangePrefix::Multiple(_) if self.format_version < 9 => true,
+            RangePrefix::Multiple(key_prefixes) => self.check_filter(|filter| {
+                filter.may_contain(out_of_domain_hash())
+                    || key_prefixes.iter().any(|p| filter.may_contain(p.hash()))
+            }),
         }
     }
 
-    /// Query all the key-value pairs in [`key_start`, `key_end`] and insert them into `kvs`
-    /// Return whether table_read_handle is overlapping with [`key_start`, `key_end`]
+    /// Check the pinned filter, or the filter in block cache. The sstable may contain any
+    /// key if its filter can't be loaded, the error is returned by the reads that follow.
+    fn check_filter(&self, check: impl FnOnce(&dyn Filter) -> bool) -> bool {
+        self.table_cache()
+            .map_or(true, |table_cache| check(table_cache.filter.as_ref()))
+    }
+
+    /// Get the pinned filter, index block and compression dictionary, or those in block
+    /// cache. They are read and inserted into block cache if they are not cached.
+    fn table_cache(&self) -> crate::Result<Arc<TableCache>> {
+        if let Some(table_cache) = &self.pinned_table_cache {
+            return Ok(table_cache.clone());
+        }
+        if let Some(CachedBlock::Table(table_cache)) = self.look_up_table_cache().value() {
+            return Ok(table_cache.clone());
+        }
+        let mut buf_reader = self.create_buf_reader_with_pos();
+        let footer = Footer::load_footer(&mut buf_reader)?;
+        let filter = self.load_filter(&mut buf_reader, &footer)?;
+        let index_block = IndexBlock::load_index(&mut buf_reader, &footer);
+        let compression_dict = load_compression_dict(&mut buf_reader, &footer);
+        let table_cache = Arc::new(TableCache::new(filter, index_block, compression_dict));
+        self.insert_table_cache(&footer, table_cache.clone());
+        Ok(table_cache)
+    }
+
+    #[inline]
+    fn look_up_table_cache(&self) -> EntryTracker<BlockCacheKey, CachedBlock> {
+        self.block_cache
+            .look_up(&BlockCacheKey::table(self.table_cache_id))
+    }
+
+    /// Insert `table_cache` into block cache with high priority, charged by the sizes of
+    /// the filter, index and compression dictionary blocks in the sstable.
+    fn insert_table_cache(&self, footer: &Footer, table_cache: Arc<TableCache>) {
+        let charge = footer.filter_length as usize
+            + footer.index_block_length as usize
+            + table_cache
+                .compression_dict
+                .as_ref()
+                .map_or(0, CompressionDict::len);
+        self.block_cache
+            .insert_table(self.table_cache_id, table_cache, charge);
+    }
+
+    /// Query the newest key-value pairs visible at `seq_num` in [`key_start`, `key_end`] and
+    /// insert them into `kvs`, the keys in `kvs` deleted by the range tombstones of this
+    /// sstable visible at `seq_num` are removed first. Return whether the sstables after this
+    /// one may contain keys in [`key_start`, `key_end`].
+    ///
+    /// If the prefixes of the keys in the range are known by `range_prefix`, the filter is
+    /// checked before reading the index block and data blocks.
     pub fn range_query<UK: DBKey>(
         &self,
         #[allow(clippy::ptr_arg)] key_start: &RawUserKey,
         #[allow(clippy::ptr_arg)] key_end: &RawUserKey,
+        seq_num: SequenceNumber,
+        range_prefix: Option<&RangePrefix>,
         kvs: &mut SrSwSkipMap<UK, Value>,
-    ) -> bool {
+    ) -> crate::Result<bool> {
         if self.is_overlapping(key_start, key_end) {
+            let comparator = self.comparator.as_ref();
+            remove_covered_keys(&self.range_tombstones, seq_num, kvs, comparator);
+            let seq_num = match self.record_seq_num(seq_num) {
+                Some(seq_num) => seq_num,
+                None => return Ok(comparator.compare(&self.max_key, key_end) == Ordering::Less),
+            };
+            if let Some(range_prefix) = range_prefix {
+                if !self.may_contain_range_prefix(range_prefix) {
+                    return Ok(comparator.compare(&self.max_key, key_end) == Ordering::Less);
+                }
+            }
+            let table_cache = self.table_cache()?;
             let mut buf_reader = self.create_buf_reader_with_pos();
-            let footer = Footer::load_footer(&mut buf_reader).unwrap();
-            let index_block = IndexBlock::load_index(&mut buf_reader, &footer);
-            let data_blocks = index_block.find_all_ge(key_start);
-            let mut remain = false;
+            let data_blocks = table_cache.index.find_all_ge(key_start, comparator);
             for (offset, length, index_offset, _key_length, max_key) in data_blocks {
-                if max_key > key_end {
+                let data_block = DataBlock::from_reader(
+                    &mut buf_reader,
+                    *offset,
+                    *length,
+                    *index_offset,
+                    self.format_version,
+                    table_cache.compression_dict.as_ref(),
+                );
+                let mut last_key: Option<RawUserKey> = None;
+                for (key, record_seq_num, value, value_type) in data_block
+                    .seek(key_start, comparator)
+                    .take_while(|(key, ..)| comparator.compare(key, key_end) != Ordering::Greater)
+                {
+                    // only the newest visible version of each key is inserted
+                    if record_seq_num > seq_num || last_key.as_ref() == Some(&key) {
+                        continue;
+                    }
+                    last_key = Some(key.clone());
+                    kvs.insert(key.into(), self.resolve_value(value, value_type)?);
+                }
+                if comparator.compare(max_key, key_end) != Ordering::Less {
                     break;
                 }
-                let data_block =
-                    DataBlock::from_reader(&mut buf_reader, *offset, *length, *index_offset);
-                remain |= data_block.get_all_record_le(key_end, kvs);
             }
-            return remain;
+            return Ok(comparator.compare(&self.max_key, key_end) == Ordering::Less);
         }
-        false
+        Ok(false)
     }
 
     /// Check whether status of sstable is `Store`.
@@ -465,10 +1260,20 @@ impl TableReadHandle {
         }
     }
 
+    /// Change the status of the sstable back to `Store` if it fails to be compacted.
+    pub(crate) fn abandon_compacting(&self) {
+        let mut guard = self.status.write().unwrap();
+        debug_assert_eq!(*guard, TableStatus::Compacting, "invalid table status");
+        *guard = TableStatus::Store;
+    }
+
+    /// Mark the sstable to be deleted when the handle is dropped, and erase its filter and
+    /// index blocks from block cache.
     pub(super) fn ready_to_delete(&self) {
         let mut guard = self.status.write().unwrap();
         debug_assert_eq!(*guard, TableStatus::Compacting, "invalid table status");
         *guard = TableStatus::ToDelete;
+        self.block_cache.erase_table(self.table_cache_id);
     }
 
     pub(crate) fn readable(&self) -> bool {
@@ -495,15 +1300,27 @@ impl TableReadHandle {
     /// ----         ------      -----    ----
     ///   |---|       |--|     |---|    |------|
     ///```
-    #[allow(clippy::ptr_arg)]
-    pub fn is_overlapping(&self, min_key: &RawUserKey, max_key: &RawUserKey) -> bool {
-        self.min_key.le(min_key) && min_key.le(&self.max_key)
-            || self.min_key.le(max_key) && max_key.le(&self.max_key)
-            || min_key.le(&self.min_key) && self.max_key.le(max_key)
+    pub fn is_overlapping(&self, min_key: &[u8], max_key: &[u8]) -> bool {
+        let le = |a: &[u8], b: &[u8]| self.comparator.compare(a, b) != Ordering::Greater;
+        le(&self.min_key, min_key) && le(min_key, &self.max_key)
+            || le(&self.min_key, max_key) && le(max_key, &self.max_key)
+            || le(min_key, &self.min_key) && le(&self.max_key, max_key)
     }
 
-    pub fn iter(handle: Arc<Self>) -> TableIterator {
-        TableIterator::new(handle)
+    /// Iterate the newest version of the keys, the iteration stops at the first blob or
+    /// block which can't be read, see [TableIterator::status].
+    pub fn iter(handle: Arc<Self>) -> crate::Result<TableIterator> {
+        TableIterator::new(handle, IteratorStatus::default())
+    }
+
+    /// Iterate all the versions of keys without reading the blobs, each value is tagged by
+    /// [tag_value] with whether it is a blob index. Used by compaction, which moves blob
+    /// indexes to the new sstables.
+    pub(crate) fn raw_iter(
+        handle: Arc<Self>,
+    ) -> crate::Result<impl Iterator<Item = VersionedKeyValue>> {
+        let mut iter = TableIterator::with_raw_values(handle, true, IteratorStatus::default())?;
+        Ok(std::iter::from_fn(move || iter.next_version()))
     }
 }
 
@@ -511,6 +1328,9 @@ impl Drop for TableReadHandle {
     fn drop(&mut self) {
         if let TableStatus::ToDelete = self.status() {
             std::fs::remove_file(&self.file_path).unwrap();
+            for blob_file in &self.blob_files {
+                blob_file.remove_table();
+            }
         }
     }
 }
@@ -522,69 +1342,151 @@ pub(crate) fn temp_file_name(file_name: &str) -> String {
 pub struct TableIterator {
     reader: Box<dyn SequentialReadableFile>,
     handle: Arc<TableReadHandle>,
-    index_block: IndexBlock,
+    /// Index block and compression dictionary of the sstable.
+    table_cache: Arc<TableCache>,
     data_block: DataBlockIter,
     cur_data_block_idx: usize,
+    /// Whether values are tagged with their types instead of resolving blob indexes.
+    raw_values: bool,
+    /// Key returned by the last [Iterator::next], whose older versions are skipped.
+    last_key: Option<RawUserKey>,
+    /// Status shared with the other iterators merged with this one.
+    status: IteratorStatus,
     #[cfg(debug_assertions)]
-    prev_key: RawUserKey,
+    prev_key: Option<(RawUserKey, SequenceNumber)>,
 }
 
 impl TableIterator {
-    pub(super) fn new(handle: Arc<TableReadHandle>) -> TableIterator {
-        let mut reader = Box::new(handle.create_buf_reader_with_pos());
-        let footer = Footer::load_footer(&mut reader).unwrap();
-        let index_block = IndexBlock::load_index(&mut reader, &footer);
+    pub(super) fn new(
+        handle: Arc<TableReadHandle>,
+        status: IteratorStatus,
+    ) -> crate::Result<TableIterator> {
+        Self::with_raw_values(handle, false, status)
+    }
 
-        let index = &index_block.indexes[0];
-        let data_block = DataBlock::from_reader(&mut reader, index.0, index.1, index.2);
+    fn with_raw_values(
+        handle: Arc<TableReadHandle>,
+        raw_values: bool,
+        status: IteratorStatus,
+    ) -> crate::Result<TableIterator> {
+        let mut reader = Box::new(handle.create_buf_reader_with_pos());
+        let table_cache = handle.table_cache()?;
+
+        // sstable with range tombstones only has no data block
+        let data_block = match table_cache.index.indexes.first() {
+            Some(index) => DataBlock::from_reader(
+                &mut reader,
+                index.0,
+                index.1,
+                index.2,
+                handle.format_version,
+                table_cache.compression_dict.as_ref(),
+            ),
+            None => DataBlock::default(),
+        };
 
-        TableIterator {
+        Ok(TableIterator {
             reader,
             handle,
-            index_block,
+            table_cache,
             data_block: data_block.into_iter(),
             cur_data_block_idx: 0,
+            raw_values,
+            last_key: None,
+            status,
             #[cfg(debug_assertions)]
-            prev_key: RawUserKey::default(),
-        }
+            prev_key: None,
+        })
     }
 
     #[inline]
     pub fn end(&self) -> bool {
-        self.cur_data_block_idx == self.index_block.indexes.len()
+        self.cur_data_block_idx == self.table_cache.index.indexes.len()
     }
-}
 
-impl Iterator for TableIterator {
-    type Item = InternalKeyValue;
+    /// Return the error which stops the iteration, if a blob can't be read.
+    pub fn status(&self) -> crate::Result<()> {
+        self.status.check()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.end() {
-            None
-        } else {
+    /// Record `error` and stop the iteration.
+    fn stop(&mut self, error: KVLiteError) {
+        self.status.set_error(error);
+        self.cur_data_block_idx = self.table_cache.index.indexes.len();
+    }
+
+    /// Read the next record, ordered by key and then by sequence number in descending order.
+    fn next_record(&mut self) -> Option<(RawUserKey, SequenceNumber, Value, ValueType)> {
+        while !self.end() {
             match self.data_block.next() {
-                Some(item) => {
+                Some((key, seq_num, value, value_type)) => {
+                    let seq_num = self.handle.global_seq_num().unwrap_or(seq_num);
                     #[cfg(debug_assertions)]
                     {
-                        assert!(self.prev_key < item.0);
-                        self.prev_key = item.0.clone();
+                        if let Some((prev_key, prev_seq_num)) = &self.prev_key {
+                            match self.handle.comparator.compare(prev_key, &key) {
+                                Ordering::Less => {}
+                                Ordering::Equal => assert!(*prev_seq_num > seq_num),
+                                Ordering::Greater => panic!("keys of sstable are not sorted"),
+                            }
+                        }
+                        self.prev_key = Some((key.clone(), seq_num));
                     }
-                    Some(item)
+                    return Some((key, seq_num, value, value_type));
                 }
                 None => {
                     self.cur_data_block_idx += 1;
-                    if self.end() {
-                        None
-                    } else {
-                        let index = &self.index_block.indexes[self.cur_data_block_idx];
-                        let data_block =
-                            DataBlock::from_reader(&mut self.reader, index.0, index.1, index.2);
+                    let indexes = &self.table_cache.index.indexes;
+                    if let Some(index) = indexes.get(self.cur_data_block_idx) {
+                        let data_block = DataBlock::from_reader(
+                            &mut self.reader,
+                            index.0,
+                            index.1,
+                            index.2,
+                            self.handle.format_version,
+                            self.table_cache.compression_dict.as_ref(),
+                        );
                         self.data_block = data_block.into_iter();
-                        self.next()
                     }
                 }
             }
         }
+        None
+    }
+
+    #[inline]
+    fn value(&mut self, value: Value, value_type: ValueType) -> Option<Value> {
+        if self.raw_values {
+            return Some(tag_value(value, value_type));
+        }
+        match self.handle.resolve_value(value, value_type) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.stop(e);
+                None
+            }
+        }
+    }
+
+    /// Read the next version of keys, see [TableIterator::next_record].
+    fn next_version(&mut self) -> Option<VersionedKeyValue> {
+        let (key, seq_num, value, value_type) = self.next_record()?;
+        Some((key, seq_num, self.value(value, value_type)?))
+    }
+}
+
+impl Iterator for TableIterator {
+    type Item = InternalKeyValue;
+
+    /// Read the newest version of the next key.
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((key, _, value, value_type)) = self.next_record() {
+            if self.last_key.as_ref() != Some(&key) {
+                self.last_key = Some(key.clone());
+                return Some((key, self.value(value, value_type)?));
+            }
+        }
+        None
     }
 }
 
@@ -592,10 +1494,27 @@ impl Iterator for TableIterator {
 pub(crate) mod tests {
     use std::ops::Range;
 
+    use crate::collections::skip_list::skipmap::SrSwSkipMap;
+    use crate::db::comparator::BytewiseComparator;
+    use crate::db::key_types::{RawUserKey, SequenceNumber};
+    use crate::db::options::{Options, PinningTier};
+    use crate::db::range_tombstone::RangeTombstone;
+    use crate::db::slice_transform::{FixedPrefixTransform, RangePrefix, SliceTransform};
+    use crate::db::Value;
+    use crate::error::KVLiteError;
+    use crate::filter::bloom_filter::BloomFilterPolicy;
+    use crate::filter::cuckoo_filter::CuckooFilterPolicy;
+    use crate::filter::ribbon_filter::RibbonFilterPolicy;
+    use crate::filter::{default_filter_policy, Filter, FilterPolicy, SEED};
+    use crate::hash::murmur_hash;
+    use crate::sstable::blob_file::{tag_value, ValueType};
+    use crate::sstable::compression::{CompressionType, LevelDict};
     use crate::sstable::data_block::DataBlock;
-    use crate::sstable::footer::Footer;
+    use crate::sstable::footer::{Footer, FORMAT_VERSION, UNVERSIONED_FOOTER_MAGIC_NUMBER};
     use crate::sstable::index_block::IndexBlock;
-    use crate::sstable::table_handle::{TableReadHandle, TableWriteHandle};
+    use crate::sstable::table_handle::{temp_file_name, TableReadHandle, TableWriteHandle};
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
     use std::sync::Arc;
 
     pub(crate) fn create_write_handle(
@@ -604,8 +1523,9 @@ pub(crate) mod tests {
         table_id: u64,
         range: Range<i32>,
     ) -> TableWriteHandle {
-        let kv_total: u32 = (range.end - range.start) as u32;
-        let mut write_handle = TableWriteHandle::new(db_path, level, table_id, kv_total);
+        let kv_total = (range.end - range.start) as u64;
+        let mut write_handle =
+            TableWriteHandle::new(db_path, level, table_id, kv_total, &Options::default());
 
         let mut kvs = vec![];
         for i in range {
@@ -626,7 +1546,7 @@ pub(crate) mod tests {
     ) -> TableReadHandle {
         let write_handle = create_write_handle(db_path, level, table_id, range);
         write_handle.rename();
-        TableReadHandle::open(db_path, level, table_id)
+        TableReadHandle::open(db_path, level, table_id, &Options::default()).unwrap()
     }
 
     #[test]
@@ -642,7 +1562,10 @@ pub(crate) mod tests {
         assert_eq!(read_handle.max_key(), "key99".as_bytes());
 
         let read_handle = Arc::new(read_handle);
-        for (i, kv) in TableReadHandle::iter(read_handle.clone()).enumerate() {
+        for (i, kv) in TableReadHandle::iter(read_handle.clone())
+            .unwrap()
+            .enumerate()
+        {
             assert_eq!(
                 kv,
                 (
@@ -658,25 +1581,744 @@ pub(crate) mod tests {
         let index_block = IndexBlock::load_index(&mut reader, &footer);
         assert_eq!(index_block.indexes.len(), 1);
         for index in index_block.indexes {
-            let data_block = DataBlock::from_reader(&mut reader, index.0, index.1, index.2);
+            let data_block = DataBlock::from_reader(
+                &mut reader,
+                index.0,
+                index.1,
+                index.2,
+                footer.format_version,
+                None,
+            );
             for i in 0..100 {
-                let res = data_block.get_value(&Vec::from(format!("key{:02}", i)));
+                let res = data_block.get_value(
+                    format!("key{:02}", i).as_bytes(),
+                    SequenceNumber::MAX,
+                    &BytewiseComparator,
+                );
                 assert_eq!(
-                    Some(Vec::from(format!("value{:02}_1", i))),
+                    Some((Vec::from(format!("value{:02}_1", i)), ValueType::Value)),
                     res,
                     "error: {}",
                     i
                 );
             }
             for s in ["key1", "key", "key100", "key-1"] {
-                let res = data_block.get_value(&Vec::from(s));
+                let res =
+                    data_block.get_value(s.as_bytes(), SequenceNumber::MAX, &BytewiseComparator);
                 assert!(res.is_none());
             }
 
-            for (i, (k, v)) in data_block.into_iter().enumerate() {
+            for (i, (k, _, v, _)) in data_block.into_iter().enumerate() {
                 assert_eq!(format!("key{:02}", i), String::from_utf8(k).unwrap());
                 assert_eq!(format!("value{:02}_1", i), String::from_utf8(v).unwrap());
             }
         }
     }
+
+    #[test]
+    fn test_filter_policy_per_level() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        let options = Options {
+            filter_policy_per_level: vec![
+                Arc::new(RibbonFilterPolicy),
+                Arc::new(CuckooFilterPolicy),
+                Arc::new(BloomFilterPolicy),
+            ],
+            ..Options::default()
+        };
+        let expected_policies = [
+            "kvlite.RibbonFilter",
+            "kvlite.CuckooFilter",
+            "kvlite.BloomFilter",
+            "kvlite.BloomFilter",
+        ];
+
+        for (level, expected_policy) in expected_policies.iter().enumerate() {
+            std::fs::create_dir_all(temp_dir.path().join(level.to_string())).unwrap();
+            let mut write_handle = TableWriteHandle::new(&path, level, 1, 100, &options);
+            let kvs = (0..100)
+                .map(|i| {
+                    (
+                        format!("key{:02}", i).into_bytes(),
+                        format!("value{:02}", i).into_bytes(),
+                    )
+                })
+                .collect();
+            write_handle.write_sstable_from_vec(kvs).unwrap();
+            write_handle.rename();
+
+            // The filter policy is found by the name in footer.
+            let read_handle = TableReadHandle::open(&path, level, 1, &Options::default()).unwrap();
+            assert_eq!(read_handle.filter_policy().name(), *expected_policy);
+            for i in 0..100 {
+                assert_eq!(
+                    read_handle
+                        .query(&format!("key{:02}", i).into_bytes(), SequenceNumber::MAX)
+                        .unwrap(),
+                    Some(format!("value{:02}", i).into_bytes())
+                );
+            }
+            assert!(read_handle
+                .query(&b"key100".to_vec(), SequenceNumber::MAX)
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_pin_filter_and_index_blocks() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        let options = Options {
+            pin_filter_and_index_blocks: PinningTier::Level0,
+            ..Options::default()
+        };
+
+        for level in 0..2 {
+            std::fs::create_dir_all(temp_dir.path().join(level.to_string())).unwrap();
+            let mut write_handle = TableWriteHandle::new(&path, level, 1, 100, &options);
+            let kvs = (0..100)
+                .map(|i| {
+                    (
+                        format!("key{:02}", i).into_bytes(),
+                        format!("value{:02}", i).into_bytes(),
+                    )
+                })
+                .collect();
+            write_handle.write_sstable_from_vec(kvs).unwrap();
+            let read_handles = [
+                TableReadHandle::from_table_write_handle(write_handle),
+                TableReadHandle::open(&path, level, 1, &options).unwrap(),
+            ];
+
+            for read_handle in read_handles.iter() {
+                assert_eq!(read_handle.is_pinned(), level == 0);
+                for i in 0..100 {
+                    assert_eq!(
+                        read_handle
+                            .query(&format!("key{:02}", i).into_bytes(), SequenceNumber::MAX)
+                            .unwrap(),
+                        Some(format!("value{:02}", i).into_bytes())
+                    );
+                }
+                assert!(read_handle
+                    .query(&b"key100".to_vec(), SequenceNumber::MAX)
+                    .unwrap()
+                    .is_none());
+            }
+        }
+    }
+
+    /// Filter policy whose filter blocks can never be decoded.
+    struct CorruptedFilterPolicy;
+
+    impl FilterPolicy for CorruptedFilterPolicy {
+        fn name(&self) -> &str {
+            "test.CorruptedFilter"
+        }
+
+        fn create_filter(&self, hashes: &[u32]) -> Vec<u8> {
+            BloomFilterPolicy.create_filter(hashes)
+        }
+
+        fn load_filter(&self, _data: Vec<u8>) -> crate::Result<Box<dyn Filter>> {
+            Err(KVLiteError::Custom("corrupted filter block".into()))
+        }
+    }
+
+    #[test]
+    fn test_invalid_filter_block() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("1")).unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        let options = Options {
+            filter_policy: Arc::new(CorruptedFilterPolicy),
+            ..Options::default()
+        };
+        let mut write_handle = TableWriteHandle::new(&path, 1, 1, 100, &options);
+        let kvs = (0..100)
+            .map(|i| {
+                (
+                    format!("key{:02}", i).into_bytes(),
+                    format!("value{:02}", i).into_bytes(),
+                )
+            })
+            .collect();
+        write_handle.write_sstable_from_vec(kvs).unwrap();
+        drop(TableReadHandle::from_table_write_handle(write_handle));
+
+        // the filter policy of the sstable is unknown
+        assert!(TableReadHandle::open(&path, 1, 1, &Options::default()).is_err());
+        // the pinned filter block can't be decoded
+        let pinned_options = Options {
+            pin_filter_and_index_blocks: PinningTier::All,
+            ..options.clone()
+        };
+        assert!(TableReadHandle::open(&path, 1, 1, &pinned_options).is_err());
+
+        // the filter block is loaded by the reads
+        let read_handle = Arc::new(TableReadHandle::open(&path, 1, 1, &options).unwrap());
+        assert!(read_handle
+            .query(&b"key00".to_vec(), SequenceNumber::MAX)
+            .is_err());
+        let mut kvs = SrSwSkipMap::<RawUserKey, Value>::new();
+        assert!(read_handle
+            .range_query(
+                &b"key00".to_vec(),
+                &b"key99".to_vec(),
+                SequenceNumber::MAX,
+                None,
+                &mut kvs
+            )
+            .is_err());
+        assert!(TableReadHandle::iter(read_handle).is_err());
+    }
+
+    #[test]
+    fn test_range_prefix() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("1")).unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        let prefix_extractor: Arc<dyn SliceTransform> = Arc::new(FixedPrefixTransform::new(4));
+        let options = Options {
+            prefix_extractor: Some(prefix_extractor.clone()),
+            ..Options::default()
+        };
+        let key = |prefix: &[u8], i: u32| [prefix, format!("obj{:03}", i).as_bytes()].concat();
+        let range_prefix = |key_start: &[u8], key_end: &[u8]| {
+            RangePrefix::from_range(prefix_extractor.clone(), key_start, key_end).unwrap()
+        };
+
+        // keys of t000 and t003
+        let kvs: Vec<_> = [b"t000", b"t003"]
+            .iter()
+            .flat_map(|prefix| (0..100).map(move |i| (key(*prefix, i), b"value".to_vec())))
+            .collect();
+        let mut write_handle = TableWriteHandle::new(&path, 1, 1, kvs.len() as u64, &options);
+        write_handle.write_sstable_from_vec(kvs).unwrap();
+        let read_handle = TableReadHandle::from_table_write_handle(write_handle);
+        let across = range_prefix(&key(b"t001", 0), &key(b"t002", 99));
+        assert!(matches!(across, RangePrefix::Multiple(_)));
+        assert!(!read_handle.may_contain_range_prefix(&across));
+        let mut kvs = SrSwSkipMap::<RawUserKey, Value>::new();
+        read_handle
+            .range_query(
+                &key(b"t001", 0),
+                &key(b"t002", 99),
+                SequenceNumber::MAX,
+                Some(&across),
+                &mut kvs,
+            )
+            .unwrap();
+        assert!(kvs.is_empty());
+        let with_t000 = range_prefix(&key(b"t000", 50), &key(b"t002", 0));
+        assert!(read_handle.may_contain_range_prefix(&with_t000));
+
+        // a key not in domain is between the prefixes t00\xff and t01\x01
+        let mut kvs: Vec<_> = (0..100)
+            .map(|i| (key(b"t00\xfe", i), b"value".to_vec()))
+            .collect();
+        kvs.push((b"t01".to_vec(), b"short".to_vec()));
+        kvs.extend((0..100).map(|i| (key(b"t01\x02", i), b"value".to_vec())));
+        let mut write_handle = TableWriteHandle::new(&path, 1, 2, kvs.len() as u64, &options);
+        write_handle.write_sstable_from_vec(kvs).unwrap();
+        let read_handle = TableReadHandle::from_table_write_handle(write_handle);
+        let (key_start, key_end) = (key(b"t00\xff", 0), key(b"t01\x01", 0));
+        let across = range_prefix(&key_start, &key_end);
+        assert!(read_handle.may_contain_range_prefix(&across));
+        let mut kvs = SrSwSkipMap::<RawUserKey, Value>::new();
+        read_handle
+            .range_query(
+                &key_start,
+                &key_end,
+                SequenceNumber::MAX,
+                Some(&across),
+                &mut kvs,
+            )
+            .unwrap();
+        assert_eq!(kvs.get_clone(&b"t01".to_vec()), Some(b"short".to_vec()));
+        assert_eq!(kvs.len(), 1);
+    }
+
+    #[test]
+    fn test_block_restart_interval() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("1")).unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+
+        for (table_id, block_restart_interval) in [1, 3, 16, 1000].iter().enumerate() {
+            let options = Options {
+                block_restart_interval: *block_restart_interval,
+                ..Options::default()
+            };
+            let mut write_handle = TableWriteHandle::new(&path, 1, table_id as u64, 1000, &options);
+            let kvs = (0..1000)
+                .map(|i| {
+                    (
+                        format!("long_shared_prefix_key{:04}", i * 2).into_bytes(),
+                        format!("value{:04}", i).into_bytes(),
+                    )
+                })
+                .collect();
+            write_handle.write_sstable_from_vec(kvs).unwrap();
+            let read_handle = Arc::new(TableReadHandle::from_table_write_handle(write_handle));
+
+            for i in 0..2000 {
+                let key = format!("long_shared_prefix_key{:04}", i).into_bytes();
+                let expected = if i % 2 == 0 {
+                    Some(format!("value{:04}", i / 2).into_bytes())
+                } else {
+                    None
+                };
+                assert_eq!(
+                    read_handle.query(&key, SequenceNumber::MAX).unwrap(),
+                    expected
+                );
+            }
+            assert_eq!(
+                TableReadHandle::iter(read_handle.clone()).unwrap().count(),
+                1000
+            );
+
+            // seek in data blocks
+            let mut kvs = SrSwSkipMap::<RawUserKey, Value>::new();
+            read_handle
+                .range_query(
+                    &b"long_shared_prefix_key0101".to_vec(),
+                    &b"long_shared_prefix_key1201".to_vec(),
+                    SequenceNumber::MAX,
+                    None,
+                    &mut kvs,
+                )
+                .unwrap();
+            let keys: Vec<_> = kvs.into_iter().map(|(k, _)| k).collect();
+            let expected: Vec<_> = (102..=1200)
+                .step_by(2)
+                .map(|i| format!("long_shared_prefix_key{:04}", i).into_bytes())
+                .collect();
+            assert_eq!(keys, expected);
+        }
+    }
+
+    #[test]
+    fn test_versions() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("1")).unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        let options = Options {
+            block_restart_interval: 3,
+            ..Options::default()
+        };
+
+        // every key has versions at 30, 20 and 10, and every fifth key is deleted at 30
+        let value = |i: usize, seq_num: SequenceNumber| {
+            if i % 5 == 0 && seq_num == 30 {
+                Value::default()
+            } else {
+                format!("value{:04}_{}", i, seq_num).into_bytes()
+            }
+        };
+        let mut write_handle = TableWriteHandle::new(&path, 1, 1, 1000, &options);
+        let kvs = (0..1000)
+            .flat_map(|i| {
+                vec![30, 20, 10].into_iter().map(move |seq_num| {
+                    (
+                        format!("key{:04}", i).into_bytes(),
+                        seq_num,
+                        tag_value(value(i, seq_num), ValueType::Value),
+                    )
+                })
+            })
+            .collect();
+        write_handle.write_sstable_from_raw_vec(kvs).unwrap();
+        let read_handle = Arc::new(TableReadHandle::from_table_write_handle(write_handle));
+        assert_eq!(read_handle.smallest_seq_num(), 10);
+        assert_eq!(read_handle.largest_seq_num(), 30);
+
+        for i in 0..1000 {
+            let key = format!("key{:04}", i).into_bytes();
+            assert_eq!(read_handle.query(&key, 5).unwrap(), None);
+            assert_eq!(read_handle.query(&key, 10).unwrap(), Some(value(i, 10)));
+            assert_eq!(read_handle.query(&key, 29).unwrap(), Some(value(i, 20)));
+            assert_eq!(
+                read_handle.query(&key, SequenceNumber::MAX).unwrap(),
+                Some(value(i, 30))
+            );
+        }
+
+        // range queries read the newest visible version of each key
+        let mut kvs = SrSwSkipMap::<RawUserKey, Value>::new();
+        read_handle
+            .range_query(
+                &b"key0100".to_vec(),
+                &b"key0199".to_vec(),
+                25,
+                None,
+                &mut kvs,
+            )
+            .unwrap();
+        let expected: Vec<_> = (100..=199)
+            .map(|i| (format!("key{:04}", i).into_bytes(), value(i, 20)))
+            .collect();
+        assert_eq!(kvs.into_iter().collect::<Vec<_>>(), expected);
+
+        // iterators read the newest versions, while raw iterators read all of them
+        let newest: Vec<_> = TableReadHandle::iter(read_handle.clone())
+            .unwrap()
+            .map(|(_, value)| value)
+            .collect();
+        assert_eq!(newest, (0..1000).map(|i| value(i, 30)).collect::<Vec<_>>());
+        let versions: Vec<_> = TableReadHandle::raw_iter(read_handle)
+            .unwrap()
+            .map(|(key, seq_num, _)| (key, seq_num))
+            .collect();
+        assert_eq!(versions.len(), 3000);
+        assert_eq!(versions[3], (b"key0001".to_vec(), 30));
+        assert_eq!(versions[5], (b"key0001".to_vec(), 10));
+    }
+
+    #[test]
+    fn test_compression_per_level() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        let options = Options {
+            compression_per_level: vec![
+                CompressionType::None,
+                CompressionType::Snappy,
+                CompressionType::Lz4,
+                CompressionType::Zstd,
+            ],
+            ..Options::default()
+        };
+        assert_eq!(options.level_compression(6), CompressionType::Zstd);
+
+        let mut file_sizes = vec![];
+        for level in 0..4 {
+            std::fs::create_dir_all(temp_dir.path().join(level.to_string())).unwrap();
+            let mut write_handle = TableWriteHandle::new(&path, level, 1, 1000, &options);
+            let kvs = (0..1000)
+                .map(|i| {
+                    (
+                        format!("key{:04}", i).into_bytes(),
+                        format!("value{:04}", i % 10).repeat(10).into_bytes(),
+                    )
+                })
+                .collect();
+            write_handle.write_sstable_from_vec(kvs).unwrap();
+            let file_path = write_handle.file_path.clone();
+            let read_handle = Arc::new(TableReadHandle::from_table_write_handle(write_handle));
+            file_sizes.push(std::fs::metadata(&file_path).unwrap().len());
+
+            for i in 0..1000 {
+                assert_eq!(
+                    read_handle
+                        .query(&format!("key{:04}", i).into_bytes(), SequenceNumber::MAX)
+                        .unwrap(),
+                    Some(format!("value{:04}", i % 10).repeat(10).into_bytes())
+                );
+            }
+            assert_eq!(
+                TableReadHandle::iter(read_handle.clone()).unwrap().count(),
+                1000
+            );
+        }
+        for compressed_size in &file_sizes[1..] {
+            assert!(*compressed_size < file_sizes[0]);
+        }
+    }
+
+    #[test]
+    fn test_zstd_dict() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("1")).unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        // small JSON documents sharing the structure but few values
+        let value = |i: usize| {
+            let h = (i as u64).wrapping_mul(0x9e3779b97f4a7c15);
+            format!(
+                r#"{{"user_id":{},"display_name":"name_{:x}","registered_at":"2021-{:02}-{:02}","preferences":{{"theme":"{}","language":"{}"}},"score":{}}}"#,
+                h % 1000003,
+                h >> 40,
+                h % 12 + 1,
+                h % 28 + 1,
+                ["dark", "light"][(h >> 3) as usize % 2],
+                ["en", "zh", "fr"][(h >> 5) as usize % 3],
+                (h >> 20) % 10007
+            )
+            .into_bytes()
+        };
+
+        let mut file_sizes = vec![];
+        for (table_id, zstd_max_dict_bytes) in [0, 4096].iter().enumerate() {
+            let options = Options {
+                compression: CompressionType::Zstd,
+                zstd_max_dict_bytes: *zstd_max_dict_bytes,
+                zstd_max_train_bytes: 64 << 10,
+                ..Options::default()
+            };
+            let mut write_handle = TableWriteHandle::new(&path, 1, table_id as u64, 5000, &options);
+            let kvs = (0..5000)
+                .map(|i| (format!("key{:04}", i).into_bytes(), value(i)))
+                .collect();
+            write_handle.write_sstable_from_vec(kvs).unwrap();
+            let file_path = write_handle.file_path.clone();
+            let read_handle = Arc::new(TableReadHandle::from_table_write_handle(write_handle));
+            file_sizes.push(std::fs::metadata(&file_path).unwrap().len());
+
+            let mut reader = read_handle.create_buf_reader_with_pos();
+            let footer = Footer::load_footer(&mut reader).unwrap();
+            assert_eq!(footer.compression_dict_length > 0, *zstd_max_dict_bytes > 0);
+
+            for i in 0..5000 {
+                assert_eq!(
+                    read_handle
+                        .query(&format!("key{:04}", i).into_bytes(), SequenceNumber::MAX)
+                        .unwrap(),
+                    Some(value(i))
+                );
+            }
+            for (i, (key, v)) in TableReadHandle::iter(read_handle.clone())
+                .unwrap()
+                .enumerate()
+            {
+                assert_eq!(key, format!("key{:04}", i).into_bytes());
+                assert_eq!(v, value(i));
+            }
+            let mut kvs = SrSwSkipMap::<RawUserKey, Value>::new();
+            read_handle
+                .range_query(
+                    &b"key1000".to_vec(),
+                    &b"key1999".to_vec(),
+                    SequenceNumber::MAX,
+                    None,
+                    &mut kvs,
+                )
+                .unwrap();
+            assert_eq!(kvs.len(), 1000);
+        }
+        assert!(file_sizes[1] < file_sizes[0]);
+
+        // the dictionary of the level is trained by the first sstable and reused by the next
+        let options = Options {
+            compression: CompressionType::Zstd,
+            zstd_max_dict_bytes: 4096,
+            zstd_max_train_bytes: 64 << 10,
+            zstd_dict_per_level: true,
+            ..Options::default()
+        };
+        let level_dict = LevelDict::default();
+        for table_id in 2..4 {
+            let mut write_handle = TableWriteHandle::new(&path, 1, table_id, 5000, &options)
+                .with_level_dict(Some(level_dict.clone()));
+            assert_eq!(write_handle.writer.sampling, table_id == 2);
+            let kvs = (0..5000)
+                .map(|i| (format!("key{:04}", i).into_bytes(), value(i)))
+                .collect();
+            write_handle.write_sstable_from_vec(kvs).unwrap();
+            let read_handle = TableReadHandle::from_table_write_handle(write_handle);
+
+            let mut reader = read_handle.create_buf_reader_with_pos();
+            let footer = Footer::load_footer(&mut reader).unwrap();
+            let dict_length = level_dict.lock().unwrap().as_ref().map(Vec::len).unwrap();
+            assert_eq!(footer.compression_dict_length, dict_length as u64);
+            for i in 0..5000 {
+                assert_eq!(
+                    read_handle
+                        .query(&format!("key{:04}", i).into_bytes(), SequenceNumber::MAX)
+                        .unwrap(),
+                    Some(value(i))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_offsets_beyond_u32() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("1")).unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+
+        // start writing after a 5GB hole, so all the blocks are beyond u32 offsets
+        let hole = 5u64 << 30;
+        let mut write_handle = TableWriteHandle::new(&path, 1, 1, 1000, &Options::default());
+        OpenOptions::new()
+            .write(true)
+            .open(temp_file_name(&write_handle.file_path))
+            .unwrap()
+            .set_len(hole)
+            .unwrap();
+        write_handle.writer.writer.pos = hole;
+        let kvs = (0..1000)
+            .map(|i| {
+                (
+                    format!("key{:04}", i).into_bytes(),
+                    format!("value{:04}", i).into_bytes(),
+                )
+            })
+            .collect();
+        write_handle.write_sstable_from_vec(kvs).unwrap();
+        let read_handle = Arc::new(TableReadHandle::from_table_write_handle(write_handle));
+        assert!(read_handle.file_size() > hole);
+
+        let mut reader = read_handle.create_buf_reader_with_pos();
+        let footer = Footer::load_footer(&mut reader).unwrap();
+        assert!(footer.index_block_offset > u32::MAX as u64);
+        assert_eq!(footer.kv_total, 1000);
+        let index_block = IndexBlock::load_index(&mut reader, &footer);
+        assert!(index_block.indexes[0].0 >= hole);
+
+        for i in 0..1000 {
+            assert_eq!(
+                read_handle
+                    .query(&format!("key{:04}", i).into_bytes(), SequenceNumber::MAX)
+                    .unwrap(),
+                Some(format!("value{:04}", i).into_bytes())
+            );
+        }
+        assert_eq!(
+            TableReadHandle::iter(read_handle.clone()).unwrap().count(),
+            1000
+        );
+    }
+
+    #[test]
+    fn test_properties() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("2")).unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        let options = Options {
+            compression_per_level: vec![CompressionType::None, CompressionType::Lz4],
+            ..Options::default()
+        };
+
+        let mut write_handle = TableWriteHandle::new(&path, 2, 1, 1000, &options);
+        write_handle.add_range_tombstones(vec![RangeTombstone::new(
+            "key1000".into(),
+            "key1010".into(),
+            1,
+        )]);
+        let kvs = (0..1000)
+            .map(|i| {
+                let value = if i % 10 == 0 {
+                    Value::default()
+                } else {
+                    format!("value{:04}", i).into_bytes()
+                };
+                (format!("key{:04}", i).into_bytes(), value)
+            })
+            .collect();
+        write_handle.write_sstable_from_vec(kvs).unwrap();
+        let read_handle = TableReadHandle::from_table_write_handle(write_handle);
+
+        let properties = read_handle.properties().unwrap();
+        assert_eq!(properties.raw_key_size, 7000);
+        assert_eq!(properties.raw_value_size, 900 * 9);
+        assert_eq!(properties.num_entries, 1000);
+        assert_eq!(properties.num_deletions, 100);
+        assert_eq!(properties.num_range_deletions, 1);
+        assert!(properties.num_data_blocks > 1);
+        assert!(properties.data_size > 0);
+        assert!(properties.index_size > 0);
+        assert!(properties.filter_size > 0);
+        assert_eq!(properties.compression, CompressionType::Lz4);
+        assert_eq!(properties.filter_policy, default_filter_policy().name());
+        assert_eq!(properties.format_version, FORMAT_VERSION);
+        assert!(properties.creation_time > 0);
+        assert_eq!(properties.min_key, b"key0000");
+        assert_eq!(properties.max_key, b"key0999");
+        assert!(
+            properties.data_size + properties.index_size + properties.filter_size
+                < read_handle.file_size()
+        );
+    }
+
+    #[test]
+    fn test_read_format_version_0() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("1")).unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+
+        // data block of full keys and the offsets of records
+        let mut data = vec![];
+        let mut record_offsets = vec![];
+        let mut hashes = vec![];
+        for i in 0..100 {
+            let key = format!("key{:02}", i).into_bytes();
+            let value = format!("value{:02}", i).into_bytes();
+            hashes.push(murmur_hash(&key, SEED));
+            record_offsets.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            data.extend_from_slice(&key);
+            data.extend_from_slice(&value);
+        }
+        let index_offset = data.len() as u32;
+        data.extend_from_slice(&record_offsets);
+
+        // index block of fixed-width lengths
+        let index_block_offset = data.len() as u32;
+        let data_block_length = data.len() as u32;
+        data.extend_from_slice(&5u32.to_le_bytes());
+        data.extend_from_slice(b"key00");
+        for field in [0, data_block_length, index_offset, 5] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(b"key99");
+        let index_block_length = data.len() as u32 - index_block_offset;
+
+        let mut file = std::io::Cursor::new(data);
+        file.seek(SeekFrom::End(0)).unwrap();
+        let filter = default_filter_policy().create_filter(&hashes);
+        file.write_all(&filter).unwrap();
+        // footer without format version
+        let filter_policy = default_filter_policy().name().to_string();
+        file.write_all(filter_policy.as_bytes()).unwrap();
+        for field in [
+            index_block_offset,
+            index_block_length,
+            filter.len() as u32,
+            0,
+            100,
+            filter_policy.len() as u32,
+            UNVERSIONED_FOOTER_MAGIC_NUMBER,
+        ] {
+            file.write_all(&field.to_le_bytes()).unwrap();
+        }
+        std::fs::write(format!("{}/1/1", path), file.into_inner()).unwrap();
+
+        let read_handle =
+            Arc::new(TableReadHandle::open(&path, 1, 1, &Options::default()).unwrap());
+        assert_eq!(read_handle.format_version, 0);
+        for i in 0..100 {
+            assert_eq!(
+                read_handle
+                    .query(&format!("key{:02}", i).into_bytes(), SequenceNumber::MAX)
+                    .unwrap(),
+                Some(format!("value{:02}", i).into_bytes())
+            );
+        }
+        assert!(read_handle
+            .query(&b"key100".to_vec(), SequenceNumber::MAX)
+            .unwrap()
+            .is_none());
+        for (i, (key, _)) in TableReadHandle::iter(read_handle.clone())
+            .unwrap()
+            .enumerate()
+        {
+            assert_eq!(key, format!("key{:02}", i).into_bytes());
+        }
+
+        // properties are derived from the footer and the index block
+        let properties = read_handle.properties().unwrap();
+        assert_eq!(properties.num_entries, 100);
+        assert_eq!(properties.num_data_blocks, 1);
+        assert_eq!(properties.data_size, data_block_length as u64);
+        assert_eq!(properties.index_size, index_block_length as u64);
+        assert_eq!(properties.filter_size, filter.len() as u64);
+        assert_eq!(properties.format_version, 0);
+        assert_eq!(properties.min_key, b"key00");
+        assert_eq!(properties.max_key, b"key99");
+        assert_eq!(properties.raw_key_size, 0);
+    }
 }
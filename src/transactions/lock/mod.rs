@@ -1,10 +1,12 @@
-pub(crate) mod point_lock_manager;
+pub mod point_lock_manager;
 pub(crate) mod row_lock;
 
-use super::transaction::Transaction;
 use crate::db::key_types::RawUserKey;
+use crate::transactions::TransactionID;
+use crate::Result;
 use std::time::Duration;
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum RowLockType {
     Exclusive = 0,
     Shared = 1,
@@ -16,10 +18,27 @@ impl Default for RowLockType {
     }
 }
 
+/// Default time to wait for a row lock.
 pub(crate) const LOCK_TIMEOUT: Duration = Duration::from_millis(10);
 
-pub trait LockManager {
+pub trait LockManager: Send + Sync {
     fn new() -> Self;
-    fn try_lock(&self, txn: &impl Transaction, key: &RawUserKey, lock_type: RowLockType);
-    fn unlock(&self, txn: &impl Transaction, key: &RawUserKey);
+
+    /// Lock `key` for the transaction `txn_id`, waiting at most `timeout` for the
+    /// transactions holding conflicting locks.
+    ///
+    /// A transaction holding the lock can lock it again, and a shared lock held only by the
+    /// transaction is upgraded to an exclusive lock. Returns [crate::error::KVLiteError::Deadlock]
+    /// if waiting for the lock makes a deadlock, and
+    /// [crate::error::KVLiteError::LockTimeout] if the lock is not acquired in time.
+    fn try_lock(
+        &self,
+        txn_id: TransactionID,
+        key: &RawUserKey,
+        lock_type: RowLockType,
+        timeout: Duration,
+    ) -> Result<()>;
+
+    /// Release the lock of `key` held by the transaction `txn_id`.
+    fn unlock(&self, txn_id: TransactionID, key: &RawUserKey);
 }
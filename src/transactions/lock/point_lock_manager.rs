@@ -1,34 +1,207 @@
 use crate::db::key_types::RawUserKey;
+use crate::error::KVLiteError;
 use crate::transactions::lock::row_lock::RowLock;
 use crate::transactions::lock::{LockManager, RowLockType};
-use crate::transactions::transaction::Transaction;
+use crate::transactions::TransactionID;
+use crate::Result;
 use dashmap::DashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+/// Lock manager of row locks, which detects deadlocks by the wait-for graph of the
+/// waiting transactions.
 pub struct PointLockManager {
-    lock_map: DashMap<RawUserKey, RowLock>,
+    lock_map: DashMap<RawUserKey, Arc<RowLock>>,
+    /// Waiting transaction -> transactions holding the lock it waits for. Row locks are
+    /// never acquired while holding it.
+    wait_for_graph: Mutex<HashMap<TransactionID, Vec<TransactionID>>>,
+}
+
+impl PointLockManager {
+    /// Record that the transaction `txn_id` waits for `blockers`, and return whether it
+    /// makes a cycle in the wait-for graph, in which case the transaction should give up
+    /// waiting.
+    fn wait_for(&self, txn_id: TransactionID, blockers: Vec<TransactionID>) -> bool {
+        let mut graph = self.wait_for_graph.lock().unwrap();
+        let mut visited = HashSet::new();
+        let mut stack = blockers.clone();
+        while let Some(waiting) = stack.pop() {
+            if waiting == txn_id {
+                warn!("deadlock detected, transaction {} is aborted", txn_id);
+                graph.remove(&txn_id);
+                return true;
+            }
+            if visited.insert(waiting) {
+                if let Some(next) = graph.get(&waiting) {
+                    stack.extend(next);
+                }
+            }
+        }
+        graph.insert(txn_id, blockers);
+        false
+    }
+
+    /// Remove the lock of `key` from the map if no transaction holds or waits for it.
+    fn remove_unused(&self, key: &RawUserKey) {
+        self.lock_map.remove_if(key, |_, row_lock| {
+            // the map holds the only reference, so no one is going to acquire it
+            Arc::strong_count(row_lock) == 1
+                && matches!(row_lock.inner.try_lock(), Ok(inner) if inner.owners.is_empty())
+        });
+    }
 }
 
 impl LockManager for PointLockManager {
     fn new() -> Self {
         PointLockManager {
             lock_map: DashMap::with_capacity(16),
+            wait_for_graph: Mutex::default(),
         }
     }
 
-    fn try_lock(&self, txn: &impl Transaction, key: &RawUserKey, lock_type: RowLockType) {
-        match self.lock_map.get(key) {
-            Some(entry) => {}
-            None => {
-                self.lock_map
-                    .insert(key.clone(), RowLock::new(lock_type, txn.txn_id()));
+    fn try_lock(
+        &self,
+        txn_id: TransactionID,
+        key: &RawUserKey,
+        lock_type: RowLockType,
+        timeout: Duration,
+    ) -> Result<()> {
+        let row_lock = self.lock_map.entry(key.clone()).or_default().clone();
+        let deadline = Instant::now() + timeout;
+        let mut inner = row_lock.inner.lock().unwrap();
+        let result = loop {
+            if inner.can_lock(txn_id, lock_type) {
+                inner.lock(txn_id, lock_type);
+                break Ok(());
+            }
+            if self.wait_for(txn_id, inner.blockers(txn_id)) {
+                break Err(KVLiteError::Deadlock);
             }
+            let now = Instant::now();
+            if now >= deadline {
+                break Err(KVLiteError::LockTimeout);
+            }
+            inner = row_lock
+                .released
+                .wait_timeout(inner, deadline - now)
+                .unwrap()
+                .0;
+        };
+        drop(inner);
+        self.wait_for_graph.lock().unwrap().remove(&txn_id);
+        if result.is_err() {
+            drop(row_lock);
+            self.remove_unused(key);
         }
+        result
     }
 
-    fn unlock(&self, txn: &impl Transaction, key: &RawUserKey) {
-        todo!()
+    fn unlock(&self, txn_id: TransactionID, key: &RawUserKey) {
+        let row_lock = self.lock_map.get(key).map(|entry| entry.value().clone());
+        if let Some(row_lock) = row_lock {
+            row_lock.inner.lock().unwrap().unlock(txn_id);
+            row_lock.released.notify_all();
+            drop(row_lock);
+            self.remove_unused(key);
+        }
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use crate::error::KVLiteError;
+    use crate::transactions::lock::point_lock_manager::PointLockManager;
+    use crate::transactions::lock::{LockManager, RowLockType};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_lock() {
+        let manager = Arc::new(PointLockManager::new());
+        let key = b"key".to_vec();
+        let timeout = Duration::from_millis(10);
+
+        // shared locks are compatible with each other, but not with exclusive locks
+        manager
+            .try_lock(1, &key, RowLockType::Shared, timeout)
+            .unwrap();
+        manager
+            .try_lock(2, &key, RowLockType::Shared, timeout)
+            .unwrap();
+        assert_eq!(
+            manager.try_lock(3, &key, RowLockType::Exclusive, timeout),
+            Err(KVLiteError::LockTimeout)
+        );
+        assert_eq!(
+            manager.try_lock(1, &key, RowLockType::Exclusive, timeout),
+            Err(KVLiteError::LockTimeout)
+        );
+        manager.unlock(2, &key);
+        // upgrade the shared lock held only by transaction 1
+        manager
+            .try_lock(1, &key, RowLockType::Exclusive, timeout)
+            .unwrap();
+        manager
+            .try_lock(1, &key, RowLockType::Shared, timeout)
+            .unwrap();
+        assert_eq!(
+            manager.try_lock(2, &key, RowLockType::Shared, timeout),
+            Err(KVLiteError::LockTimeout)
+        );
+
+        // a waiter acquires the lock once it is released
+        let manager2 = manager.clone();
+        let key2 = key.clone();
+        let waiter = std::thread::spawn(move || {
+            manager2.try_lock(2, &key2, RowLockType::Exclusive, Duration::from_secs(10))
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        manager.unlock(1, &key);
+        waiter.join().unwrap().unwrap();
+        manager.unlock(2, &key);
+        assert!(manager.lock_map.is_empty());
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let manager = Arc::new(PointLockManager::new());
+        let (key1, key2, key3) = (b"key1".to_vec(), b"key2".to_vec(), b"key3".to_vec());
+        let timeout = Duration::from_secs(10);
+        for (txn_id, key) in [(1, &key1), (2, &key2), (3, &key3)] {
+            manager
+                .try_lock(txn_id, key, RowLockType::Exclusive, timeout)
+                .unwrap();
+        }
+
+        // transaction 1 waits for 2, and 2 waits for 3
+        let waiters: Vec<_> = vec![(1, key2.clone()), (2, key3.clone())]
+            .into_iter()
+            .map(|(txn_id, key)| {
+                let manager = manager.clone();
+                let waiter = std::thread::spawn(move || {
+                    manager.try_lock(txn_id, &key, RowLockType::Exclusive, timeout)
+                });
+                std::thread::sleep(Duration::from_millis(50));
+                waiter
+            })
+            .collect();
+
+        // transaction 3 waiting for 1 makes a cycle
+        assert_eq!(
+            manager.try_lock(3, &key1, RowLockType::Exclusive, timeout),
+            Err(KVLiteError::Deadlock)
+        );
+        // the victim releases its locks, and the others go on
+        manager.unlock(3, &key3);
+        let mut waiters = waiters.into_iter();
+        waiters.next_back().unwrap().join().unwrap().unwrap();
+        manager.unlock(2, &key2);
+        manager.unlock(2, &key3);
+        waiters.next().unwrap().join().unwrap().unwrap();
+        manager.unlock(1, &key1);
+        manager.unlock(1, &key2);
+        assert!(manager.lock_map.is_empty());
+        assert!(manager.wait_for_graph.lock().unwrap().is_empty());
+    }
+}
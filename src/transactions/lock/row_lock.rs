@@ -2,21 +2,53 @@ use crate::transactions::lock::RowLockType;
 use crate::transactions::TransactionID;
 use std::sync::{Condvar, Mutex};
 
+/// Lock of a row, the waiters are notified by `released` when the owners release it.
 #[derive(Default)]
 pub struct RowLock {
     pub inner: Mutex<RowLockInner>,
+    pub released: Condvar,
 }
 
 #[derive(Default)]
 pub struct RowLockInner {
     pub ty: RowLockType,
-    pub owner: TransactionID,
+    /// Transactions holding the lock, an exclusive lock has one owner.
+    pub owners: Vec<TransactionID>,
 }
 
-impl RowLock {
-    pub fn new(ty: RowLockType, txn_id: TransactionID) -> RowLock {
-        RowLock {
-            inner: Mutex::new(RowLockInner { ty, owner: txn_id }),
+impl RowLockInner {
+    /// Whether the transaction `txn_id` can acquire the lock of `ty`.
+    pub fn can_lock(&self, txn_id: TransactionID, ty: RowLockType) -> bool {
+        match self.owners.as_slice() {
+            [] => true,
+            [owner] if *owner == txn_id => true,
+            _ => ty == RowLockType::Shared && self.ty == RowLockType::Shared,
         }
     }
+
+    pub fn lock(&mut self, txn_id: TransactionID, ty: RowLockType) {
+        debug_assert!(self.can_lock(txn_id, ty));
+        if self.owners.is_empty() {
+            self.ty = ty;
+        } else if ty == RowLockType::Exclusive {
+            // upgrade the lock held only by the transaction
+            self.ty = ty;
+        }
+        if !self.owners.contains(&txn_id) {
+            self.owners.push(txn_id);
+        }
+    }
+
+    pub fn unlock(&mut self, txn_id: TransactionID) {
+        self.owners.retain(|owner| *owner != txn_id);
+    }
+
+    /// Transactions that the transaction `txn_id` waits for.
+    pub fn blockers(&self, txn_id: TransactionID) -> Vec<TransactionID> {
+        self.owners
+            .iter()
+            .copied()
+            .filter(|owner| *owner != txn_id)
+            .collect()
+    }
 }
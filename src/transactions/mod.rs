@@ -1,12 +1,34 @@
 use crate::db::key_types::DBKey;
 use crate::db::DB;
 use crate::memory::MemTable;
+use crate::transactions::lock::LOCK_TIMEOUT;
+use std::time::Duration;
 
-pub(super) mod lock;
+pub mod lock;
+pub mod optimistic_transaction_db;
 pub mod pessimistic_transaction_db;
+pub mod serializable_transaction_db;
 pub mod transaction;
 
 pub type TransactionID = u64;
 pub const TRANSACTION_NULL: TransactionID = 0;
 
 pub trait TransactionDB<SK: DBKey, UK: DBKey, M: MemTable<SK, UK>>: DB<SK, UK, M> {}
+
+/// Options of a transaction.
+#[derive(Copy, Clone, Debug)]
+pub struct TransactionOptions {
+    /// Time to wait for a row lock held by other transactions, after which the operation
+    /// fails with [crate::error::KVLiteError::LockTimeout].
+    ///
+    /// Default: 10ms
+    pub lock_timeout: Duration,
+}
+
+impl Default for TransactionOptions {
+    fn default() -> Self {
+        TransactionOptions {
+            lock_timeout: LOCK_TIMEOUT,
+        }
+    }
+}
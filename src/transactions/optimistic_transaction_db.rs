@@ -0,0 +1,417 @@
+use crate::collections::skip_list::skipmap::SrSwSkipMap;
+use crate::db::key_types::{DBKey, SeqNumKey, SequenceNumber};
+use crate::db::options::{Options, WriteOptions};
+use crate::db::write_batch_db::WriteBatchDB;
+use crate::db::{Value, DB};
+use crate::error::KVLiteError;
+use crate::memory::MemTable;
+use crate::transactions::TransactionDB;
+use crate::wal::TransactionWAL;
+use crate::Result;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Default maximum number of keys in the write history.
+const MAX_WRITE_HISTORY: usize = 1 << 16;
+
+/// Latest writes of the keys, by which the transactions are validated.
+struct WriteHistory<UK: DBKey> {
+    /// User key -> sequence number of its latest write.
+    last_writes: BTreeMap<UK, SequenceNumber>,
+    /// Writes with sequence numbers smaller than it may be missing in `last_writes`.
+    min_seq_num: SequenceNumber,
+    /// Sequence numbers of the alive transactions -> count of the transactions.
+    alive_seq_nums: BTreeMap<SequenceNumber, usize>,
+}
+
+impl<UK: DBKey> WriteHistory<UK> {
+    /// Discard the writes older than all the alive transactions. If there are still more
+    /// than `max_len` keys, the whole history is discarded.
+    fn trim(&mut self, max_len: usize, next_seq_num: SequenceNumber) {
+        let oldest = match self.alive_seq_nums.keys().next() {
+            Some(&seq_num) => seq_num,
+            None => next_seq_num,
+        };
+        self.last_writes.retain(|_, seq_num| *seq_num >= oldest);
+        self.min_seq_num = self.min_seq_num.max(oldest);
+        if self.last_writes.len() > max_len {
+            warn!(
+                "write history exceeds {} keys, transactions before {} need to try again",
+                max_len, next_seq_num
+            );
+            self.last_writes.clear();
+            self.min_seq_num = next_seq_num;
+        }
+    }
+}
+
+/// Database whose transactions take no locks, see [OptimisticTransaction].
+///
+/// Transactions are validated at commit time by the sequence numbers of the latest writes
+/// of the keys. At most `max_write_history` keys are kept in the history, the transactions
+/// older than the discarded history fail with [KVLiteError::TryAgain].
+pub struct OptimisticTransactionDB<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK> + 'static,
+{
+    inner: WriteBatchDB<UK, M, L>,
+    history: Mutex<WriteHistory<UK>>,
+    max_write_history: usize,
+}
+
+impl<UK, M, L> DB<SeqNumKey<UK>, UK, M> for OptimisticTransactionDB<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK>,
+{
+    fn open(db_path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_options(db_path, Options::default())
+    }
+
+    #[inline]
+    fn get(&self, key: &SeqNumKey<UK>) -> Result<Option<Value>> {
+        self.inner.get(key)
+    }
+
+    /// Write `key` outside transactions with a new sequence number, the sequence number of
+    /// `key` is ignored.
+    fn set(&self, write_options: &WriteOptions, key: SeqNumKey<UK>, value: Value) -> Result<()> {
+        self.write_outside_transaction(write_options, UK::from(key), value)
+    }
+
+    /// Remove `key` outside transactions with a new sequence number, the sequence number of
+    /// `key` is ignored.
+    fn remove(&self, write_options: &WriteOptions, key: SeqNumKey<UK>) -> Result<()> {
+        self.write_outside_transaction(write_options, UK::from(key), Value::default())
+    }
+
+    #[inline]
+    fn range_get(
+        &self,
+        key_start: &SeqNumKey<UK>,
+        key_end: &SeqNumKey<UK>,
+    ) -> Result<SrSwSkipMap<UK, Value>> {
+        self.inner.range_get(key_start, key_end)
+    }
+
+    fn db_path(&self) -> &String {
+        self.inner.db_path()
+    }
+}
+
+impl<UK, M, L> TransactionDB<SeqNumKey<UK>, UK, M> for OptimisticTransactionDB<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK>,
+{
+}
+
+impl<UK, M, L> OptimisticTransactionDB<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK>,
+{
+    pub fn open_with_options(db_path: impl AsRef<Path>, options: Options) -> Result<Self> {
+        let inner = WriteBatchDB::open_with_options(db_path, options)?;
+        let history = WriteHistory {
+            last_writes: BTreeMap::new(),
+            min_seq_num: inner.next_seq_num(),
+            alive_seq_nums: BTreeMap::new(),
+        };
+        Ok(OptimisticTransactionDB {
+            inner,
+            history: Mutex::new(history),
+            max_write_history: MAX_WRITE_HISTORY,
+        })
+    }
+
+    /// Begin a transaction, which reads the snapshot at the time it begins, and whose
+    /// writes are written with `write_options` when it is committed.
+    pub fn begin(db: &Arc<Self>, write_options: WriteOptions) -> OptimisticTransaction<UK, M, L> {
+        let mut history = db.history.lock().unwrap();
+        let seq_num = db.inner.acquire_seq_num();
+        *history.alive_seq_nums.entry(seq_num).or_default() += 1;
+        OptimisticTransaction {
+            txn_db: db.clone(),
+            seq_num,
+            write_options,
+            writes: SrSwSkipMap::default(),
+            mem_usage: 0,
+            tracked_keys: BTreeMap::new(),
+        }
+    }
+
+    fn write_outside_transaction(
+        &self,
+        write_options: &WriteOptions,
+        key: UK,
+        value: Value,
+    ) -> Result<()> {
+        let mut history = self.history.lock().unwrap();
+        let seq_num = self
+            .inner
+            .write_by_user_key(write_options, key.clone(), value)?;
+        self.record_write(&mut history, key, seq_num);
+        Ok(())
+    }
+
+    fn record_write(&self, history: &mut WriteHistory<UK>, key: UK, seq_num: SequenceNumber) {
+        let last_seq_num = history.last_writes.entry(key).or_default();
+        *last_seq_num = seq_num.max(*last_seq_num);
+        if history.last_writes.len() > self.max_write_history {
+            history.trim(self.max_write_history, self.inner.next_seq_num());
+        }
+    }
+
+    /// Validate the keys tracked by a transaction, and write its writes if none of the keys
+    /// is written after the transaction observed it.
+    fn commit(
+        &self,
+        write_options: &WriteOptions,
+        tracked_keys: &BTreeMap<UK, SequenceNumber>,
+        writes: SrSwSkipMap<SeqNumKey<UK>, Value>,
+        mem_usage: u64,
+    ) -> Result<()> {
+        let mut history = self.history.lock().unwrap();
+        for (key, &seq_num) in tracked_keys {
+            match history.last_writes.get(key) {
+                Some(&last_seq_num) if last_seq_num >= seq_num => return Err(KVLiteError::Busy),
+                None if seq_num < history.min_seq_num => return Err(KVLiteError::TryAgain),
+                _ => {}
+            }
+        }
+        if writes.is_empty() {
+            return Ok(());
+        }
+
+        let keys: Vec<UK> = writes
+            .iter()
+            .map(|(key, _)| key.user_key().clone())
+            .collect();
+        let seq_num = self.inner.write_batch(write_options, writes, mem_usage)?;
+        for key in keys {
+            self.record_write(&mut history, key, seq_num);
+        }
+        Ok(())
+    }
+
+    fn release(&self, seq_num: SequenceNumber) {
+        let mut history = self.history.lock().unwrap();
+        if let Some(count) = history.alive_seq_nums.get_mut(&seq_num) {
+            *count -= 1;
+            if *count == 0 {
+                history.alive_seq_nums.remove(&seq_num);
+            }
+        }
+        if history.alive_seq_nums.is_empty() {
+            // no transaction needs the history
+            history.last_writes.clear();
+            history.min_seq_num = self.inner.next_seq_num();
+        }
+        self.inner.release_seq_num(seq_num);
+    }
+}
+
+/// Transaction that takes no locks. It reads the snapshot at the time it begins, tracks
+/// the keys it reads or writes, and buffers the writes until it is committed.
+///
+/// Committing fails with [KVLiteError::Busy] if any tracked key is written by others after
+/// the transaction began, in which case the writes are discarded.
+pub struct OptimisticTransaction<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK> + 'static,
+{
+    txn_db: Arc<OptimisticTransactionDB<UK, M, L>>,
+    /// Next sequence number when the transaction began.
+    seq_num: SequenceNumber,
+    write_options: WriteOptions,
+    /// Writes of the transaction, empty values are deletions.
+    writes: SrSwSkipMap<SeqNumKey<UK>, Value>,
+    mem_usage: i64,
+    /// Keys read or written -> sequence number of the snapshot where they are observed.
+    tracked_keys: BTreeMap<UK, SequenceNumber>,
+}
+
+impl<UK, M, L> OptimisticTransaction<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK>,
+{
+    /// Get the value of `key` written by this transaction or in the snapshot.
+    pub fn get(&mut self, key: UK) -> Result<Option<Value>> {
+        self.track(&key);
+        let seq_num_key = SeqNumKey::new(key, self.seq_num);
+        match self.writes.get_clone(&seq_num_key) {
+            Some(value) if value.is_empty() => Ok(None),
+            Some(value) => Ok(Some(value)),
+            None => self.txn_db.get(&SeqNumKey::new(
+                seq_num_key.user_key().clone(),
+                self.seq_num - 1,
+            )),
+        }
+    }
+
+    pub fn put(&mut self, key: UK, value: Value) -> Result<()> {
+        self.track(&key);
+        let key_len = key.mem_size() as i64;
+        let value_len = value.len() as i64;
+        self.mem_usage += match self.writes.insert(SeqNumKey::new(key, self.seq_num), value) {
+            Some(v) => value_len - (v.len() as i64),
+            None => key_len + value_len,
+        };
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: UK) -> Result<()> {
+        self.track(&key);
+        let key_len = key.mem_size() as i64;
+        self.mem_usage += match self
+            .writes
+            .insert(SeqNumKey::new(key, self.seq_num), Value::default())
+        {
+            Some(v) => -(v.len() as i64),
+            None => key_len,
+        };
+        Ok(())
+    }
+
+    /// Write the writes of the transaction if none of the keys it read or wrote is written
+    /// after it began, otherwise return [KVLiteError::Busy]. Returns [KVLiteError::TryAgain]
+    /// if the write history is too short to validate the transaction.
+    pub fn commit(mut self) -> Result<()> {
+        let writes = std::mem::take(&mut self.writes);
+        debug_assert!(self.mem_usage >= 0);
+        self.txn_db.commit(
+            &self.write_options,
+            &self.tracked_keys,
+            writes,
+            self.mem_usage as u64,
+        )
+    }
+
+    /// Discard the writes of the transaction.
+    pub fn rollback(self) {}
+
+    #[inline]
+    fn track(&mut self, key: &UK) {
+        if !self.tracked_keys.contains_key(key) {
+            self.tracked_keys.insert(key.clone(), self.seq_num);
+        }
+    }
+}
+
+impl<UK, M, L> Drop for OptimisticTransaction<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK> + 'static,
+{
+    fn drop(&mut self) {
+        self.txn_db.release(self.seq_num);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::key_types::{RawUserKey, SeqNumKey};
+    use crate::db::options::WriteOptions;
+    use crate::db::DB;
+    use crate::error::KVLiteError;
+    use crate::memory::MutexSkipMapMemTable;
+    use crate::transactions::optimistic_transaction_db::OptimisticTransactionDB;
+    use crate::wal::lsn_wal::LSNWriteAheadLog;
+    use std::sync::Arc;
+
+    type TestDB = OptimisticTransactionDB<
+        RawUserKey,
+        MutexSkipMapMemTable<SeqNumKey<RawUserKey>>,
+        LSNWriteAheadLog,
+    >;
+
+    #[test]
+    fn test_transaction() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("optimistic")
+            .tempdir()
+            .unwrap();
+        let db = Arc::new(TestDB::open(temp_dir.path()).unwrap());
+        let wo = || WriteOptions { sync: false };
+        let (key1, key2, key3) = (b"key1".to_vec(), b"key2".to_vec(), b"key3".to_vec());
+
+        let mut txn = TestDB::begin(&db, wo());
+        txn.put(key1.clone(), b"value1".to_vec()).unwrap();
+        txn.put(key2.clone(), b"value2".to_vec()).unwrap();
+        assert_eq!(txn.get(key1.clone()).unwrap(), Some(b"value1".to_vec()));
+        txn.commit().unwrap();
+
+        let mut txn1 = TestDB::begin(&db, wo());
+        let mut txn2 = TestDB::begin(&db, wo());
+        let mut txn3 = TestDB::begin(&db, wo());
+        assert_eq!(txn1.get(key1.clone()).unwrap(), Some(b"value1".to_vec()));
+        txn1.put(key3.clone(), b"value3_1".to_vec()).unwrap();
+        txn2.delete(key1.clone()).unwrap();
+        assert_eq!(txn2.get(key1.clone()).unwrap(), None);
+        txn3.put(key2.clone(), b"value2_3".to_vec()).unwrap();
+        txn2.commit().unwrap();
+
+        // txn1 read key1 deleted by txn2, and txn3 wrote no key written by others
+        assert_eq!(txn1.get(key1.clone()).unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(txn1.commit(), Err(KVLiteError::Busy));
+        txn3.commit().unwrap();
+
+        let mut txn = TestDB::begin(&db, wo());
+        assert_eq!(txn.get(key1).unwrap(), None);
+        assert_eq!(txn.get(key2).unwrap(), Some(b"value2_3".to_vec()));
+        assert_eq!(txn.get(key3.clone()).unwrap(), None);
+        txn.put(key3.clone(), b"value3".to_vec()).unwrap();
+        txn.rollback();
+        let mut txn = TestDB::begin(&db, wo());
+        assert_eq!(txn.get(key3.clone()).unwrap(), None);
+
+        // writes outside transactions take new sequence numbers, whatever the keys carry
+        db.set(&wo(), SeqNumKey::new(key3.clone(), 0), b"value3".to_vec())
+            .unwrap();
+        assert_eq!(
+            db.get(&SeqNumKey::new(key3.clone(), u64::MAX)).unwrap(),
+            Some(b"value3".to_vec())
+        );
+        txn.put(key3, b"value3_2".to_vec()).unwrap();
+        assert_eq!(txn.commit(), Err(KVLiteError::Busy));
+    }
+
+    #[test]
+    fn test_try_again() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("optimistic")
+            .tempdir()
+            .unwrap();
+        let mut db = TestDB::open(temp_dir.path()).unwrap();
+        db.max_write_history = 2;
+        let db = Arc::new(db);
+        let wo = || WriteOptions { sync: false };
+
+        let mut txn1 = TestDB::begin(&db, wo());
+        txn1.put(b"key0".to_vec(), b"value0".to_vec()).unwrap();
+        for i in 1..=3 {
+            let mut txn = TestDB::begin(&db, wo());
+            txn.put(format!("key{}", i).into_bytes(), b"value".to_vec())
+                .unwrap();
+            txn.commit().unwrap();
+        }
+        // writes after txn1 began are discarded from the history
+        assert_eq!(txn1.commit(), Err(KVLiteError::TryAgain));
+
+        let mut txn = TestDB::begin(&db, wo());
+        txn.put(b"key0".to_vec(), b"value0".to_vec()).unwrap();
+        txn.commit().unwrap();
+    }
+}
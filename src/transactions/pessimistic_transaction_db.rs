@@ -1,57 +1,251 @@
 use crate::collections::skip_list::skipmap::SrSwSkipMap;
+use crate::db::dbimpl::DBImpl;
 use crate::db::key_types::{DBKey, RawUserKey};
-use crate::db::options::WriteOptions;
+use crate::db::options::{Options, WriteOptions};
 use crate::db::{Value, DB};
 use crate::memory::MemTable;
-use crate::transactions::lock::{LockManager, RowLockType};
-use crate::transactions::transaction::Transaction;
-use crate::transactions::TransactionDB;
+use crate::transactions::lock::point_lock_manager::PointLockManager;
+use crate::transactions::lock::{LockManager, RowLockType, LOCK_TIMEOUT};
+use crate::transactions::transaction::PessimisticTransaction;
+use crate::transactions::{TransactionDB, TransactionID, TransactionOptions, TRANSACTION_NULL};
+use crate::wal::WAL;
+use crate::Result;
+use std::collections::BTreeMap;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
-pub struct PessimisticTransactionDB<LM: LockManager> {
-    lock_manager: LM,
+/// Database whose transactions lock the keys they access, see [PessimisticTransaction].
+///
+/// Writes outside transactions lock the keys as well, so they wait for the transactions
+/// writing the same keys.
+pub struct PessimisticTransactionDB<M, L, LM = PointLockManager>
+where
+    M: MemTable<RawUserKey, RawUserKey> + 'static,
+    L: WAL<RawUserKey, RawUserKey> + 'static,
+    LM: LockManager,
+{
+    inner: DBImpl<RawUserKey, RawUserKey, M, L>,
+    pub(crate) lock_manager: LM,
+    next_txn_id: AtomicU64,
 }
 
-impl<SK: DBKey, UK: DBKey, M: MemTable<SK, UK>, LM: LockManager> DB<SK, UK, M>
-    for PessimisticTransactionDB<LM>
+impl<M, L, LM> DB<RawUserKey, RawUserKey, M> for PessimisticTransactionDB<M, L, LM>
+where
+    M: MemTable<RawUserKey, RawUserKey> + 'static,
+    L: WAL<RawUserKey, RawUserKey> + 'static,
+    LM: LockManager,
 {
-    fn open(db_path: impl AsRef<Path>) -> crate::Result<Self> {
-        let lock_manager = LM::new();
-        let pessimistic_db = PessimisticTransactionDB { lock_manager };
-        todo!()
+    fn open(db_path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_options(db_path, Options::default())
     }
 
-    fn get(&self, key: &SK) -> crate::Result<Option<Value>> {
-        todo!()
+    #[inline]
+    fn get(&self, key: &RawUserKey) -> Result<Option<Value>> {
+        self.inner.get(key)
     }
 
-    fn set(&self, write_options: &WriteOptions, key: SK, value: Value) -> crate::Result<()> {
-        todo!()
+    fn set(&self, write_options: &WriteOptions, key: RawUserKey, value: Value) -> Result<()> {
+        self.write_locked(&key, || self.inner.set(write_options, key.clone(), value))
     }
 
-    fn remove(&self, write_options: &WriteOptions, key: SK) -> crate::Result<()> {
-        todo!()
+    fn remove(&self, write_options: &WriteOptions, key: RawUserKey) -> Result<()> {
+        self.write_locked(&key, || self.inner.remove(write_options, key.clone()))
     }
 
-    fn range_get(&self, key_start: &SK, key_end: &SK) -> crate::Result<SrSwSkipMap<UK, Value>>
-    where
-        UK: From<SK>,
-    {
-        todo!()
+    #[inline]
+    fn range_get(
+        &self,
+        key_start: &RawUserKey,
+        key_end: &RawUserKey,
+    ) -> Result<SrSwSkipMap<RawUserKey, Value>> {
+        self.inner.range_get(key_start, key_end)
     }
 
     fn db_path(&self) -> &String {
-        todo!()
+        self.inner.db_path()
     }
 }
 
-impl<SK: DBKey, UK: DBKey, M: MemTable<SK, UK>, LM: LockManager> TransactionDB<SK, UK, M>
-    for PessimisticTransactionDB<LM>
+impl<M, L, LM> TransactionDB<RawUserKey, RawUserKey, M> for PessimisticTransactionDB<M, L, LM>
+where
+    M: MemTable<RawUserKey, RawUserKey> + 'static,
+    L: WAL<RawUserKey, RawUserKey> + 'static,
+    LM: LockManager,
+{
+}
+
+impl<M, L, LM> PessimisticTransactionDB<M, L, LM>
+where
+    M: MemTable<RawUserKey, RawUserKey> + 'static,
+    L: WAL<RawUserKey, RawUserKey> + 'static,
+    LM: LockManager,
 {
+    pub fn open_with_options(db_path: impl AsRef<Path>, options: Options) -> Result<Self> {
+        Ok(PessimisticTransactionDB {
+            inner: DBImpl::open_with_options(db_path, options)?,
+            lock_manager: LM::new(),
+            next_txn_id: AtomicU64::new(TRANSACTION_NULL + 1),
+        })
+    }
+
+    /// Begin a transaction, whose writes are written with `write_options` when it is
+    /// committed.
+    pub fn begin(
+        db: &Arc<Self>,
+        write_options: WriteOptions,
+        options: TransactionOptions,
+    ) -> PessimisticTransaction<M, L, LM> {
+        PessimisticTransaction::new(db.next_txn_id(), db.clone(), write_options, options)
+    }
+
+    #[inline]
+    fn next_txn_id(&self) -> TransactionID {
+        self.next_txn_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Write `key` holding its exclusive lock.
+    fn write_locked(&self, key: &RawUserKey, write: impl FnOnce() -> Result<()>) -> Result<()> {
+        let txn_id = self.next_txn_id();
+        self.lock_manager
+            .try_lock(txn_id, key, RowLockType::Exclusive, LOCK_TIMEOUT)?;
+        let result = write();
+        self.lock_manager.unlock(txn_id, key);
+        result
+    }
+
+    /// Write the writes of a committed transaction, whose keys are locked. The writes are
+    /// logged as one batch and applied to the memory table at once.
+    pub(crate) fn write(
+        &self,
+        write_options: &WriteOptions,
+        writes: BTreeMap<RawUserKey, Value>,
+    ) -> Result<()> {
+        if writes.is_empty() {
+            return Ok(());
+        }
+        let batch = SrSwSkipMap::new();
+        let mut mem_usage = 0;
+        for (key, value) in writes {
+            mem_usage += (key.mem_size() + value.len()) as u64;
+            batch.insert(key, value);
+        }
+        self.inner.write_batch(write_options, batch, mem_usage)
+    }
 }
 
-impl<LM: LockManager> PessimisticTransactionDB<LM> {
-    pub fn try_lock(&self, txn: &impl Transaction, key: &RawUserKey, lock_type: RowLockType) {
-        self.lock_manager.try_lock(txn, key, lock_type);
+#[cfg(test)]
+mod tests {
+    use crate::db::key_types::RawUserKey;
+    use crate::db::options::WriteOptions;
+    use crate::db::DB;
+    use crate::error::KVLiteError;
+    use crate::memory::MrMwSkipMapMemTable;
+    use crate::transactions::lock::RowLockType;
+    use crate::transactions::pessimistic_transaction_db::PessimisticTransactionDB;
+    use crate::transactions::TransactionOptions;
+    use crate::wal::simple_wal::SimpleWriteAheadLog;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    type TestDB = PessimisticTransactionDB<MrMwSkipMapMemTable<RawUserKey>, SimpleWriteAheadLog>;
+
+    #[test]
+    fn test_transaction() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("pessimistic")
+            .tempdir()
+            .unwrap();
+        let db = Arc::new(TestDB::open(temp_dir.path()).unwrap());
+        let wo = || WriteOptions { sync: false };
+        let (key1, key2) = (b"key1".to_vec(), b"key2".to_vec());
+        db.set(&wo(), key2.clone(), b"value2".to_vec()).unwrap();
+
+        let mut txn1 = TestDB::begin(&db, wo(), TransactionOptions::default());
+        let mut txn2 = TestDB::begin(&db, wo(), TransactionOptions::default());
+        txn1.put(key1.clone(), b"value1".to_vec()).unwrap();
+        assert_eq!(txn1.get(&key1).unwrap(), Some(b"value1".to_vec()));
+        // uncommitted writes are invisible to others, and the written keys are locked
+        assert_eq!(txn2.get(&key1).unwrap(), None);
+        assert_eq!(
+            txn2.put(key1.clone(), b"value1_2".to_vec()),
+            Err(KVLiteError::LockTimeout)
+        );
+        assert_eq!(
+            db.set(&wo(), key1.clone(), b"value1_3".to_vec()),
+            Err(KVLiteError::LockTimeout)
+        );
+
+        // shared locks of reads for update block writes
+        assert_eq!(
+            txn2.get_for_update(&key2, RowLockType::Shared).unwrap(),
+            Some(b"value2".to_vec())
+        );
+        assert_eq!(
+            txn1.get_for_update(&key2, RowLockType::Shared).unwrap(),
+            Some(b"value2".to_vec())
+        );
+        assert_eq!(txn1.delete(key2.clone()), Err(KVLiteError::LockTimeout));
+        txn2.rollback();
+        txn1.delete(key2.clone()).unwrap();
+        assert_eq!(db.get(&key2).unwrap(), Some(b"value2".to_vec()));
+        txn1.commit().unwrap();
+        assert_eq!(db.get(&key1).unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(db.get(&key2).unwrap(), None);
+
+        // rolled back writes are discarded
+        let mut txn3 = TestDB::begin(&db, wo(), TransactionOptions::default());
+        assert_eq!(
+            txn3.get_for_update(&key1, RowLockType::Exclusive).unwrap(),
+            Some(b"value1".to_vec())
+        );
+        txn3.delete(key1.clone()).unwrap();
+        assert_eq!(txn3.get(&key1).unwrap(), None);
+        drop(txn3);
+        assert_eq!(db.get(&key1).unwrap(), Some(b"value1".to_vec()));
+        db.set(&wo(), key1.clone(), b"value1_4".to_vec()).unwrap();
+        assert_eq!(db.get(&key1).unwrap(), Some(b"value1_4".to_vec()));
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("pessimistic")
+            .tempdir()
+            .unwrap();
+        let db = Arc::new(TestDB::open(temp_dir.path()).unwrap());
+        let wo = || WriteOptions { sync: false };
+        let options = TransactionOptions {
+            lock_timeout: Duration::from_secs(10),
+        };
+        let (key1, key2) = (b"key1".to_vec(), b"key2".to_vec());
+
+        let mut txn1 = TestDB::begin(&db, wo(), options);
+        let mut txn2 = TestDB::begin(&db, wo(), options);
+        txn1.put(key1.clone(), b"value1_1".to_vec()).unwrap();
+        txn2.put(key2.clone(), b"value2_2".to_vec()).unwrap();
+
+        let key2_clone = key2.clone();
+        let handle = std::thread::spawn(move || {
+            txn1.put(key2_clone, b"value2_1".to_vec()).unwrap();
+            txn1.commit().unwrap();
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        // txn2 is the victim of the deadlock, whose writes and locks are discarded
+        assert_eq!(
+            txn2.put(key1.clone(), b"value1_2".to_vec()),
+            Err(KVLiteError::Deadlock)
+        );
+        handle.join().unwrap();
+        // the aborted transaction cannot be used anymore
+        assert_eq!(txn2.get(&key2), Err(KVLiteError::Deadlock));
+        assert_eq!(
+            txn2.put(key2.clone(), b"value2_2".to_vec()),
+            Err(KVLiteError::Deadlock)
+        );
+        assert_eq!(txn2.delete(key2.clone()), Err(KVLiteError::Deadlock));
+        assert_eq!(txn2.commit(), Err(KVLiteError::Deadlock));
+        assert_eq!(db.get(&key1).unwrap(), Some(b"value1_1".to_vec()));
+        assert_eq!(db.get(&key2).unwrap(), Some(b"value2_1".to_vec()));
     }
 }
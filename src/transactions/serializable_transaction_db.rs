@@ -0,0 +1,609 @@
+use crate::collections::skip_list::skipmap::SrSwSkipMap;
+use crate::db::comparator::Comparator;
+use crate::db::key_types::{DBKey, SeqNumKey, SequenceNumber};
+use crate::db::options::{Options, WriteOptions};
+use crate::db::write_batch_db::WriteBatchDB;
+use crate::db::{Value, DB};
+use crate::error::KVLiteError;
+use crate::memory::MemTable;
+use crate::transactions::{TransactionDB, TransactionID, TRANSACTION_NULL};
+use crate::wal::TransactionWAL;
+use crate::Result;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Keys and ranges read by a transaction.
+struct ReadSet<UK: DBKey> {
+    keys: BTreeSet<UK>,
+    /// Inclusive ranges of the keys.
+    ranges: Vec<(UK, UK)>,
+}
+
+impl<UK: DBKey> ReadSet<UK> {
+    /// Whether `key` is read, the keys are compared by `comparator` of the database.
+    fn contains(&self, key: &UK, comparator: &dyn Comparator) -> bool {
+        let compare = |a: &UK, b: &UK| comparator.compare(a.raw_user_key(), b.raw_user_key());
+        self.keys
+            .iter()
+            .any(|read_key| compare(read_key, key) == Ordering::Equal)
+            || self.ranges.iter().any(|(key_start, key_end)| {
+                compare(key_start, key) != Ordering::Greater
+                    && compare(key, key_end) != Ordering::Greater
+            })
+    }
+
+    fn contains_any(&self, keys: &BTreeSet<UK>, comparator: &dyn Comparator) -> bool {
+        keys.iter().any(|key| self.contains(key, comparator))
+    }
+}
+
+/// Reads, writes and rw-antidependencies of a transaction, kept after it is committed
+/// until no concurrent transaction is alive.
+struct TransactionState<UK: DBKey> {
+    /// Next sequence number when the transaction began.
+    seq_num: SequenceNumber,
+    /// Sequence number of the writes, set when the transaction is committed.
+    commit_seq_num: Option<SequenceNumber>,
+    reads: ReadSet<UK>,
+    /// Keys written by the committed transaction.
+    writes: BTreeSet<UK>,
+    /// A concurrent transaction read the old versions of the keys it writes.
+    in_conflict: bool,
+    /// It read the old versions of the keys written by a concurrent transaction.
+    out_conflict: bool,
+}
+
+impl<UK: DBKey> TransactionState<UK> {
+    fn new(seq_num: SequenceNumber) -> Self {
+        TransactionState {
+            seq_num,
+            commit_seq_num: None,
+            reads: ReadSet {
+                keys: BTreeSet::new(),
+                ranges: vec![],
+            },
+            writes: BTreeSet::new(),
+            in_conflict: false,
+            out_conflict: false,
+        }
+    }
+}
+
+/// Transactions that are alive, or committed while some concurrent transactions are
+/// alive.
+struct SerializableState<UK: DBKey> {
+    transactions: BTreeMap<TransactionID, TransactionState<UK>>,
+    next_txn_id: TransactionID,
+}
+
+impl<UK: DBKey> SerializableState<UK> {
+    fn next_txn_id(&mut self) -> TransactionID {
+        let txn_id = self.next_txn_id;
+        self.next_txn_id += 1;
+        txn_id
+    }
+
+    /// Discard the committed transactions which are not concurrent with any alive
+    /// transaction.
+    fn remove_committed(&mut self) {
+        let oldest = self
+            .transactions
+            .values()
+            .filter(|txn| txn.commit_seq_num.is_none())
+            .map(|txn| txn.seq_num)
+            .min();
+        self.transactions
+            .retain(|_, txn| match (txn.commit_seq_num, oldest) {
+                (None, _) => true,
+                (Some(commit_seq_num), Some(oldest)) => commit_seq_num >= oldest,
+                (Some(_), None) => false,
+            });
+    }
+}
+
+/// Database whose transactions are serializable, see [SerializableTransaction].
+///
+/// It implements serializable snapshot isolation (SSI). Transactions read snapshots and
+/// take no locks, and the keys and ranges they read are tracked to find the
+/// rw-antidependencies among concurrent transactions, where a transaction reads the old
+/// version of a key written by another. Every cycle of the dependencies contains a
+/// transaction with both incoming and outgoing rw-antidependencies, so committing a
+/// transaction fails with [KVLiteError::Busy] if it makes such a transaction, and a
+/// non-serializable history like write skew is never committed. Aborts may be false
+/// positives, which succeed if retried.
+pub struct SerializableTransactionDB<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK> + 'static,
+{
+    inner: WriteBatchDB<UK, M, L>,
+    state: Mutex<SerializableState<UK>>,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl<UK, M, L> DB<SeqNumKey<UK>, UK, M> for SerializableTransactionDB<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK>,
+{
+    fn open(db_path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_options(db_path, Options::default())
+    }
+
+    #[inline]
+    fn get(&self, key: &SeqNumKey<UK>) -> Result<Option<Value>> {
+        self.inner.get(key)
+    }
+
+    /// Write `key` outside transactions with a new sequence number, the sequence number of
+    /// `key` is ignored.
+    fn set(&self, write_options: &WriteOptions, key: SeqNumKey<UK>, value: Value) -> Result<()> {
+        self.write_outside_transaction(write_options, UK::from(key), value)
+    }
+
+    /// Remove `key` outside transactions with a new sequence number, the sequence number of
+    /// `key` is ignored.
+    fn remove(&self, write_options: &WriteOptions, key: SeqNumKey<UK>) -> Result<()> {
+        self.write_outside_transaction(write_options, UK::from(key), Value::default())
+    }
+
+    #[inline]
+    fn range_get(
+        &self,
+        key_start: &SeqNumKey<UK>,
+        key_end: &SeqNumKey<UK>,
+    ) -> Result<SrSwSkipMap<UK, Value>> {
+        self.inner.range_get(key_start, key_end)
+    }
+
+    fn db_path(&self) -> &String {
+        self.inner.db_path()
+    }
+}
+
+impl<UK, M, L> TransactionDB<SeqNumKey<UK>, UK, M> for SerializableTransactionDB<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK>,
+{
+}
+
+impl<UK, M, L> SerializableTransactionDB<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK>,
+{
+    pub fn open_with_options(db_path: impl AsRef<Path>, options: Options) -> Result<Self> {
+        let comparator = options.comparator.clone();
+        Ok(SerializableTransactionDB {
+            inner: WriteBatchDB::open_with_options(db_path, options)?,
+            comparator,
+            state: Mutex::new(SerializableState {
+                transactions: BTreeMap::new(),
+                next_txn_id: TRANSACTION_NULL + 1,
+            }),
+        })
+    }
+
+    /// Begin a transaction, which reads the snapshot at the time it begins, and whose
+    /// writes are written with `write_options` when it is committed.
+    pub fn begin(db: &Arc<Self>, write_options: WriteOptions) -> SerializableTransaction<UK, M, L> {
+        let mut state = db.state.lock().unwrap();
+        let txn_id = state.next_txn_id();
+        let seq_num = db.inner.acquire_seq_num();
+        state
+            .transactions
+            .insert(txn_id, TransactionState::new(seq_num));
+        SerializableTransaction {
+            txn_id,
+            txn_db: db.clone(),
+            seq_num,
+            write_options,
+            writes: SrSwSkipMap::default(),
+            mem_usage: 0,
+        }
+    }
+
+    fn write_outside_transaction(
+        &self,
+        write_options: &WriteOptions,
+        key: UK,
+        value: Value,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let seq_num = self
+            .inner
+            .write_by_user_key(write_options, key.clone(), value)?;
+        Self::record_write(&mut state, key, seq_num);
+        Ok(())
+    }
+
+    /// Record a write outside transactions with sequence number `seq_num` as a committed
+    /// transaction, if any transaction is alive.
+    fn record_write(state: &mut SerializableState<UK>, key: UK, seq_num: SequenceNumber) {
+        if state
+            .transactions
+            .values()
+            .all(|txn| txn.commit_seq_num.is_some())
+        {
+            return;
+        }
+        let txn_id = state.next_txn_id();
+        let mut txn = TransactionState::new(seq_num);
+        txn.commit_seq_num = Some(seq_num);
+        txn.writes.insert(key);
+        state.transactions.insert(txn_id, txn);
+    }
+
+    fn record_read(&self, txn_id: TransactionID, record: impl FnOnce(&mut ReadSet<UK>)) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(txn) = state.transactions.get_mut(&txn_id) {
+            record(&mut txn.reads);
+        }
+    }
+
+    /// Find the rw-antidependencies between the transaction `txn_id` and the concurrent
+    /// transactions, and write `writes` if no transaction gets both incoming and outgoing
+    /// rw-antidependencies. Concurrent writes of the same key fail as well, where the
+    /// first committed one wins.
+    fn commit(
+        &self,
+        txn_id: TransactionID,
+        write_options: &WriteOptions,
+        writes: SrSwSkipMap<SeqNumKey<UK>, Value>,
+        mem_usage: u64,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let keys: BTreeSet<UK> = writes
+            .iter()
+            .map(|(key, _)| key.user_key().clone())
+            .collect();
+        let txn = &state.transactions[&txn_id];
+        let (mut in_conflict, mut out_conflict) = (txn.in_conflict, txn.out_conflict);
+        // transactions reading the old versions of `keys`, and those writing the keys
+        // read by this transaction
+        let (mut readers, mut writers) = (vec![], vec![]);
+        for (&other_id, other) in state.transactions.iter() {
+            if other_id == txn_id {
+                continue;
+            }
+            match other.commit_seq_num {
+                Some(commit_seq_num) if commit_seq_num >= txn.seq_num => {
+                    if !other.writes.is_disjoint(&keys) {
+                        return Err(KVLiteError::Busy);
+                    }
+                    if txn.reads.contains_any(&other.writes, &*self.comparator) {
+                        // `other` is committed with an outgoing rw-antidependency
+                        if other.out_conflict {
+                            return Err(KVLiteError::Busy);
+                        }
+                        out_conflict = true;
+                        writers.push(other_id);
+                    }
+                    if other.reads.contains_any(&keys, &*self.comparator) {
+                        // `other` is committed with an incoming rw-antidependency
+                        if other.in_conflict {
+                            return Err(KVLiteError::Busy);
+                        }
+                        in_conflict = true;
+                        readers.push(other_id);
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    // the keys read by this transaction and written by `other` are
+                    // checked when `other` is committed
+                    if other.reads.contains_any(&keys, &*self.comparator) {
+                        in_conflict = true;
+                        readers.push(other_id);
+                    }
+                }
+            }
+        }
+        if in_conflict && out_conflict {
+            return Err(KVLiteError::Busy);
+        }
+
+        let commit_seq_num = if writes.is_empty() {
+            self.inner.next_seq_num()
+        } else {
+            self.inner.write_batch(write_options, writes, mem_usage)?
+        };
+        for other_id in readers {
+            state.transactions.get_mut(&other_id).unwrap().out_conflict = true;
+        }
+        for other_id in writers {
+            state.transactions.get_mut(&other_id).unwrap().in_conflict = true;
+        }
+        let txn = state.transactions.get_mut(&txn_id).unwrap();
+        txn.commit_seq_num = Some(commit_seq_num);
+        txn.writes = keys;
+        txn.in_conflict = in_conflict;
+        txn.out_conflict = out_conflict;
+        Ok(())
+    }
+
+    fn release(&self, txn_id: TransactionID, seq_num: SequenceNumber) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(txn) = state.transactions.get(&txn_id) {
+            if txn.commit_seq_num.is_none() {
+                state.transactions.remove(&txn_id);
+            }
+        }
+        state.remove_committed();
+        self.inner.release_seq_num(seq_num);
+    }
+}
+
+/// Serializable transaction that takes no locks. It reads the snapshot at the time it
+/// begins, tracks the keys and ranges it reads, and buffers the writes until it is
+/// committed.
+///
+/// Committing fails with [KVLiteError::Busy] if the transaction may not be serializable
+/// with the concurrent transactions, in which case the writes are discarded.
+pub struct SerializableTransaction<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK> + 'static,
+{
+    txn_id: TransactionID,
+    txn_db: Arc<SerializableTransactionDB<UK, M, L>>,
+    /// Next sequence number when the transaction began.
+    seq_num: SequenceNumber,
+    write_options: WriteOptions,
+    /// Writes of the transaction, empty values are deletions.
+    writes: SrSwSkipMap<SeqNumKey<UK>, Value>,
+    mem_usage: i64,
+}
+
+impl<UK, M, L> SerializableTransaction<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK>,
+{
+    /// Get the value of `key` written by this transaction or in the snapshot.
+    pub fn get(&self, key: UK) -> Result<Option<Value>> {
+        let seq_num_key = SeqNumKey::new(key, self.seq_num);
+        match self.writes.get_clone(&seq_num_key) {
+            Some(value) if value.is_empty() => Ok(None),
+            Some(value) => Ok(Some(value)),
+            None => {
+                let key = seq_num_key.user_key();
+                self.txn_db.record_read(self.txn_id, |reads| {
+                    reads.keys.insert(key.clone());
+                });
+                self.txn_db
+                    .get(&SeqNumKey::new(key.clone(), self.seq_num - 1))
+            }
+        }
+    }
+
+    /// Get the key-value pairs in [`key_start`, `key_end`] written by this transaction or
+    /// in the snapshot. Keys inserted into the range by others conflict with the
+    /// transaction as well.
+    pub fn range_get(&self, key_start: UK, key_end: UK) -> Result<SrSwSkipMap<UK, Value>> {
+        self.txn_db.record_read(self.txn_id, |reads| {
+            reads.ranges.push((key_start.clone(), key_end.clone()));
+        });
+        let mut kvs = self.txn_db.range_get(
+            &SeqNumKey::new(key_start.clone(), self.seq_num - 1),
+            &SeqNumKey::new(key_end.clone(), self.seq_num - 1),
+        )?;
+        self.writes.range_get::<UK>(
+            &SeqNumKey::new(key_start, self.seq_num),
+            &SeqNumKey::new(key_end, self.seq_num),
+            &mut kvs,
+        );
+        let deleted: Vec<UK> = kvs
+            .iter()
+            .filter(|(_, value)| value.is_empty())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in deleted {
+            kvs.remove(key);
+        }
+        Ok(kvs)
+    }
+
+    pub fn put(&mut self, key: UK, value: Value) -> Result<()> {
+        let key_len = key.mem_size() as i64;
+        let value_len = value.len() as i64;
+        self.mem_usage += match self.writes.insert(SeqNumKey::new(key, self.seq_num), value) {
+            Some(v) => value_len - (v.len() as i64),
+            None => key_len + value_len,
+        };
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: UK) -> Result<()> {
+        let key_len = key.mem_size() as i64;
+        self.mem_usage += match self
+            .writes
+            .insert(SeqNumKey::new(key, self.seq_num), Value::default())
+        {
+            Some(v) => -(v.len() as i64),
+            None => key_len,
+        };
+        Ok(())
+    }
+
+    /// Write the writes of the transaction if it is serializable with the concurrent
+    /// transactions, otherwise return [KVLiteError::Busy].
+    pub fn commit(mut self) -> Result<()> {
+        let writes = std::mem::take(&mut self.writes);
+        debug_assert!(self.mem_usage >= 0);
+        self.txn_db.commit(
+            self.txn_id,
+            &self.write_options,
+            writes,
+            self.mem_usage as u64,
+        )
+    }
+
+    /// Discard the writes of the transaction.
+    pub fn rollback(self) {}
+}
+
+impl<UK, M, L> Drop for SerializableTransaction<UK, M, L>
+where
+    UK: DBKey + From<SeqNumKey<UK>> + 'static,
+    M: MemTable<SeqNumKey<UK>, UK> + 'static,
+    L: TransactionWAL<SeqNumKey<UK>, UK> + 'static,
+{
+    fn drop(&mut self) {
+        self.txn_db.release(self.txn_id, self.seq_num);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::comparator::I32Comparator;
+    use crate::db::key_types::{RawUserKey, SeqNumKey};
+    use crate::db::options::{Options, WriteOptions};
+    use crate::db::DB;
+    use crate::error::KVLiteError;
+    use crate::memory::MutexSkipMapMemTable;
+    use crate::transactions::serializable_transaction_db::SerializableTransactionDB;
+    use crate::wal::lsn_wal::LSNWriteAheadLog;
+    use std::sync::Arc;
+
+    type TestDB = SerializableTransactionDB<
+        RawUserKey,
+        MutexSkipMapMemTable<SeqNumKey<RawUserKey>>,
+        LSNWriteAheadLog,
+    >;
+
+    fn value(i: i32) -> Vec<u8> {
+        i.to_be_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_write_skew() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("serializable")
+            .tempdir()
+            .unwrap();
+        let db = Arc::new(TestDB::open(temp_dir.path()).unwrap());
+        let wo = || WriteOptions { sync: false };
+        let (alice, bob) = (b"alice".to_vec(), b"bob".to_vec());
+
+        let mut txn = TestDB::begin(&db, wo());
+        txn.put(alice.clone(), value(100)).unwrap();
+        txn.put(bob.clone(), value(100)).unwrap();
+        txn.commit().unwrap();
+
+        // both withdraw 150 if the total balance is enough, which is allowed by snapshot
+        // isolation but not serializable
+        let mut txn1 = TestDB::begin(&db, wo());
+        let mut txn2 = TestDB::begin(&db, wo());
+        assert_eq!(txn1.get(alice.clone()).unwrap(), Some(value(100)));
+        assert_eq!(txn1.get(bob.clone()).unwrap(), Some(value(100)));
+        assert_eq!(txn2.get(alice.clone()).unwrap(), Some(value(100)));
+        assert_eq!(txn2.get(bob.clone()).unwrap(), Some(value(100)));
+        txn1.put(alice.clone(), value(-50)).unwrap();
+        txn2.put(bob.clone(), value(-50)).unwrap();
+        txn1.commit().unwrap();
+        assert_eq!(txn2.commit(), Err(KVLiteError::Busy));
+
+        // the retry sees the total balance is not enough any more
+        let txn2 = TestDB::begin(&db, wo());
+        assert_eq!(txn2.get(alice.clone()).unwrap(), Some(value(-50)));
+        assert_eq!(txn2.get(bob.clone()).unwrap(), Some(value(100)));
+        txn2.commit().unwrap();
+
+        // concurrent writes of the same key
+        let mut txn1 = TestDB::begin(&db, wo());
+        let mut txn2 = TestDB::begin(&db, wo());
+        txn1.put(bob.clone(), value(1)).unwrap();
+        txn2.put(bob.clone(), value(2)).unwrap();
+        txn2.commit().unwrap();
+        assert_eq!(txn1.commit(), Err(KVLiteError::Busy));
+        assert_eq!(TestDB::begin(&db, wo()).get(bob).unwrap(), Some(value(2)));
+        assert!(db.state.lock().unwrap().transactions.is_empty());
+    }
+
+    #[test]
+    fn test_range_read() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("serializable")
+            .tempdir()
+            .unwrap();
+        let db = Arc::new(TestDB::open(temp_dir.path()).unwrap());
+        let wo = || WriteOptions { sync: false };
+        let key = |i: i32| format!("key{:02}", i).into_bytes();
+
+        let mut txn = TestDB::begin(&db, wo());
+        for i in 0..10 {
+            txn.put(key(i), value(i)).unwrap();
+        }
+        txn.commit().unwrap();
+
+        // both insert a key if there are less than 11 keys in the range
+        let mut txn1 = TestDB::begin(&db, wo());
+        let mut txn2 = TestDB::begin(&db, wo());
+        txn1.delete(key(0)).unwrap();
+        assert_eq!(txn1.range_get(key(0), key(20)).unwrap().len(), 9);
+        assert_eq!(txn2.range_get(key(0), key(20)).unwrap().len(), 10);
+        txn1.put(key(10), value(10)).unwrap();
+        txn2.put(key(11), value(11)).unwrap();
+        let kvs = txn1.range_get(key(0), key(20)).unwrap();
+        assert_eq!(kvs.len(), 10);
+        assert!(kvs.iter().any(|(k, _)| k == &key(10)));
+        txn1.commit().unwrap();
+        assert_eq!(txn2.commit(), Err(KVLiteError::Busy));
+
+        // an rw-antidependency without a cycle is allowed
+        let mut txn1 = TestDB::begin(&db, wo());
+        let mut txn2 = TestDB::begin(&db, wo());
+        assert_eq!(txn1.range_get(key(0), key(4)).unwrap().len(), 4);
+        assert_eq!(txn2.range_get(key(5), key(9)).unwrap().len(), 5);
+        txn1.put(key(5), value(55)).unwrap();
+        txn2.put(key(12), value(12)).unwrap();
+        txn1.commit().unwrap();
+        txn2.commit().unwrap();
+
+        // writes outside transactions take new sequence numbers, whatever the keys carry
+        let mut txn1 = TestDB::begin(&db, wo());
+        let mut txn2 = TestDB::begin(&db, wo());
+        assert_eq!(txn1.get(key(1)).unwrap(), Some(value(1)));
+        assert_eq!(txn2.get(key(2)).unwrap(), Some(value(2)));
+        txn1.put(key(2), value(22)).unwrap();
+        db.set(&wo(), SeqNumKey::new(key(1), 0), value(11)).unwrap();
+        txn2.put(key(3), value(33)).unwrap();
+        txn2.commit().unwrap();
+        assert_eq!(txn1.commit(), Err(KVLiteError::Busy));
+    }
+
+    #[test]
+    fn test_comparator() {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("serializable")
+            .tempdir()
+            .unwrap();
+        let options = Options {
+            comparator: Arc::new(I32Comparator),
+            ..Options::default()
+        };
+        let db = Arc::new(TestDB::open_with_options(temp_dir.path(), options).unwrap());
+        let wo = || WriteOptions { sync: false };
+        let key = |i: i32| i.to_le_bytes().to_vec();
+
+        // [1, 300] contains 256 in the order of the comparator, but not in the byte order
+        let mut txn1 = TestDB::begin(&db, wo());
+        let mut txn2 = TestDB::begin(&db, wo());
+        txn1.range_get(key(1), key(300)).unwrap();
+        assert_eq!(txn2.get(key(500)).unwrap(), None);
+        txn1.put(key(500), value(500)).unwrap();
+        txn2.put(key(256), value(256)).unwrap();
+        txn1.commit().unwrap();
+        assert_eq!(txn2.commit(), Err(KVLiteError::Busy));
+    }
+}
@@ -1,28 +1,169 @@
 use crate::db::key_types::RawUserKey;
+use crate::db::options::WriteOptions;
+use crate::db::{Value, DB};
+use crate::error::KVLiteError;
+use crate::memory::MemTable;
 use crate::transactions::lock::point_lock_manager::PointLockManager;
-use crate::transactions::lock::RowLockType;
+use crate::transactions::lock::{LockManager, RowLockType};
 use crate::transactions::pessimistic_transaction_db::PessimisticTransactionDB;
-use crate::transactions::TransactionID;
+use crate::transactions::{TransactionID, TransactionOptions};
+use crate::wal::WAL;
+use crate::Result;
+use std::collections::{BTreeMap, HashSet};
 use std::sync::Arc;
 
 pub trait Transaction {
     fn txn_id(&self) -> TransactionID;
-    fn try_lock(&self, key: &RawUserKey, lock_type: RowLockType);
+    fn try_lock(&mut self, key: &RawUserKey, lock_type: RowLockType) -> Result<()>;
 }
 
-pub struct PessimisticTransaction {
+/// Transaction that locks the keys it reads for update or writes, and buffers the writes
+/// until it is committed. The locks are released when the transaction ends.
+pub struct PessimisticTransaction<M, L, LM = PointLockManager>
+where
+    M: MemTable<RawUserKey, RawUserKey> + 'static,
+    L: WAL<RawUserKey, RawUserKey> + 'static,
+    LM: LockManager,
+{
     txn_id: TransactionID,
-    txn_db: Arc<PessimisticTransactionDB<PointLockManager>>,
+    txn_db: Arc<PessimisticTransactionDB<M, L, LM>>,
+    write_options: WriteOptions,
+    options: TransactionOptions,
+    /// Writes of the transaction, empty values are deletions.
+    writes: BTreeMap<RawUserKey, Value>,
+    locked_keys: HashSet<RawUserKey>,
+    /// Whether the transaction is rolled back by a deadlock.
+    aborted: bool,
 }
 
-impl Transaction for PessimisticTransaction {
+impl<M, L, LM> Transaction for PessimisticTransaction<M, L, LM>
+where
+    M: MemTable<RawUserKey, RawUserKey> + 'static,
+    L: WAL<RawUserKey, RawUserKey> + 'static,
+    LM: LockManager,
+{
     #[inline]
     fn txn_id(&self) -> TransactionID {
         self.txn_id
     }
 
-    fn try_lock(&self, key: &RawUserKey, lock_type: RowLockType) {
-        self.txn_db.try_lock(self, key, lock_type);
-        todo!()
+    /// Lock `key` until the transaction ends. If waiting for the lock makes a deadlock,
+    /// the transaction is rolled back and [KVLiteError::Deadlock] is returned, and so is
+    /// every later operation of the transaction.
+    fn try_lock(&mut self, key: &RawUserKey, lock_type: RowLockType) -> Result<()> {
+        self.check_aborted()?;
+        let result = self.txn_db.lock_manager.try_lock(
+            self.txn_id,
+            key,
+            lock_type,
+            self.options.lock_timeout,
+        );
+        match result {
+            Ok(()) => {
+                self.locked_keys.insert(key.clone());
+            }
+            Err(KVLiteError::Deadlock) => {
+                self.rollback_and_unlock();
+                self.aborted = true;
+            }
+            Err(_) => {}
+        }
+        result
+    }
+}
+
+impl<M, L, LM> PessimisticTransaction<M, L, LM>
+where
+    M: MemTable<RawUserKey, RawUserKey> + 'static,
+    L: WAL<RawUserKey, RawUserKey> + 'static,
+    LM: LockManager,
+{
+    pub(crate) fn new(
+        txn_id: TransactionID,
+        txn_db: Arc<PessimisticTransactionDB<M, L, LM>>,
+        write_options: WriteOptions,
+        options: TransactionOptions,
+    ) -> Self {
+        PessimisticTransaction {
+            txn_id,
+            txn_db,
+            write_options,
+            options,
+            writes: BTreeMap::new(),
+            locked_keys: HashSet::new(),
+            aborted: false,
+        }
+    }
+
+    fn check_aborted(&self) -> Result<()> {
+        if self.aborted {
+            Err(KVLiteError::Deadlock)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Get the value of `key` written by this transaction or committed, without locking
+    /// the key.
+    pub fn get(&self, key: &RawUserKey) -> Result<Option<Value>> {
+        self.check_aborted()?;
+        match self.writes.get(key) {
+            Some(value) if value.is_empty() => Ok(None),
+            Some(value) => Ok(Some(value.clone())),
+            None => self.txn_db.get(key),
+        }
+    }
+
+    /// Lock `key` and get its value, so that other transactions cannot write the key
+    /// until this transaction ends.
+    pub fn get_for_update(
+        &mut self,
+        key: &RawUserKey,
+        lock_type: RowLockType,
+    ) -> Result<Option<Value>> {
+        self.try_lock(key, lock_type)?;
+        self.get(key)
+    }
+
+    pub fn put(&mut self, key: RawUserKey, value: Value) -> Result<()> {
+        self.try_lock(&key, RowLockType::Exclusive)?;
+        self.writes.insert(key, value);
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: RawUserKey) -> Result<()> {
+        self.try_lock(&key, RowLockType::Exclusive)?;
+        self.writes.insert(key, Value::default());
+        Ok(())
+    }
+
+    /// Write the writes of the transaction to the database and release the locks.
+    pub fn commit(mut self) -> Result<()> {
+        self.check_aborted()?;
+        let writes = std::mem::take(&mut self.writes);
+        self.txn_db.write(&self.write_options, writes)
+    }
+
+    /// Discard the writes of the transaction and release the locks.
+    pub fn rollback(mut self) {
+        self.rollback_and_unlock();
+    }
+
+    fn rollback_and_unlock(&mut self) {
+        self.writes.clear();
+        for key in self.locked_keys.drain() {
+            self.txn_db.lock_manager.unlock(self.txn_id, &key);
+        }
+    }
+}
+
+impl<M, L, LM> Drop for PessimisticTransaction<M, L, LM>
+where
+    M: MemTable<RawUserKey, RawUserKey> + 'static,
+    L: WAL<RawUserKey, RawUserKey> + 'static,
+    LM: LockManager,
+{
+    fn drop(&mut self) {
+        self.rollback_and_unlock();
     }
 }
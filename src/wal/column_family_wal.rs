@@ -0,0 +1,376 @@
+//! Log shared by the column families of a [crate::db::column_family::ColumnFamilyDB].
+//!
+//! Every write is appended as a batch, so the writes of a batch spanning column families
+//! are loaded either all or none of them after a crash.
+//!
+//! ```text
+//! +--------------+-----------------+---------------+---------+-----+---------+
+//! | batch length | sequence number | entries count | entry 1 | ... | entry n |
+//! +--------------+-----------------+---------------+---------+-----+---------+
+//!
+//! +------------------+----------------+--------------+------------+-----------+---------+
+//! | column family id | key length     | value length | key        | value     |
+//! +------------------+----------------+--------------+------------+-----------+---------+
+//! | column family id | RANGE_DELETION | start length | end length | start key | end key |
+//! +------------------+----------------+--------------+------------+-----------+---------+
+//! ```
+//!
+//! All the numbers are varints, and the batch length is the byte size of the rest of the
+//! batch. Batches are loaded in the order of their sequence numbers, since the two log
+//! files swap roles when the mutable log is frozen.
+
+use crate::collections::skip_list::skipmap::SrSwSkipMap;
+use crate::db::key_types::{DBKey, RawUserKey};
+use crate::db::options::WriteOptions;
+use crate::db::Value;
+use crate::error::KVLiteError;
+use crate::ioutils::{
+    put_varint, read_bytes_exact, read_varint, BufReaderWithPos, MAX_VARINT_LENGTH,
+};
+use crate::memory::MemTable;
+use crate::wal::{read_log_header, WALInner, WAL};
+use crate::Result;
+use std::fs::File;
+use std::io::{Cursor, Write};
+use std::sync::{Arc, Mutex};
+
+/// Key length of a range deletion entry.
+const RANGE_DELETION: u64 = u32::MAX as u64;
+
+/// Write of a column family in a log batch.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ColumnFamilyWrite {
+    /// Set the value of the key, an empty value deletes the key.
+    Put(RawUserKey, Value),
+    /// Delete all the keys in [start, end).
+    DeleteRange(RawUserKey, RawUserKey),
+}
+
+/// Log of the writes to all the column families.
+pub(crate) struct ColumnFamilyLog {
+    inner: WALInner,
+    next_sequence_number: u64,
+    /// Number of column families whose immutable memory tables are being flushed, the
+    /// immutable log is cleared after all of them are flushed.
+    pending_flushes: usize,
+}
+
+type Batch = (u64, Vec<(u32, ColumnFamilyWrite)>);
+
+impl ColumnFamilyLog {
+    /// Open the logs at `db_path`, and call `f` with the writes of the logs in order.
+    /// Loading a log stops at an incomplete batch, which is left by a crash while
+    /// appending it.
+    pub(crate) fn open_and_load_logs(
+        db_path: &str,
+        mut f: impl FnMut(u32, ColumnFamilyWrite) -> Result<()>,
+    ) -> Result<ColumnFamilyLog> {
+        let inner = WALInner::open_logs(db_path)?;
+        let imm_batches = Self::read_batches(inner.log0.get_ref())?;
+        let mut batches = Self::read_batches(inner.log1.get_ref())?;
+        let mut log = ColumnFamilyLog {
+            inner,
+            next_sequence_number: 1,
+            pending_flushes: 0,
+        };
+
+        // all the writes are loaded to the mutable memory tables, so the batches of the
+        // immutable log are moved to the mutable log, which is frozen with the memory
+        // tables. Batches moved before a crash are loaded twice and deduplicated.
+        if !imm_batches.is_empty() {
+            for (sequence_number, writes) in &imm_batches {
+                log.write_batch(*sequence_number, writes)?;
+            }
+            log.inner.log1.get_mut().sync_data()?;
+            log.inner.clear_imm_log()?;
+            batches.extend(imm_batches);
+        }
+        batches.sort_by_key(|(sequence_number, _)| *sequence_number);
+        batches.dedup_by_key(|(sequence_number, _)| *sequence_number);
+
+        for (sequence_number, writes) in batches {
+            log.next_sequence_number = sequence_number + 1;
+            for (column_family_id, write) in writes {
+                f(column_family_id, write)?;
+            }
+        }
+        Ok(log)
+    }
+
+    fn read_batches(file: &File) -> Result<Vec<Batch>> {
+        let mut reader = BufReaderWithPos::new(file)?;
+        let version = read_log_header(&mut reader)?;
+        if version == 0 {
+            return Err(KVLiteError::Custom(
+                "column family log has no header".to_string(),
+            ));
+        }
+        let mut batches = vec![];
+        while let Ok(batch_length) = read_varint(&mut reader) {
+            let batch = match read_bytes_exact(&mut reader, batch_length) {
+                Ok(batch) => batch,
+                Err(_) => {
+                    warn!("incomplete batch at the end of log");
+                    break;
+                }
+            };
+            let mut batch = Cursor::new(batch);
+            let sequence_number = read_varint(&mut batch)?;
+            let count = read_varint(&mut batch)?;
+            let mut writes = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let column_family_id = read_varint(&mut batch)? as u32;
+                let key_length = read_varint(&mut batch)?;
+                let write = if key_length == RANGE_DELETION {
+                    let start_length = read_varint(&mut batch)?;
+                    let end_length = read_varint(&mut batch)?;
+                    ColumnFamilyWrite::DeleteRange(
+                        read_bytes_exact(&mut batch, start_length)?,
+                        read_bytes_exact(&mut batch, end_length)?,
+                    )
+                } else {
+                    let value_length = read_varint(&mut batch)?;
+                    ColumnFamilyWrite::Put(
+                        read_bytes_exact(&mut batch, key_length)?,
+                        read_bytes_exact(&mut batch, value_length)?,
+                    )
+                };
+                writes.push((column_family_id, write));
+            }
+            batches.push((sequence_number, writes));
+        }
+        Ok(batches)
+    }
+
+    /// Append the writes to the mutable log as a batch.
+    pub(crate) fn append(
+        &mut self,
+        write_options: &WriteOptions,
+        writes: &[(u32, ColumnFamilyWrite)],
+    ) -> Result<()> {
+        self.write_batch(self.next_sequence_number, writes)?;
+        if write_options.sync {
+            self.inner.log1.get_mut().sync_data()?;
+        }
+        self.next_sequence_number += 1;
+        Ok(())
+    }
+
+    fn write_batch(
+        &mut self,
+        sequence_number: u64,
+        writes: &[(u32, ColumnFamilyWrite)],
+    ) -> Result<()> {
+        let mut batch = vec![];
+        put_varint(&mut batch, sequence_number);
+        put_varint(&mut batch, writes.len() as u64);
+        for (column_family_id, write) in writes {
+            put_varint(&mut batch, *column_family_id as u64);
+            match write {
+                ColumnFamilyWrite::Put(key, value) => {
+                    put_varint(&mut batch, key.len() as u64);
+                    put_varint(&mut batch, value.len() as u64);
+                    batch.extend_from_slice(key);
+                    batch.extend_from_slice(value);
+                }
+                ColumnFamilyWrite::DeleteRange(key_start, key_end) => {
+                    put_varint(&mut batch, RANGE_DELETION);
+                    put_varint(&mut batch, key_start.len() as u64);
+                    put_varint(&mut batch, key_end.len() as u64);
+                    batch.extend_from_slice(key_start);
+                    batch.extend_from_slice(key_end);
+                }
+            }
+        }
+        let mut record = Vec::with_capacity(batch.len() + MAX_VARINT_LENGTH);
+        put_varint(&mut record, batch.len() as u64);
+        record.extend_from_slice(&batch);
+
+        self.inner.log1.write_all(&record)?;
+        self.inner.log1.flush()?;
+        Ok(())
+    }
+
+    /// Make the mutable log immutable before the memory tables of `pending_flushes`
+    /// column families are frozen.
+    pub(crate) fn freeze(&mut self, pending_flushes: usize) -> Result<()> {
+        debug_assert_eq!(self.pending_flushes, 0);
+        self.inner.freeze_mut_log()?;
+        self.pending_flushes = pending_flushes;
+        Ok(())
+    }
+
+    /// Called after the immutable memory table of a column family is flushed.
+    fn flushed(&mut self) -> Result<()> {
+        debug_assert!(self.pending_flushes > 0);
+        self.pending_flushes -= 1;
+        if self.pending_flushes == 0 {
+            self.inner.clear_imm_log()?;
+        }
+        Ok(())
+    }
+}
+
+/// The log shared by the column families, seen by the database of a column family.
+///
+/// The log is opened, loaded and frozen by [crate::db::column_family::ColumnFamilyDB]
+/// for all the column families, so [WAL::open_and_load_logs] and [WAL::load_log] are not
+/// supported, and [WAL::freeze_mut_log] does nothing.
+pub struct ColumnFamilyWAL {
+    column_family_id: u32,
+    log: Arc<Mutex<ColumnFamilyLog>>,
+}
+
+impl ColumnFamilyWAL {
+    pub(crate) fn new(column_family_id: u32, log: Arc<Mutex<ColumnFamilyLog>>) -> Self {
+        ColumnFamilyWAL {
+            column_family_id,
+            log,
+        }
+    }
+}
+
+impl<UK: DBKey> WAL<RawUserKey, UK> for ColumnFamilyWAL {
+    fn open_and_load_logs(
+        _db_path: &str,
+        _mut_mem_table: &mut impl MemTable<RawUserKey, UK>,
+    ) -> Result<Self> {
+        Err(KVLiteError::Custom(
+            "column family logs are opened by ColumnFamilyDB".to_string(),
+        ))
+    }
+
+    fn load_log(_file: &File, _mem_table: &mut impl MemTable<RawUserKey, UK>) -> Result<()> {
+        Err(KVLiteError::Custom(
+            "column family logs are loaded by ColumnFamilyDB".to_string(),
+        ))
+    }
+
+    fn append(
+        &mut self,
+        write_options: &WriteOptions,
+        key: &RawUserKey,
+        value: Option<&Value>,
+    ) -> Result<()> {
+        let write = ColumnFamilyWrite::Put(key.clone(), value.cloned().unwrap_or_default());
+        let mut log = self.log.lock().unwrap();
+        log.append(write_options, &[(self.column_family_id, write)])
+    }
+
+    fn append_batch(
+        &mut self,
+        write_options: &WriteOptions,
+        batch: &SrSwSkipMap<RawUserKey, Value>,
+    ) -> Result<()> {
+        let writes: Vec<_> = batch
+            .iter()
+            .map(|(key, value)| {
+                let write = ColumnFamilyWrite::Put(key.clone(), value.clone());
+                (self.column_family_id, write)
+            })
+            .collect();
+        let mut log = self.log.lock().unwrap();
+        log.append(write_options, &writes)
+    }
+
+    fn append_range_deletion(
+        &mut self,
+        write_options: &WriteOptions,
+        key_start: &RawUserKey,
+        key_end: &RawUserKey,
+    ) -> Result<()> {
+        let write = ColumnFamilyWrite::DeleteRange(key_start.clone(), key_end.clone());
+        let mut log = self.log.lock().unwrap();
+        log.append(write_options, &[(self.column_family_id, write)])
+    }
+
+    fn clear_imm_log(&mut self) -> Result<()> {
+        let mut log = self.log.lock().unwrap();
+        log.flushed()
+    }
+
+    fn freeze_mut_log(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::options::WriteOptions;
+    use crate::wal::column_family_wal::{ColumnFamilyLog, ColumnFamilyWrite};
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_column_family_log() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let wo = WriteOptions { sync: false };
+        let open = || {
+            let mut writes = vec![];
+            let log = ColumnFamilyLog::open_and_load_logs(path, |id, write| {
+                writes.push((id, write));
+                Ok(())
+            })
+            .unwrap();
+            (log, writes)
+        };
+
+        let (mut log, writes) = open();
+        assert!(writes.is_empty());
+        let batch = vec![
+            (
+                0,
+                ColumnFamilyWrite::Put(b"key1".to_vec(), b"value1".to_vec()),
+            ),
+            (1, ColumnFamilyWrite::Put(b"key1".to_vec(), vec![])),
+            (
+                2,
+                ColumnFamilyWrite::DeleteRange(b"key1".to_vec(), b"key5".to_vec()),
+            ),
+        ];
+        let put = (
+            1,
+            ColumnFamilyWrite::Put(b"key2".to_vec(), b"value2".to_vec()),
+        );
+        log.append(&wo, &batch).unwrap();
+        log.freeze(1).unwrap();
+        log.append(&wo, std::slice::from_ref(&put)).unwrap();
+        drop(log);
+
+        // the immutable log is loaded before the mutable log, which is appended after
+        // reopening the logs
+        let (mut log, writes) = open();
+        let mut expected = batch.clone();
+        expected.push(put.clone());
+        assert_eq!(writes, expected);
+        log.append(&wo, &batch).unwrap();
+        drop(log);
+        let (mut log, writes) = open();
+        expected.extend(batch);
+        assert_eq!(writes, expected);
+
+        // the immutable log is kept until all the column families are flushed
+        log.freeze(2).unwrap();
+        log.append(&wo, std::slice::from_ref(&put)).unwrap();
+        log.flushed().unwrap();
+        drop(log);
+        let (mut log, writes) = open();
+        expected.push(put.clone());
+        assert_eq!(writes, expected);
+        log.freeze(2).unwrap();
+        log.append(&wo, std::slice::from_ref(&put)).unwrap();
+        log.flushed().unwrap();
+        log.flushed().unwrap();
+        drop(log);
+        assert_eq!(open().1, vec![put.clone()]);
+
+        // a torn batch is not loaded
+        let (mut log, _) = open();
+        log.append(&wo, &[put.clone(), put.clone()]).unwrap();
+        log.inner.log1.write_all(&[100, 1, 0]).unwrap();
+        log.inner.log1.flush().unwrap();
+        drop(log);
+        assert_eq!(open().1, vec![put.clone(), put.clone(), put]);
+    }
+}
@@ -1,12 +1,12 @@
 //! ```text
 //! +-------------------+
-//! | START_TRANSACTION | u64
+//! | TRANSACTION       |
 //! +-------------------+
-//! | LSN1              | u64
+//! | LSN1              |
 //! +-------------------+
-//! | key1 length       | u64
+//! | key1 length       |
 //! +-------------------+
-//! | value1 length     | u64
+//! | value1 length     |
 //! +-------------------+
 //! | key1              | variant length
 //! +-------------------+
@@ -22,10 +22,12 @@
 //! +-------------------+
 //! | ...               |
 //! +-------------------+
-//! | END_TRANSACTION   | u64
+//! | END_TRANSACTION   |
+//! +-------------------+
+//! | VALUE             | transaction with single command is a `VALUE` record
+//! +-------------------+
+//! | LSN2              |
 //! +-------------------+
-//! | LSN2              | transaction with single command can emit `START_TRANSACTION` and
-//! +-------------------+ `END_TRANSACTION`
 //! | key3 length       |
 //! +-------------------+
 //! | value3 length     |
@@ -34,26 +36,126 @@
 //! +-------------------+
 //! | value3            |
 //! +-------------------+
+//! | RANGE_DELETION    |
+//! +-------------------+
+//! | LSN3              |
+//! +-------------------+
+//! | start length      |
+//! +-------------------+
+//! | end length        |
+//! +-------------------+
+//! | start key         |
+//! +-------------------+
+//! | end key           |
+//! +-------------------+
 //! ```
+//!
+//! Record types, markers, LSNs and lengths are varints after the log header, and u64 in
+//! legacy logs without header. Logs before format version 2 have no record types, where
+//! `START_TRANSACTION` and `RANGE_DELETION` markers take the place of the LSN, and a record
+//! without marker is a `VALUE` record.
+use crate::collections::skip_list::skipmap::SrSwSkipMap;
 use crate::db::key_types::{DBKey, RawUserKey, SeqNumKey, SequenceNumber};
 use crate::db::options::WriteOptions;
 use crate::db::Value;
 use crate::error::KVLiteError;
-use crate::ioutils::{read_bytes_exact, read_u64, BufReaderWithPos};
+use crate::ioutils::{put_varint, read_bytes_exact, read_u64, read_varint, BufReaderWithPos};
 use crate::memory::MemTable;
-use crate::wal::{TransactionWAL, WALInner, WAL};
+use crate::wal::{read_log_header, TransactionWAL, WALInner, WAL};
 use crate::Result;
 use std::fs::File;
-use std::io::{Seek, SeekFrom, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 
 const START_TRANSACTION: u64 = u64::MAX;
 const END_TRANSACTION: u64 = u64::MIN;
+const RANGE_DELETION: u64 = u64::MAX - 1;
+
+/// Types of the records since log format version 2.
+const VALUE_RECORD: u64 = 1;
+const TRANSACTION_RECORD: u64 = 2;
+const RANGE_DELETION_RECORD: u64 = 3;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum RecordType {
+    Value,
+    Transaction,
+    RangeDeletion,
+}
+
+/// Read the type and the LSN of the next record.
+fn read_record_header(
+    reader: &mut (impl Read + Seek),
+    format_version: u32,
+) -> Result<(RecordType, SequenceNumber)> {
+    let record_type = if format_version < 2 {
+        match read_number(reader, format_version)? {
+            START_TRANSACTION => RecordType::Transaction,
+            RANGE_DELETION => RecordType::RangeDeletion,
+            END_TRANSACTION => return Err(KVLiteError::Custom(String::from("invalid log"))),
+            lsn => return Ok((RecordType::Value, lsn)),
+        }
+    } else {
+        match read_number(reader, format_version)? {
+            VALUE_RECORD => RecordType::Value,
+            TRANSACTION_RECORD => RecordType::Transaction,
+            RANGE_DELETION_RECORD => RecordType::RangeDeletion,
+            record_type => {
+                return Err(KVLiteError::Custom(format!(
+                    "unknown log record type {}",
+                    record_type
+                )))
+            }
+        }
+    };
+    let lsn = read_number(reader, format_version)?;
+    Ok((record_type, lsn))
+}
+
+fn put_record_header(
+    buf: &mut Vec<u8>,
+    format_version: u32,
+    record_type: RecordType,
+    lsn: SequenceNumber,
+) {
+    if format_version < 2 {
+        match record_type {
+            RecordType::Value => {}
+            RecordType::Transaction => put_number(buf, format_version, START_TRANSACTION),
+            RecordType::RangeDeletion => put_number(buf, format_version, RANGE_DELETION),
+        }
+    } else {
+        let record_type = match record_type {
+            RecordType::Value => VALUE_RECORD,
+            RecordType::Transaction => TRANSACTION_RECORD,
+            RecordType::RangeDeletion => RANGE_DELETION_RECORD,
+        };
+        put_number(buf, format_version, record_type);
+    }
+    put_number(buf, format_version, lsn);
+}
+
+/// Read a number, which is u64 in format version 0 and varint since version 1.
+fn read_number(reader: &mut (impl Read + Seek), format_version: u32) -> Result<u64> {
+    if format_version == 0 {
+        read_u64(reader)
+    } else {
+        read_varint(reader)
+    }
+}
+
+fn put_number(buf: &mut Vec<u8>, format_version: u32, number: u64) {
+    if format_version == 0 {
+        buf.extend_from_slice(&number.to_le_bytes());
+    } else {
+        put_varint(buf, number);
+    }
+}
 
 pub struct LSNWriteAheadLog {
     inner: WALInner,
 }
 
-impl<UK: DBKey> WAL<SeqNumKey<UK>, UK> for LSNWriteAheadLog {
+impl<UK: DBKey + From<SeqNumKey<UK>>> WAL<SeqNumKey<UK>, UK> for LSNWriteAheadLog {
     fn open_and_load_logs(
         db_path: &str,
         mut_mem_table: &mut impl MemTable<SeqNumKey<UK>, UK>,
@@ -68,21 +170,43 @@ impl<UK: DBKey> WAL<SeqNumKey<UK>, UK> for LSNWriteAheadLog {
 
     fn load_log(file: &File, mem_table: &mut impl MemTable<SeqNumKey<UK>, UK>) -> Result<()> {
         let mut reader = BufReaderWithPos::new(file)?;
-        reader.seek(SeekFrom::Start(0))?;
-        while let Ok(lsn) = read_u64(&mut reader) {
-            match lsn {
-                START_TRANSACTION => {
-                    let lsn = read_u64(&mut reader)?;
-                    if lsn != START_TRANSACTION && lsn != END_TRANSACTION {
-                        Self::load_kvs_in_lsn(lsn, &mut reader, mem_table)?;
-                    } else {
+        let version = read_log_header(&mut reader)?;
+        while let Ok((record_type, lsn)) = read_record_header(&mut reader, version) {
+            match record_type {
+                RecordType::Transaction => {
+                    if version < 2 && (lsn == START_TRANSACTION || lsn == END_TRANSACTION) {
                         return Err(KVLiteError::Custom(String::from("invalid log")));
                     }
+                    match Self::read_kvs_in_lsn(&mut reader, version)? {
+                        Some(kvs) => {
+                            for (key, value) in kvs {
+                                let lsn_key = SeqNumKey::new(UK::from(key), lsn);
+                                if value.is_empty() {
+                                    mem_table.remove(lsn_key)?;
+                                } else {
+                                    mem_table.set(lsn_key, value)?;
+                                }
+                            }
+                        }
+                        None => {
+                            warn!("incomplete transaction at the end of log");
+                            break;
+                        }
+                    }
+                }
+                RecordType::RangeDeletion => {
+                    let start_length = read_number(&mut reader, version)?;
+                    let end_length = read_number(&mut reader, version)?;
+                    let key_start: RawUserKey = read_bytes_exact(&mut reader, start_length)?;
+                    let key_end: RawUserKey = read_bytes_exact(&mut reader, end_length)?;
+                    mem_table.delete_range(
+                        &SeqNumKey::new(UK::from(key_start), lsn),
+                        &SeqNumKey::new(UK::from(key_end), lsn),
+                    )?;
                 }
-                END_TRANSACTION => return Err(KVLiteError::Custom(String::from("invalid log"))),
-                lsn => {
-                    let key_length = read_u64(&mut reader)?;
-                    let value_length = read_u64(&mut reader)?;
+                RecordType::Value => {
+                    let key_length = read_number(&mut reader, version)?;
+                    let value_length = read_number(&mut reader, version)?;
                     let key: RawUserKey = read_bytes_exact(&mut reader, key_length)?;
                     let lsn_key = SeqNumKey::new(UK::from(key), lsn);
                     if value_length > 0 {
@@ -104,26 +228,64 @@ impl<UK: DBKey> WAL<SeqNumKey<UK>, UK> for LSNWriteAheadLog {
         key: &SeqNumKey<UK>,
         value: Option<&Value>,
     ) -> Result<()> {
-        let internal_key = key.raw_user_key();
-        let key_length: [u8; 4] = (internal_key.len() as u32).to_le_bytes();
-        self.inner.log1.write_all(&key_length)?;
-        match value {
-            Some(v) => {
-                let value_length = (v.len() as u32).to_le_bytes();
-                self.inner.log1.write_all(&value_length)?;
-                self.inner.log1.write_all(internal_key)?;
-                self.inner.log1.write_all(v)?;
-            }
-            None => {
-                self.inner.log1.write_all(&0u32.to_le_bytes())?;
-                self.inner.log1.write_all(internal_key)?;
-            }
-        }
-        self.inner.log1.flush()?;
-        if write_options.sync {
-            self.inner.log1.get_mut().sync_data()?;
+        let version = self.inner.log1_format_version;
+        let raw_key = key.raw_user_key();
+        let value = value.map(Vec::as_slice).unwrap_or_default();
+        let mut record = Vec::with_capacity(raw_key.len() + value.len() + 32);
+        put_record_header(&mut record, version, RecordType::Value, key.seq_num());
+        put_number(&mut record, version, raw_key.len() as u64);
+        put_number(&mut record, version, value.len() as u64);
+        record.extend_from_slice(raw_key);
+        record.extend_from_slice(value);
+        self.write_record(write_options, &record)
+    }
+
+    /// The keys of `batch` share one sequence number, which is the LSN of the transaction
+    /// record.
+    fn append_batch(
+        &mut self,
+        write_options: &WriteOptions,
+        batch: &SrSwSkipMap<SeqNumKey<UK>, Value>,
+    ) -> Result<()> {
+        let lsn = match batch.iter().next() {
+            Some((key, _)) => key.seq_num(),
+            None => return Ok(()),
+        };
+        let version = self.inner.log1_format_version;
+        let mut record = vec![];
+        put_record_header(&mut record, version, RecordType::Transaction, lsn);
+        for (key, value) in batch.iter() {
+            debug_assert_eq!(key.seq_num(), lsn);
+            let raw_key = key.raw_user_key();
+            put_number(&mut record, version, raw_key.len() as u64);
+            put_number(&mut record, version, value.len() as u64);
+            record.extend_from_slice(raw_key);
+            record.extend_from_slice(value);
         }
-        Ok(())
+        put_number(&mut record, version, END_TRANSACTION);
+        self.write_record(write_options, &record)
+    }
+
+    fn append_range_deletion(
+        &mut self,
+        write_options: &WriteOptions,
+        key_start: &SeqNumKey<UK>,
+        key_end: &SeqNumKey<UK>,
+    ) -> Result<()> {
+        let version = self.inner.log1_format_version;
+        let (raw_start, raw_end) = (key_start.raw_user_key(), key_end.raw_user_key());
+        let mut record = Vec::with_capacity(raw_start.len() + raw_end.len() + 32);
+        put_record_header(
+            &mut record,
+            version,
+            RecordType::RangeDeletion,
+            key_start.seq_num(),
+        );
+        put_number(&mut record, version, raw_start.len() as u64);
+        put_number(&mut record, version, raw_end.len() as u64);
+        record.extend_from_slice(raw_start);
+        record.extend_from_slice(raw_end);
+        self.write_record(write_options, &record)
     }
 
     fn clear_imm_log(&mut self) -> Result<()> {
@@ -135,43 +297,132 @@ impl<UK: DBKey> WAL<SeqNumKey<UK>, UK> for LSNWriteAheadLog {
     }
 }
 
-impl<UK: DBKey> TransactionWAL<SeqNumKey<UK>, UK> for LSNWriteAheadLog {
+impl<UK: DBKey + From<SeqNumKey<UK>>> TransactionWAL<SeqNumKey<UK>, UK> for LSNWriteAheadLog {
     fn start_transaction(&mut self) -> Result<()> {
-        let bytes = START_TRANSACTION.to_le_bytes();
-        self.inner.log1.write_all(&bytes)?;
+        let version = self.inner.log1_format_version;
+        let mut record = vec![];
+        if version < 2 {
+            put_number(&mut record, version, START_TRANSACTION);
+        } else {
+            put_number(&mut record, version, TRANSACTION_RECORD);
+        }
+        self.inner.log1.write_all(&record)?;
         Ok(())
     }
 
     fn end_transaction(&mut self) -> Result<()> {
-        let bytes = END_TRANSACTION.to_le_bytes();
-        self.inner.log1.write_all(&bytes)?;
+        let mut record = vec![];
+        put_number(&mut record, self.inner.log1_format_version, END_TRANSACTION);
+        self.inner.log1.write_all(&record)?;
         Ok(())
     }
 }
 
 impl LSNWriteAheadLog {
-    fn load_kvs_in_lsn<UK: DBKey>(
-        lsn: SequenceNumber,
+    fn write_record(&mut self, write_options: &WriteOptions, record: &[u8]) -> Result<()> {
+        self.inner.log1.write_all(record)?;
+        self.inner.log1.flush()?;
+        if write_options.sync {
+            self.inner.log1.get_mut().sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Read the key-values of a transaction until `END_TRANSACTION`, `None` if the log ends
+    /// before it.
+    fn read_kvs_in_lsn(
         reader: &mut BufReaderWithPos<&File>,
-        mem_table: &mut impl MemTable<SeqNumKey<UK>, UK>,
-    ) -> Result<()> {
-        while let Ok(key_length) = read_u64(reader) {
+        format_version: u32,
+    ) -> Result<Option<Vec<(RawUserKey, Value)>>> {
+        let mut kvs = vec![];
+        while let Ok(key_length) = read_number(reader, format_version) {
             match key_length {
-                END_TRANSACTION => return Ok(()),
+                END_TRANSACTION => return Ok(Some(kvs)),
                 START_TRANSACTION => return Err(KVLiteError::Custom(String::from("invalid log"))),
                 key_length => {
-                    let value_length = read_u64(reader)?;
-                    let key: RawUserKey = read_bytes_exact(reader, key_length)?;
-                    let lsn_key = SeqNumKey::new(UK::from(key), lsn);
-                    if value_length > 0 {
+                    let kv = read_number(reader, format_version).and_then(|value_length| {
+                        let key = read_bytes_exact(reader, key_length)?;
                         let value = read_bytes_exact(reader, value_length)?;
-                        mem_table.set(lsn_key, value)?;
-                    } else {
-                        mem_table.remove(lsn_key)?;
+                        Ok((key, value))
+                    });
+                    match kv {
+                        Ok(kv) => kvs.push(kv),
+                        Err(_) => return Ok(None),
                     }
                 }
             }
         }
-        Err(KVLiteError::Custom(String::from("invalid log")))
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::comparator::BytewiseComparator;
+    use crate::db::key_types::{RawUserKey, SeqNumKey};
+    use crate::db::options::WriteOptions;
+    use crate::db::DBCommand;
+    use crate::memory::{MemTable, MutexSkipMapMemTable};
+    use crate::wal::lsn_wal::{LSNWriteAheadLog, RANGE_DELETION};
+    use crate::wal::WAL;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_range_deletion() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let key = |i: u64| SeqNumKey::new(format!("key{}", i).into_bytes(), i + 1);
+
+        let mut mut_mem = MutexSkipMapMemTable::<SeqNumKey<RawUserKey>>::default();
+        let mut wal = LSNWriteAheadLog::open_and_load_logs(path, &mut mut_mem).unwrap();
+        let wo = WriteOptions { sync: false };
+        for i in 0..10 {
+            <LSNWriteAheadLog as WAL<_, RawUserKey>>::append(
+                &mut wal,
+                &wo,
+                &key(i),
+                Some(&format!("value{}", i).into_bytes()),
+            )
+            .unwrap();
+        }
+        <LSNWriteAheadLog as WAL<_, RawUserKey>>::append_range_deletion(
+            &mut wal,
+            &wo,
+            &SeqNumKey::new(b"key3".to_vec(), 20),
+            &SeqNumKey::new(b"key7".to_vec(), 20),
+        )
+        .unwrap();
+        // a record whose LSN equals the legacy range deletion marker
+        let newest = SeqNumKey::new(b"key5".to_vec(), RANGE_DELETION);
+        <LSNWriteAheadLog as WAL<_, RawUserKey>>::append(
+            &mut wal,
+            &wo,
+            &newest,
+            Some(&b"newest".to_vec()),
+        )
+        .unwrap();
+
+        mut_mem = MutexSkipMapMemTable::default();
+        let _wal = LSNWriteAheadLog::open_and_load_logs(path, &mut mut_mem).unwrap();
+        for i in 0..10 {
+            let value = mut_mem.get(&SeqNumKey::new(key(i).into(), 20)).unwrap();
+            if (3..7).contains(&i) {
+                assert_eq!(value, Some(vec![]));
+            } else {
+                assert_eq!(value, Some(format!("value{}", i).into_bytes()));
+            }
+        }
+        assert_eq!(
+            mut_mem
+                .get(&SeqNumKey::new(b"key5".to_vec(), u64::MAX))
+                .unwrap(),
+            Some(b"newest".to_vec())
+        );
+        assert!(mut_mem
+            .range_tombstones()
+            .covers(b"key3", 20, &BytewiseComparator));
+        assert!(!mut_mem
+            .range_tombstones()
+            .covers(b"key3", 19, &BytewiseComparator));
     }
 }
@@ -1,13 +1,17 @@
+use crate::collections::skip_list::skipmap::SrSwSkipMap;
 use crate::db::key_types::DBKey;
 use crate::db::options::WriteOptions;
 use crate::db::Value;
+use crate::error::KVLiteError;
+use crate::ioutils::read_u32;
 use crate::memory::MemTable;
 use crate::Result;
 use std::fs;
 use std::fs::{File, OpenOptions};
-use std::io::BufWriter;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
+pub mod column_family_wal;
 pub mod lsn_wal;
 pub mod simple_wal;
 
@@ -25,6 +29,22 @@ pub trait WAL<SK: DBKey, UK: DBKey>: Sized + Sync + Send {
         value: Option<&Value>,
     ) -> Result<()>;
 
+    /// Append the key-values of `batch` to `mut_log` as one record, which is loaded either
+    /// all or none of them after a crash. Empty values are deletions.
+    fn append_batch(
+        &mut self,
+        write_options: &WriteOptions,
+        batch: &SrSwSkipMap<SK, Value>,
+    ) -> Result<()>;
+
+    /// Append a range deletion of [`key_start`, `key_end`) to `mut_log`
+    fn append_range_deletion(
+        &mut self,
+        write_options: &WriteOptions,
+        key_start: &SK,
+        key_end: &SK,
+    ) -> Result<()>;
+
     fn clear_imm_log(&mut self) -> Result<()>;
 
     fn freeze_mut_log(&mut self) -> Result<()>;
@@ -35,10 +55,54 @@ pub trait TransactionWAL<SK: DBKey, UK: DBKey>: WAL<SK, UK> {
     fn end_transaction(&mut self) -> Result<()>;
 }
 
+/// Magic number at the start of log files with a header.
+const LOG_MAGIC_NUMBER: u32 = 0x6c6f6701;
+
+/// Format version of log records.
+///
+/// * 0: lengths are fixed-width integers, log files of this version have no header.
+/// * 1: lengths are varints.
+/// * 2: records of [lsn_wal::LSNWriteAheadLog] start with their types.
+/// * 3: batch records of [simple_wal::SimpleWriteAheadLog].
+pub const LOG_FORMAT_VERSION: u32 = 3;
+
+/// Read the header `| magic number u32 | format version u32 |` of a log file and return
+/// its format version, the reader is positioned at the first record.
+fn read_log_header(reader: &mut (impl Read + Seek)) -> Result<u32> {
+    reader.seek(SeekFrom::Start(0))?;
+    match read_u32(reader) {
+        Ok(LOG_MAGIC_NUMBER) => {
+            let version = read_u32(reader)?;
+            if version > LOG_FORMAT_VERSION {
+                return Err(KVLiteError::Custom(format!(
+                    "unsupported log format version {}",
+                    version
+                )));
+            }
+            Ok(version)
+        }
+        _ => {
+            reader.seek(SeekFrom::Start(0))?;
+            Ok(0)
+        }
+    }
+}
+
+/// Write the header to an empty log file.
+fn write_log_header(log: &mut BufWriter<File>) -> Result<()> {
+    debug_assert_eq!(log.get_ref().metadata()?.len(), 0);
+    log.write_all(&LOG_MAGIC_NUMBER.to_le_bytes())?;
+    log.write_all(&LOG_FORMAT_VERSION.to_le_bytes())?;
+    log.flush()?;
+    Ok(())
+}
+
 struct WALInner {
     log_path: PathBuf,
     log0: BufWriter<File>,
     log1: BufWriter<File>,
+    /// Records appended to a legacy mutable log keep its format version.
+    log1_format_version: u32,
 }
 
 impl WALInner {
@@ -65,15 +129,25 @@ impl WALInner {
             .open(&mut_log)
             .unwrap();
 
+        let mut log0 = BufWriter::new(log0);
+        let mut log1 = BufWriter::new(log1);
+        for log in [&mut log0, &mut log1] {
+            if log.get_ref().metadata()?.len() == 0 {
+                write_log_header(log)?;
+            }
+        }
+        let log1_format_version = read_log_header(&mut log1.get_ref())?;
         Ok(WALInner {
             log_path,
-            log0: BufWriter::new(log0),
-            log1: BufWriter::new(log1),
+            log0,
+            log1,
+            log1_format_version,
         })
     }
 
     fn clear_imm_log(&mut self) -> Result<()> {
         self.log0.get_mut().set_len(0)?;
+        write_log_header(&mut self.log0)?;
         self.log0.get_mut().sync_data()?;
         Ok(())
     }
@@ -81,7 +155,9 @@ impl WALInner {
     fn freeze_mut_log(&mut self) -> Result<()> {
         std::mem::swap(&mut self.log0, &mut self.log1);
         self.log1.get_mut().set_len(0)?;
+        write_log_header(&mut self.log1)?;
         self.log1.get_mut().sync_data()?;
+        self.log1_format_version = LOG_FORMAT_VERSION;
         Ok(())
     }
 }
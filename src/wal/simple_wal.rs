@@ -1,17 +1,87 @@
+use crate::collections::skip_list::skipmap::SrSwSkipMap;
 use crate::db::key_types::{DBKey, RawUserKey};
 use crate::db::options::WriteOptions;
 use crate::db::Value;
-use crate::ioutils::{read_bytes_exact, read_u32, BufReaderWithPos};
+use crate::ioutils::{put_varint, read_bytes_exact, read_u32, read_varint, BufReaderWithPos};
 use crate::memory::MemTable;
-use crate::wal::{WALInner, WAL};
+use crate::wal::{read_log_header, WALInner, WAL};
 use crate::Result;
 use std::fs::File;
-use std::io::{Seek, SeekFrom, Write};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 
+/// Key length of a range deletion record.
+const RANGE_DELETION: u32 = u32::MAX;
+/// Key length of a batch record.
+const BATCH: u32 = u32::MAX - 1;
+
+/// Read a length, which is u32 in format version 0 and varint since version 1.
+fn read_length(reader: &mut (impl Read + Seek), format_version: u32) -> Result<u64> {
+    if format_version == 0 {
+        Ok(read_u32(reader)? as u64)
+    } else {
+        read_varint(reader)
+    }
+}
+
+fn put_length(buf: &mut Vec<u8>, format_version: u32, length: u64) {
+    if format_version == 0 {
+        buf.extend_from_slice(&(length as u32).to_le_bytes());
+    } else {
+        put_varint(buf, length);
+    }
+}
+
+/// ```text
+/// +----------------+--------------+-----+-------+
+/// | key length     | value length | key | value |
+/// +----------------+--------------+-----+-------+
+/// | RANGE_DELETION | start length | end length | start key | end key |
+/// +----------------+--------------+------------+-----------+---------+
+/// | BATCH          | batch length | record 1   | ...       | record n |
+/// +----------------+--------------+------------+-----------+---------+
+/// ```
+///
+/// Lengths are varints after the log header, and u32 in legacy logs without header. The
+/// records of a batch are key-value records, and the batch length is their byte size, so
+/// an incomplete batch at the end of the log is discarded.
 pub struct SimpleWriteAheadLog {
     inner: WALInner,
 }
 
+impl SimpleWriteAheadLog {
+    fn write_record(&mut self, write_options: &WriteOptions, record: &[u8]) -> Result<()> {
+        self.inner.log1.write_all(record)?;
+        self.inner.log1.flush()?;
+        if write_options.sync {
+            self.inner.log1.get_mut().sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn put_key_value(buf: &mut Vec<u8>, format_version: u32, key: &[u8], value: &[u8]) {
+        put_length(buf, format_version, key.len() as u64);
+        put_length(buf, format_version, value.len() as u64);
+        buf.extend_from_slice(key);
+        buf.extend_from_slice(value);
+    }
+
+    fn load_key_value<UK: DBKey>(
+        reader: &mut (impl Read + Seek),
+        format_version: u32,
+        key_length: u64,
+        mem_table: &mut impl MemTable<RawUserKey, UK>,
+    ) -> Result<()> {
+        let value_length = read_length(reader, format_version)?;
+        let key = read_bytes_exact(reader, key_length)?;
+        if value_length > 0 {
+            let value = read_bytes_exact(reader, value_length)?;
+            mem_table.set(key, value)
+        } else {
+            mem_table.remove(key)
+        }
+    }
+}
+
 impl<UK: DBKey> WAL<RawUserKey, UK> for SimpleWriteAheadLog {
     fn open_and_load_logs(
         db_path: &str,
@@ -27,16 +97,35 @@ impl<UK: DBKey> WAL<RawUserKey, UK> for SimpleWriteAheadLog {
 
     fn load_log(file: &File, mem_table: &mut impl MemTable<RawUserKey, UK>) -> Result<()> {
         let mut reader = BufReaderWithPos::new(file)?;
-        reader.seek(SeekFrom::Start(0))?;
-        while let Ok(key_length) = read_u32(&mut reader) {
-            let value_length = read_u32(&mut reader)?;
-            let key = read_bytes_exact(&mut reader, key_length as u64)?;
-            if value_length > 0 {
-                let value = read_bytes_exact(&mut reader, value_length as u64)?;
-                mem_table.set(key, value)?;
-            } else {
-                mem_table.remove(key)?;
+        let version = read_log_header(&mut reader)?;
+        while let Ok(key_length) = read_length(&mut reader, version) {
+            if key_length == RANGE_DELETION as u64 {
+                let start_length = read_length(&mut reader, version)?;
+                let end_length = read_length(&mut reader, version)?;
+                let key_start = read_bytes_exact(&mut reader, start_length)?;
+                let key_end = read_bytes_exact(&mut reader, end_length)?;
+                mem_table.delete_range(&key_start, &key_end)?;
+                continue;
+            }
+            if key_length == BATCH as u64 {
+                let batch = read_length(&mut reader, version)
+                    .and_then(|batch_length| read_bytes_exact(&mut reader, batch_length));
+                let batch = match batch {
+                    Ok(batch) => batch,
+                    Err(_) => {
+                        warn!("incomplete batch at the end of log");
+                        break;
+                    }
+                };
+                let batch_length = batch.len() as u64;
+                let mut batch = Cursor::new(batch);
+                while batch.position() < batch_length {
+                    let key_length = read_length(&mut batch, version)?;
+                    Self::load_key_value(&mut batch, version, key_length, mem_table)?;
+                }
+                continue;
             }
+            Self::load_key_value(&mut reader, version, key_length, mem_table)?;
         }
         reader.seek(SeekFrom::End(0))?;
         Ok(())
@@ -48,25 +137,44 @@ impl<UK: DBKey> WAL<RawUserKey, UK> for SimpleWriteAheadLog {
         key: &RawUserKey,
         value: Option<&Value>,
     ) -> Result<()> {
-        let key_length: [u8; 4] = (key.len() as u32).to_le_bytes();
-        self.inner.log1.write_all(&key_length)?;
-        match value {
-            Some(v) => {
-                let value_length = (v.len() as u32).to_le_bytes();
-                self.inner.log1.write_all(&value_length)?;
-                self.inner.log1.write_all(key)?;
-                self.inner.log1.write_all(v)?;
-            }
-            None => {
-                self.inner.log1.write_all(&0u32.to_le_bytes())?;
-                self.inner.log1.write_all(key)?;
-            }
-        }
-        self.inner.log1.flush()?;
-        if write_options.sync {
-            self.inner.log1.get_mut().sync_data()?;
+        let version = self.inner.log1_format_version;
+        let value = value.map(Vec::as_slice).unwrap_or_default();
+        let mut record = Vec::with_capacity(key.len() + value.len() + 8);
+        Self::put_key_value(&mut record, version, key, value);
+        self.write_record(write_options, &record)
+    }
+
+    fn append_batch(
+        &mut self,
+        write_options: &WriteOptions,
+        batch: &SrSwSkipMap<RawUserKey, Value>,
+    ) -> Result<()> {
+        let version = self.inner.log1_format_version;
+        let mut records = vec![];
+        for (key, value) in batch.iter() {
+            Self::put_key_value(&mut records, version, key, value);
         }
-        Ok(())
+        let mut record = Vec::with_capacity(records.len() + 16);
+        put_length(&mut record, version, BATCH as u64);
+        put_length(&mut record, version, records.len() as u64);
+        record.extend_from_slice(&records);
+        self.write_record(write_options, &record)
+    }
+
+    fn append_range_deletion(
+        &mut self,
+        write_options: &WriteOptions,
+        key_start: &RawUserKey,
+        key_end: &RawUserKey,
+    ) -> Result<()> {
+        let version = self.inner.log1_format_version;
+        let mut record = Vec::with_capacity(key_start.len() + key_end.len() + 12);
+        put_length(&mut record, version, RANGE_DELETION as u64);
+        put_length(&mut record, version, key_start.len() as u64);
+        put_length(&mut record, version, key_end.len() as u64);
+        record.extend_from_slice(key_start);
+        record.extend_from_slice(key_end);
+        self.write_record(write_options, &record)
     }
 
     fn clear_imm_log(&mut self) -> Result<()> {
@@ -80,9 +188,14 @@ impl<UK: DBKey> WAL<RawUserKey, UK> for SimpleWriteAheadLog {
 
 #[cfg(test)]
 mod tests {
-    use crate::db::key_types::RawUserKey;
+    use crate::collections::skip_list::skipmap::SrSwSkipMap;
+    use crate::db::comparator::BytewiseComparator;
+    use crate::db::key_types::{RawUserKey, SequenceNumber};
     use crate::db::options::WriteOptions;
-    use crate::memory::{InternalKeyValueIterator, MutexSkipMapMemTable, SkipMapMemTable};
+    use crate::db::DBCommand;
+    use crate::memory::{
+        InternalKeyValueIterator, MemTable, MutexSkipMapMemTable, SkipMapMemTable,
+    };
     use crate::wal::simple_wal::SimpleWriteAheadLog;
     use crate::wal::WAL;
     use tempfile::TempDir;
@@ -127,4 +240,155 @@ mod tests {
         wal = SimpleWriteAheadLog::open_and_load_logs(path, &mut mut_mem).unwrap();
         assert!(mut_mem.is_empty());
     }
+
+    #[test]
+    fn test_range_deletion() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+
+        let mut mut_mem = MutexSkipMapMemTable::<RawUserKey>::default();
+        let mut wal: SimpleWriteAheadLog =
+            SimpleWriteAheadLog::open_and_load_logs(path, &mut mut_mem).unwrap();
+        let wo = WriteOptions { sync: false };
+        for i in 0..10 {
+            <SimpleWriteAheadLog as WAL<RawUserKey, RawUserKey>>::append(
+                &mut wal,
+                &wo,
+                &format!("key{}", i).into_bytes(),
+                Some(&format!("value{}", i).into_bytes()),
+            )
+            .unwrap();
+        }
+        <SimpleWriteAheadLog as WAL<RawUserKey, RawUserKey>>::append_range_deletion(
+            &mut wal,
+            &wo,
+            &Vec::from("key3"),
+            &Vec::from("key7"),
+        )
+        .unwrap();
+
+        mut_mem = MutexSkipMapMemTable::default();
+        let _wal: SimpleWriteAheadLog =
+            SimpleWriteAheadLog::open_and_load_logs(path, &mut mut_mem).unwrap();
+        for i in 0..10 {
+            let value = mut_mem.get(&format!("key{}", i).into_bytes()).unwrap();
+            if (3..7).contains(&i) {
+                assert_eq!(value, Some(vec![]));
+            } else {
+                assert_eq!(value, Some(format!("value{}", i).into_bytes()));
+            }
+        }
+        assert!(mut_mem.range_tombstones().covers(
+            b"key3",
+            SequenceNumber::MAX,
+            &BytewiseComparator
+        ));
+    }
+
+    #[test]
+    fn test_batch() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+
+        let mut mut_mem = MutexSkipMapMemTable::<RawUserKey>::default();
+        let mut wal: SimpleWriteAheadLog =
+            SimpleWriteAheadLog::open_and_load_logs(path, &mut mut_mem).unwrap();
+        let wo = WriteOptions { sync: false };
+        let batch = |start: usize| {
+            let batch = SrSwSkipMap::new();
+            for i in start..start + 10 {
+                let value = if i % 3 == 0 {
+                    vec![]
+                } else {
+                    format!("value{}", i).into_bytes()
+                };
+                batch.insert(format!("key{:02}", i).into_bytes(), value);
+            }
+            batch
+        };
+        <SimpleWriteAheadLog as WAL<RawUserKey, RawUserKey>>::append_batch(
+            &mut wal,
+            &wo,
+            &batch(0),
+        )
+        .unwrap();
+        <SimpleWriteAheadLog as WAL<RawUserKey, RawUserKey>>::append_batch(
+            &mut wal,
+            &wo,
+            &batch(10),
+        )
+        .unwrap();
+        drop(wal);
+        // the second batch is torn by a crash
+        let log = temp_dir.path().join("log").join("1.log");
+        let length = std::fs::metadata(&log).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&log)
+            .unwrap()
+            .set_len(length - 5)
+            .unwrap();
+
+        mut_mem = MutexSkipMapMemTable::default();
+        let _wal: SimpleWriteAheadLog =
+            SimpleWriteAheadLog::open_and_load_logs(path, &mut mut_mem).unwrap();
+        for i in 0..20 {
+            let value = mut_mem.get(&format!("key{:02}", i).into_bytes()).unwrap();
+            if i >= 10 {
+                assert_eq!(value, None);
+            } else if i % 3 == 0 {
+                assert_eq!(value, Some(vec![]));
+            } else {
+                assert_eq!(value, Some(format!("value{}", i).into_bytes()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_legacy_log() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+
+        // log of fixed-width lengths without header
+        std::fs::create_dir_all(temp_dir.path().join("log")).unwrap();
+        let mut legacy = vec![];
+        for (key, value) in [("key1", "value1"), ("key2", "value2")] {
+            legacy.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            legacy.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            legacy.extend_from_slice(key.as_bytes());
+            legacy.extend_from_slice(value.as_bytes());
+        }
+        std::fs::write(temp_dir.path().join("log").join("1.log"), legacy).unwrap();
+
+        let mut mut_mem = MutexSkipMapMemTable::<RawUserKey>::default();
+        let mut wal: SimpleWriteAheadLog =
+            SimpleWriteAheadLog::open_and_load_logs(path, &mut mut_mem).unwrap();
+        assert_eq!(mut_mem.len(), 2);
+        // records appended to the legacy log keep its format
+        <SimpleWriteAheadLog as WAL<RawUserKey, RawUserKey>>::append(
+            &mut wal,
+            &WriteOptions { sync: false },
+            &b"key3".to_vec(),
+            Some(&b"value3".to_vec()),
+        )
+        .unwrap();
+        <SimpleWriteAheadLog as WAL<RawUserKey, RawUserKey>>::freeze_mut_log(&mut wal).unwrap();
+        <SimpleWriteAheadLog as WAL<RawUserKey, RawUserKey>>::append(
+            &mut wal,
+            &WriteOptions { sync: false },
+            &b"key4".to_vec(),
+            Some(&b"value4".to_vec()),
+        )
+        .unwrap();
+
+        mut_mem = MutexSkipMapMemTable::default();
+        let _wal: SimpleWriteAheadLog =
+            SimpleWriteAheadLog::open_and_load_logs(path, &mut mut_mem).unwrap();
+        for i in 1..5 {
+            assert_eq!(
+                mut_mem.get(&format!("key{}", i).into_bytes()).unwrap(),
+                Some(format!("value{}", i).into_bytes())
+            );
+        }
+    }
 }